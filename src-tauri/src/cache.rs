@@ -10,8 +10,8 @@ pub fn init_db(app_data_dir: &Path) -> Result<CacheState, String> {
         .map_err(|e| format!("Failed to create app data dir: {}", e))?;
 
     let db_path = app_data_dir.join("frame_cache.db");
-    let conn = Connection::open(&db_path)
-        .map_err(|e| format!("Failed to open cache database: {}", e))?;
+    let conn =
+        Connection::open(&db_path).map_err(|e| format!("Failed to open cache database: {}", e))?;
 
     conn.execute_batch(
         "CREATE TABLE IF NOT EXISTS frame_cache (
@@ -20,7 +20,12 @@ pub fn init_db(app_data_dir: &Path) -> Result<CacheState, String> {
             timestamp_secs REAL NOT NULL,
             frame_jpeg BLOB NOT NULL,
             PRIMARY KEY (file_path, file_modified, timestamp_secs)
-        );"
+        );
+        CREATE TABLE IF NOT EXISTS duration_cache (
+            file_path TEXT PRIMARY KEY,
+            file_modified TEXT NOT NULL,
+            duration_secs REAL NOT NULL
+        );",
     )
     .map_err(|e| format!("Failed to create cache table: {}", e))?;
 
@@ -53,3 +58,19 @@ pub fn store_frame(
         rusqlite::params![path, modified, timestamp_secs, jpeg_data],
     );
 }
+
+pub fn get_cached_duration(conn: &Connection, path: &str, modified: &str) -> Option<f64> {
+    conn.query_row(
+        "SELECT duration_secs FROM duration_cache WHERE file_path = ?1 AND file_modified = ?2",
+        rusqlite::params![path, modified],
+        |row| row.get(0),
+    )
+    .ok()
+}
+
+pub fn store_duration(conn: &Connection, path: &str, modified: &str, duration_secs: f64) {
+    let _ = conn.execute(
+        "INSERT OR REPLACE INTO duration_cache (file_path, file_modified, duration_secs) VALUES (?1, ?2, ?3)",
+        rusqlite::params![path, modified, duration_secs],
+    );
+}
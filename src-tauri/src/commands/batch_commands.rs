@@ -0,0 +1,376 @@
+use std::path::{Path, PathBuf};
+
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use super::fs_commands::{convert_node, delete_node_with, move_path, rename_path, select_codec};
+use crate::encoding::rename_plan::Direction;
+use crate::journal::{BatchRecorder, JournalState};
+use crate::models::batch_report::{BatchItemResult, BatchProgress, BatchRename, BatchReport};
+use crate::models::file_entry::FileEntry;
+use crate::models::operation::OperationKind;
+use crate::models::rename_report::{CollisionPolicy, RenameReport};
+use crate::session::SessionState;
+use crate::trash;
+
+pub const BATCH_PROGRESS_EVENT: &str = "batch-progress";
+
+/// The batch commands run the single-item command on every path in turn.
+/// Everything they change is journaled as one operation, so a single undo
+/// reverts the whole batch. Without `continue_on_error` the batch stops at
+/// the first failure; items done up to then stay done.
+#[tauri::command]
+pub async fn batch_encode_nodes(
+    paths: Vec<String>,
+    scheme: Option<String>,
+    collision_policy: Option<CollisionPolicy>,
+    continue_on_error: Option<bool>,
+    app: AppHandle,
+    session: State<'_, SessionState>,
+) -> Result<BatchReport, String> {
+    let key = session.key();
+    let policy = collision_policy.unwrap_or_default();
+    run_in_background(
+        app,
+        paths,
+        OperationKind::Encode,
+        continue_on_error,
+        move |_, path, recorder, _| {
+            let mut encrypted = None;
+            let codec = select_codec(scheme.as_deref(), key.as_ref(), &mut encrypted)?;
+            rename_outcome(convert_node(
+                path,
+                Direction::Encode(codec),
+                policy,
+                key.as_ref(),
+                Some(recorder),
+            ))
+        },
+    )
+    .await
+}
+
+#[tauri::command]
+pub async fn batch_decode_nodes(
+    paths: Vec<String>,
+    collision_policy: Option<CollisionPolicy>,
+    continue_on_error: Option<bool>,
+    app: AppHandle,
+    session: State<'_, SessionState>,
+) -> Result<BatchReport, String> {
+    let key = session.key();
+    let policy = collision_policy.unwrap_or_default();
+    run_in_background(
+        app,
+        paths,
+        OperationKind::Decode,
+        continue_on_error,
+        move |_, path, recorder, _| {
+            rename_outcome(convert_node(
+                path,
+                Direction::Decode,
+                policy,
+                key.as_ref(),
+                Some(recorder),
+            ))
+        },
+    )
+    .await
+}
+
+#[tauri::command]
+pub async fn batch_move_nodes(
+    paths: Vec<String>,
+    target_dir: String,
+    verify_checksum: Option<bool>,
+    continue_on_error: Option<bool>,
+    app: AppHandle,
+    session: State<'_, SessionState>,
+) -> Result<BatchReport, String> {
+    let key = session.key();
+    let total = paths.len();
+    run_in_background(
+        app,
+        paths,
+        OperationKind::Move,
+        continue_on_error,
+        move |index, path, recorder, app| {
+            move_path(
+                Path::new(path),
+                Path::new(&target_dir),
+                verify_checksum.unwrap_or(false),
+                key.as_ref(),
+                Some(recorder),
+                &mut |transfer| {
+                    let progress = BatchProgress {
+                        kind: OperationKind::Move,
+                        done: index,
+                        total,
+                        current_path: Some(path.clone()),
+                        transfer: Some(transfer.clone()),
+                    };
+                    let _ = app.emit(BATCH_PROGRESS_EVENT, progress);
+                },
+            )
+            .map(Some)
+        },
+    )
+    .await
+}
+
+#[tauri::command]
+pub async fn batch_delete_nodes(
+    paths: Vec<String>,
+    continue_on_error: Option<bool>,
+    app: AppHandle,
+) -> Result<BatchReport, String> {
+    run_in_background(
+        app,
+        paths,
+        OperationKind::Delete,
+        continue_on_error,
+        |_, path, recorder, _| {
+            delete_node_with(path.clone(), trash::move_to_trash, Some(recorder)).map(|_| None)
+        },
+    )
+    .await
+}
+
+#[tauri::command]
+pub async fn batch_rename_nodes(
+    renames: Vec<BatchRename>,
+    encode: bool,
+    scheme: Option<String>,
+    collision_policy: Option<CollisionPolicy>,
+    continue_on_error: Option<bool>,
+    app: AppHandle,
+    session: State<'_, SessionState>,
+) -> Result<BatchReport, String> {
+    let key = session.key();
+    let policy = collision_policy.unwrap_or_default();
+    let paths = renames.iter().map(|r| r.path.clone()).collect();
+    run_in_background(
+        app,
+        paths,
+        OperationKind::Rename,
+        continue_on_error,
+        move |index, path, recorder, _| {
+            let report = rename_path(
+                Path::new(path),
+                &renames[index].new_name,
+                encode,
+                scheme.as_deref(),
+                policy,
+                key.as_ref(),
+                Some(recorder),
+            );
+            rename_outcome(report)
+        },
+    )
+    .await
+}
+
+/// Runs the batch off the main thread and journals it once it is done.
+async fn run_in_background(
+    app: AppHandle,
+    paths: Vec<String>,
+    kind: OperationKind,
+    continue_on_error: Option<bool>,
+    mut op: impl FnMut(usize, &String, &BatchRecorder, &AppHandle) -> Result<Option<FileEntry>, String>
+        + Send
+        + 'static,
+) -> Result<BatchReport, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let recorder = BatchRecorder::default();
+        let report = run_batch(
+            &paths,
+            kind,
+            continue_on_error.unwrap_or(false),
+            &mut |progress| {
+                let _ = app.emit(BATCH_PROGRESS_EVENT, progress);
+            },
+            |index, path| op(index, path, &recorder, &app),
+        );
+        let journal = app.state::<JournalState>();
+        recorder.finish(&*journal, kind, &common_parent(&paths));
+        report
+    })
+    .await
+    .map_err(|e| format!("Task join error: {}", e))
+}
+
+pub fn run_batch(
+    paths: &[String],
+    kind: OperationKind,
+    continue_on_error: bool,
+    on_progress: &mut dyn FnMut(&BatchProgress),
+    mut op: impl FnMut(usize, &String) -> Result<Option<FileEntry>, String>,
+) -> BatchReport {
+    let mut report = BatchReport::default();
+    let mut progress = BatchProgress {
+        kind,
+        done: 0,
+        total: paths.len(),
+        current_path: None,
+        transfer: None,
+    };
+
+    for (index, path) in paths.iter().enumerate() {
+        progress.current_path = Some(path.clone());
+        on_progress(&progress);
+
+        let result = op(index, path);
+        let failed = result.is_err();
+        report.results.push(match result {
+            Ok(entry) => BatchItemResult {
+                path: path.clone(),
+                entry,
+                error: None,
+            },
+            Err(e) => BatchItemResult {
+                path: path.clone(),
+                entry: None,
+                error: Some(e),
+            },
+        });
+        progress.done += 1;
+
+        if failed && !continue_on_error {
+            report.not_attempted = paths[index + 1..].to_vec();
+            break;
+        }
+    }
+
+    progress.current_path = None;
+    on_progress(&progress);
+    report
+}
+
+/// A rename that was rolled back is a failure of that item.
+pub fn rename_outcome(report: Result<RenameReport, String>) -> Result<Option<FileEntry>, String> {
+    let report = report?;
+    match report.error {
+        Some(e) => Err(e),
+        None => Ok(report.entry),
+    }
+}
+
+/// The deepest directory containing all of `paths`, used as the path of the
+/// journaled operation.
+pub fn common_parent(paths: &[String]) -> PathBuf {
+    let mut parents = paths
+        .iter()
+        .map(|p| Path::new(p).parent().unwrap_or(Path::new(p)));
+    let Some(first) = parents.next() else {
+        return PathBuf::new();
+    };
+    parents.fold(first.to_path_buf(), |common, parent| {
+        common
+            .ancestors()
+            .find(|ancestor| parent.starts_with(ancestor))
+            .map(Path::to_path_buf)
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::name_codec::default_codec;
+    use crate::journal::{self, Recorder};
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn encode(path: &str, recorder: Option<&dyn Recorder>) -> Result<RenameReport, String> {
+        convert_node(
+            path,
+            Direction::Encode(default_codec()),
+            CollisionPolicy::default(),
+            None,
+            recorder,
+        )
+    }
+
+    fn encode_all(
+        paths: &[String],
+        continue_on_error: bool,
+        recorder: &BatchRecorder,
+    ) -> BatchReport {
+        run_batch(
+            paths,
+            OperationKind::Encode,
+            continue_on_error,
+            &mut |_| {},
+            |_, path| rename_outcome(encode(path, Some(recorder as &dyn Recorder))),
+        )
+    }
+
+    fn setup() -> (TempDir, Vec<String>) {
+        let tmp = TempDir::new().unwrap();
+        let mut paths = Vec::new();
+        for name in ["a.mkv", "missing.mkv", "b.mkv"] {
+            let path = tmp.path().join(name);
+            if name != "missing.mkv" {
+                fs::write(&path, "video").unwrap();
+            }
+            paths.push(path.to_string_lossy().to_string());
+        }
+        (tmp, paths)
+    }
+
+    #[test]
+    fn test_batch_stops_on_error() {
+        let (tmp, paths) = setup();
+        let report = encode_all(&paths, false, &BatchRecorder::default());
+
+        assert_eq!(report.results.len(), 2);
+        assert!(report.results[0].error.is_none());
+        assert!(report.results[1].error.is_some());
+        assert_eq!(report.not_attempted, vec![paths[2].clone()]);
+        assert!(tmp.path().join("b.mkv").exists());
+    }
+
+    #[test]
+    fn test_batch_continues_on_error() {
+        let (tmp, paths) = setup();
+        let mut reports = Vec::new();
+        let report = run_batch(
+            &paths,
+            OperationKind::Encode,
+            true,
+            &mut |p| reports.push(p.clone()),
+            |_, path| rename_outcome(encode(path, None)),
+        );
+
+        assert_eq!(report.results.len(), 3);
+        assert!(report.results[2].entry.as_ref().unwrap().is_encoded);
+        assert!(!tmp.path().join("b.mkv").exists());
+        assert_eq!(reports.len(), 4);
+        assert_eq!(reports.last().unwrap().done, 3);
+    }
+
+    #[test]
+    fn test_batch_is_undone_as_one_operation() {
+        let (tmp, paths) = setup();
+        let journal = JournalState(std::sync::Mutex::new(
+            rusqlite::Connection::open_in_memory().unwrap(),
+        ));
+        journal::init_schema(&journal.0.lock().unwrap()).unwrap();
+
+        let recorder = BatchRecorder::default();
+        encode_all(&paths, true, &recorder);
+        recorder.finish(&journal, OperationKind::Encode, &common_parent(&paths));
+
+        let conn = journal.0.lock().unwrap();
+        assert_eq!(journal::history(&conn, 10).unwrap().len(), 1);
+        journal::undo_last(&conn).unwrap();
+        assert!(tmp.path().join("a.mkv").exists());
+        assert!(tmp.path().join("b.mkv").exists());
+    }
+
+    #[test]
+    fn test_common_parent() {
+        let paths = vec!["/videos/a/x.mkv".to_string(), "/videos/b/y.mkv".to_string()];
+        assert_eq!(common_parent(&paths), PathBuf::from("/videos"));
+        assert_eq!(common_parent(&paths[..1]), PathBuf::from("/videos/a"));
+    }
+}
@@ -0,0 +1,645 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use super::batch_commands::{common_parent, rename_outcome, run_batch, BATCH_PROGRESS_EVENT};
+use super::fs_commands::{build_file_entry, rename_path};
+use super::video_commands::read_video_info;
+use crate::encoding::encoded_dir::{name_state_in, NameState};
+use crate::encoding::name_codec::{codec_for_name, legacy_codec};
+use crate::encoding::name_crypto::{is_encrypted_name, SessionKey, ENCRYPTED_SCHEME};
+use crate::encoding::name_index::{HASHED_SCHEME, INDEX_FILE_NAME};
+use crate::journal::{BatchRecorder, JournalState, Recorder};
+use crate::models::batch_report::{BatchProgress, BatchReport};
+use crate::models::bulk_rename::{
+    BulkCollisionKind, BulkRenameCollision, BulkRenamePreview, PlannedBulkRename, RenameTemplate,
+};
+use crate::models::operation::OperationKind;
+use crate::models::rename_report::CollisionPolicy;
+use crate::models::video_frame::VideoInfo;
+use crate::rename_template::Renamer;
+use crate::session::SessionState;
+
+/// Lists the names `bulk_rename` would give the entries, and the collisions
+/// and errors that would stop it, without touching anything.
+#[tauri::command]
+pub async fn preview_bulk_rename(
+    paths: Vec<String>,
+    template: RenameTemplate,
+    session: State<'_, SessionState>,
+) -> Result<BulkRenamePreview, String> {
+    let key = session.key();
+    tauri::async_runtime::spawn_blocking(move || {
+        let probe = |path: &str| read_video_info(path, key.as_ref());
+        Ok(plan(&paths, &template, key.as_ref(), &probe)?.preview)
+    })
+    .await
+    .map_err(|e| format!("Task join error: {}", e))?
+}
+
+/// Renames the entries after `template`, working on their decoded names.
+/// Encoded entries are encoded again with their own scheme. Nothing is
+/// renamed if the preview has any problem; otherwise the renames run in an
+/// order that frees each name before it is taken, and are journaled as one
+/// operation.
+#[tauri::command]
+pub async fn bulk_rename(
+    paths: Vec<String>,
+    template: RenameTemplate,
+    app: AppHandle,
+    session: State<'_, SessionState>,
+) -> Result<BatchReport, String> {
+    let key = session.key();
+    tauri::async_runtime::spawn_blocking(move || {
+        let journal = app.state::<JournalState>();
+        let probe = |path: &str| read_video_info(path, key.as_ref());
+        run_bulk_rename(
+            &paths,
+            &template,
+            key.as_ref(),
+            Some(&*journal),
+            &probe,
+            &mut |progress| {
+                let _ = app.emit(BATCH_PROGRESS_EVENT, progress);
+            },
+        )
+    })
+    .await
+    .map_err(|e| format!("Task join error: {}", e))?
+}
+
+fn run_bulk_rename(
+    paths: &[String],
+    template: &RenameTemplate,
+    key: Option<&SessionKey>,
+    journal: Option<&dyn Recorder>,
+    probe: &dyn Fn(&str) -> Result<VideoInfo, String>,
+    on_progress: &mut dyn FnMut(&BatchProgress),
+) -> Result<BatchReport, String> {
+    let plan = plan(paths, template, key, probe)?;
+    let problems = plan.preview.problems();
+    if problems > 0 {
+        return Err(format!(
+            "Bulk rename has {} problem(s), preview it to see them",
+            problems
+        ));
+    }
+
+    let ordered: Vec<String> = plan
+        .order
+        .iter()
+        .map(|step| step.path.to_string_lossy().to_string())
+        .collect();
+    let recorder = BatchRecorder::default();
+    let report = run_batch(
+        &ordered,
+        OperationKind::Rename,
+        false,
+        on_progress,
+        |index, path| {
+            let step = &plan.order[index];
+            let item = &plan.preview.items[step.item];
+            let report = match &step.aside {
+                Some(temp_name) => rename_path(
+                    Path::new(path),
+                    temp_name,
+                    false,
+                    None,
+                    CollisionPolicy::Fail,
+                    key,
+                    Some(&recorder),
+                ),
+                None => rename_path(
+                    Path::new(path),
+                    item.new_name.as_deref().unwrap_or_default(),
+                    item.encode,
+                    plan.schemes[step.item],
+                    CollisionPolicy::Fail,
+                    key,
+                    Some(&recorder),
+                ),
+            };
+            rename_outcome(report)
+        },
+    );
+    if let Some(journal) = journal {
+        recorder.finish(journal, OperationKind::Rename, &common_parent(&ordered));
+    }
+    Ok(report)
+}
+
+struct BulkPlan {
+    preview: BulkRenamePreview,
+    /// The scheme each item is encoded with again, by index
+    schemes: Vec<Option<&'static str>>,
+    /// The renames of the items that change, in the order they can run in
+    order: Vec<BulkStep>,
+}
+
+/// One rename of a bulk rename. Items that would take each other's names
+/// are first moved aside to a temporary name, then to their new one.
+struct BulkStep {
+    item: usize,
+    /// Where the item is when the step runs
+    path: PathBuf,
+    /// The plain name the item is moved aside to, or None for its new name
+    aside: Option<String>,
+}
+
+fn plan(
+    paths: &[String],
+    template: &RenameTemplate,
+    key: Option<&SessionKey>,
+    probe: &dyn Fn(&str) -> Result<VideoInfo, String>,
+) -> Result<BulkPlan, String> {
+    let renamer = Renamer::new(template)?;
+    let mut items = Vec::with_capacity(paths.len());
+    let mut schemes = Vec::with_capacity(paths.len());
+
+    for (index, path) in paths.iter().enumerate() {
+        let mut item = PlannedBulkRename {
+            path: path.clone(),
+            old_name: String::new(),
+            new_name: None,
+            encode: false,
+            collision: None,
+            error: None,
+        };
+        let mut scheme = None;
+        let result = build_file_entry(Path::new(path), key).and_then(|entry| {
+            item.old_name = entry.name.clone();
+            item.encode = entry.is_encoded;
+            scheme = scheme_of(&entry.physical_name);
+            if entry.is_locked {
+                return Err("The name is encrypted and the session is locked".to_string());
+            }
+            let video = match renamer.needs_video_info() && !entry.is_directory {
+                true => Some(probe(path)?),
+                false => None,
+            };
+            renamer.rename(&entry.name, entry.is_directory, index, video.as_ref())
+        });
+        match result {
+            Ok(new_name) => item.new_name = Some(new_name),
+            Err(e) => item.error = Some(e),
+        }
+        items.push(item);
+        schemes.push(scheme);
+    }
+
+    let changing: Vec<usize> = (0..items.len())
+        .filter(|&i| {
+            items[i]
+                .new_name
+                .as_ref()
+                .is_some_and(|new| *new != items[i].old_name)
+        })
+        .collect();
+    find_collisions(&mut items, &changing, key)?;
+    let order = order_renames(&items, &changing);
+
+    Ok(BulkPlan {
+        preview: BulkRenamePreview { items },
+        schemes,
+        order,
+    })
+}
+
+/// The scheme a renamed entry is encoded with: its own, except that legacy
+/// names are brought up to the default scheme. Hashed names get the default
+/// too and only come back if the new name is still too long.
+fn scheme_of(physical: &str) -> Option<&'static str> {
+    if is_encrypted_name(physical) {
+        return Some(ENCRYPTED_SCHEME);
+    }
+    codec_for_name(physical)
+        .map(|codec| codec.id())
+        .filter(|&id| id != legacy_codec().id() && id != HASHED_SCHEME)
+}
+
+/// Flags items whose new name another item also gets, or that an entry of
+/// the directory keeps. Names of items that are renamed become free.
+fn find_collisions(
+    items: &mut [PlannedBulkRename],
+    changing: &[usize],
+    key: Option<&SessionKey>,
+) -> Result<(), String> {
+    let mut by_parent: HashMap<PathBuf, Vec<usize>> = HashMap::new();
+    for &i in changing {
+        let parent = Path::new(&items[i].path)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .to_path_buf();
+        by_parent.entry(parent).or_default().push(i);
+    }
+
+    for (parent, group) in by_parent {
+        let siblings = display_names(&parent, key)?;
+        let mut taken: HashMap<String, usize> = HashMap::new();
+        for &i in &group {
+            let new_name = items[i].new_name.clone().unwrap_or_default();
+            if let Some(&other) = taken.get(&new_name) {
+                let other_path = items[other].path.clone();
+                items[other].collision.get_or_insert(BulkRenameCollision {
+                    kind: BulkCollisionKind::Duplicate,
+                    with: items[i].path.clone(),
+                });
+                items[i].collision = Some(BulkRenameCollision {
+                    kind: BulkCollisionKind::Duplicate,
+                    with: other_path,
+                });
+                continue;
+            }
+            taken.insert(new_name.clone(), i);
+
+            let vacated = group.iter().any(|&j| items[j].old_name == new_name);
+            if let Some(existing) = siblings.get(&new_name).filter(|_| !vacated) {
+                items[i].collision = Some(BulkRenameCollision {
+                    kind: BulkCollisionKind::Existing,
+                    with: existing.to_string_lossy().to_string(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Display names of the entries of `dir`. Locked names can't be compared and
+/// are left out.
+fn display_names(dir: &Path, key: Option<&SessionKey>) -> Result<HashMap<String, PathBuf>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read directory: {}", e))?;
+    let mut names = HashMap::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
+        let physical = entry.file_name().to_string_lossy().to_string();
+        let name = match name_state_in(dir, &physical, key) {
+            _ if physical == INDEX_FILE_NAME => continue,
+            NameState::Plain => physical,
+            NameState::Decoded(decoded) => decoded,
+            NameState::Locked => continue,
+        };
+        names.insert(name, entry.path());
+    }
+    Ok(names)
+}
+
+/// Orders the renames so that no name is taken before its current owner has
+/// been renamed, and entries inside a renamed directory go before it. Items
+/// that take each other's names are broken up by moving one of them aside.
+fn order_renames(items: &[PlannedBulkRename], changing: &[usize]) -> Vec<BulkStep> {
+    let parent = |i: usize| Path::new(&items[i].path).parent().unwrap_or(Path::new(""));
+    let target = |i: usize| parent(i).join(items[i].new_name.as_deref().unwrap_or_default());
+    let owners: HashMap<PathBuf, usize> = changing
+        .iter()
+        .map(|&i| (parent(i).join(&items[i].old_name), i))
+        .collect();
+    let by_path: HashMap<&Path, usize> = changing
+        .iter()
+        .map(|&i| (Path::new(&items[i].path), i))
+        .collect();
+    let targets: HashSet<PathBuf> = changing.iter().map(|&i| target(i)).collect();
+
+    // The item currently holding the new name of each item, who waits for
+    // each name to be freed and for each entry to be renamed, and how many
+    // of those each item still waits for
+    let mut blocker = vec![None; items.len()];
+    let mut name_waiters: Vec<Vec<usize>> = vec![Vec::new(); items.len()];
+    let mut dir_waiters: Vec<Vec<usize>> = vec![Vec::new(); items.len()];
+    let mut waiting = vec![0usize; items.len()];
+    for &i in changing {
+        if let Some(&owner) = owners.get(&target(i)).filter(|&&owner| owner != i) {
+            blocker[i] = Some(owner);
+            name_waiters[owner].push(i);
+            waiting[i] += 1;
+        }
+        for ancestor in Path::new(&items[i].path).ancestors().skip(1) {
+            if let Some(&dir) = by_path.get(ancestor) {
+                dir_waiters[i].push(dir);
+                waiting[dir] += 1;
+            }
+        }
+    }
+
+    let mut ready: BTreeSet<usize> = changing
+        .iter()
+        .copied()
+        .filter(|&i| waiting[i] == 0)
+        .collect();
+    let release = |waiters: &[usize], waiting: &mut [usize], ready: &mut BTreeSet<usize>| {
+        for &j in waiters {
+            waiting[j] -= 1;
+            if waiting[j] == 0 {
+                ready.insert(j);
+            }
+        }
+    };
+    let mut aside: HashMap<usize, PathBuf> = HashMap::new();
+    let mut done = vec![false; items.len()];
+    let mut left = changing.len();
+    let mut steps = Vec::with_capacity(changing.len());
+
+    loop {
+        while let Some(i) = ready.pop_first() {
+            let path = match aside.get(&i) {
+                Some(temp_path) => temp_path.clone(),
+                None => {
+                    release(&name_waiters[i], &mut waiting, &mut ready);
+                    PathBuf::from(&items[i].path)
+                }
+            };
+            steps.push(BulkStep {
+                item: i,
+                path,
+                aside: None,
+            });
+            release(&dir_waiters[i], &mut waiting, &mut ready);
+            done[i] = true;
+            left -= 1;
+        }
+        if left == 0 {
+            break;
+        }
+
+        // Only cycles of items taking each other's names are left. The
+        // deepest entries have nothing left inside them, so one item of each
+        // cycle among them is moved aside, which frees its name for the others.
+        let depth = |i: usize| Path::new(&items[i].path).components().count();
+        let deepest = changing
+            .iter()
+            .filter(|&&i| !done[i])
+            .map(|&i| depth(i))
+            .max()
+            .unwrap_or_default();
+        let mut walked = vec![None; items.len()];
+        let mut moved = false;
+        for &start in changing {
+            if done[start] || walked[start].is_some() || depth(start) != deepest {
+                continue;
+            }
+            let mut i = start;
+            let in_cycle = loop {
+                if let Some(walk) = walked[i] {
+                    break walk == start;
+                }
+                walked[i] = Some(start);
+                match blocker[i] {
+                    Some(next) if !done[next] && !aside.contains_key(&next) => i = next,
+                    _ => break false,
+                }
+            };
+            if !in_cycle {
+                continue;
+            }
+            let temp_name = (1..)
+                .map(|n| format!(".bulk-rename{}", n))
+                .find(|name| {
+                    let candidate = parent(i).join(name);
+                    !aside.values().any(|taken| *taken == candidate)
+                        && !targets.contains(&candidate)
+                        && fs::symlink_metadata(&candidate).is_err()
+                })
+                .expect("some temporary name is free");
+            steps.push(BulkStep {
+                item: i,
+                path: PathBuf::from(&items[i].path),
+                aside: Some(temp_name.clone()),
+            });
+            aside.insert(i, parent(i).join(temp_name));
+            release(&name_waiters[i], &mut waiting, &mut ready);
+            moved = true;
+        }
+        if !moved {
+            break;
+        }
+    }
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::encoded_dir::encode_name_with;
+    use crate::encoding::name_codec::default_codec;
+    use crate::models::bulk_rename::CaseConversion;
+    use tempfile::TempDir;
+
+    fn template(find: Option<&str>, replace: &str) -> RenameTemplate {
+        RenameTemplate {
+            find: find.map(str::to_string),
+            replace: replace.to_string(),
+            case: CaseConversion::Keep,
+            include_extension: false,
+            counter_start: None,
+            counter_step: None,
+        }
+    }
+
+    fn no_probe(path: &str) -> Result<VideoInfo, String> {
+        Err(format!("Not probed: {}", path))
+    }
+
+    fn setup(names: &[&str]) -> (TempDir, Vec<String>) {
+        let tmp = TempDir::new().unwrap();
+        let paths = names
+            .iter()
+            .map(|name| {
+                let path = tmp.path().join(name);
+                fs::write(&path, "video").unwrap();
+                path.to_string_lossy().to_string()
+            })
+            .collect();
+        (tmp, paths)
+    }
+
+    #[test]
+    fn test_preview_flags_collisions() {
+        let (tmp, paths) = setup(&["a.mkv", "b.mkv", "c.mkv", "taken.mkv"]);
+        fs::write(tmp.path().join("x2.mkv"), "video").unwrap();
+        let numbered = template(None, "x{n}");
+        let mut constant = numbered.clone();
+        constant.counter_step = Some(0);
+
+        let preview = plan(&paths[..2], &constant, None, &no_probe)
+            .unwrap()
+            .preview;
+        assert_eq!(
+            preview.items[0].collision.as_ref().unwrap().kind,
+            BulkCollisionKind::Duplicate
+        );
+        assert_eq!(preview.items[1].collision.as_ref().unwrap().with, paths[0]);
+
+        let preview = plan(&paths[..2], &numbered, None, &no_probe)
+            .unwrap()
+            .preview;
+        assert!(preview.items[0].collision.is_none());
+        assert_eq!(preview.items[1].new_name.as_deref(), Some("x2.mkv"));
+        assert_eq!(
+            preview.items[1].collision.as_ref().unwrap().kind,
+            BulkCollisionKind::Existing
+        );
+
+        // "taken" keeps its name, so "c" can't have it
+        let preview = plan(
+            &paths[2..],
+            &template(Some("^c$"), "taken"),
+            None,
+            &no_probe,
+        )
+        .unwrap()
+        .preview;
+        assert_eq!(preview.problems(), 1);
+        assert!(preview.items[1].collision.is_none());
+        assert!(tmp.path().join("c.mkv").exists());
+    }
+
+    #[test]
+    fn test_bulk_rename_breaks_cycles() {
+        let (tmp, mut paths) = setup(&["1.mkv", "2.mkv", "3.mkv"]);
+        for (n, path) in paths.iter().enumerate() {
+            fs::write(path, n.to_string()).unwrap();
+        }
+        let journal = JournalState(std::sync::Mutex::new(
+            rusqlite::Connection::open_in_memory().unwrap(),
+        ));
+        crate::journal::init_schema(&journal.0.lock().unwrap()).unwrap();
+
+        // 3 -> 1, 2 -> 2 and 1 -> 3, with only 1 and 3 swapping
+        paths.reverse();
+        let tpl = template(None, "{n}");
+        let preview = plan(&paths, &tpl, None, &no_probe).unwrap().preview;
+        assert_eq!(preview.problems(), 0);
+
+        let report =
+            run_bulk_rename(&paths, &tpl, None, Some(&journal), &no_probe, &mut |_| {}).unwrap();
+        assert!(report.results.iter().all(|r| r.error.is_none()));
+        let read = |name: &str| fs::read_to_string(tmp.path().join(name)).unwrap();
+        assert_eq!(
+            (read("1.mkv"), read("2.mkv"), read("3.mkv")),
+            ("2".into(), "1".into(), "0".into())
+        );
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 3);
+
+        let conn = journal.0.lock().unwrap();
+        crate::journal::undo_last(&conn).unwrap();
+        assert_eq!(
+            (read("1.mkv"), read("2.mkv"), read("3.mkv")),
+            ("0".into(), "1".into(), "2".into())
+        );
+    }
+
+    #[test]
+    fn test_cycles_are_broken_after_the_entries_inside() {
+        let tmp = TempDir::new().unwrap();
+        for dir in ["a", "b"] {
+            fs::create_dir(tmp.path().join(dir)).unwrap();
+            fs::write(tmp.path().join(dir).join("x.mkv"), dir).unwrap();
+            fs::write(tmp.path().join(dir).join("y.mkv"), "").unwrap();
+        }
+        let path = |relative: &str| tmp.path().join(relative).to_string_lossy().to_string();
+        // a <-> b, and x <-> y inside a
+        let renames = [
+            ("a", "b"),
+            ("b", "a"),
+            ("a/x.mkv", "y.mkv"),
+            ("a/y.mkv", "x.mkv"),
+        ];
+        let items: Vec<PlannedBulkRename> = renames
+            .iter()
+            .map(|(old, new)| PlannedBulkRename {
+                path: path(old),
+                old_name: Path::new(old)
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string(),
+                new_name: Some(new.to_string()),
+                encode: false,
+                collision: None,
+                error: None,
+            })
+            .collect();
+        let steps = order_renames(&items, &[0, 1, 2, 3]);
+        let position = |item: usize| steps.iter().position(|s| s.item == item).unwrap();
+        assert_eq!(steps.len(), 6);
+        assert!(position(0) > position(2) && position(0) > position(3));
+        assert_eq!(steps.iter().filter(|s| s.aside.is_some()).count(), 2);
+    }
+
+    #[test]
+    fn test_bulk_rename_shifts_a_series() {
+        let (tmp, paths) = setup(&["ep1.mkv", "ep2.mkv", "ep3.mkv"]);
+        let mut tpl = template(None, "ep{n}");
+        tpl.counter_start = Some(2);
+
+        let report = run_bulk_rename(&paths, &tpl, None, None, &no_probe, &mut |_| {}).unwrap();
+        assert!(report.results.iter().all(|r| r.error.is_none()));
+        for n in 2..=4 {
+            assert!(tmp.path().join(format!("ep{}.mkv", n)).exists());
+        }
+        assert!(!tmp.path().join("ep1.mkv").exists());
+    }
+
+    #[test]
+    fn test_bulk_rename_reencodes_and_uses_metadata() {
+        let tmp = TempDir::new().unwrap();
+        let encoded = tmp
+            .path()
+            .join(encode_name_with(default_codec(), "pilot.mp4"));
+        fs::write(&encoded, "video").unwrap();
+        let paths = vec![encoded.to_string_lossy().to_string()];
+        let probe = |_: &str| {
+            Ok(VideoInfo {
+                duration_secs: 1500.0,
+                file_size_bytes: 5,
+                width: Some(1280),
+                height: Some(720),
+                display_aspect_ratio: None,
+                codec: Some("h264".to_string()),
+                bitrate: None,
+                framerate: None,
+            })
+        };
+
+        let tpl = template(None, "{name} {height}p {duration}");
+        let preview = plan(&paths, &tpl, None, &probe).unwrap().preview;
+        assert_eq!(preview.items[0].old_name, "pilot.mp4");
+        assert_eq!(
+            preview.items[0].new_name.as_deref(),
+            Some("pilot 720p 25m00s.mp4")
+        );
+        assert!(preview.items[0].encode);
+
+        run_bulk_rename(&paths, &tpl, None, None, &probe, &mut |_| {}).unwrap();
+        assert!(tmp
+            .path()
+            .join(encode_name_with(default_codec(), "pilot 720p 25m00s.mp4"))
+            .exists());
+    }
+
+    #[test]
+    fn test_bulk_rename_refuses_problems_and_is_undone_at_once() {
+        let (tmp, paths) = setup(&["a.mkv", "b.mkv"]);
+        let journal = JournalState(std::sync::Mutex::new(
+            rusqlite::Connection::open_in_memory().unwrap(),
+        ));
+        crate::journal::init_schema(&journal.0.lock().unwrap()).unwrap();
+
+        let same = template(None, "same");
+        assert!(
+            run_bulk_rename(&paths, &same, None, Some(&journal), &no_probe, &mut |_| {}).is_err()
+        );
+        assert!(tmp.path().join("a.mkv").exists());
+
+        let mut upper = template(None, "{name}");
+        upper.case = CaseConversion::Upper;
+        run_bulk_rename(&paths, &upper, None, Some(&journal), &no_probe, &mut |_| {}).unwrap();
+        assert!(tmp.path().join("B.mkv").exists());
+
+        let conn = journal.0.lock().unwrap();
+        crate::journal::undo_last(&conn).unwrap();
+        assert!(tmp.path().join("a.mkv").exists());
+        assert!(tmp.path().join("b.mkv").exists());
+    }
+}
@@ -0,0 +1,728 @@
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use super::fs_commands::build_file_entry;
+use crate::encoding::encoded_dir::{encode_name_with, name_state_in, NameState};
+use crate::encoding::name_codec::{codec_for_name, resolve_codec, NameCodec};
+use crate::encoding::name_crypto::{
+    is_encrypted_name, EncryptedNameCodec, SessionKey, ENCRYPTED_SCHEME,
+};
+use crate::encoding::name_index::{self, HASHED_SCHEME, INDEX_FILE_NAME};
+use crate::encoding::rename_plan::{fitting_codec, with_suffix};
+use crate::jobs::JobRegistry;
+use crate::models::copy_report::{
+    ConflictAnswer, ConflictPolicy, ConflictResolution, CopyConflict, CopyOptions, CopyReport,
+    NameMode,
+};
+use crate::models::rename_report::RenameRecord;
+use crate::models::transfer_progress::TransferProgress;
+use crate::session::SessionState;
+use crate::transfer::{self, Reporter, CANCELLED};
+
+pub const COPY_PROGRESS_EVENT: &str = "copy-progress";
+pub const COPY_CONFLICT_EVENT: &str = "copy-conflict";
+
+/// Running copies, by the id the frontend started them with.
+pub type CopyJobs = JobRegistry<ConflictAnswer>;
+
+/// Copies a file or directory tree into `target_dir`. The frontend picks
+/// `copy_id` so it can cancel the copy or answer its `copy-conflict` events
+/// while it runs. A cancelled or failed copy removes what it created.
+#[tauri::command]
+pub async fn copy_node(
+    copy_id: String,
+    source: String,
+    target_dir: String,
+    options: Option<CopyOptions>,
+    app: AppHandle,
+    session: State<'_, SessionState>,
+) -> Result<CopyReport, String> {
+    let key = session.key();
+    tauri::async_runtime::spawn_blocking(move || {
+        let jobs = app.state::<CopyJobs>();
+        let job = jobs.start(&copy_id)?;
+        let result = copy_path(
+            Path::new(&source),
+            Path::new(&target_dir),
+            options.unwrap_or_default(),
+            key.as_ref(),
+            &mut |progress| {
+                let _ = app.emit(COPY_PROGRESS_EVENT, progress);
+            },
+            &|| job.is_cancelled(),
+            &mut |conflict| {
+                let conflict = CopyConflict {
+                    copy_id: copy_id.clone(),
+                    ..conflict.clone()
+                };
+                app.emit(COPY_CONFLICT_EVENT, conflict).ok()?;
+                job.wait_for_reply()
+            },
+        );
+        jobs.finish(&copy_id);
+        result
+    })
+    .await
+    .map_err(|e| format!("Task join error: {}", e))?
+}
+
+#[tauri::command]
+pub fn resolve_copy_conflict(
+    copy_id: String,
+    resolution: ConflictResolution,
+    apply_to_all: Option<bool>,
+    jobs: State<'_, CopyJobs>,
+) -> Result<(), String> {
+    jobs.get(&copy_id)?.reply(ConflictAnswer {
+        resolution,
+        apply_to_all: apply_to_all.unwrap_or(false),
+    });
+    Ok(())
+}
+
+#[tauri::command]
+pub fn cancel_copy(copy_id: String, jobs: State<'_, CopyJobs>) -> Result<(), String> {
+    jobs.get(&copy_id)?.cancel();
+    Ok(())
+}
+
+/// `ask` is called for conflicts when the policy is to ask, and returns
+/// `None` if the copy was cancelled while waiting.
+fn copy_path(
+    source_path: &Path,
+    target_path: &Path,
+    options: CopyOptions,
+    key: Option<&SessionKey>,
+    on_progress: &mut dyn FnMut(&TransferProgress),
+    is_cancelled: &dyn Fn() -> bool,
+    ask: &mut dyn FnMut(&CopyConflict) -> Option<ConflictAnswer>,
+) -> Result<CopyReport, String> {
+    if fs::symlink_metadata(source_path).is_err() {
+        return Err(format!("Source does not exist: {}", source_path.display()));
+    }
+    if !target_path.is_dir() {
+        return Err(format!(
+            "Target is not a directory: {}",
+            target_path.display()
+        ));
+    }
+    if source_path.is_dir() && is_within(target_path, source_path) {
+        return Err("Cannot copy a directory into itself".to_string());
+    }
+
+    let encrypted = key.map(|key| EncryptedNameCodec::new(key.clone()));
+    let codec: &dyn NameCodec = match options.scheme.as_deref() {
+        Some(ENCRYPTED_SCHEME) => encrypted.as_ref().ok_or("Session is locked")?,
+        scheme => resolve_codec(scheme)?,
+    };
+
+    let (bytes_total, files_total) = measure(source_path);
+    let mut copier = Copier {
+        mode: options.name_mode,
+        policy: options.conflict_policy,
+        codec,
+        encrypted: encrypted.as_ref(),
+        key,
+        reporter: Reporter::new(
+            source_path,
+            bytes_total,
+            files_total,
+            on_progress,
+            is_cancelled,
+        ),
+        ask,
+        report: CopyReport::default(),
+        created: Vec::new(),
+        recorded: Vec::new(),
+        set_aside: Vec::new(),
+    };
+
+    match copier.copy_entry(source_path, target_path) {
+        Ok(copy) => {
+            copier.discard_set_aside();
+            let mut report = copier.report;
+            report.entry = copy.and_then(|path| build_file_entry(&path, key).ok());
+            Ok(report)
+        }
+        Err(e) => {
+            copier.roll_back();
+            Err(e)
+        }
+    }
+}
+
+struct Copier<'a> {
+    mode: NameMode,
+    policy: ConflictPolicy,
+    /// Scheme for names encoded during the copy
+    codec: &'a dyn NameCodec,
+    encrypted: Option<&'a EncryptedNameCodec>,
+    key: Option<&'a SessionKey>,
+    reporter: Reporter<'a>,
+    ask: &'a mut dyn FnMut(&CopyConflict) -> Option<ConflictAnswer>,
+    report: CopyReport,
+    /// Entries this copy created, in creation order
+    created: Vec<PathBuf>,
+    /// Names this copy added to a sidecar index: (directory, physical name)
+    recorded: Vec<(PathBuf, String)>,
+    /// Overwritten entries, moved out of the way: (temporary path, path)
+    set_aside: Vec<(PathBuf, PathBuf)>,
+}
+
+/// How a copy is named in its target directory.
+struct CopyName<'a> {
+    /// The name the user sees, which suffixes are added to
+    display: String,
+    /// Physical name of the copy before any suffix
+    physical: String,
+    /// Re-encodes a suffixed name; None if the physical name is the display
+    /// name, or if it is locked and cannot be re-encoded
+    codec: Option<&'a dyn NameCodec>,
+    locked: bool,
+}
+
+impl CopyName<'_> {
+    /// Display and physical name with the suffix `n`.
+    fn suffixed(&self, n: u32, is_dir: bool) -> Option<(String, String)> {
+        let display = with_suffix(&self.display, n, is_dir);
+        match self.codec {
+            Some(codec) => Some((display.clone(), encode_name_with(codec, &display))),
+            None if self.locked => None,
+            None => Some((display.clone(), display)),
+        }
+    }
+}
+
+impl<'a> Copier<'a> {
+    /// Copies `source` into `target_dir` and returns the path of the copy,
+    /// or None if it was skipped.
+    fn copy_entry(&mut self, source: &Path, target_dir: &Path) -> Result<Option<PathBuf>, String> {
+        let metadata = fs::symlink_metadata(source)
+            .map_err(|e| format!("Failed to read metadata of {}: {}", source.display(), e))?;
+        let name = self.copy_name(source, target_dir)?;
+        let mut display = name.display.clone();
+        let mut physical = name.physical.clone();
+        let mut target = target_dir.join(&physical);
+
+        if let Ok(existing) = fs::symlink_metadata(&target) {
+            match self.resolve_conflict(source, &target, &name, &metadata, &existing)? {
+                ConflictResolution::Skip => {
+                    self.report
+                        .skipped
+                        .push(source.to_string_lossy().to_string());
+                    return Ok(None);
+                }
+                ConflictResolution::Overwrite if existing.is_dir() && metadata.is_dir() => {
+                    self.copy_children(source, &target)?;
+                    return Ok(Some(target));
+                }
+                ConflictResolution::Overwrite => {
+                    self.overwrite(source, &target, &metadata)?;
+                    self.report
+                        .overwritten
+                        .push(target.to_string_lossy().to_string());
+                    self.record_name(target_dir, &physical, &display, &name)?;
+                    return Ok(Some(target));
+                }
+                ConflictResolution::KeepBoth => {
+                    let Some((free_display, free_physical)) = (2..)
+                        .map(|n| name.suffixed(n, metadata.is_dir()))
+                        .take_while(Option::is_some)
+                        .flatten()
+                        .find(|(_, candidate)| {
+                            fs::symlink_metadata(target_dir.join(candidate)).is_err()
+                        })
+                    else {
+                        // A locked name cannot be given a suffix
+                        self.report
+                            .skipped
+                            .push(source.to_string_lossy().to_string());
+                        return Ok(None);
+                    };
+                    let taken = target;
+                    display = free_display;
+                    physical = free_physical;
+                    target = target_dir.join(&physical);
+                    self.report.renamed.push(RenameRecord {
+                        from: taken.to_string_lossy().to_string(),
+                        to: target.to_string_lossy().to_string(),
+                    });
+                }
+            }
+        }
+
+        self.copy_new(source, &target, &metadata)?;
+        self.record_name(target_dir, &physical, &display, &name)?;
+        Ok(Some(target))
+    }
+
+    fn copy_name(&self, source: &Path, target_dir: &Path) -> Result<CopyName<'a>, String> {
+        let physical = source
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| format!("Invalid file name: {}", source.display()))?;
+        let dir = source.parent().unwrap_or(source);
+        let state = name_state_in(dir, physical, self.key);
+
+        let keep = |display: String, codec: Option<&'a dyn NameCodec>| CopyName {
+            display,
+            physical: physical.to_string(),
+            codec,
+            locked: false,
+        };
+        Ok(match (self.mode, state) {
+            (_, NameState::Locked) => CopyName {
+                locked: true,
+                ..keep(physical.to_string(), None)
+            },
+            // Names that decode to a path are left encoded
+            (NameMode::Decode, NameState::Decoded(display)) if is_single_component(&display) => {
+                CopyName {
+                    physical: display.clone(),
+                    ..keep(display, None)
+                }
+            }
+            (_, NameState::Decoded(display)) => keep(display, self.codec_of(physical)),
+            (NameMode::Encode, NameState::Plain) => {
+                let codec = fitting_codec(self.codec, &target_dir.join(physical));
+                CopyName {
+                    physical: encode_name_with(codec, physical),
+                    ..keep(physical.to_string(), Some(codec))
+                }
+            }
+            (_, NameState::Plain) => keep(physical.to_string(), None),
+        })
+    }
+
+    /// The scheme an encoded name was written with.
+    fn codec_of(&self, physical: &str) -> Option<&'a dyn NameCodec> {
+        if is_encrypted_name(physical) {
+            return self.encrypted.map(|codec| codec as &dyn NameCodec);
+        }
+        codec_for_name(physical)
+    }
+
+    fn resolve_conflict(
+        &mut self,
+        source: &Path,
+        target: &Path,
+        name: &CopyName,
+        metadata: &Metadata,
+        existing: &Metadata,
+    ) -> Result<ConflictResolution, String> {
+        let resolution = match self.policy {
+            ConflictPolicy::Skip => ConflictResolution::Skip,
+            ConflictPolicy::Overwrite => ConflictResolution::Overwrite,
+            ConflictPolicy::KeepBoth => ConflictResolution::KeepBoth,
+            ConflictPolicy::Ask => {
+                let conflict = CopyConflict {
+                    copy_id: String::new(),
+                    source: source.to_string_lossy().to_string(),
+                    target: target.to_string_lossy().to_string(),
+                    name: name.display.clone(),
+                    is_directory: metadata.is_dir(),
+                    target_is_directory: existing.is_dir(),
+                };
+                let answer = (self.ask)(&conflict).ok_or(CANCELLED)?;
+                if answer.apply_to_all {
+                    self.policy = match answer.resolution {
+                        ConflictResolution::Skip => ConflictPolicy::Skip,
+                        ConflictResolution::Overwrite => ConflictPolicy::Overwrite,
+                        ConflictResolution::KeepBoth => ConflictPolicy::KeepBoth,
+                    };
+                }
+                answer.resolution
+            }
+        };
+        Ok(resolution)
+    }
+
+    /// Copies to a name that is free.
+    fn copy_new(
+        &mut self,
+        source: &Path,
+        target: &Path,
+        metadata: &Metadata,
+    ) -> Result<(), String> {
+        if metadata.is_dir() {
+            fs::create_dir(target)
+                .map_err(|e| format!("Failed to create directory {}: {}", target.display(), e))?;
+            self.created.push(target.to_path_buf());
+            self.copy_children(source, target)?;
+            transfer::finish_dir(target, metadata)
+        } else if metadata.file_type().is_symlink() {
+            transfer::copy_symlink(source, target)?;
+            self.created.push(target.to_path_buf());
+            Ok(())
+        } else {
+            self.copy_file(source, target, metadata)?;
+            self.created.push(target.to_path_buf());
+            Ok(())
+        }
+    }
+
+    /// The old entry is moved to a temporary name next to it while the copy
+    /// is made. Rolling back puts it back; it is only deleted once the whole
+    /// copy succeeded.
+    fn overwrite(
+        &mut self,
+        source: &Path,
+        target: &Path,
+        metadata: &Metadata,
+    ) -> Result<(), String> {
+        let file_name = target.file_name().unwrap_or_default().to_string_lossy();
+        let aside = (1..)
+            .map(|n| target.with_file_name(format!(".{}.copy-old{}", file_name, n)))
+            .find(|candidate| fs::symlink_metadata(candidate).is_err())
+            .expect("some temporary name is free");
+        fs::rename(target, &aside)
+            .map_err(|e| format!("Failed to replace {}: {}", target.display(), e))?;
+        self.set_aside.push((aside, target.to_path_buf()));
+        self.copy_new(source, target, metadata)
+    }
+
+    fn copy_file(
+        &mut self,
+        source: &Path,
+        target: &Path,
+        metadata: &Metadata,
+    ) -> Result<(), String> {
+        self.reporter.progress.current_path = Some(source.to_string_lossy().to_string());
+        transfer::copy_file(source, target, metadata, false, &mut self.reporter)?;
+        self.report.files_copied += 1;
+        self.reporter.progress.files_done += 1;
+        self.reporter.report(true);
+        Ok(())
+    }
+
+    /// Sidecar indexes are not copied: every hashed name is recorded in the
+    /// index of the directory it ends up in.
+    fn copy_children(&mut self, source: &Path, target: &Path) -> Result<(), String> {
+        let entries = fs::read_dir(source)
+            .map_err(|e| format!("Failed to read directory {}: {}", source.display(), e))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
+            if entry.file_name() != INDEX_FILE_NAME {
+                self.copy_entry(&entry.path(), target)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn record_name(
+        &mut self,
+        dir: &Path,
+        physical: &str,
+        display: &str,
+        name: &CopyName,
+    ) -> Result<(), String> {
+        if name.codec.is_none_or(|codec| codec.id() != HASHED_SCHEME) {
+            return Ok(());
+        }
+        name_index::record_name(dir, physical, display)?;
+        self.recorded
+            .push((dir.to_path_buf(), physical.to_string()));
+        Ok(())
+    }
+
+    fn roll_back(&mut self) {
+        for path in self.created.iter().rev() {
+            if fs::symlink_metadata(path).is_ok() {
+                let _ = transfer::remove_tree(path);
+            }
+        }
+        for (dir, physical) in &self.recorded {
+            let _ = name_index::forget_name(dir, physical);
+        }
+        for (aside, path) in self.set_aside.iter().rev() {
+            if fs::symlink_metadata(path).is_ok() {
+                let _ = transfer::remove_tree(path);
+            }
+            let _ = fs::rename(aside, path);
+        }
+    }
+
+    fn discard_set_aside(&mut self) {
+        for (aside, _) in self.set_aside.drain(..) {
+            let _ = transfer::remove_tree(&aside);
+        }
+    }
+}
+
+/// Total size and number of the files below `path`, for progress reporting.
+fn measure(path: &Path) -> (u64, usize) {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return (0, 0);
+    };
+    if metadata.is_file() {
+        return (metadata.len(), 1);
+    }
+    if !metadata.is_dir() {
+        return (0, 0);
+    }
+    fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.file_name() != INDEX_FILE_NAME)
+        .map(|entry| measure(&entry.path()))
+        .fold((0, 0), |(bytes, files), (b, f)| (bytes + b, files + f))
+}
+
+fn is_single_component(name: &str) -> bool {
+    Path::new(name).file_name().is_some_and(|n| n == name)
+}
+
+fn is_within(path: &Path, dir: &Path) -> bool {
+    match (fs::canonicalize(path), fs::canonicalize(dir)) {
+        (Ok(path), Ok(dir)) => path.starts_with(dir),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::encoded_dir::encode_name_with;
+    use crate::encoding::name_codec::default_codec;
+    use crate::encoding::name_index::{lookup_name, HashedNameCodec};
+    use tempfile::TempDir;
+
+    fn copy(source: &Path, target_dir: &Path, options: CopyOptions) -> Result<CopyReport, String> {
+        copy_path(
+            source,
+            target_dir,
+            options,
+            None,
+            &mut |_| {},
+            &|| false,
+            &mut |_| None,
+        )
+    }
+
+    fn with_policy(conflict_policy: ConflictPolicy) -> CopyOptions {
+        CopyOptions {
+            conflict_policy,
+            ..CopyOptions::default()
+        }
+    }
+
+    fn setup() -> (TempDir, PathBuf, PathBuf) {
+        let tmp = TempDir::new().unwrap();
+        let source = tmp.path().join("Season 1");
+        fs::create_dir(&source).unwrap();
+        fs::write(source.join("episode.mkv"), "video").unwrap();
+        let target = tmp.path().join("backup");
+        fs::create_dir(&target).unwrap();
+        (tmp, source, target)
+    }
+
+    #[test]
+    fn test_copy_directory_recursive() {
+        let (_tmp, source, target) = setup();
+        fs::create_dir(source.join("extras")).unwrap();
+        fs::write(source.join("extras").join("trailer.mkv"), "trailer").unwrap();
+
+        let report = copy(&source, &target, CopyOptions::default()).unwrap();
+
+        assert_eq!(report.files_copied, 2);
+        assert_eq!(report.entry.unwrap().name, "Season 1");
+        assert!(source.join("episode.mkv").exists());
+        let copied = target.join("Season 1").join("extras").join("trailer.mkv");
+        assert_eq!(fs::read_to_string(copied).unwrap(), "trailer");
+    }
+
+    #[test]
+    fn test_copy_encodes_names() {
+        let (_tmp, source, target) = setup();
+        let options = CopyOptions {
+            name_mode: NameMode::Encode,
+            ..CopyOptions::default()
+        };
+
+        let entry = copy(&source, &target, options).unwrap().entry.unwrap();
+
+        assert!(entry.is_encoded);
+        assert_eq!(entry.name, "Season 1");
+        let copied_dir = target.join(encode_name_with(default_codec(), "Season 1"));
+        assert!(copied_dir
+            .join(encode_name_with(default_codec(), "episode.mkv"))
+            .exists());
+    }
+
+    #[test]
+    fn test_copy_decodes_names() {
+        let tmp = TempDir::new().unwrap();
+        let source = tmp
+            .path()
+            .join(encode_name_with(default_codec(), "Movie.mkv"));
+        fs::write(&source, "video").unwrap();
+        let target = tmp.path().join("plain");
+        fs::create_dir(&target).unwrap();
+        let options = CopyOptions {
+            name_mode: NameMode::Decode,
+            ..CopyOptions::default()
+        };
+
+        copy(&source, &target, options).unwrap();
+        assert!(target.join("Movie.mkv").exists());
+    }
+
+    #[test]
+    fn test_copy_keeps_hashed_names_decodable() {
+        let tmp = TempDir::new().unwrap();
+        let long_name = "x".repeat(300);
+        let physical = encode_name_with(&HashedNameCodec, &long_name);
+        let source = tmp.path().join(&physical);
+        fs::write(&source, "video").unwrap();
+        name_index::record_name(tmp.path(), &physical, &long_name).unwrap();
+        let target = tmp.path().join("backup");
+        fs::create_dir(&target).unwrap();
+
+        copy(&source, &target, CopyOptions::default()).unwrap();
+        assert_eq!(lookup_name(&target, &physical), Some(long_name));
+    }
+
+    #[test]
+    fn test_conflict_skip_and_overwrite() {
+        let (_tmp, source, target) = setup();
+        let file = source.join("episode.mkv");
+        fs::write(target.join("episode.mkv"), "old").unwrap();
+
+        let report = copy(&file, &target, with_policy(ConflictPolicy::Skip)).unwrap();
+        assert!(report.entry.is_none());
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(
+            fs::read_to_string(target.join("episode.mkv")).unwrap(),
+            "old"
+        );
+
+        let report = copy(&file, &target, with_policy(ConflictPolicy::Overwrite)).unwrap();
+        assert_eq!(report.overwritten.len(), 1);
+        assert_eq!(
+            fs::read_to_string(target.join("episode.mkv")).unwrap(),
+            "video"
+        );
+    }
+
+    #[test]
+    fn test_conflict_keep_both() {
+        let (_tmp, source, target) = setup();
+        copy(&source, &target, CopyOptions::default()).unwrap();
+
+        let report = copy(&source, &target, with_policy(ConflictPolicy::KeepBoth)).unwrap();
+        assert_eq!(report.entry.unwrap().name, "Season 1 (2)");
+        assert_eq!(report.renamed.len(), 1);
+        assert!(target.join("Season 1 (2)").join("episode.mkv").exists());
+    }
+
+    #[test]
+    fn test_conflict_ask_merges_directories() {
+        let (_tmp, source, target) = setup();
+        fs::create_dir(target.join("Season 1")).unwrap();
+        fs::write(target.join("Season 1").join("other.mkv"), "other").unwrap();
+
+        let mut asked = Vec::new();
+        let report = copy_path(
+            &source,
+            &target,
+            CopyOptions::default(),
+            None,
+            &mut |_| {},
+            &|| false,
+            &mut |conflict| {
+                asked.push(conflict.name.clone());
+                Some(ConflictAnswer {
+                    resolution: ConflictResolution::Overwrite,
+                    apply_to_all: false,
+                })
+            },
+        )
+        .unwrap();
+
+        assert_eq!(asked, vec!["Season 1"]);
+        assert_eq!(report.files_copied, 1);
+        assert!(target.join("Season 1").join("other.mkv").exists());
+        assert!(target.join("Season 1").join("episode.mkv").exists());
+    }
+
+    #[test]
+    fn test_cancel_removes_partial_copy() {
+        let (_tmp, source, target) = setup();
+
+        let result = copy_path(
+            &source,
+            &target,
+            CopyOptions::default(),
+            None,
+            &mut |_| {},
+            &|| true,
+            &mut |_| None,
+        );
+
+        assert_eq!(result.unwrap_err(), CANCELLED);
+        assert!(!target.join("Season 1").exists());
+    }
+
+    #[test]
+    fn test_failed_copy_restores_overwritten_entries() {
+        let (_tmp, source, target) = setup();
+        fs::write(source.join("extras"), "new extras").unwrap();
+        let existing = target.join("Season 1");
+        for name in ["episode.mkv", "extras"] {
+            fs::create_dir_all(existing.join(name)).unwrap();
+            fs::write(existing.join(name).join("keep.txt"), name).unwrap();
+        }
+
+        // Merges the directory, replaces the first directory inside it with a
+        // file, then cancels at the second one
+        let mut answers = 0;
+        let result = copy_path(
+            &source,
+            &target,
+            with_policy(ConflictPolicy::Ask),
+            None,
+            &mut |_| {},
+            &|| false,
+            &mut |_| {
+                answers += 1;
+                (answers < 3).then_some(ConflictAnswer {
+                    resolution: ConflictResolution::Overwrite,
+                    apply_to_all: false,
+                })
+            },
+        );
+
+        assert_eq!(result.unwrap_err(), CANCELLED);
+        for name in ["episode.mkv", "extras"] {
+            assert_eq!(
+                fs::read_to_string(existing.join(name).join("keep.txt")).unwrap(),
+                name
+            );
+        }
+        assert_eq!(fs::read_dir(&existing).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_overwrite_directory_with_file() {
+        let (_tmp, source, target) = setup();
+        let file = source.join("episode.mkv");
+        fs::create_dir_all(target.join("episode.mkv")).unwrap();
+
+        let report = copy(&file, &target, with_policy(ConflictPolicy::Overwrite)).unwrap();
+        assert_eq!(report.overwritten.len(), 1);
+        assert_eq!(
+            fs::read_to_string(target.join("episode.mkv")).unwrap(),
+            "video"
+        );
+        assert_eq!(fs::read_dir(&target).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_copy_into_itself_fails() {
+        let (_tmp, source, _target) = setup();
+        assert!(copy(&source, &source, CopyOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,601 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State};
+
+use crate::cache::CacheState;
+use crate::commands::fs_commands::{build_file_entry, list_directory};
+use crate::commands::video_commands::video_duration;
+use crate::encoding::name_crypto::SessionKey;
+use crate::exclusions::{ExclusionState, Exclusions};
+use crate::models::directory_page::{DirectoryPage, PageRequest, SortKey, SortOrder};
+use crate::models::exclusion_rules::ExclusionRules;
+use crate::models::file_entry::{FileEntry, FileKind};
+use crate::natural_sort::{natural_key, NaturalKey};
+use crate::session::SessionState;
+
+/// Most sorted listings kept at once; the least recently used goes first.
+const MAX_CACHED_LISTINGS: usize = 16;
+
+/// Sorted listings of recently paged directories, so that paging through a
+/// directory lists, stats and probes it once instead of on every page.
+#[derive(Default)]
+pub struct PageCache {
+    listings: Mutex<HashMap<ListingKey, Listing>>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct ListingKey {
+    dir: PathBuf,
+    sort_key: SortKey,
+    order: SortOrder,
+    directories_first: bool,
+}
+
+/// A sorted listing, valid while the directory and what names are shown as
+/// don't change. Entries are stat'ed again when they are served, since
+/// files can change without changing the directory.
+struct Listing {
+    dir_modified: SystemTime,
+    key_verifier: Option<[u8; 32]>,
+    rules: ExclusionRules,
+    sorted: Arc<Vec<(Position, FileEntry)>>,
+    used: Instant,
+}
+
+impl PageCache {
+    /// The sorted listing of `dir`, from the cache unless the directory was
+    /// modified since it was sorted. Sizes and modified times change without
+    /// the directory changing, e.g. while a download grows, so orders by
+    /// those are sorted afresh every time.
+    fn sorted(
+        &self,
+        dir: &Path,
+        request: &PageRequest,
+        key: Option<&SessionKey>,
+        exclusions: &Exclusions,
+        duration_of: &dyn Fn(&FileEntry) -> Option<f64>,
+    ) -> Result<Arc<Vec<(Position, FileEntry)>>, String> {
+        let dir_modified = fs::metadata(dir)
+            .and_then(|m| m.modified())
+            .map_err(|e| format!("Failed to read metadata of {}: {}", dir.display(), e))?;
+        let listing_key = ListingKey {
+            dir: dir.to_path_buf(),
+            sort_key: request.sort_key,
+            order: request.order,
+            directories_first: request.directories_first,
+        };
+        let key_verifier = key.map(SessionKey::verifier);
+        if matches!(request.sort_key, SortKey::Size | SortKey::Modified) {
+            return sort_listing(dir, request, key, exclusions, duration_of).map(Arc::new);
+        }
+
+        if let Some(listing) = self.lock().get_mut(&listing_key) {
+            if listing.dir_modified == dir_modified
+                && listing.key_verifier == key_verifier
+                && &listing.rules == exclusions.rules()
+            {
+                listing.used = Instant::now();
+                return Ok(listing.sorted.clone());
+            }
+        }
+
+        let sorted = Arc::new(sort_listing(dir, request, key, exclusions, duration_of)?);
+
+        let mut listings = self.lock();
+        if listings.len() >= MAX_CACHED_LISTINGS && !listings.contains_key(&listing_key) {
+            let oldest = listings
+                .iter()
+                .min_by_key(|(_, listing)| listing.used)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                listings.remove(&oldest);
+            }
+        }
+        listings.insert(
+            listing_key,
+            Listing {
+                dir_modified,
+                key_verifier,
+                rules: exclusions.rules().clone(),
+                sorted: sorted.clone(),
+                used: Instant::now(),
+            },
+        );
+        Ok(sorted)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<ListingKey, Listing>> {
+        match self.listings.lock() {
+            Ok(listings) => listings,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+fn sort_listing(
+    dir: &Path,
+    request: &PageRequest,
+    key: Option<&SessionKey>,
+    exclusions: &Exclusions,
+    duration_of: &dyn Fn(&FileEntry) -> Option<f64>,
+) -> Result<Vec<(Position, FileEntry)>, String> {
+    let mut sorted: Vec<(Position, FileEntry)> = list_directory(dir, key, exclusions)?
+        .into_iter()
+        .map(|entry| (Position::of(&entry, request.sort_key, duration_of), entry))
+        .collect();
+    sorted.sort_by(|(a, _), (b, _)| compare(a, b, request));
+    Ok(sorted)
+}
+
+/// One page of a directory in the requested order. Sorting by duration
+/// probes the videos that aren't in the cache yet, which can take a while
+/// the first time.
+#[tauri::command]
+pub async fn read_directory_page(
+    path: String,
+    request: PageRequest,
+    app: AppHandle,
+    session: State<'_, SessionState>,
+    exclusions: State<'_, ExclusionState>,
+) -> Result<DirectoryPage, String> {
+    let key = session.key();
+    let exclusions = exclusions.get();
+    tauri::async_runtime::spawn_blocking(move || {
+        let cache_state = app.state::<CacheState>();
+        let page_cache = app.state::<PageCache>();
+        let duration_of =
+            |entry: &FileEntry| video_duration(&entry.path, key.as_ref(), &cache_state);
+        read_page(
+            Path::new(&path),
+            &request,
+            key.as_ref(),
+            &exclusions,
+            &page_cache,
+            &duration_of,
+        )
+    })
+    .await
+    .map_err(|e| format!("Task join error: {}", e))?
+}
+
+fn read_page(
+    dir: &Path,
+    request: &PageRequest,
+    key: Option<&SessionKey>,
+    exclusions: &Exclusions,
+    cache: &PageCache,
+    duration_of: &dyn Fn(&FileEntry) -> Option<f64>,
+) -> Result<DirectoryPage, String> {
+    let cursor = request
+        .cursor
+        .as_deref()
+        .map(|cursor| decode_cursor(cursor, request))
+        .transpose()?;
+    let sorted = cache.sorted(dir, request, key, exclusions, duration_of)?;
+
+    let total = sorted.len();
+    let offset = match &cursor {
+        Some(cursor) => sorted.partition_point(|(position, _)| {
+            compare(position, cursor, request) != Ordering::Greater
+        }),
+        None => request.offset.min(total),
+    };
+    let end = offset.saturating_add(request.limit).min(total);
+    let next_cursor = match end {
+        end if end < total && end > offset => Some(encode_cursor(&sorted[end - 1].0, request)?),
+        _ => None,
+    };
+
+    Ok(DirectoryPage {
+        entries: sorted[offset..end]
+            .iter()
+            .map(|(_, entry)| {
+                build_file_entry(Path::new(&entry.path), key).unwrap_or_else(|_| entry.clone())
+            })
+            .collect(),
+        offset,
+        total,
+        next_cursor,
+    })
+}
+
+/// The sort values of an entry. Cursors hold the position of the last entry
+/// of a page, so the next page starts after it even if that entry is gone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Position {
+    is_directory: bool,
+    name: String,
+    size: u64,
+    modified: Option<i64>,
+    duration: Option<f64>,
+    kind: FileKind,
+    path: String,
+    #[serde(skip)]
+    name_key: NaturalKey,
+}
+
+impl Position {
+    fn of(
+        entry: &FileEntry,
+        sort_key: SortKey,
+        duration_of: &dyn Fn(&FileEntry) -> Option<f64>,
+    ) -> Position {
+        let probe =
+            sort_key == SortKey::Duration && !entry.is_directory && entry.kind == FileKind::Video;
+        Position {
+            is_directory: entry.is_directory,
+            name: entry.name.clone(),
+            size: entry.file_size,
+            modified: entry.modified,
+            duration: if probe { duration_of(entry) } else { None },
+            kind: entry.kind,
+            path: entry.path.clone(),
+            name_key: natural_key(&entry.name),
+        }
+    }
+}
+
+/// Orders by the sort key, then by name, then by path so that no two entries
+/// are equal. Entries without a modified time or duration come last in
+/// either order.
+fn compare(a: &Position, b: &Position, request: &PageRequest) -> Ordering {
+    let directed = |order: Ordering| match request.order {
+        SortOrder::Ascending => order,
+        SortOrder::Descending => order.reverse(),
+    };
+    let directories = match request.directories_first {
+        true => b.is_directory.cmp(&a.is_directory),
+        false => Ordering::Equal,
+    };
+    let by_key = match request.sort_key {
+        SortKey::Name => Ordering::Equal,
+        SortKey::Size => directed(a.size.cmp(&b.size)),
+        SortKey::Modified => missing_last(a.modified, b.modified, |x, y| directed(x.cmp(&y))),
+        SortKey::Duration => missing_last(a.duration, b.duration, |x, y| directed(x.total_cmp(&y))),
+        SortKey::Kind => directed(a.kind.cmp(&b.kind)),
+    };
+    directories
+        .then(by_key)
+        .then_with(|| directed(a.name_key.cmp(&b.name_key)))
+        .then_with(|| a.path.cmp(&b.path))
+}
+
+fn missing_last<T>(a: Option<T>, b: Option<T>, cmp: impl Fn(T, T) -> Ordering) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => cmp(a, b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// A cursor, with the order of the pages it was made for.
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    sort_key: SortKey,
+    order: SortOrder,
+    directories_first: bool,
+    last: Position,
+}
+
+fn encode_cursor(position: &Position, request: &PageRequest) -> Result<String, String> {
+    let cursor = Cursor {
+        sort_key: request.sort_key,
+        order: request.order,
+        directories_first: request.directories_first,
+        last: position.clone(),
+    };
+    let json =
+        serde_json::to_vec(&cursor).map_err(|e| format!("Failed to encode cursor: {}", e))?;
+    Ok(URL_SAFE_NO_PAD.encode(json))
+}
+
+/// The position in a cursor, which has to be for the order of `request`.
+fn decode_cursor(cursor: &str, request: &PageRequest) -> Result<Position, String> {
+    let json = URL_SAFE_NO_PAD
+        .decode(cursor)
+        .map_err(|e| format!("Invalid cursor: {}", e))?;
+    let cursor: Cursor =
+        serde_json::from_slice(&json).map_err(|e| format!("Invalid cursor: {}", e))?;
+    if cursor.sort_key != request.sort_key
+        || cursor.order != request.order
+        || cursor.directories_first != request.directories_first
+    {
+        return Err("The cursor is for a different sort order".to_string());
+    }
+    let mut last = cursor.last;
+    last.name_key = natural_key(&last.name);
+    Ok(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::encoded_dir::encode_name_with;
+    use crate::encoding::name_codec::default_codec;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn no_duration(_: &FileEntry) -> Option<f64> {
+        None
+    }
+
+    fn names(page: &DirectoryPage) -> Vec<&str> {
+        page.entries.iter().map(|e| e.name.as_str()).collect()
+    }
+
+    fn page(dir: &TempDir, request: &PageRequest) -> DirectoryPage {
+        read_page(
+            dir.path(),
+            request,
+            None,
+            &Exclusions::default(),
+            &PageCache::default(),
+            &no_duration,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_pages_follow_the_cursor() {
+        let tmp = TempDir::new().unwrap();
+        for name in [
+            "Episode 1.mkv",
+            "Episode 2.mkv",
+            "Episode 10.mkv",
+            "Episode 11.mkv",
+        ] {
+            fs::write(tmp.path().join(encode_name_with(default_codec(), name)), "").unwrap();
+        }
+        fs::create_dir(tmp.path().join("Extras")).unwrap();
+
+        let mut request = PageRequest {
+            limit: 2,
+            ..PageRequest::default()
+        };
+        let first = page(&tmp, &request);
+        assert_eq!(names(&first), ["Extras", "Episode 1.mkv"]);
+        assert_eq!(first.total, 5);
+
+        // Entries added or removed before the cursor don't shift the next page
+        fs::write(tmp.path().join("Episode 0.mkv"), "").unwrap();
+        fs::remove_file(
+            tmp.path()
+                .join(encode_name_with(default_codec(), "Episode 1.mkv")),
+        )
+        .unwrap();
+        request.cursor = first.next_cursor;
+        let second = page(&tmp, &request);
+        assert_eq!(names(&second), ["Episode 2.mkv", "Episode 10.mkv"]);
+        assert_eq!(second.offset, 2);
+
+        request.cursor = second.next_cursor;
+        let last = page(&tmp, &request);
+        assert_eq!(names(&last), ["Episode 11.mkv"]);
+        assert_eq!(last.next_cursor, None);
+
+        let by_offset = page(
+            &tmp,
+            &PageRequest {
+                offset: 3,
+                limit: 10,
+                ..PageRequest::default()
+            },
+        );
+        assert_eq!(names(&by_offset), ["Episode 10.mkv", "Episode 11.mkv"]);
+        assert!(page_result(&tmp, "not a cursor").is_err());
+
+        let by_size = PageRequest {
+            sort_key: SortKey::Size,
+            cursor: first_page_cursor(&tmp),
+            ..PageRequest::default()
+        };
+        assert_eq!(
+            read_page(
+                tmp.path(),
+                &by_size,
+                None,
+                &Exclusions::default(),
+                &PageCache::default(),
+                &no_duration
+            )
+            .unwrap_err(),
+            "The cursor is for a different sort order"
+        );
+    }
+
+    fn first_page_cursor(dir: &TempDir) -> Option<String> {
+        page(
+            dir,
+            &PageRequest {
+                limit: 1,
+                ..PageRequest::default()
+            },
+        )
+        .next_cursor
+    }
+
+    #[test]
+    fn test_sorted_listing_is_cached_until_the_directory_changes() {
+        let tmp = TempDir::new().unwrap();
+        for name in ["a.mkv", "b.mkv"] {
+            fs::write(tmp.path().join(name), "").unwrap();
+        }
+        let probes = std::cell::Cell::new(0);
+        let duration_of = |_: &FileEntry| {
+            probes.set(probes.get() + 1);
+            Some(1.0)
+        };
+        let cache = PageCache::default();
+        let request = PageRequest {
+            sort_key: SortKey::Duration,
+            limit: 1,
+            ..PageRequest::default()
+        };
+        let read = |request: &PageRequest| {
+            read_page(
+                tmp.path(),
+                request,
+                None,
+                &Exclusions::default(),
+                &cache,
+                &duration_of,
+            )
+            .unwrap()
+        };
+
+        let first = read(&request);
+        let second = read(&PageRequest {
+            cursor: first.next_cursor,
+            ..request.clone()
+        });
+        assert_eq!(names(&second), ["b.mkv"]);
+        assert_eq!(probes.get(), 2);
+
+        fs::write(tmp.path().join("c.mkv"), "").unwrap();
+        let later = SystemTime::now() + std::time::Duration::from_secs(10);
+        fs::File::open(tmp.path())
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert_eq!(read(&request).total, 3);
+        assert_eq!(probes.get(), 5);
+    }
+
+    #[test]
+    fn test_files_changed_in_place_are_not_served_stale() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join("a.mkv"), "1").unwrap();
+        fs::write(tmp.path().join("b.mkv"), "12").unwrap();
+        let cache = PageCache::default();
+        let read = |request: &PageRequest| {
+            read_page(
+                tmp.path(),
+                request,
+                None,
+                &Exclusions::default(),
+                &cache,
+                &no_duration,
+            )
+            .unwrap()
+        };
+        let by_size = PageRequest {
+            sort_key: SortKey::Size,
+            ..PageRequest::default()
+        };
+        assert_eq!(names(&read(&by_size)), ["a.mkv", "b.mkv"]);
+        read(&PageRequest::default());
+
+        // Writing to a file leaves the time of the directory alone
+        let modified = fs::metadata(tmp.path()).unwrap().modified().unwrap();
+        fs::write(tmp.path().join("a.mkv"), "12345").unwrap();
+        assert_eq!(
+            fs::metadata(tmp.path()).unwrap().modified().unwrap(),
+            modified
+        );
+
+        assert_eq!(names(&read(&by_size)), ["b.mkv", "a.mkv"]);
+        assert_eq!(read(&PageRequest::default()).entries[0].file_size, 5);
+    }
+
+    fn page_result(dir: &TempDir, cursor: &str) -> Result<DirectoryPage, String> {
+        let request = PageRequest {
+            cursor: Some(cursor.to_string()),
+            ..PageRequest::default()
+        };
+        read_page(
+            dir.path(),
+            &request,
+            None,
+            &Exclusions::default(),
+            &PageCache::default(),
+            &no_duration,
+        )
+    }
+
+    #[test]
+    fn test_sort_keys_and_order() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join("small.mkv"), "1").unwrap();
+        fs::write(tmp.path().join("large.srt"), "12345").unwrap();
+        fs::write(tmp.path().join("medium.jpg"), "123").unwrap();
+        fs::create_dir(tmp.path().join("dir")).unwrap();
+
+        let by_size = PageRequest {
+            sort_key: SortKey::Size,
+            order: SortOrder::Descending,
+            ..PageRequest::default()
+        };
+        assert_eq!(
+            names(&page(&tmp, &by_size)),
+            ["dir", "large.srt", "medium.jpg", "small.mkv"]
+        );
+
+        let mixed = PageRequest {
+            directories_first: false,
+            ..by_size
+        };
+        assert_eq!(
+            names(&page(&tmp, &mixed)),
+            ["large.srt", "medium.jpg", "small.mkv", "dir"]
+        );
+
+        let by_kind = PageRequest {
+            sort_key: SortKey::Kind,
+            ..PageRequest::default()
+        };
+        assert_eq!(
+            names(&page(&tmp, &by_kind)),
+            ["dir", "small.mkv", "medium.jpg", "large.srt"]
+        );
+    }
+
+    #[test]
+    fn test_sort_by_duration_puts_unknown_last() {
+        let tmp = TempDir::new().unwrap();
+        for name in ["long.mkv", "short.mp4", "broken.mkv", "notes.txt"] {
+            fs::write(tmp.path().join(name), "").unwrap();
+        }
+        let duration_of = |entry: &FileEntry| match entry.name.as_str() {
+            "long.mkv" => Some(3600.0),
+            "short.mp4" => Some(60.0),
+            "notes.txt" => panic!("only videos are probed"),
+            _ => None,
+        };
+
+        for (order, expected) in [
+            (
+                SortOrder::Ascending,
+                ["short.mp4", "long.mkv", "broken.mkv", "notes.txt"],
+            ),
+            (
+                SortOrder::Descending,
+                ["long.mkv", "short.mp4", "notes.txt", "broken.mkv"],
+            ),
+        ] {
+            let request = PageRequest {
+                sort_key: SortKey::Duration,
+                order,
+                ..PageRequest::default()
+            };
+            let page = read_page(
+                tmp.path(),
+                &request,
+                None,
+                &Exclusions::default(),
+                &PageCache::default(),
+                &duration_of,
+            )
+            .unwrap();
+            assert_eq!(names(&page), expected);
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use tauri::State;
+
+use crate::exclusions::ExclusionState;
+use crate::models::exclusion_rules::ExclusionRules;
+
+#[tauri::command]
+pub fn get_exclusion_rules(exclusions: State<'_, ExclusionState>) -> ExclusionRules {
+    exclusions.get().rules().clone()
+}
+
+/// Replaces the exclusion rules and saves them for the next start. Fails
+/// without changing anything if a pattern is invalid.
+#[tauri::command]
+pub fn set_exclusion_rules(
+    rules: ExclusionRules,
+    exclusions: State<'_, ExclusionState>,
+) -> Result<(), String> {
+    exclusions.set(rules)
+}
@@ -1,9 +1,37 @@
 use std::fs;
 use std::path::Path;
 use std::process::Command;
-
-use crate::encoding::encoded_dir::{can_encode, encode_name, try_decode_name};
-use crate::models::file_entry::FileEntry;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::encoding::content_crypto;
+use crate::encoding::encoded_dir::{
+    check_encoded_length_within, locked_placeholder, name_state_in, NameState,
+};
+use crate::encoding::fs_limits::limits_for;
+use crate::encoding::name_codec::{default_codec, legacy_codec, resolve_codec, NameCodec};
+use crate::encoding::name_crypto::{EncryptedNameCodec, SessionKey, ENCRYPTED_SCHEME};
+use crate::encoding::name_index::{self, is_hashed_name};
+use crate::encoding::rename_plan::{self, Collision, Direction, Plan, RenameStep};
+use crate::exclusions::{ExclusionState, Exclusions};
+use crate::journal::{self, JournalData, JournalState, Recorder};
+use crate::models::file_entry::{mime_type_from_name, FileEntry, FileKind};
+use crate::models::length_check::EncodeCheck;
+use crate::models::operation::OperationKind;
+use crate::models::rename_report::{
+    CollisionPolicy, CollisionRecord, PlanIssueKind, PlannedRename, RenameIssue, RenamePreview,
+    RenameRecord, RenameReport,
+};
+use crate::models::search::SearchError;
+use crate::models::transfer_progress::TransferProgress;
+use crate::natural_sort::natural_key;
+use crate::search::{search, SearchEvent, SearchOptions};
+use crate::search_index::SearchIndexState;
+use crate::search_query::Query;
+use crate::session::SessionState;
+use crate::transfer;
+use crate::trash;
 
 #[tauri::command]
 pub fn get_cli_path() -> Option<String> {
@@ -12,172 +40,380 @@ pub fn get_cli_path() -> Option<String> {
         .filter(|arg| Path::new(arg).is_dir())
 }
 
-const EXCLUDED_FILES: &[&str] = &[".gitignore"];
-
 #[tauri::command]
-pub fn read_directory(path: String) -> Result<Vec<FileEntry>, String> {
-    let dir_path = Path::new(&path);
+pub fn read_directory(
+    path: String,
+    session: State<'_, SessionState>,
+    exclusions: State<'_, ExclusionState>,
+) -> Result<Vec<FileEntry>, String> {
+    list_directory(Path::new(&path), session.key().as_ref(), &exclusions.get())
+}
 
+/// The entries of `dir_path` that aren't excluded, sorted by display name.
+pub fn list_directory(
+    dir_path: &Path,
+    key: Option<&SessionKey>,
+    exclusions: &Exclusions,
+) -> Result<Vec<FileEntry>, String> {
     if !dir_path.is_dir() {
-        return Err(format!("Not a directory: {}", path));
+        return Err(format!("Not a directory: {}", dir_path.display()));
     }
 
     let entries = fs::read_dir(dir_path).map_err(|e| format!("Failed to read directory: {}", e))?;
+    let rules = exclusions.in_dir(dir_path, key);
 
     let mut file_entries: Vec<FileEntry> = Vec::new();
 
     for entry in entries {
         let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
         let file_name = entry.file_name().to_string_lossy().to_string();
+        let entry_path = entry.path();
 
-        if EXCLUDED_FILES.contains(&file_name.as_str()) {
+        let state = name_state_in(dir_path, &file_name, key);
+        if rules.is_excluded(&file_name, &state, entry_path.is_dir()) {
             continue;
         }
 
-        let entry_path = entry.path();
-        let is_directory = entry_path.is_dir();
-        let file_size = if is_directory {
-            0
-        } else {
-            fs::metadata(&entry_path).map(|m| m.len()).unwrap_or(0)
-        };
-        let decoded_name = try_decode_name(&file_name);
-        let is_encoded = decoded_name.is_some();
-        let display_name = decoded_name.clone().unwrap_or_else(|| file_name.clone());
-
-        file_entries.push(FileEntry {
-            path: entry_path.to_string_lossy().to_string(),
-            name: display_name,
-            physical_name: file_name,
-            is_directory,
-            is_encoded,
-            decoded_name,
-            has_children: is_directory,
-            file_size,
-        });
+        file_entries.push(file_entry_for(&entry_path, file_name, key));
     }
 
-    file_entries.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+    file_entries.sort_by_cached_key(|entry| natural_key(&entry.name));
 
     Ok(file_entries)
 }
 
 #[tauri::command]
-pub fn encode_node(path: String) -> Result<FileEntry, String> {
-    let node_path = Path::new(&path);
+pub fn encode_node(
+    path: String,
+    scheme: Option<String>,
+    collision_policy: Option<CollisionPolicy>,
+    session: State<'_, SessionState>,
+    journal: State<'_, JournalState>,
+) -> Result<RenameReport, String> {
+    let key = session.key();
+    let mut encrypted = None;
+    let codec = select_codec(scheme.as_deref(), key.as_ref(), &mut encrypted)?;
+    let policy = collision_policy.unwrap_or_default();
+    convert_node(
+        &path,
+        Direction::Encode(codec),
+        policy,
+        key.as_ref(),
+        Some(&*journal),
+    )
+}
+
+#[tauri::command]
+pub fn decode_node(
+    path: String,
+    collision_policy: Option<CollisionPolicy>,
+    session: State<'_, SessionState>,
+    journal: State<'_, JournalState>,
+) -> Result<RenameReport, String> {
+    let policy = collision_policy.unwrap_or_default();
+    convert_node(
+        &path,
+        Direction::Decode,
+        policy,
+        session.key().as_ref(),
+        Some(&*journal),
+    )
+}
+
+/// Resolves the scheme requested by the frontend. The encrypted scheme needs
+/// the session key, so its codec is created in `slot`.
+pub fn select_codec<'a>(
+    scheme: Option<&str>,
+    key: Option<&SessionKey>,
+    slot: &'a mut Option<EncryptedNameCodec>,
+) -> Result<&'a dyn NameCodec, String> {
+    if scheme == Some(ENCRYPTED_SCHEME) {
+        let key = key.ok_or("Session is locked")?;
+        return Ok(slot.insert(EncryptedNameCodec::new(key.clone())));
+    }
+    resolve_codec(scheme)
+}
+
+/// Rewrites every name below `path` (and `path` itself) that still uses the
+/// legacy `.dat_` scheme into the current default scheme.
+#[tauri::command]
+pub fn migrate_encoded_names(
+    path: String,
+    collision_policy: Option<CollisionPolicy>,
+    session: State<'_, SessionState>,
+    journal: State<'_, JournalState>,
+) -> Result<RenameReport, String> {
+    let direction = Direction::Reencode {
+        from: legacy_codec(),
+        to: default_codec(),
+    };
+    let policy = collision_policy.unwrap_or_default();
+    convert_node(
+        &path,
+        direction,
+        policy,
+        session.key().as_ref(),
+        Some(&*journal),
+    )
+}
+
+/// Encodes or decodes a node and everything below it as one transaction:
+/// all renames are planned up front and undone again if any of them fails.
+pub fn convert_node(
+    path: &str,
+    direction: Direction,
+    policy: CollisionPolicy,
+    key: Option<&SessionKey>,
+    journal: Option<&dyn Recorder>,
+) -> Result<RenameReport, String> {
+    let node_path = Path::new(path);
     if !node_path.exists() {
         return Err(format!("Path does not exist: {}", path));
     }
 
-    let physical_name = node_path
-        .file_name()
-        .and_then(|n| n.to_str())
-        .ok_or("Invalid file name")?;
+    let plan = rename_plan::plan(node_path, direction, policy, key)?;
+    for issue in &plan.issues {
+        match issue.kind {
+            PlanIssueKind::InvalidUtf8 => {
+                return Err(format!("Invalid file name: {}", issue.path.display()))
+            }
+            PlanIssueKind::Locked => {
+                return Err(format!("Session is locked: {}", issue.path.display()))
+            }
+            PlanIssueKind::Undecodable => {
+                return Err(format!("Cannot decode name: {}", issue.path.display()))
+            }
+            _ => {}
+        }
+    }
+
+    let kind = match direction {
+        Direction::Encode(_) => OperationKind::Encode,
+        Direction::Decode => OperationKind::Decode,
+        Direction::Reencode { .. } => OperationKind::Migrate,
+    };
+    Ok(run_plan(node_path, plan, key, journal.map(|j| (j, kind))))
+}
+
+/// Applies a plan for `node_path` and reports the outcome. Plans with
+/// unresolved collisions are refused without touching anything. Applied
+/// plans are recorded in `journal`, if given, as an operation of that kind.
+fn run_plan(
+    node_path: &Path,
+    plan: Plan,
+    key: Option<&SessionKey>,
+    journal: Option<(&dyn Recorder, OperationKind)>,
+) -> RenameReport {
+    let collisions = to_collision_records(&plan.collisions);
+
+    if plan.has_failed_collisions() {
+        return RenameReport {
+            entry: build_file_entry(node_path, key).ok(),
+            renamed: Vec::new(),
+            rolled_back: Vec::new(),
+            collisions,
+            error: Some("Target name already exists".to_string()),
+        };
+    }
 
-    let is_encoded = try_decode_name(physical_name).is_some();
+    let fingerprints = plan
+        .steps
+        .iter()
+        .map(|step| journal::fingerprint(&step.from))
+        .collect();
+    let outcome = rename_plan::apply(&plan.steps);
+    if let (Some((journal, kind)), None) = (journal, &outcome.error) {
+        if !outcome.applied.is_empty() {
+            journal.record(
+                kind,
+                node_path,
+                &JournalData::renames(&outcome.applied, &outcome.undo, fingerprints),
+            );
+        }
+    }
 
-    // Encode this node if not already encoded
-    let current_path = if !is_encoded {
-        let encoded_name = encode_name(physical_name);
-        let parent = node_path.parent().ok_or("No parent directory")?;
-        let new_path = parent.join(&encoded_name);
-        fs::rename(node_path, &new_path).map_err(|e| format!("Failed to rename: {}", e))?;
-        new_path
+    let current_path = if outcome.error.is_none() {
+        rename_plan::final_path(node_path, &plan.steps)
     } else {
         node_path.to_path_buf()
     };
 
-    // Recursively encode children if directory
-    if current_path.is_dir() {
-        encode_children_recursive(&current_path)?;
+    RenameReport {
+        entry: build_file_entry(&current_path, key).ok(),
+        renamed: to_records(&outcome.applied),
+        rolled_back: to_records(&outcome.rolled_back),
+        collisions,
+        error: outcome.error,
     }
-
-    build_file_entry(&current_path)
 }
 
-fn encode_children_recursive(dir: &Path) -> Result<(), String> {
-    let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read directory: {}", e))?;
-    for entry in entries {
-        let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
-        let entry_path = entry.path();
-        let name = entry.file_name().to_string_lossy().to_string();
-
-        if try_decode_name(&name).is_none() {
-            let encoded_name = encode_name(&name);
-            let new_path = dir.join(&encoded_name);
-            fs::rename(&entry_path, &new_path).map_err(|e| format!("Failed to rename: {}", e))?;
-
-            if new_path.is_dir() {
-                encode_children_recursive(&new_path)?;
-            }
-        } else if entry_path.is_dir() {
-            encode_children_recursive(&entry_path)?;
-        }
-    }
-    Ok(())
+#[tauri::command]
+pub fn preview_encode_node(
+    path: String,
+    scheme: Option<String>,
+    collision_policy: Option<CollisionPolicy>,
+    session: State<'_, SessionState>,
+) -> Result<RenamePreview, String> {
+    let key = session.key();
+    let mut encrypted = None;
+    let codec = select_codec(scheme.as_deref(), key.as_ref(), &mut encrypted)?;
+    preview_node(
+        &path,
+        Direction::Encode(codec),
+        collision_policy.unwrap_or_default(),
+        key.as_ref(),
+    )
 }
 
 #[tauri::command]
-pub fn decode_node(path: String) -> Result<FileEntry, String> {
-    let node_path = Path::new(&path);
+pub fn preview_decode_node(
+    path: String,
+    collision_policy: Option<CollisionPolicy>,
+    session: State<'_, SessionState>,
+) -> Result<RenamePreview, String> {
+    preview_node(
+        &path,
+        Direction::Decode,
+        collision_policy.unwrap_or_default(),
+        session.key().as_ref(),
+    )
+}
+
+/// Dry run of `convert_node`: lists the renames it would perform and the
+/// problems it would run into, without touching anything.
+fn preview_node(
+    path: &str,
+    direction: Direction,
+    policy: CollisionPolicy,
+    key: Option<&SessionKey>,
+) -> Result<RenamePreview, String> {
+    let node_path = Path::new(path);
     if !node_path.exists() {
         return Err(format!("Path does not exist: {}", path));
     }
 
-    let physical_name = node_path
-        .file_name()
-        .and_then(|n| n.to_str())
-        .ok_or("Invalid file name")?;
+    let plan = rename_plan::plan(node_path, direction, policy, key)?;
+
+    let renames = plan
+        .steps
+        .iter()
+        .map(|step| PlannedRename {
+            path: step.from.to_string_lossy().to_string(),
+            old_name: file_name_lossy(&step.from),
+            new_name: file_name_lossy(&step.to),
+        })
+        .collect();
+    let issues = plan
+        .issues
+        .iter()
+        .map(|issue| RenameIssue {
+            path: issue.path.to_string_lossy().to_string(),
+            kind: issue.kind,
+        })
+        .collect();
+
+    Ok(RenamePreview {
+        renames,
+        issues,
+        collisions: to_collision_records(&plan.collisions),
+    })
+}
 
-    let is_encoded = try_decode_name(physical_name).is_some();
+fn file_name_lossy(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
 
-    // Decode this node if encoded
-    let current_path = if is_encoded {
-        let decoded_name = try_decode_name(physical_name).unwrap();
-        let parent = node_path.parent().ok_or("No parent directory")?;
-        let new_path = parent.join(&decoded_name);
-        fs::rename(node_path, &new_path).map_err(|e| format!("Failed to rename: {}", e))?;
-        new_path
-    } else {
-        node_path.to_path_buf()
-    };
+fn to_records(steps: &[RenameStep]) -> Vec<RenameRecord> {
+    steps
+        .iter()
+        .map(|step| RenameRecord {
+            from: step.from.to_string_lossy().to_string(),
+            to: step.to.to_string_lossy().to_string(),
+        })
+        .collect()
+}
 
-    // Recursively decode children if directory
-    if current_path.is_dir() {
-        decode_children_recursive(&current_path)?;
-    }
+fn to_collision_records(collisions: &[Collision]) -> Vec<CollisionRecord> {
+    collisions
+        .iter()
+        .map(|c| CollisionRecord {
+            path: c.path.to_string_lossy().to_string(),
+            target: c.target.to_string_lossy().to_string(),
+            resolution: c.resolution,
+            resolved_path: c.resolved.as_ref().map(|p| p.to_string_lossy().to_string()),
+        })
+        .collect()
+}
 
-    build_file_entry(&current_path)
+/// Encrypts the contents of a file, or of every file below a directory, in
+/// place. Files that are already encrypted are left alone, so an interrupted
+/// run can simply be repeated.
+#[tauri::command]
+pub fn encrypt_node(path: String, session: State<'_, SessionState>) -> Result<FileEntry, String> {
+    crypt_node(Path::new(&path), session.key().as_ref(), true)
 }
 
-fn decode_children_recursive(dir: &Path) -> Result<(), String> {
-    let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read directory: {}", e))?;
-    for entry in entries {
-        let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
-        let entry_path = entry.path();
-        let name = entry.file_name().to_string_lossy().to_string();
+#[tauri::command]
+pub fn decrypt_node(path: String, session: State<'_, SessionState>) -> Result<FileEntry, String> {
+    crypt_node(Path::new(&path), session.key().as_ref(), false)
+}
 
-        if let Some(decoded_name) = try_decode_name(&name) {
-            let new_path = dir.join(&decoded_name);
-            fs::rename(&entry_path, &new_path).map_err(|e| format!("Failed to rename: {}", e))?;
+/// Encrypts or decrypts the contents of every file at or below `node_path`.
+fn crypt_node(
+    node_path: &Path,
+    key: Option<&SessionKey>,
+    encrypt: bool,
+) -> Result<FileEntry, String> {
+    let session_key = key.ok_or("Session is locked")?;
+    for_each_file(node_path, &mut |file| {
+        let result = if encrypt {
+            content_crypto::encrypt_file(session_key, file)
+        } else {
+            content_crypto::decrypt_file(session_key, file)
+        };
+        let action = if encrypt { "encrypt" } else { "decrypt" };
+        result
+            .map(|_| ())
+            .map_err(|e| format!("Failed to {} {}: {}", action, file.display(), e))
+    })?;
+    build_file_entry(node_path, key)
+}
 
-            if new_path.is_dir() {
-                decode_children_recursive(&new_path)?;
-            }
-        } else if entry_path.is_dir() {
-            decode_children_recursive(&entry_path)?;
+fn for_each_file(
+    path: &Path,
+    action: &mut dyn FnMut(&Path) -> Result<(), String>,
+) -> Result<(), String> {
+    let metadata = fs::symlink_metadata(path)
+        .map_err(|e| format!("Path does not exist: {}: {}", path.display(), e))?;
+    if metadata.is_dir() {
+        let entries = fs::read_dir(path).map_err(|e| format!("Failed to read directory: {}", e))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
+            for_each_file(&entry.path(), action)?;
         }
+        Ok(())
+    } else if metadata.is_file() {
+        action(path)
+    } else {
+        Ok(())
     }
-    Ok(())
 }
 
 #[tauri::command]
-pub fn can_encode_node(path: String) -> Result<bool, String> {
+pub fn can_encode_node(path: String) -> Result<EncodeCheck, String> {
     let node_path = Path::new(&path);
     if !node_path.exists() {
         return Err(format!("Path does not exist: {}", path));
     }
-    Ok(can_encode(node_path))
+    let limits = limits_for(node_path);
+    let reason = check_encoded_length_within(default_codec(), node_path, limits);
+    Ok(EncodeCheck {
+        can_encode: reason.is_none(),
+        reason,
+        limits,
+    })
 }
 
 #[tauri::command]
@@ -190,76 +426,215 @@ pub fn copy_to_clipboard(text: String) -> Result<(), String> {
 }
 
 #[tauri::command]
-pub fn rename_node(path: String, new_name: String, encode: bool) -> Result<FileEntry, String> {
-    let node_path = Path::new(&path);
+pub fn rename_node(
+    path: String,
+    new_name: String,
+    encode: bool,
+    scheme: Option<String>,
+    collision_policy: Option<CollisionPolicy>,
+    session: State<'_, SessionState>,
+    journal: State<'_, JournalState>,
+) -> Result<RenameReport, String> {
+    let policy = collision_policy.unwrap_or_default();
+    let key = session.key();
+    rename_path(
+        Path::new(&path),
+        &new_name,
+        encode,
+        scheme.as_deref(),
+        policy,
+        key.as_ref(),
+        Some(&*journal),
+    )
+}
+
+/// Renames the node to `new_name`, encoded with `scheme` if `encode` is set.
+pub fn rename_path(
+    node_path: &Path,
+    new_name: &str,
+    encode: bool,
+    scheme: Option<&str>,
+    policy: CollisionPolicy,
+    key: Option<&SessionKey>,
+    journal: Option<&dyn Recorder>,
+) -> Result<RenameReport, String> {
     if !node_path.exists() {
-        return Err(format!("Path does not exist: {}", path));
+        return Err(format!("Path does not exist: {}", node_path.display()));
     }
 
-    let parent = node_path.parent().ok_or("No parent directory")?;
-
-    let target_name = if encode {
-        encode_name(&new_name)
+    let mut encrypted = None;
+    let codec = if encode {
+        let codec = select_codec(scheme, key, &mut encrypted)?;
+        Some(rename_plan::fitting_codec(
+            codec,
+            &node_path.with_file_name(new_name),
+        ))
     } else {
-        new_name
+        None
     };
+    let mut plan = Plan::default();
+    if let Some(step) =
+        rename_plan::resolve_step(node_path, new_name, codec, policy, key, &mut plan)?
+    {
+        plan.steps.push(step);
+    }
 
-    let new_path = parent.join(&target_name);
-    fs::rename(node_path, &new_path).map_err(|e| format!("Failed to rename: {}", e))?;
-
-    build_file_entry(&new_path)
+    Ok(run_plan(
+        node_path,
+        plan,
+        key,
+        journal.map(|j| (j, OperationKind::Rename)),
+    ))
 }
 
+/// Moves the node to the system trash. Hashed names keep their entry in the
+/// sidecar index, so they still decode in the trash and after a restore.
 #[tauri::command]
-pub fn delete_node(path: String) -> Result<(), String> {
+pub fn delete_node(path: String, journal: State<'_, JournalState>) -> Result<(), String> {
+    delete_node_with(path, trash::move_to_trash, Some(&*journal))
+}
+
+/// `move_to_trash` returns the id of the trashed item.
+pub fn delete_node_with(
+    path: String,
+    move_to_trash: impl FnOnce(&Path) -> Result<String, String>,
+    journal: Option<&dyn Recorder>,
+) -> Result<(), String> {
     let node_path = Path::new(&path);
-    if !node_path.exists() {
+    if fs::symlink_metadata(node_path).is_err() {
         return Err(format!("Path does not exist: {}", path));
     }
-
-    if node_path.is_dir() {
-        fs::remove_dir_all(node_path).map_err(|e| format!("Failed to delete directory: {}", e))
-    } else {
-        fs::remove_file(node_path).map_err(|e| format!("Failed to delete file: {}", e))
+    let fingerprint = journal::fingerprint(node_path);
+    let trash_id = move_to_trash(node_path)?;
+    if let Some(journal) = journal {
+        let data = JournalData::Trash {
+            path: node_path.to_path_buf(),
+            trash_id,
+            fingerprint,
+        };
+        journal.record(OperationKind::Delete, node_path, &data);
     }
+    Ok(())
 }
 
+/// Counts the entries `read_directory` would list.
 #[tauri::command]
-pub fn count_children(path: String) -> Result<usize, String> {
-    let dir_path = Path::new(&path);
+pub fn count_children(
+    path: String,
+    session: State<'_, SessionState>,
+    exclusions: State<'_, ExclusionState>,
+) -> Result<usize, String> {
+    count_entries(Path::new(&path), session.key().as_ref(), &exclusions.get())
+}
+
+fn count_entries(
+    dir_path: &Path,
+    key: Option<&SessionKey>,
+    exclusions: &Exclusions,
+) -> Result<usize, String> {
     if !dir_path.is_dir() {
         return Ok(0);
     }
-    let count = fs::read_dir(dir_path)
-        .map_err(|e| format!("Failed to read directory: {}", e))?
-        .count();
+    let rules = exclusions.in_dir(dir_path, key);
+    let mut count = 0;
+    for entry in fs::read_dir(dir_path).map_err(|e| format!("Failed to read directory: {}", e))? {
+        let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let state = name_state_in(dir_path, &file_name, key);
+        if !rules.is_excluded(&file_name, &state, entry.path().is_dir()) {
+            count += 1;
+        }
+    }
     Ok(count)
 }
 
+pub const MOVE_PROGRESS_EVENT: &str = "move-progress";
+
+/// Moves across file systems copy the node, so this runs off the main
+/// thread and reports `move-progress` events while copying.
 #[tauri::command]
-pub fn move_node(source: String, target_dir: String) -> Result<FileEntry, String> {
-    let source_path = Path::new(&source);
-    let target_path = Path::new(&target_dir);
+pub async fn move_node(
+    source: String,
+    target_dir: String,
+    verify_checksum: Option<bool>,
+    app: AppHandle,
+    session: State<'_, SessionState>,
+) -> Result<FileEntry, String> {
+    let key = session.key();
+    tauri::async_runtime::spawn_blocking(move || {
+        let journal = app.state::<JournalState>();
+        move_path(
+            Path::new(&source),
+            Path::new(&target_dir),
+            verify_checksum.unwrap_or(false),
+            key.as_ref(),
+            Some(&*journal),
+            &mut |progress| {
+                let _ = app.emit(MOVE_PROGRESS_EVENT, progress);
+            },
+        )
+    })
+    .await
+    .map_err(|e| format!("Task join error: {}", e))?
+}
 
+/// Moves the node into `target_path`, copying it if that is on another file
+/// system.
+pub fn move_path(
+    source_path: &Path,
+    target_path: &Path,
+    verify_checksum: bool,
+    key: Option<&SessionKey>,
+    journal: Option<&dyn Recorder>,
+    on_progress: &mut dyn FnMut(&TransferProgress),
+) -> Result<FileEntry, String> {
     if !source_path.exists() {
-        return Err(format!("Source does not exist: {}", source));
+        return Err(format!("Source does not exist: {}", source_path.display()));
     }
     if !target_path.is_dir() {
-        return Err(format!("Target is not a directory: {}", target_dir));
+        return Err(format!(
+            "Target is not a directory: {}",
+            target_path.display()
+        ));
     }
 
-    let file_name = source_path
-        .file_name()
-        .ok_or("Invalid source file name")?;
+    let file_name = source_path.file_name().ok_or("Invalid source file name")?;
     let new_path = target_path.join(file_name);
 
     if new_path.exists() {
         return Err(format!("Target already exists: {}", new_path.display()));
     }
 
-    fs::rename(source_path, &new_path).map_err(|e| format!("Failed to move: {}", e))?;
+    // A hashed name keeps its physical name, so its entry just moves from
+    // one sidecar index to the other
+    let indexed_name = match (source_path.parent(), file_name.to_str()) {
+        (Some(dir), Some(name)) if is_hashed_name(name) => name_index::lookup_name(dir, name),
+        _ => None,
+    };
+    let step = RenameStep {
+        from: source_path.to_path_buf(),
+        to: new_path.clone(),
+        indexed_name,
+    };
+    let fingerprint = journal::fingerprint(source_path);
+    let data = match rename_plan::apply_same_device(&step)
+        .map_err(|e| format!("Failed to move: {}", e))?
+    {
+        Some(undo) => JournalData::renames(&[step], &[undo], vec![fingerprint]),
+        None => {
+            transfer::move_across_devices(&step, verify_checksum, on_progress)?;
+            // Undone by copying everything back
+            JournalData::DeviceMove {
+                fingerprint: journal::fingerprint(&step.to),
+                step,
+            }
+        }
+    };
+    if let Some(journal) = journal {
+        journal.record(OperationKind::Move, source_path, &data);
+    }
 
-    build_file_entry(&new_path)
+    build_file_entry(&new_path, key)
 }
 
 #[tauri::command]
@@ -336,82 +711,195 @@ pub fn open_in_vlc(path: String, start_time: Option<f64>) -> Result<(), String>
     Ok(())
 }
 
+/// Searches with the query language of `search_query::Query`, in the search
+/// index if `path` is in an indexed directory.
 #[tauri::command]
-pub fn search_files(path: String, pattern: String) -> Result<Vec<FileEntry>, String> {
-    let dir_path = Path::new(&path);
-    if !dir_path.is_dir() {
-        return Err(format!("Not a directory: {}", path));
-    }
-
-    let pattern_lower = pattern.to_lowercase();
-    let mut results = Vec::new();
-    search_recursive(dir_path, &pattern_lower, &mut results)?;
-    results.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
-    Ok(results)
+pub fn search_files(
+    path: String,
+    pattern: String,
+    session: State<'_, SessionState>,
+    exclusions: State<'_, ExclusionState>,
+    index: State<'_, SearchIndexState>,
+) -> Result<Vec<FileEntry>, SearchError> {
+    let query = Query::parse(&pattern)?;
+    find_files(
+        Path::new(&path),
+        &query,
+        session.key().as_ref(),
+        &exclusions.get(),
+        Some(&index),
+    )
 }
 
-fn search_recursive(dir: &Path, pattern: &str, results: &mut Vec<FileEntry>) -> Result<(), String> {
-    let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read directory: {}", e))?;
-
-    for entry in entries {
-        let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
-        let file_name = entry.file_name().to_string_lossy().to_string();
-
-        if EXCLUDED_FILES.contains(&file_name.as_str()) {
-            continue;
-        }
-
-        let entry_path = entry.path();
-        let display_name = try_decode_name(&file_name).unwrap_or_else(|| file_name.clone());
-
-        if display_name.to_lowercase().contains(pattern) {
-            results.push(build_file_entry(&entry_path)?);
-        }
-
-        if entry_path.is_dir() {
-            search_recursive(&entry_path, pattern, results)?;
+/// Every match of `query` below `dir`, in the search index if `dir` is in an
+/// indexed directory.
+fn find_files(
+    dir: &Path,
+    query: &Query,
+    key: Option<&SessionKey>,
+    exclusions: &Exclusions,
+    index: Option<&SearchIndexState>,
+) -> Result<Vec<FileEntry>, SearchError> {
+    let mut results = Vec::new();
+    let options = SearchOptions {
+        key,
+        exclusions,
+        max_results: None,
+        is_cancelled: &|| false,
+    };
+    search(index, dir, query, &options, &mut |event, _| {
+        if let SearchEvent::Match(entry) = event {
+            results.push(*entry);
         }
-    }
-
-    Ok(())
+    })?;
+    results.sort_by_cached_key(|entry| natural_key(&entry.name));
+    Ok(results)
 }
 
-pub fn build_file_entry(path: &Path) -> Result<FileEntry, String> {
+pub fn build_file_entry(path: &Path, key: Option<&SessionKey>) -> Result<FileEntry, String> {
     let physical_name = path
         .file_name()
         .and_then(|n| n.to_str())
         .ok_or("Invalid file name")?
         .to_string();
-    let is_directory = path.is_dir();
-    let file_size = if is_directory {
-        0
-    } else {
-        fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+    Ok(file_entry_for(path, physical_name, key))
+}
+
+/// Encrypted names are shown as a placeholder unless `key` decrypts them.
+/// Symlinks are described by their target, or by the link itself if it is
+/// broken. Contents are never read here, so files with encrypted contents
+/// report their size on disk; probing a video reports the plaintext size.
+fn file_entry_for(path: &Path, physical_name: String, key: Option<&SessionKey>) -> FileEntry {
+    let link_metadata = fs::symlink_metadata(path).ok();
+    let is_symlink = link_metadata
+        .as_ref()
+        .is_some_and(|m| m.file_type().is_symlink());
+    let metadata = fs::metadata(path).ok().or(link_metadata);
+    let is_directory = metadata.as_ref().is_some_and(|m| m.is_dir());
+    let file_size = match &metadata {
+        Some(m) if !is_directory => m.len(),
+        _ => 0,
     };
-    let decoded_name = try_decode_name(&physical_name);
-    let is_encoded = decoded_name.is_some();
-    let display_name = decoded_name.clone().unwrap_or_else(|| physical_name.clone());
-    Ok(FileEntry {
+    let state = name_state_in(path.parent().unwrap_or(path), &physical_name, key);
+    let is_locked = state == NameState::Locked;
+    let decoded_name = match state {
+        NameState::Decoded(decoded) => Some(decoded),
+        _ => None,
+    };
+    let is_encoded = decoded_name.is_some() || is_locked;
+    let display_name = match &decoded_name {
+        Some(decoded) => decoded.clone(),
+        None if is_locked => locked_placeholder(&physical_name),
+        None => physical_name.clone(),
+    };
+    FileEntry {
         path: path.to_string_lossy().to_string(),
-        name: display_name,
         physical_name,
         is_directory,
         is_encoded,
         decoded_name,
+        is_locked,
         has_children: is_directory,
         file_size,
+        created: metadata.as_ref().and_then(|m| epoch_secs(m.created())),
+        modified: metadata.as_ref().and_then(|m| epoch_secs(m.modified())),
+        accessed: metadata.as_ref().and_then(|m| epoch_secs(m.accessed())),
+        is_symlink,
+        symlink_target: is_symlink
+            .then(|| fs::read_link(path).ok())
+            .flatten()
+            .map(|target| target.to_string_lossy().to_string()),
+        permissions: metadata.as_ref().and_then(permission_bits),
+        is_read_only: metadata
+            .as_ref()
+            .is_some_and(|m| m.permissions().readonly()),
+        kind: match (is_directory, is_locked) {
+            (false, false) => FileKind::from_name(&display_name),
+            _ => FileKind::Other,
+        },
+        mime_type: match (is_directory, is_locked) {
+            (false, false) => mime_type_from_name(&display_name),
+            _ => None,
+        },
+        name: display_name,
+    }
+}
+
+fn epoch_secs(time: std::io::Result<SystemTime>) -> Option<i64> {
+    let time = time.ok()?;
+    Some(match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => after.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
     })
 }
 
+#[cfg(unix)]
+fn permission_bits(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn permission_bits(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::encoding::encoded_dir::encode_name_with;
+    use crate::encoding::name_codec::default_codec;
+    use crate::encoding::name_index::INDEX_FILE_NAME;
+    use crate::models::exclusion_rules::ExclusionRules;
+    use crate::models::length_check::LengthIssue;
+    use crate::models::rename_report::CollisionResolution;
     use tempfile::TempDir;
 
     fn create_test_dir() -> TempDir {
         TempDir::new().unwrap()
     }
 
+    fn encode(
+        path: &str,
+        scheme: Option<&str>,
+        key: Option<&SessionKey>,
+    ) -> Result<RenameReport, String> {
+        let mut encrypted = None;
+        let codec = select_codec(scheme, key, &mut encrypted)?;
+        convert_node(
+            path,
+            Direction::Encode(codec),
+            CollisionPolicy::default(),
+            key,
+            None,
+        )
+    }
+
+    fn decode(path: &str) -> Result<RenameReport, String> {
+        convert_node(
+            path,
+            Direction::Decode,
+            CollisionPolicy::default(),
+            None,
+            None,
+        )
+    }
+
+    fn find(dir: &Path, pattern: &str, exclusions: &Exclusions) -> Vec<FileEntry> {
+        find_files(dir, &Query::parse(pattern).unwrap(), None, exclusions, None).unwrap()
+    }
+
+    /// Stands in for the system trash, which tests must not touch.
+    fn test_trash(tmp: &TempDir) -> impl FnOnce(&Path) -> Result<String, String> + '_ {
+        move |path| {
+            let trash = tmp.path().join("trash");
+            fs::create_dir_all(&trash).unwrap();
+            let trashed = trash.join(path.file_name().unwrap());
+            fs::rename(path, &trashed).map_err(|e| e.to_string())?;
+            Ok(trashed.to_string_lossy().to_string())
+        }
+    }
+
     // --- build_file_entry ---
 
     #[test]
@@ -420,7 +908,7 @@ mod tests {
         let file = tmp.path().join("hello.txt");
         fs::write(&file, "content").unwrap();
 
-        let entry = build_file_entry(&file).unwrap();
+        let entry = build_file_entry(&file, None).unwrap();
         assert_eq!(entry.name, "hello.txt");
         assert_eq!(entry.physical_name, "hello.txt");
         assert!(!entry.is_directory);
@@ -435,7 +923,7 @@ mod tests {
         let file = tmp.path().join(".dat_VGVzdA==");
         fs::write(&file, "content").unwrap();
 
-        let entry = build_file_entry(&file).unwrap();
+        let entry = build_file_entry(&file, None).unwrap();
         assert_eq!(entry.name, "Test");
         assert_eq!(entry.physical_name, ".dat_VGVzdA==");
         assert!(entry.is_encoded);
@@ -448,9 +936,52 @@ mod tests {
         let dir = tmp.path().join("subdir");
         fs::create_dir(&dir).unwrap();
 
-        let entry = build_file_entry(&dir).unwrap();
+        let entry = build_file_entry(&dir, None).unwrap();
         assert!(entry.is_directory);
         assert!(entry.has_children);
+        assert_eq!(entry.kind, FileKind::Other);
+        assert_eq!(entry.mime_type, None);
+    }
+
+    #[test]
+    fn test_build_file_entry_metadata() {
+        let tmp = create_test_dir();
+        let file = tmp
+            .path()
+            .join(encode_name_with(default_codec(), "Movie.MKV"));
+        fs::write(&file, "content").unwrap();
+        let mut permissions = fs::metadata(&file).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&file, permissions).unwrap();
+
+        let entry = build_file_entry(&file, None).unwrap();
+        assert_eq!(entry.kind, FileKind::Video);
+        assert_eq!(entry.mime_type, Some("video/x-matroska"));
+        assert!(entry.modified.is_some_and(|t| t > 0));
+        assert!(entry.is_read_only);
+        assert!(!entry.is_symlink);
+        #[cfg(unix)]
+        assert_eq!(entry.permissions.map(|p| p & 0o222), Some(0));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_build_file_entry_symlink() {
+        let tmp = create_test_dir();
+        fs::write(tmp.path().join("song.flac"), "content").unwrap();
+        std::os::unix::fs::symlink("song.flac", tmp.path().join("link.flac")).unwrap();
+        std::os::unix::fs::symlink("missing", tmp.path().join("broken")).unwrap();
+
+        let entry = build_file_entry(&tmp.path().join("link.flac"), None).unwrap();
+        assert!(entry.is_symlink);
+        assert_eq!(entry.symlink_target.as_deref(), Some("song.flac"));
+        assert_eq!(entry.file_size, 7);
+        assert_eq!(entry.kind, FileKind::Audio);
+
+        let broken = build_file_entry(&tmp.path().join("broken"), None).unwrap();
+        assert!(broken.is_symlink);
+        assert_eq!(broken.symlink_target.as_deref(), Some("missing"));
+        assert!(!broken.is_directory);
     }
 
     // --- read_directory ---
@@ -462,7 +993,7 @@ mod tests {
         fs::write(tmp.path().join("a.txt"), "").unwrap();
         fs::create_dir(tmp.path().join("c_dir")).unwrap();
 
-        let entries = read_directory(tmp.path().to_string_lossy().to_string()).unwrap();
+        let entries = list_directory(tmp.path(), None, &Exclusions::default()).unwrap();
         assert_eq!(entries.len(), 3);
         // Should be sorted alphabetically
         assert_eq!(entries[0].name, "a.txt");
@@ -476,7 +1007,7 @@ mod tests {
         fs::write(tmp.path().join(".gitignore"), "").unwrap();
         fs::write(tmp.path().join("file.txt"), "").unwrap();
 
-        let entries = read_directory(tmp.path().to_string_lossy().to_string()).unwrap();
+        let entries = list_directory(tmp.path(), None, &Exclusions::default()).unwrap();
         assert_eq!(entries.len(), 1);
         assert_eq!(entries[0].name, "file.txt");
     }
@@ -486,20 +1017,52 @@ mod tests {
         let tmp = create_test_dir();
         fs::write(tmp.path().join(".dat_VGVzdA=="), "").unwrap();
 
-        let entries = read_directory(tmp.path().to_string_lossy().to_string()).unwrap();
+        let entries = list_directory(tmp.path(), None, &Exclusions::default()).unwrap();
         assert_eq!(entries.len(), 1);
         assert_eq!(entries[0].name, "Test");
         assert_eq!(entries[0].physical_name, ".dat_VGVzdA==");
         assert!(entries[0].is_encoded);
     }
 
+    #[test]
+    fn test_read_directory_encrypted_names() {
+        let tmp = create_test_dir();
+        let key = SessionKey::derive("secret", b"0123456789abcdef").unwrap();
+        let file = tmp.path().join("Test");
+        fs::write(&file, "").unwrap();
+        encode(&file.to_string_lossy(), Some(ENCRYPTED_SCHEME), Some(&key)).unwrap();
+
+        let path = tmp.path().to_string_lossy().to_string();
+        let locked = list_directory(Path::new(&path), None, &Exclusions::default()).unwrap();
+        assert!(locked[0].is_locked);
+        assert!(locked[0].is_encoded);
+        assert_eq!(locked[0].decoded_name, None);
+        assert!(locked[0].name.starts_with("🔒"));
+
+        let unlocked =
+            list_directory(Path::new(&path), Some(&key), &Exclusions::default()).unwrap();
+        assert!(!unlocked[0].is_locked);
+        assert_eq!(unlocked[0].name, "Test");
+    }
+
+    #[test]
+    fn test_encode_node_encrypted_requires_key() {
+        let tmp = create_test_dir();
+        let file = tmp.path().join("Test");
+        fs::write(&file, "").unwrap();
+
+        let result = encode(&file.to_string_lossy(), Some(ENCRYPTED_SCHEME), None);
+        assert!(result.is_err());
+        assert!(file.exists());
+    }
+
     #[test]
     fn test_read_directory_not_a_dir() {
         let tmp = create_test_dir();
         let file = tmp.path().join("file.txt");
         fs::write(&file, "").unwrap();
 
-        let result = read_directory(file.to_string_lossy().to_string());
+        let result = list_directory(&file, None, &Exclusions::default());
         assert!(result.is_err());
     }
 
@@ -511,10 +1074,13 @@ mod tests {
         let file = tmp.path().join("hello.txt");
         fs::write(&file, "content").unwrap();
 
-        let entry = encode_node(file.to_string_lossy().to_string()).unwrap();
+        let entry = encode(&file.to_string_lossy(), None, None)
+            .unwrap()
+            .entry
+            .unwrap();
         assert!(entry.is_encoded);
         assert_eq!(entry.name, "hello.txt");
-        assert!(entry.physical_name.starts_with(".dat_"));
+        assert!(entry.physical_name.starts_with(".dat2_"));
         // Original should no longer exist
         assert!(!file.exists());
         // Encoded file should exist
@@ -527,7 +1093,10 @@ mod tests {
         let file = tmp.path().join(".dat_VGVzdA==");
         fs::write(&file, "content").unwrap();
 
-        let entry = encode_node(file.to_string_lossy().to_string()).unwrap();
+        let entry = encode(&file.to_string_lossy(), None, None)
+            .unwrap()
+            .entry
+            .unwrap();
         // Should remain unchanged
         assert_eq!(entry.physical_name, ".dat_VGVzdA==");
         assert!(file.exists());
@@ -542,7 +1111,10 @@ mod tests {
         fs::create_dir(dir.join("subdir")).unwrap();
         fs::write(dir.join("subdir").join("nested.txt"), "").unwrap();
 
-        let entry = encode_node(dir.to_string_lossy().to_string()).unwrap();
+        let entry = encode(&dir.to_string_lossy(), None, None)
+            .unwrap()
+            .entry
+            .unwrap();
         assert!(entry.is_encoded);
 
         // Children should also be encoded
@@ -551,7 +1123,70 @@ mod tests {
             .unwrap()
             .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
             .collect();
-        assert!(children.iter().all(|c| c.starts_with(".dat_")));
+        assert!(children.iter().all(|c| c.starts_with(".dat2_")));
+    }
+
+    #[test]
+    fn test_encode_node_with_scheme() {
+        let tmp = create_test_dir();
+        let file = tmp.path().join("Test");
+        fs::write(&file, "").unwrap();
+
+        let entry = encode(&file.to_string_lossy(), Some("base32"), None)
+            .unwrap()
+            .entry
+            .unwrap();
+        assert_eq!(entry.physical_name, ".dat32_krsxg5a");
+        assert_eq!(entry.name, "Test");
+    }
+
+    #[test]
+    fn test_encode_node_unknown_scheme() {
+        let tmp = create_test_dir();
+        let file = tmp.path().join("Test");
+        fs::write(&file, "").unwrap();
+
+        let result = encode(&file.to_string_lossy(), Some("rot13"), None);
+        assert!(result.is_err());
+        assert!(file.exists());
+    }
+
+    #[test]
+    fn test_encode_node_reports_renames() {
+        let tmp = create_test_dir();
+        let dir = tmp.path().join("parent");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("child.txt"), "").unwrap();
+
+        let report = encode(&dir.to_string_lossy(), None, None).unwrap();
+        assert!(report.error.is_none());
+        assert_eq!(report.renamed.len(), 2);
+        assert!(report.rolled_back.is_empty());
+        assert_eq!(report.renamed[1].from, dir.to_string_lossy());
+    }
+
+    #[test]
+    fn test_preview_encode_node_touches_nothing() {
+        let tmp = create_test_dir();
+        let dir = tmp.path().join("parent");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("child.txt"), "").unwrap();
+
+        let preview = preview_node(
+            &dir.to_string_lossy(),
+            Direction::Encode(default_codec()),
+            CollisionPolicy::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(preview.renames.len(), 2);
+        assert_eq!(preview.renames[0].old_name, "child.txt");
+        assert_eq!(
+            preview.renames[0].new_name,
+            encode_name_with(default_codec(), "child.txt")
+        );
+        assert!(preview.issues.is_empty());
+        assert!(dir.join("child.txt").exists());
     }
 
     // --- decode_node ---
@@ -562,7 +1197,7 @@ mod tests {
         let file = tmp.path().join(".dat_VGVzdA==");
         fs::write(&file, "content").unwrap();
 
-        let entry = decode_node(file.to_string_lossy().to_string()).unwrap();
+        let entry = decode(&file.to_string_lossy()).unwrap().entry.unwrap();
         assert!(!entry.is_encoded);
         assert_eq!(entry.name, "Test");
         assert_eq!(entry.physical_name, "Test");
@@ -577,7 +1212,7 @@ mod tests {
         let file = tmp.path().join("plain.txt");
         fs::write(&file, "content").unwrap();
 
-        let entry = decode_node(file.to_string_lossy().to_string()).unwrap();
+        let entry = decode(&file.to_string_lossy()).unwrap().entry.unwrap();
         assert_eq!(entry.physical_name, "plain.txt");
         assert!(file.exists());
     }
@@ -589,7 +1224,7 @@ mod tests {
         fs::create_dir(&dir).unwrap();
         fs::write(dir.join(".dat_Y2hpbGQudHh0"), "").unwrap();
 
-        let entry = decode_node(dir.to_string_lossy().to_string()).unwrap();
+        let entry = decode(&dir.to_string_lossy()).unwrap().entry.unwrap();
         assert!(!entry.is_encoded);
         assert_eq!(entry.name, "parent");
 
@@ -601,6 +1236,105 @@ mod tests {
         assert_eq!(children, vec!["child.txt"]);
     }
 
+    #[test]
+    fn test_preview_decode_node_flags_collision() {
+        let tmp = create_test_dir();
+        fs::write(tmp.path().join(".dat_VGVzdA=="), "encoded").unwrap();
+        fs::write(tmp.path().join("Test"), "plain").unwrap();
+
+        let path = tmp
+            .path()
+            .join(".dat_VGVzdA==")
+            .to_string_lossy()
+            .to_string();
+        let preview =
+            preview_node(&path, Direction::Decode, CollisionPolicy::default(), None).unwrap();
+        assert_eq!(preview.renames.len(), 1);
+        assert_eq!(preview.issues.len(), 1);
+        assert_eq!(preview.issues[0].path, path);
+        assert_eq!(preview.issues[0].kind, PlanIssueKind::Collision);
+    }
+
+    #[test]
+    fn test_decode_node_collision_leaves_tree_untouched() {
+        let tmp = create_test_dir();
+        let dir = tmp.path().join(".dat_cGFyZW50");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join(".dat_VGVzdA=="), "encoded").unwrap();
+        fs::write(dir.join("Test"), "plain").unwrap();
+
+        let report = decode(&dir.to_string_lossy()).unwrap();
+        assert!(report.error.is_some());
+        assert!(report.renamed.is_empty());
+        assert_eq!(report.collisions.len(), 1);
+        assert!(dir.join(".dat_VGVzdA==").exists());
+        assert_eq!(fs::read_to_string(dir.join("Test")).unwrap(), "plain");
+    }
+
+    #[test]
+    fn test_decode_node_collision_skip() {
+        let tmp = create_test_dir();
+        let dir = tmp.path().join(".dat_cGFyZW50");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join(".dat_VGVzdA=="), "encoded").unwrap();
+        fs::write(dir.join("Test"), "plain").unwrap();
+
+        let report = convert_node(
+            &dir.to_string_lossy(),
+            Direction::Decode,
+            CollisionPolicy::Skip,
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(report.error.is_none());
+        assert_eq!(
+            report.collisions[0].resolution,
+            CollisionResolution::Skipped
+        );
+
+        let decoded_dir = tmp.path().join("parent");
+        assert!(decoded_dir.join(".dat_VGVzdA==").exists());
+        assert_eq!(
+            fs::read_to_string(decoded_dir.join("Test")).unwrap(),
+            "plain"
+        );
+    }
+
+    // --- migrate_encoded_names ---
+
+    #[test]
+    fn test_migrate_encoded_names() {
+        let tmp = create_test_dir();
+        let dir = tmp.path().join(".dat_cGFyZW50");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join(".dat_VGVzdA=="), "content").unwrap();
+
+        let migration = Direction::Reencode {
+            from: legacy_codec(),
+            to: default_codec(),
+        };
+        let entry = convert_node(
+            &dir.to_string_lossy(),
+            migration,
+            CollisionPolicy::default(),
+            None,
+            None,
+        )
+        .unwrap()
+        .entry
+        .unwrap();
+        assert_eq!(entry.physical_name, ".dat2_cGFyZW50");
+        assert_eq!(entry.name, "parent");
+
+        let migrated = Path::new(&entry.path);
+        assert_eq!(
+            fs::read_to_string(migrated.join(".dat2_VGVzdA")).unwrap(),
+            "content"
+        );
+        assert!(!migrated.join(".dat_VGVzdA==").exists());
+    }
+
     // --- encode then decode roundtrip ---
 
     #[test]
@@ -611,11 +1345,14 @@ mod tests {
         fs::write(dir.join("file.txt"), "hello").unwrap();
 
         // Encode
-        let encoded = encode_node(dir.to_string_lossy().to_string()).unwrap();
+        let encoded = encode(&dir.to_string_lossy(), None, None)
+            .unwrap()
+            .entry
+            .unwrap();
         assert!(encoded.is_encoded);
 
         // Decode
-        let decoded = decode_node(encoded.path.clone()).unwrap();
+        let decoded = decode(&encoded.path).unwrap().entry.unwrap();
         assert!(!decoded.is_encoded);
         assert_eq!(decoded.name, "my_folder");
 
@@ -624,6 +1361,34 @@ mod tests {
         assert_eq!(fs::read_to_string(restored_file).unwrap(), "hello");
     }
 
+    #[test]
+    fn test_encode_is_journaled_and_undone() {
+        let tmp = create_test_dir();
+        let dir = tmp.path().join("my_folder");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("file.txt"), "hello").unwrap();
+        let journal = JournalState(std::sync::Mutex::new(
+            rusqlite::Connection::open_in_memory().unwrap(),
+        ));
+        journal::init_schema(&journal.0.lock().unwrap()).unwrap();
+
+        let path = dir.to_string_lossy();
+        convert_node(
+            &path,
+            Direction::Encode(default_codec()),
+            CollisionPolicy::default(),
+            None,
+            Some(&journal),
+        )
+        .unwrap();
+        assert!(!dir.exists());
+
+        let conn = journal.0.lock().unwrap();
+        let undone = journal::undo_last(&conn).unwrap();
+        assert_eq!(undone.kind, OperationKind::Encode);
+        assert_eq!(fs::read_to_string(dir.join("file.txt")).unwrap(), "hello");
+    }
+
     // --- rename_node ---
 
     #[test]
@@ -632,11 +1397,17 @@ mod tests {
         let file = tmp.path().join("old.txt");
         fs::write(&file, "content").unwrap();
 
-        let entry = rename_node(
-            file.to_string_lossy().to_string(),
-            "new.txt".to_string(),
+        let entry = rename_path(
+            &file,
+            "new.txt",
             false,
+            None,
+            CollisionPolicy::default(),
+            None,
+            None,
         )
+        .unwrap()
+        .entry
         .unwrap();
         assert_eq!(entry.name, "new.txt");
         assert!(!entry.is_encoded);
@@ -650,15 +1421,217 @@ mod tests {
         let file = tmp.path().join("old.txt");
         fs::write(&file, "content").unwrap();
 
-        let entry = rename_node(
-            file.to_string_lossy().to_string(),
-            "new.txt".to_string(),
+        let entry = rename_path(
+            &file,
+            "new.txt",
             true,
+            None,
+            CollisionPolicy::default(),
+            None,
+            None,
         )
+        .unwrap()
+        .entry
         .unwrap();
         assert_eq!(entry.name, "new.txt");
         assert!(entry.is_encoded);
-        assert!(entry.physical_name.starts_with(".dat_"));
+        assert!(entry.physical_name.starts_with(".dat2_"));
+    }
+
+    #[test]
+    fn test_rename_node_collision_fails_by_default() {
+        let tmp = create_test_dir();
+        let file = tmp.path().join("old.txt");
+        fs::write(&file, "old").unwrap();
+        fs::write(tmp.path().join("new.txt"), "existing").unwrap();
+
+        let report = rename_path(
+            &file,
+            "new.txt",
+            false,
+            None,
+            CollisionPolicy::default(),
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(report.error.is_some());
+        assert_eq!(report.collisions.len(), 1);
+        assert_eq!(report.collisions[0].resolution, CollisionResolution::Failed);
+        assert!(file.exists());
+        assert_eq!(
+            fs::read_to_string(tmp.path().join("new.txt")).unwrap(),
+            "existing"
+        );
+    }
+
+    #[test]
+    fn test_rename_node_collision_suffix() {
+        let tmp = create_test_dir();
+        let file = tmp.path().join("old.txt");
+        fs::write(&file, "old").unwrap();
+        fs::write(tmp.path().join("new.txt"), "existing").unwrap();
+
+        let entry = rename_path(
+            &file,
+            "new.txt",
+            false,
+            None,
+            CollisionPolicy::Suffix,
+            None,
+            None,
+        )
+        .unwrap()
+        .entry
+        .unwrap();
+        assert_eq!(entry.name, "new (2).txt");
+        assert_eq!(
+            fs::read_to_string(tmp.path().join("new.txt")).unwrap(),
+            "existing"
+        );
+    }
+
+    // --- over-long names (sidecar index) ---
+
+    #[test]
+    fn test_encode_node_long_name_uses_sidecar() {
+        let tmp = create_test_dir();
+        let long_name = format!("{}.mkv", "A very long title ".repeat(11));
+        fs::write(tmp.path().join(&long_name), "").unwrap();
+        let dir = tmp.path().to_string_lossy().to_string();
+
+        let entry = encode(&tmp.path().join(&long_name).to_string_lossy(), None, None)
+            .unwrap()
+            .entry
+            .unwrap();
+        assert!(entry.physical_name.starts_with(".dath_"));
+        assert!(entry.is_encoded);
+        assert_eq!(entry.name, long_name);
+        assert!(tmp.path().join(INDEX_FILE_NAME).exists());
+
+        // The sidecar itself stays hidden
+        let entries = list_directory(Path::new(&dir), None, &Exclusions::default()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, long_name);
+
+        let results = find(Path::new(&dir), "long title", &Exclusions::default());
+        assert_eq!(results.len(), 1);
+
+        let report = decode(&entry.path).unwrap();
+        assert_eq!(report.entry.unwrap().physical_name, long_name);
+        assert!(!tmp.path().join(INDEX_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn test_encode_directory_keeps_sidecar() {
+        let tmp = create_test_dir();
+        let dir = tmp.path().join("show");
+        fs::create_dir(&dir).unwrap();
+        let long_name = format!("{}.mkv", "A very long title ".repeat(11));
+        fs::write(dir.join(&long_name), "").unwrap();
+        fs::write(dir.join("short.mkv"), "").unwrap();
+        encode(&dir.join(&long_name).to_string_lossy(), None, None).unwrap();
+
+        let report = encode(&dir.to_string_lossy(), None, None).unwrap();
+        assert!(report.error.is_none());
+        let encoded_dir = std::path::PathBuf::from(report.entry.unwrap().path);
+        assert!(encoded_dir.join(INDEX_FILE_NAME).exists());
+        let mut names: Vec<String> = list_directory(&encoded_dir, None, &Exclusions::default())
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        names.sort();
+        assert_eq!(names, [long_name.clone(), "short.mkv".to_string()]);
+
+        decode(&encoded_dir.to_string_lossy()).unwrap();
+        assert!(dir.join(&long_name).exists());
+        assert!(dir.join("short.mkv").exists());
+        assert!(!dir.join(INDEX_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn test_sidecar_follows_rename_move_and_delete() {
+        let tmp = create_test_dir();
+        let long_name = "x".repeat(200);
+        let source_dir = tmp.path().join("source");
+        let target_dir = tmp.path().join("target");
+        fs::create_dir(&source_dir).unwrap();
+        fs::create_dir(&target_dir).unwrap();
+        fs::write(source_dir.join("short"), "").unwrap();
+
+        let report = rename_path(
+            &source_dir.join("short"),
+            &long_name,
+            true,
+            None,
+            CollisionPolicy::default(),
+            None,
+            None,
+        )
+        .unwrap();
+        let hashed = report.entry.unwrap();
+        assert!(hashed.physical_name.starts_with(".dath_"));
+        assert_eq!(hashed.name, long_name);
+
+        let moved = move_path(
+            Path::new(&hashed.path),
+            &target_dir,
+            false,
+            None,
+            None,
+            &mut |_| {},
+        )
+        .unwrap();
+        assert_eq!(moved.name, long_name);
+        assert!(!source_dir.join(INDEX_FILE_NAME).exists());
+        assert!(target_dir.join(INDEX_FILE_NAME).exists());
+
+        // Kept while the entry is in the trash, so it can be restored
+        delete_node_with(moved.path, test_trash(&tmp), None).unwrap();
+        assert!(target_dir.join(INDEX_FILE_NAME).exists());
+    }
+
+    // --- encrypt_node / decrypt_node ---
+
+    #[test]
+    fn test_encrypt_node_directory_recursive() {
+        let tmp = create_test_dir();
+        let dir = tmp.path().join("videos");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.mp4"), "first").unwrap();
+        fs::write(dir.join("sub").join("b.mp4"), "second").unwrap();
+        let key = SessionKey::derive("secret", b"0123456789abcdef").unwrap();
+
+        crypt_node(&dir, Some(&key), true).unwrap();
+        assert!(content_crypto::is_encrypted_file(&dir.join("a.mp4")));
+        assert!(content_crypto::is_encrypted_file(
+            &dir.join("sub").join("b.mp4")
+        ));
+        // Listings report the size on disk without reading the header
+        let entry = build_file_entry(&dir.join("a.mp4"), None).unwrap();
+        assert_eq!(
+            entry.file_size,
+            fs::metadata(dir.join("a.mp4")).unwrap().len()
+        );
+        assert_eq!(content_crypto::plaintext_len(entry.file_size), 5);
+
+        crypt_node(&dir, Some(&key), false).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("a.mp4")).unwrap(), "first");
+        assert_eq!(
+            fs::read_to_string(dir.join("sub").join("b.mp4")).unwrap(),
+            "second"
+        );
+    }
+
+    #[test]
+    fn test_encrypt_node_requires_key() {
+        let tmp = create_test_dir();
+        let file = tmp.path().join("a.mp4");
+        fs::write(&file, "plain").unwrap();
+
+        assert!(crypt_node(&file, None, true).is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "plain");
     }
 
     // --- delete_node ---
@@ -669,8 +1642,9 @@ mod tests {
         let file = tmp.path().join("delete_me.txt");
         fs::write(&file, "").unwrap();
 
-        delete_node(file.to_string_lossy().to_string()).unwrap();
+        delete_node_with(file.to_string_lossy().to_string(), test_trash(&tmp), None).unwrap();
         assert!(!file.exists());
+        assert!(tmp.path().join("trash").join("delete_me.txt").exists());
     }
 
     #[test]
@@ -680,13 +1654,23 @@ mod tests {
         fs::create_dir(&dir).unwrap();
         fs::write(dir.join("child.txt"), "").unwrap();
 
-        delete_node(dir.to_string_lossy().to_string()).unwrap();
+        delete_node_with(dir.to_string_lossy().to_string(), test_trash(&tmp), None).unwrap();
         assert!(!dir.exists());
+        assert!(tmp
+            .path()
+            .join("trash")
+            .join("delete_dir")
+            .join("child.txt")
+            .exists());
     }
 
     #[test]
     fn test_delete_nonexistent() {
-        let result = delete_node("/nonexistent/path/file.txt".to_string());
+        let result = delete_node_with(
+            "/nonexistent/path/file.txt".to_string(),
+            |_| Ok(String::new()),
+            None,
+        );
         assert!(result.is_err());
     }
 
@@ -695,7 +1679,7 @@ mod tests {
     #[test]
     fn test_count_children_empty() {
         let tmp = create_test_dir();
-        let count = count_children(tmp.path().to_string_lossy().to_string()).unwrap();
+        let count = count_entries(tmp.path(), None, &Exclusions::default()).unwrap();
         assert_eq!(count, 0);
     }
 
@@ -706,7 +1690,7 @@ mod tests {
         fs::write(tmp.path().join("b.txt"), "").unwrap();
         fs::create_dir(tmp.path().join("sub")).unwrap();
 
-        let count = count_children(tmp.path().to_string_lossy().to_string()).unwrap();
+        let count = count_entries(tmp.path(), None, &Exclusions::default()).unwrap();
         assert_eq!(count, 3);
     }
 
@@ -716,10 +1700,40 @@ mod tests {
         let file = tmp.path().join("file.txt");
         fs::write(&file, "").unwrap();
 
-        let count = count_children(file.to_string_lossy().to_string()).unwrap();
+        let count = count_entries(&file, None, &Exclusions::default()).unwrap();
         assert_eq!(count, 0);
     }
 
+    #[test]
+    fn test_listing_search_and_count_apply_the_same_exclusions() {
+        let tmp = create_test_dir();
+        fs::write(tmp.path().join("movie.mkv"), "").unwrap();
+        fs::write(tmp.path().join(".hidden movie.mkv"), "").unwrap();
+        fs::write(tmp.path().join("movie.mkv.part"), "").unwrap();
+        fs::write(
+            tmp.path()
+                .join(encode_name_with(default_codec(), "encoded movie.mkv")),
+            "",
+        )
+        .unwrap();
+        let exclusions = Exclusions::new(ExclusionRules {
+            patterns: vec!["*.part".to_string()],
+            hide_dotfiles: true,
+            ignore_file: None,
+        })
+        .unwrap();
+
+        let path = tmp.path().to_string_lossy().to_string();
+        let listed = list_directory(Path::new(&path), None, &exclusions).unwrap();
+        let found = find(Path::new(&path), "movie", &exclusions);
+        let count = count_entries(Path::new(&path), None, &exclusions).unwrap();
+
+        let names: Vec<_> = listed.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["encoded movie.mkv", "movie.mkv"]);
+        assert_eq!(found.len(), 2);
+        assert_eq!(count, 2);
+    }
+
     // --- move_node ---
 
     #[test]
@@ -730,11 +1744,7 @@ mod tests {
         let target = tmp.path().join("target_dir");
         fs::create_dir(&target).unwrap();
 
-        let entry = move_node(
-            file.to_string_lossy().to_string(),
-            target.to_string_lossy().to_string(),
-        )
-        .unwrap();
+        let entry = move_path(&file, &target, false, None, None, &mut |_| {}).unwrap();
 
         assert!(!file.exists());
         assert_eq!(entry.name, "source.txt");
@@ -750,11 +1760,7 @@ mod tests {
         let target = tmp.path().join("target_dir");
         fs::create_dir(&target).unwrap();
 
-        move_node(
-            dir.to_string_lossy().to_string(),
-            target.to_string_lossy().to_string(),
-        )
-        .unwrap();
+        move_path(&dir, &target, false, None, None, &mut |_| {}).unwrap();
 
         assert!(!dir.exists());
         let moved = target.join("source_dir").join("child.txt");
@@ -770,10 +1776,7 @@ mod tests {
         fs::create_dir(&target).unwrap();
         fs::write(target.join("source.txt"), "").unwrap();
 
-        let result = move_node(
-            file.to_string_lossy().to_string(),
-            target.to_string_lossy().to_string(),
-        );
+        let result = move_path(&file, &target, false, None, None, &mut |_| {});
         assert!(result.is_err());
     }
 
@@ -790,9 +1793,11 @@ mod tests {
         fs::create_dir(&sub).unwrap();
         fs::write(sub.join("hello_nested.txt"), "").unwrap();
 
-        let results = search_files(tmp.path().to_string_lossy().to_string(), "hello".to_string()).unwrap();
+        let results = find(tmp.path(), "hello", &Exclusions::default());
         assert_eq!(results.len(), 2);
-        assert!(results.iter().all(|r| r.name.to_lowercase().contains("hello")));
+        assert!(results
+            .iter()
+            .all(|r| r.name.to_lowercase().contains("hello")));
     }
 
     #[test]
@@ -801,7 +1806,7 @@ mod tests {
         fs::write(tmp.path().join("Hello.TXT"), "").unwrap();
         fs::write(tmp.path().join("other.txt"), "").unwrap();
 
-        let results = search_files(tmp.path().to_string_lossy().to_string(), "hello".to_string()).unwrap();
+        let results = find(tmp.path(), "hello", &Exclusions::default());
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].name, "Hello.TXT");
     }
@@ -813,7 +1818,7 @@ mod tests {
         fs::write(tmp.path().join(".dat_VGVzdA=="), "").unwrap();
         fs::write(tmp.path().join("other.txt"), "").unwrap();
 
-        let results = search_files(tmp.path().to_string_lossy().to_string(), "test".to_string()).unwrap();
+        let results = find(tmp.path(), "test", &Exclusions::default());
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].name, "Test");
     }
@@ -823,7 +1828,7 @@ mod tests {
         let tmp = create_test_dir();
         fs::write(tmp.path().join(".gitignore"), "").unwrap();
 
-        let results = search_files(tmp.path().to_string_lossy().to_string(), "gitignore".to_string()).unwrap();
+        let results = find(tmp.path(), "gitignore", &Exclusions::default());
         assert_eq!(results.len(), 0);
     }
 
@@ -836,6 +1841,27 @@ mod tests {
         fs::write(&file, "").unwrap();
 
         let result = can_encode_node(file.to_string_lossy().to_string()).unwrap();
-        assert!(result);
+        assert!(result.can_encode);
+        assert_eq!(result.reason, None);
+    }
+
+    #[test]
+    fn test_can_encode_node_long_name_reports_component() {
+        let tmp = create_test_dir();
+        let name = "a".repeat(200);
+        let file = tmp.path().join(&name);
+        fs::write(&file, "").unwrap();
+
+        let result = can_encode_node(file.to_string_lossy().to_string()).unwrap();
+        assert!(!result.can_encode);
+        match result.reason {
+            Some(LengthIssue::ComponentTooLong {
+                component, limit, ..
+            }) => {
+                assert_eq!(component, name);
+                assert_eq!(limit, result.limits.max_component_len);
+            }
+            other => panic!("unexpected reason: {:?}", other),
+        }
     }
 }
@@ -0,0 +1,65 @@
+use std::path::Path;
+
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::models::search::{IndexFailure, IndexedDirectory};
+use crate::search_index::SearchIndexState;
+use crate::transfer::CANCELLED;
+
+pub const SEARCH_INDEX_READY_EVENT: &str = "search-index-ready";
+pub const SEARCH_INDEX_FAILED_EVENT: &str = "search-index-failed";
+
+/// Adds `path` to the search index. The tree is scanned in the background;
+/// a `search-index-ready` event with the directory follows once searches
+/// below it use the index. From then on the index follows changes to it.
+/// If the scan fails, the directory is dropped again and a
+/// `search-index-failed` event says why.
+#[tauri::command]
+pub fn index_directory(path: String, app: AppHandle) -> Result<(), String> {
+    if !Path::new(&path).is_dir() {
+        return Err(format!("Not a directory: {}", path));
+    }
+    scan_in_background(app, path);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn remove_index(path: String, index: State<'_, SearchIndexState>) -> Result<(), String> {
+    index.remove(Path::new(&path))
+}
+
+#[tauri::command]
+pub fn list_indexed_directories(
+    index: State<'_, SearchIndexState>,
+) -> Result<Vec<IndexedDirectory>, String> {
+    index.roots()
+}
+
+/// Brings the indexed directories up to date after a start, re-reading only
+/// the directories that changed while the app wasn't running.
+pub fn resume_indexing(app: &AppHandle) -> Result<(), String> {
+    for root in app.state::<SearchIndexState>().roots()? {
+        scan_in_background(app.clone(), root.path);
+    }
+    Ok(())
+}
+
+fn scan_in_background(app: AppHandle, path: String) {
+    tauri::async_runtime::spawn_blocking(move || {
+        let index = app.state::<SearchIndexState>();
+        match index.index(Path::new(&path)) {
+            Ok(()) => {
+                let _ = app.emit(
+                    SEARCH_INDEX_READY_EVENT,
+                    IndexedDirectory { path, ready: true },
+                );
+            }
+            // Removed while it was being scanned
+            Err(e) if e == CANCELLED => {}
+            Err(message) => {
+                let _ = index.remove(Path::new(&path));
+                let _ = app.emit(SEARCH_INDEX_FAILED_EVENT, IndexFailure { path, message });
+            }
+        }
+    });
+}
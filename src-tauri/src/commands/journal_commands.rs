@@ -0,0 +1,39 @@
+use tauri::State;
+
+use crate::journal::{self, JournalState};
+use crate::models::operation::OperationRecord;
+
+const DEFAULT_HISTORY_LIMIT: usize = 100;
+
+/// Reverts the most recent operation. Refused without touching anything if
+/// an entry it involved was changed, replaced or removed since.
+#[tauri::command]
+pub fn undo_last_operation(journal: State<'_, JournalState>) -> Result<OperationRecord, String> {
+    let conn = journal
+        .0
+        .lock()
+        .map_err(|e| format!("Journal lock error: {}", e))?;
+    journal::undo_last(&conn)
+}
+
+#[tauri::command]
+pub fn redo_operation(journal: State<'_, JournalState>) -> Result<OperationRecord, String> {
+    let conn = journal
+        .0
+        .lock()
+        .map_err(|e| format!("Journal lock error: {}", e))?;
+    journal::redo(&conn)
+}
+
+/// Most recent operations first.
+#[tauri::command]
+pub fn list_operation_history(
+    limit: Option<usize>,
+    journal: State<'_, JournalState>,
+) -> Result<Vec<OperationRecord>, String> {
+    let conn = journal
+        .0
+        .lock()
+        .map_err(|e| format!("Journal lock error: {}", e))?;
+    journal::history(&conn, limit.unwrap_or(DEFAULT_HISTORY_LIMIT))
+}
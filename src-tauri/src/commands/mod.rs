@@ -1,2 +1,14 @@
+pub mod batch_commands;
+pub mod bulk_rename_commands;
+pub mod copy_commands;
+pub mod directory_page_commands;
+pub mod exclusion_commands;
 pub mod fs_commands;
+pub mod index_commands;
+pub mod journal_commands;
+pub mod search_commands;
+pub mod session_commands;
+pub mod trash_commands;
+pub mod verify_commands;
 pub mod video_commands;
+pub mod watch_commands;
@@ -0,0 +1,131 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::exclusions::ExclusionState;
+use crate::jobs::JobRegistry;
+use crate::models::file_entry::FileEntry;
+use crate::models::search::{SearchError, SearchProgress, SearchResults};
+use crate::search::{search, SearchEvent, SearchOptions};
+use crate::search_index::SearchIndexState;
+use crate::search_query::Query;
+use crate::session::SessionState;
+
+pub const SEARCH_RESULTS_EVENT: &str = "search-results";
+pub const SEARCH_PROGRESS_EVENT: &str = "search-progress";
+
+/// Results stop after this many matches unless the caller asks for more.
+pub const DEFAULT_MAX_RESULTS: usize = 1000;
+/// How long matches are held back to be sent together.
+const BATCH_INTERVAL: Duration = Duration::from_millis(100);
+const BATCH_SIZE: usize = 200;
+
+/// Running searches, by the id the frontend started them with.
+pub type SearchJobs = JobRegistry<()>;
+
+/// Starts searching the tree below `path` and returns right away, or with
+/// the error if `pattern` isn't a valid query. The frontend picks
+/// `search_id` before starting, so it knows the events of the search even if
+/// they arrive before this returns. Matches arrive in `search-results`
+/// events and the search ends with a `search-progress` event that has
+/// `finished` set. Directories in the search index are searched there
+/// instead of being walked.
+#[tauri::command]
+pub fn start_search(
+    search_id: String,
+    path: String,
+    pattern: String,
+    max_results: Option<usize>,
+    app: AppHandle,
+    session: State<'_, SessionState>,
+    exclusions: State<'_, ExclusionState>,
+) -> Result<(), SearchError> {
+    let query = Query::parse(&pattern)?;
+    let job = app.state::<SearchJobs>().start(&search_id)?;
+    let key = session.key();
+    let exclusions = exclusions.get();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut batch = Batch::new(&app, &search_id);
+        let options = SearchOptions {
+            key: key.as_ref(),
+            exclusions: &exclusions,
+            max_results: Some(max_results.unwrap_or(DEFAULT_MAX_RESULTS)),
+            is_cancelled: &|| job.is_cancelled(),
+        };
+        let result = search(
+            Some(&app.state::<SearchIndexState>()),
+            Path::new(&path),
+            &query,
+            &options,
+            &mut |event, progress| batch.add(event, progress),
+        );
+        let progress = result.unwrap_or_else(|e| SearchProgress {
+            finished: true,
+            error: Some(e),
+            ..batch.progress.clone()
+        });
+        batch.flush(progress);
+        app.state::<SearchJobs>().finish(&search_id);
+    });
+
+    Ok(())
+}
+
+/// Stops a running search. Searches that already finished are ignored.
+#[tauri::command]
+pub fn cancel_search(search_id: String, jobs: State<'_, SearchJobs>) -> Result<(), String> {
+    if let Ok(job) = jobs.get(&search_id) {
+        job.cancel();
+    }
+    Ok(())
+}
+
+/// Collects matches and sends them with the progress every
+/// `BATCH_INTERVAL` or `BATCH_SIZE` matches, whichever comes first.
+struct Batch<'a> {
+    app: &'a AppHandle,
+    search_id: &'a str,
+    entries: Vec<FileEntry>,
+    progress: SearchProgress,
+    sent_at: Instant,
+}
+
+impl<'a> Batch<'a> {
+    fn new(app: &'a AppHandle, search_id: &'a str) -> Self {
+        Batch {
+            app,
+            search_id,
+            entries: Vec::new(),
+            progress: SearchProgress::default(),
+            sent_at: Instant::now(),
+        }
+    }
+
+    fn add(&mut self, event: SearchEvent, progress: &SearchProgress) {
+        if let SearchEvent::Match(entry) = event {
+            self.entries.push(*entry);
+        }
+        if self.entries.len() >= BATCH_SIZE || self.sent_at.elapsed() >= BATCH_INTERVAL {
+            self.flush(progress.clone());
+        }
+        self.progress = progress.clone();
+    }
+
+    fn flush(&mut self, progress: SearchProgress) {
+        if !self.entries.is_empty() {
+            let results = SearchResults {
+                search_id: self.search_id.to_string(),
+                entries: std::mem::take(&mut self.entries),
+            };
+            let _ = self.app.emit(SEARCH_RESULTS_EVENT, results);
+        }
+        let progress = SearchProgress {
+            search_id: self.search_id.to_string(),
+            ..progress
+        };
+        let _ = self.app.emit(SEARCH_PROGRESS_EVENT, progress);
+        self.sent_at = Instant::now();
+    }
+}
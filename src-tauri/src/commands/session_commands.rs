@@ -0,0 +1,51 @@
+use std::path::Path;
+
+use tauri::State;
+
+use crate::encoding::keyring;
+use crate::encoding::name_crypto::SessionKey;
+use crate::session::SessionState;
+
+/// Derives the keys for encrypted names and contents of the library `path`
+/// belongs to and keeps them for the rest of the session. Fails on a wrong
+/// passphrase or outside of any library; see `keyring::unlock`.
+#[tauri::command]
+pub fn unlock_session(
+    path: String,
+    passphrase: String,
+    session: State<'_, SessionState>,
+) -> Result<(), String> {
+    let key = keyring::unlock(Path::new(&path), &passphrase)?;
+    set_key(&session, Some(key))
+}
+
+/// Makes the directory the root of a new library protected by `passphrase`
+/// and unlocks the session with it.
+#[tauri::command]
+pub fn create_keyring(
+    path: String,
+    passphrase: String,
+    session: State<'_, SessionState>,
+) -> Result<(), String> {
+    let key = keyring::create(Path::new(&path), &passphrase)?;
+    set_key(&session, Some(key))
+}
+
+#[tauri::command]
+pub fn lock_session(session: State<'_, SessionState>) -> Result<(), String> {
+    set_key(&session, None)
+}
+
+#[tauri::command]
+pub fn is_session_unlocked(session: State<'_, SessionState>) -> bool {
+    session.key().is_some()
+}
+
+fn set_key(session: &SessionState, key: Option<SessionKey>) -> Result<(), String> {
+    let mut slot = session
+        .0
+        .write()
+        .map_err(|e| format!("Session lock error: {}", e))?;
+    *slot = key;
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use std::cmp::Reverse;
+use std::fs;
+
+use tauri::State;
+
+use super::fs_commands::build_file_entry;
+use crate::encoding::encoded_dir::{locked_placeholder, name_state_in, NameState};
+use crate::encoding::name_crypto::SessionKey;
+use crate::encoding::name_index::{self, is_hashed_name};
+use crate::models::file_entry::FileEntry;
+use crate::models::trash_entry::TrashEntry;
+use crate::session::SessionState;
+use crate::trash::{self, TrashedItem};
+
+#[tauri::command]
+pub fn list_trash(session: State<'_, SessionState>) -> Result<Vec<TrashEntry>, String> {
+    let key = session.key();
+    let mut entries: Vec<TrashEntry> = trash::list()?
+        .into_iter()
+        .map(|item| trash_entry_for(item, key.as_ref()))
+        .collect();
+    entries.sort_by_key(|entry| Reverse(entry.deleted_at));
+    Ok(entries)
+}
+
+#[tauri::command]
+pub fn restore_from_trash(
+    id: String,
+    session: State<'_, SessionState>,
+) -> Result<FileEntry, String> {
+    let restored = trash::restore(&id)?;
+    build_file_entry(&restored, session.key().as_ref())
+}
+
+/// Permanently deletes everything in the trash. Hashed names are dropped
+/// from the sidecar index of the directory they were trashed from, which
+/// keeps them while the entry can still be restored. Hashed names are
+/// deterministic, so an entry is kept while a node of that name is back in
+/// the directory.
+#[tauri::command]
+pub fn empty_trash() -> Result<(), String> {
+    let items = trash::list()?;
+    trash::empty()?;
+    for item in items {
+        forget_trashed_name(&item);
+    }
+    Ok(())
+}
+
+/// The trash is already emptied when this runs, so failing to drop the
+/// entry only leaves it unused in the sidecar index.
+fn forget_trashed_name(item: &TrashedItem) {
+    let Some(dir) = item.original_path.parent() else {
+        return;
+    };
+    if is_hashed_name(&item.physical_name)
+        && dir.is_dir()
+        && fs::symlink_metadata(&item.original_path).is_err()
+    {
+        let _ = name_index::forget_name(dir, &item.physical_name);
+    }
+}
+
+/// Hashed names are looked up in the sidecar index of the directory the
+/// entry was trashed from.
+fn trash_entry_for(item: TrashedItem, key: Option<&SessionKey>) -> TrashEntry {
+    let dir = item.original_path.parent().unwrap_or(&item.original_path);
+    let state = name_state_in(dir, &item.physical_name, key);
+    let (name, is_encoded, is_locked) = match state {
+        NameState::Decoded(decoded) => (decoded, true, false),
+        NameState::Locked => (locked_placeholder(&item.physical_name), true, true),
+        NameState::Plain => (item.physical_name.clone(), false, false),
+    };
+    TrashEntry {
+        id: item.id,
+        name,
+        physical_name: item.physical_name,
+        is_encoded,
+        is_locked,
+        is_directory: item.is_directory,
+        original_path: item.original_path.to_string_lossy().to_string(),
+        deleted_at: item.deleted_at,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::encoded_dir::encode_name_with;
+    use crate::encoding::name_codec::default_codec;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_trash_entry_decodes_name() {
+        let item = TrashedItem {
+            id: "1".to_string(),
+            physical_name: encode_name_with(default_codec(), "Movie.mkv"),
+            original_path: PathBuf::from("/videos")
+                .join(encode_name_with(default_codec(), "Movie.mkv")),
+            deleted_at: Some(10),
+            is_directory: false,
+        };
+
+        let entry = trash_entry_for(item, None);
+        assert_eq!(entry.name, "Movie.mkv");
+        assert!(entry.is_encoded);
+        assert!(!entry.is_locked);
+    }
+
+    #[test]
+    fn test_emptied_names_are_kept_while_recreated() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let trashed = |name: &str| TrashedItem {
+            id: name.to_string(),
+            physical_name: name.to_string(),
+            original_path: tmp.path().join(name),
+            deleted_at: None,
+            is_directory: false,
+        };
+        name_index::record_name(tmp.path(), ".dath_gone", "Gone.mkv").unwrap();
+        name_index::record_name(tmp.path(), ".dath_back", "Back.mkv").unwrap();
+        fs::write(tmp.path().join(".dath_back"), "").unwrap();
+
+        forget_trashed_name(&trashed(".dath_gone"));
+        forget_trashed_name(&trashed(".dath_back"));
+
+        assert_eq!(name_index::lookup_name(tmp.path(), ".dath_gone"), None);
+        assert_eq!(
+            name_index::lookup_name(tmp.path(), ".dath_back"),
+            Some("Back.mkv".to_string())
+        );
+    }
+}
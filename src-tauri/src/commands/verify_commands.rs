@@ -0,0 +1,277 @@
+use std::fs;
+use std::path::Path;
+
+use tauri::State;
+
+use crate::encoding::encoded_dir::name_state_in;
+use crate::encoding::name_codec::{codec_for_name, NameCodec};
+use crate::encoding::name_crypto::{EncryptedNameCodec, SessionKey, ENCRYPTED_PREFIX};
+use crate::encoding::name_index::{is_hashed_name, lookup_name};
+use crate::exclusions::{DirExclusions, ExclusionState, Exclusions};
+use crate::models::verify_report::{TreeIssue, TreeIssueKind, VerifyReport};
+use crate::session::SessionState;
+
+/// What checking a single physical name found.
+#[derive(Debug, PartialEq)]
+enum NameCheck {
+    Plain,
+    Encoded,
+    /// Encrypted, and the session is locked
+    Locked,
+    Undecodable(String),
+    Unsafe(String),
+}
+
+impl NameCheck {
+    fn is_encoded(&self) -> bool {
+        !matches!(self, NameCheck::Plain)
+    }
+}
+
+/// Walks the tree below `path` and reports names that carry an encoding
+/// prefix but don't decode, directories that mix encoded and plain names,
+/// and names that decode to something unsafe to use as a file name.
+/// Entries left out by the exclusion rules are not checked.
+#[tauri::command]
+pub fn verify_tree(
+    path: String,
+    session: State<'_, SessionState>,
+    exclusions: State<'_, ExclusionState>,
+) -> Result<VerifyReport, String> {
+    check_tree(Path::new(&path), session.key().as_ref(), &exclusions.get())
+}
+
+fn check_tree(
+    dir_path: &Path,
+    key: Option<&SessionKey>,
+    exclusions: &Exclusions,
+) -> Result<VerifyReport, String> {
+    if !dir_path.is_dir() {
+        return Err(format!("Not a directory: {}", dir_path.display()));
+    }
+
+    let mut report = VerifyReport::default();
+    verify_dir(
+        dir_path,
+        None,
+        key,
+        &exclusions.in_dir(dir_path, key),
+        &mut report,
+    )?;
+    Ok(report)
+}
+
+/// `own_check` is the check of the directory's own name, which counts
+/// towards it being partially encoded; None for the root of the walk.
+fn verify_dir(
+    dir: &Path,
+    own_check: Option<&NameCheck>,
+    key: Option<&SessionKey>,
+    rules: &DirExclusions,
+    report: &mut VerifyReport,
+) -> Result<(), String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read directory: {}", e))?;
+    let mut encoded = own_check.map_or(0, |c| c.is_encoded() as usize);
+    let mut total = own_check.map_or(0, |_| 1);
+
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+        if rules.is_excluded(&name, &name_state_in(dir, &name, key), is_dir) {
+            continue;
+        }
+
+        let entry_path = entry.path();
+        let check = check_name(dir, &name, key);
+        report.counts.entries += 1;
+        total += 1;
+        encoded += check.is_encoded() as usize;
+
+        let issue = match &check {
+            NameCheck::Undecodable(detail) => {
+                report.counts.undecodable += 1;
+                Some((TreeIssueKind::Undecodable, detail.clone()))
+            }
+            NameCheck::Unsafe(detail) => {
+                report.counts.unsafe_names += 1;
+                Some((TreeIssueKind::UnsafeName, detail.clone()))
+            }
+            NameCheck::Locked => {
+                report.counts.locked += 1;
+                None
+            }
+            NameCheck::Plain | NameCheck::Encoded => None,
+        };
+        if let Some((kind, detail)) = issue {
+            report.issues.push(TreeIssue {
+                path: entry_path.to_string_lossy().to_string(),
+                physical_name: name.clone(),
+                kind,
+                detail,
+            });
+        }
+
+        if is_dir {
+            verify_dir(
+                &entry_path,
+                Some(&check),
+                key,
+                &rules.subdir(&entry_path),
+                report,
+            )?;
+        }
+    }
+
+    if encoded > 0 && encoded < total {
+        report.counts.partially_encoded += 1;
+        report.issues.push(TreeIssue {
+            path: dir.to_string_lossy().to_string(),
+            physical_name: dir
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            kind: TreeIssueKind::PartiallyEncoded,
+            detail: format!("{} of {} names encoded", encoded, total),
+        });
+    }
+
+    Ok(())
+}
+
+fn check_name(dir: &Path, name: &str, key: Option<&SessionKey>) -> NameCheck {
+    if is_hashed_name(name) {
+        return match lookup_name(dir, name) {
+            Some(decoded) => check_decoded(&decoded),
+            None => NameCheck::Undecodable("missing from the sidecar index".to_string()),
+        };
+    }
+
+    if let Some(payload) = name.strip_prefix(ENCRYPTED_PREFIX) {
+        return match key {
+            Some(key) => check_payload(&EncryptedNameCodec::new(key.clone()), payload),
+            None => NameCheck::Locked,
+        };
+    }
+
+    match codec_for_name(name) {
+        Some(codec) => check_payload(codec, &name[codec.prefix().len()..]),
+        None => NameCheck::Plain,
+    }
+}
+
+fn check_payload(codec: &dyn NameCodec, payload: &str) -> NameCheck {
+    match codec.decode_bytes(payload) {
+        None => NameCheck::Undecodable(format!("not valid {}", codec.id())),
+        Some(bytes) => match String::from_utf8(bytes) {
+            Ok(decoded) => check_decoded(&decoded),
+            Err(_) => NameCheck::Undecodable("decodes to invalid UTF-8".to_string()),
+        },
+    }
+}
+
+fn check_decoded(decoded: &str) -> NameCheck {
+    let reason = if decoded.is_empty() {
+        Some("decodes to an empty name")
+    } else if decoded == "." || decoded == ".." {
+        Some("decodes to a relative path component")
+    } else if decoded.contains(['/', '\\']) {
+        Some("contains a path separator")
+    } else if decoded.chars().any(char::is_control) {
+        Some("contains control characters")
+    } else {
+        None
+    };
+    match reason {
+        Some(reason) => NameCheck::Unsafe(reason.to_string()),
+        None => NameCheck::Encoded,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::encoded_dir::encode_name_with;
+    use crate::encoding::name_codec::default_codec;
+    use crate::encoding::name_codec::legacy_codec;
+    use tempfile::TempDir;
+
+    fn verify(tmp: &TempDir) -> VerifyReport {
+        check_tree(tmp.path(), None, &Exclusions::default()).unwrap()
+    }
+
+    #[test]
+    fn test_verify_clean_tree() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp
+            .path()
+            .join(encode_name_with(default_codec(), "Season 1"));
+        fs::create_dir(&dir).unwrap();
+        fs::write(
+            dir.join(encode_name_with(default_codec(), "Episode 1.mkv")),
+            "",
+        )
+        .unwrap();
+        fs::write(tmp.path().join(".gitignore"), "").unwrap();
+
+        let report = verify(&tmp);
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+        assert_eq!(report.counts.entries, 2);
+    }
+
+    #[test]
+    fn test_verify_reports_undecodable_names() {
+        let tmp = TempDir::new().unwrap();
+        // Not base64 at all
+        fs::write(tmp.path().join(".dat_not*base64"), "").unwrap();
+        // Valid base64 of the bytes FF EE, which aren't UTF-8
+        fs::write(tmp.path().join(".dat2__-4"), "").unwrap();
+
+        let report = verify(&tmp);
+        assert_eq!(report.counts.undecodable, 2);
+        let details: Vec<_> = report.issues.iter().map(|i| i.detail.as_str()).collect();
+        assert!(details.contains(&"not valid base64"));
+        assert!(details.contains(&"decodes to invalid UTF-8"));
+    }
+
+    #[test]
+    fn test_verify_reports_partially_encoded_dirs() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp
+            .path()
+            .join(encode_name_with(default_codec(), "Season 1"));
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("Episode 1.mkv"), "").unwrap();
+        fs::write(
+            dir.join(encode_name_with(default_codec(), "Episode 2.mkv")),
+            "",
+        )
+        .unwrap();
+
+        let report = verify(&tmp);
+        assert_eq!(report.counts.partially_encoded, 1);
+        assert_eq!(report.issues[0].kind, TreeIssueKind::PartiallyEncoded);
+        assert_eq!(report.issues[0].detail, "2 of 3 names encoded");
+    }
+
+    #[test]
+    fn test_verify_reports_unsafe_names() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join(encode_name_with(legacy_codec(), "a/b")), "").unwrap();
+        fs::write(
+            tmp.path()
+                .join(encode_name_with(default_codec(), "bell\u{7}")),
+            "",
+        )
+        .unwrap();
+
+        let report = verify(&tmp);
+        assert_eq!(report.counts.unsafe_names, 2);
+        assert!(report
+            .issues
+            .iter()
+            .all(|i| i.kind == TreeIssueKind::UnsafeName));
+    }
+}
@@ -1,13 +1,24 @@
-use std::path::Path;
-use std::process::Command;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread;
 use std::time::UNIX_EPOCH;
 
-use base64::Engine;
 use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 
 use crate::cache::{self, CacheState};
-use crate::encoding::encoding::decode_string;
-use crate::models::video_frame::{VideoFrame, VideoInfo};
+use crate::encoding::content_crypto::{self, DecryptingReader};
+use crate::encoding::encoded_dir::{locked_placeholder, name_state_in, NameState};
+use crate::encoding::name_crypto::SessionKey;
+use crate::exclusions::{DirExclusions, ExclusionState, Exclusions, TreeExclusions};
+use crate::models::file_entry::FileKind;
+use crate::models::video_frame::{VideoFile, VideoFrame, VideoInfo};
+use crate::natural_sort::natural_path_key;
+use crate::search_index::SearchIndexState;
+use crate::session::SessionState;
 
 fn new_command(program: &str) -> Command {
     #[allow(unused_mut)]
@@ -20,85 +31,272 @@ fn new_command(program: &str) -> Command {
     cmd
 }
 
-const VIDEO_EXTENSIONS: &[&str] = &["mp4", "mkv", "avi", "webm", "mov", "mpg", "mpeg"];
+/// The key needed to read the video, if its contents are encrypted.
+fn content_key(path: &Path, session: &SessionState) -> Result<Option<SessionKey>, String> {
+    content_key_from(path, session.key())
+}
+
+fn content_key_from(
+    path: &Path,
+    session_key: Option<SessionKey>,
+) -> Result<Option<SessionKey>, String> {
+    if !content_crypto::is_encrypted_file(path) {
+        return Ok(None);
+    }
+    session_key
+        .map(Some)
+        .ok_or_else(|| "Session is locked".to_string())
+}
+
+/// What to pass to `-i`: the path itself, or stdin for encrypted videos.
+fn input_arg(path: &str, key: &Option<SessionKey>) -> String {
+    if key.is_some() {
+        "pipe:0".to_string()
+    } else {
+        path.to_string()
+    }
+}
 
+/// Runs the command, feeding it the decrypted video over stdin when `key` is
+/// set. Pipes can't seek, so ffmpeg has to read up to the requested
+/// timestamp, and files with the index at the end (non-faststart mp4) may not
+/// probe at all.
+fn run_on_video(mut cmd: Command, path: &str, key: Option<SessionKey>) -> io::Result<Output> {
+    let key = match key {
+        Some(key) => key,
+        None => return cmd.output(),
+    };
+
+    let file = File::open(path)?;
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let feeder = thread::spawn(move || -> io::Result<()> {
+        let mut plaintext = DecryptingReader::new(&key, BufReader::new(file))?;
+        match io::copy(&mut plaintext, &mut stdin) {
+            // ffmpeg stops reading once it has what it needs
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            result => result.map(|_| ()),
+        }
+    });
+
+    let output = child.wait_with_output()?;
+    let fed = feeder
+        .join()
+        .unwrap_or_else(|_| Err(io::Error::other("decryption thread panicked")));
+    match fed {
+        Err(e) if !output.status.success() => Err(e),
+        _ => Ok(output),
+    }
+}
+
+/// The videos below `path`, taken from the search index if `path` is in an
+/// indexed directory.
 #[tauri::command]
-pub fn list_video_files(path: String) -> Result<Vec<String>, String> {
-    let dir_path = Path::new(&path);
+pub fn list_video_files(
+    path: String,
+    session: tauri::State<'_, SessionState>,
+    exclusions: tauri::State<'_, ExclusionState>,
+    index: tauri::State<'_, SearchIndexState>,
+) -> Result<Vec<VideoFile>, String> {
+    find_video_files(
+        Path::new(&path),
+        session.key().as_ref(),
+        &exclusions.get(),
+        Some(&index),
+    )
+}
+
+fn find_video_files(
+    dir_path: &Path,
+    key: Option<&SessionKey>,
+    exclusions: &Exclusions,
+    index: Option<&SearchIndexState>,
+) -> Result<Vec<VideoFile>, String> {
     if !dir_path.is_dir() {
-        return Err(format!("Not a directory: {}", path));
+        return Err(format!("Not a directory: {}", dir_path.display()));
     }
 
     let mut results = Vec::new();
-    collect_video_files(dir_path, &mut results)?;
-    results.sort();
+    match index.filter(|index| index.ready_root_for(dir_path).is_some()) {
+        Some(index) => collect_indexed_video_files(index, dir_path, key, exclusions, &mut results)?,
+        None => collect_video_files(
+            dir_path,
+            "",
+            "",
+            key,
+            &exclusions.in_dir(dir_path, key),
+            &mut results,
+        )?,
+    }
+    results.sort_by_cached_key(|video| natural_path_key(Path::new(&video.decoded_relative_path)));
     Ok(results)
 }
 
-fn collect_video_files(dir: &Path, results: &mut Vec<String>) -> Result<(), String> {
+/// Collects the videos below `dir`. `relative_dir` and `decoded_dir` are the
+/// path of `dir` relative to the listed directory, as on disk and decoded.
+fn collect_video_files(
+    dir: &Path,
+    relative_dir: &str,
+    decoded_dir: &str,
+    key: Option<&SessionKey>,
+    rules: &DirExclusions,
+    results: &mut Vec<VideoFile>,
+) -> Result<(), String> {
     let entries = std::fs::read_dir(dir)
         .map_err(|e| format!("Failed to read directory {}: {}", dir.display(), e))?;
 
     for entry in entries {
         let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
         let path = entry.path();
+        let Some(file_name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
 
+        let state = name_state_in(dir, &file_name, key);
+        if rules.is_excluded(&file_name, &state, path.is_dir()) {
+            continue;
+        }
+
+        let relative_path = join_relative(relative_dir, &file_name);
+        let decoded_relative_path = join_relative(decoded_dir, &display_name(&file_name, &state));
         if path.is_dir() {
-            collect_video_files(&path, results)?;
-        } else if is_video_file(&path) {
-            results.push(path.to_string_lossy().to_string());
+            collect_video_files(
+                &path,
+                &relative_path,
+                &decoded_relative_path,
+                key,
+                &rules.subdir(&path),
+                results,
+            )?;
+        } else if is_video_name(&file_name, state) {
+            results.push(VideoFile {
+                path: path.to_string_lossy().to_string(),
+                relative_path,
+                decoded_relative_path,
+            });
         }
     }
     Ok(())
 }
 
-fn is_video_file(path: &Path) -> bool {
-    let file_name = match path.file_name().and_then(|n| n.to_str()) {
-        Some(name) => name,
-        None => return false,
-    };
-
-    // Check if it's a .dat_ encoded file
-    if file_name.starts_with(".dat_") {
-        let encoded_part = &file_name[5..];
-        if let Some(decoded) = decode_string(encoded_part) {
-            let ext = decoded.rsplit('.').next().unwrap_or("").to_lowercase();
-            return VIDEO_EXTENSIONS.contains(&ext.as_str());
+/// Like `collect_video_files`, with the entries below `dir` taken from the
+/// search index instead of read from disk.
+fn collect_indexed_video_files(
+    index: &SearchIndexState,
+    dir: &Path,
+    key: Option<&SessionKey>,
+    exclusions: &Exclusions,
+    results: &mut Vec<VideoFile>,
+) -> Result<(), String> {
+    let mut rules = TreeExclusions::new(dir, key, exclusions);
+    // Relative paths of the visible directories, as on disk and decoded.
+    // Entries come in path order, so directories before what is in them.
+    let mut dirs: HashMap<PathBuf, (String, String)> =
+        HashMap::from([(dir.to_path_buf(), Default::default())]);
+
+    for entry in index.entries_below(dir)? {
+        let (Some(parent), Some(file_name)) = (
+            entry.path.parent(),
+            entry.path.file_name().and_then(|n| n.to_str()),
+        ) else {
+            continue;
+        };
+        let Some(state) = rules.visible_state(&entry.path, entry.is_directory) else {
+            continue;
+        };
+        let Some((relative_dir, decoded_dir)) = dirs.get(parent) else {
+            continue;
+        };
+
+        let relative_path = join_relative(relative_dir, file_name);
+        let decoded_relative_path = join_relative(decoded_dir, &display_name(file_name, &state));
+        if entry.is_directory {
+            dirs.insert(entry.path.clone(), (relative_path, decoded_relative_path));
+        } else if is_video_name(file_name, state) {
+            results.push(VideoFile {
+                path: entry.path.to_string_lossy().to_string(),
+                relative_path,
+                decoded_relative_path,
+            });
         }
-        return false;
     }
+    Ok(())
+}
+
+fn display_name(file_name: &str, state: &NameState) -> String {
+    match state {
+        NameState::Decoded(decoded) => decoded.clone(),
+        NameState::Plain => file_name.to_string(),
+        NameState::Locked => locked_placeholder(file_name),
+    }
+}
 
-    let ext = file_name.rsplit('.').next().unwrap_or("").to_lowercase();
-    VIDEO_EXTENSIONS.contains(&ext.as_str())
+fn join_relative(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+fn is_video_name(file_name: &str, state: NameState) -> bool {
+    let display_name = match state {
+        NameState::Decoded(decoded) => decoded,
+        NameState::Plain => file_name.to_string(),
+        NameState::Locked => return false,
+    };
+    FileKind::from_name(&display_name) == FileKind::Video
 }
 
 #[tauri::command]
-pub async fn get_video_info(path: String) -> Result<VideoInfo, String> {
-    let file_path = Path::new(&path);
+pub async fn get_video_info(
+    path: String,
+    session: tauri::State<'_, SessionState>,
+) -> Result<VideoInfo, String> {
+    let session_key = session.key();
+    tauri::async_runtime::spawn_blocking(move || read_video_info(&path, session_key.as_ref()))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+/// Probes the video with ffprobe. Blocks until ffprobe is done.
+pub fn read_video_info(path: &str, session_key: Option<&SessionKey>) -> Result<VideoInfo, String> {
+    let file_path = Path::new(path);
     if !file_path.is_file() {
         return Err(format!("Not a file: {}", path));
     }
 
-    let file_size_bytes = std::fs::metadata(&path)
+    let key = content_key_from(file_path, session_key.cloned())?;
+    let stored_size = std::fs::metadata(path)
         .map_err(|e| format!("Failed to read file metadata: {}", e))?
         .len();
+    let file_size_bytes = if key.is_some() {
+        content_crypto::plaintext_len(stored_size)
+    } else {
+        stored_size
+    };
 
-    let path_clone = path.clone();
-    let output = tauri::async_runtime::spawn_blocking(move || {
-        new_command("ffprobe")
-            .args([
-                "-v", "error",
-                "-show_entries", "format=duration,bit_rate",
-                "-show_entries", "stream=width,height,display_aspect_ratio,codec_name,r_frame_rate",
-                "-select_streams", "v:0",
-                "-of", "json",
-                &path_clone,
-            ])
-            .output()
-    })
-    .await
-    .map_err(|e| format!("Task join error: {}", e))?
-    .map_err(|e| format!("Failed to run ffprobe: {}", e))?;
+    let input = input_arg(path, &key);
+    let mut cmd = new_command("ffprobe");
+    cmd.args([
+        "-v",
+        "error",
+        "-show_entries",
+        "format=duration,bit_rate",
+        "-show_entries",
+        "stream=width,height,display_aspect_ratio,codec_name,r_frame_rate",
+        "-select_streams",
+        "v:0",
+        "-of",
+        "json",
+        &input,
+    ]);
+    let output =
+        run_on_video(cmd, path, key).map_err(|e| format!("Failed to run ffprobe: {}", e))?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -126,7 +324,9 @@ pub async fn get_video_info(path: String) -> Result<VideoInfo, String> {
         .and_then(|s| s["display_aspect_ratio"].as_str())
         .filter(|s| *s != "0:1")
         .map(|s| s.to_string());
-    let codec = stream.and_then(|s| s["codec_name"].as_str()).map(|s| s.to_string());
+    let codec = stream
+        .and_then(|s| s["codec_name"].as_str())
+        .map(|s| s.to_string());
     let framerate = stream
         .and_then(|s| s["r_frame_rate"].as_str())
         .map(|s| simplify_framerate(s));
@@ -143,6 +343,31 @@ pub async fn get_video_info(path: String) -> Result<VideoInfo, String> {
     })
 }
 
+/// The duration of the video, from the cache if it hasn't changed since it
+/// was last probed. None if it can't be probed.
+pub fn video_duration(
+    path: &str,
+    session_key: Option<&SessionKey>,
+    cache_state: &CacheState,
+) -> Option<f64> {
+    // Like frames, nothing about encrypted videos goes in the cache
+    let use_cache = !content_crypto::is_encrypted_file(Path::new(path));
+    let modified_str = modified_epoch_secs(path).ok()?.to_string();
+    if use_cache {
+        let conn = cache_state.0.lock().ok()?;
+        if let Some(duration) = cache::get_cached_duration(&conn, path, &modified_str) {
+            return Some(duration);
+        }
+    }
+
+    let duration = read_video_info(path, session_key).ok()?.duration_secs;
+    if use_cache {
+        let conn = cache_state.0.lock().ok()?;
+        cache::store_duration(&conn, path, &modified_str, duration);
+    }
+    Some(duration)
+}
+
 fn simplify_framerate(rate: &str) -> String {
     if let Some((num, den)) = rate.split_once('/') {
         if let (Ok(n), Ok(d)) = (num.parse::<f64>(), den.parse::<f64>()) {
@@ -156,8 +381,8 @@ fn simplify_framerate(rate: &str) -> String {
 }
 
 fn modified_epoch_secs(path: &str) -> Result<u64, String> {
-    let metadata = std::fs::metadata(path)
-        .map_err(|e| format!("Failed to read file metadata: {}", e))?;
+    let metadata =
+        std::fs::metadata(path).map_err(|e| format!("Failed to read file metadata: {}", e))?;
     let modified = metadata
         .modified()
         .map_err(|e| format!("Failed to read mtime: {}", e))?;
@@ -173,19 +398,29 @@ pub async fn extract_video_frame(
     timestamp_secs: f64,
     index: u32,
     cache_state: tauri::State<'_, CacheState>,
+    session: tauri::State<'_, SessionState>,
 ) -> Result<VideoFrame, String> {
     let file_path = Path::new(&path);
     if !file_path.is_file() {
         return Err(format!("Not a file: {}", path));
     }
 
+    let key = content_key(file_path, &session)?;
+    // Frames of encrypted videos would end up in the cache as plain JPEGs
+    let use_cache = key.is_none();
+
     let modified_secs = modified_epoch_secs(&path)?;
     let modified_str = modified_secs.to_string();
 
     // Check cache (brief lock)
-    {
-        let conn = cache_state.0.lock().map_err(|e| format!("Cache lock error: {}", e))?;
-        if let Some(jpeg_data) = cache::get_cached_frame(&conn, &path, &modified_str, timestamp_secs) {
+    if use_cache {
+        let conn = cache_state
+            .0
+            .lock()
+            .map_err(|e| format!("Cache lock error: {}", e))?;
+        if let Some(jpeg_data) =
+            cache::get_cached_frame(&conn, &path, &modified_str, timestamp_secs)
+        {
             return Ok(VideoFrame {
                 index,
                 timestamp_secs,
@@ -196,17 +431,23 @@ pub async fn extract_video_frame(
 
     // Run ffmpeg off the main thread
     let path_clone = path.clone();
+    let input = input_arg(&path, &key);
     let output = tauri::async_runtime::spawn_blocking(move || {
-        new_command("ffmpeg")
-            .args([
-                "-ss", &timestamp_secs.to_string(),
-                "-i", &path_clone,
-                "-frames:v", "1",
-                "-f", "image2pipe",
-                "-vcodec", "mjpeg",
-                "pipe:1",
-            ])
-            .output()
+        let mut cmd = new_command("ffmpeg");
+        cmd.args([
+            "-ss",
+            &timestamp_secs.to_string(),
+            "-i",
+            &input,
+            "-frames:v",
+            "1",
+            "-f",
+            "image2pipe",
+            "-vcodec",
+            "mjpeg",
+            "pipe:1",
+        ]);
+        run_on_video(cmd, &path_clone, key)
     })
     .await
     .map_err(|e| format!("Task join error: {}", e))?
@@ -222,8 +463,11 @@ pub async fn extract_video_frame(
     }
 
     // Store in cache (brief lock)
-    {
-        let conn = cache_state.0.lock().map_err(|e| format!("Cache lock error: {}", e))?;
+    if use_cache {
+        let conn = cache_state
+            .0
+            .lock()
+            .map_err(|e| format!("Cache lock error: {}", e))?;
         cache::store_frame(&conn, &path, &modified_str, timestamp_secs, &output.stdout);
     }
 
@@ -235,7 +479,12 @@ pub async fn extract_video_frame(
 }
 
 #[cfg(test)]
-fn calculate_timestamps(duration: f64, mode_type: &str, count: Option<u32>, minutes: Option<f64>) -> Result<Vec<f64>, String> {
+fn calculate_timestamps(
+    duration: f64,
+    mode_type: &str,
+    count: Option<u32>,
+    minutes: Option<f64>,
+) -> Result<Vec<f64>, String> {
     match mode_type {
         "fixed" => {
             let count = count.ok_or("Missing 'count' for fixed mode")?;
@@ -266,6 +515,169 @@ fn calculate_timestamps(duration: f64, mode_type: &str, count: Option<u32>, minu
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::encoding::encoded_dir::encode_name_with;
+    use crate::encoding::name_codec::default_codec;
+
+    #[test]
+    fn test_list_video_files_applies_exclusions() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let samples = tmp.path().join("samples");
+        std::fs::create_dir(&samples).unwrap();
+        std::fs::write(samples.join("sample.mkv"), "").unwrap();
+        std::fs::write(tmp.path().join(".vbignore"), "samples/\n").unwrap();
+        std::fs::write(
+            tmp.path()
+                .join(encode_name_with(default_codec(), "movie.mkv")),
+            "",
+        )
+        .unwrap();
+        std::fs::write(tmp.path().join("notes.txt"), "").unwrap();
+
+        let videos = find_video_files(tmp.path(), None, &Exclusions::default(), None).unwrap();
+        let paths: Vec<&str> = videos.iter().map(|v| v.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [tmp.path()
+                .join(encode_name_with(default_codec(), "movie.mkv"))
+                .to_string_lossy()]
+        );
+    }
+
+    #[test]
+    fn test_list_video_files_sorts_by_display_path() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let season = tmp
+            .path()
+            .join(encode_name_with(default_codec(), "Season 1"));
+        std::fs::create_dir(&season).unwrap();
+        for name in ["Episode 10.mkv", "Episode 2.mkv", "Episode 1.mkv"] {
+            std::fs::write(season.join(encode_name_with(default_codec(), name)), "").unwrap();
+        }
+        std::fs::write(tmp.path().join("Trailer.mp4"), "").unwrap();
+
+        let videos = find_video_files(tmp.path(), None, &Exclusions::default(), None).unwrap();
+        let decoded: Vec<&str> = videos
+            .iter()
+            .map(|v| v.decoded_relative_path.as_str())
+            .collect();
+        assert_eq!(
+            decoded,
+            [
+                "Season 1/Episode 1.mkv",
+                "Season 1/Episode 2.mkv",
+                "Season 1/Episode 10.mkv",
+                "Trailer.mp4"
+            ]
+        );
+        let first = &videos[0];
+        assert_eq!(
+            first.path,
+            season
+                .join(encode_name_with(default_codec(), "Episode 1.mkv"))
+                .to_string_lossy()
+        );
+        assert_eq!(
+            first.relative_path,
+            format!(
+                "{}/{}",
+                encode_name_with(default_codec(), "Season 1"),
+                encode_name_with(default_codec(), "Episode 1.mkv")
+            )
+        );
+    }
+
+    #[test]
+    fn test_list_video_files_from_search_index() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let season = tmp
+            .path()
+            .join(encode_name_with(default_codec(), "Season 1"));
+        std::fs::create_dir_all(season.join("samples")).unwrap();
+        std::fs::write(
+            season.join(encode_name_with(default_codec(), "Episode 2.mkv")),
+            "",
+        )
+        .unwrap();
+        std::fs::write(season.join("Episode 10.mkv"), "").unwrap();
+        std::fs::write(season.join("samples").join("sample.mkv"), "").unwrap();
+        std::fs::write(season.join("notes.txt"), "").unwrap();
+        std::fs::write(tmp.path().join(".vbignore"), "samples/\n").unwrap();
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::search_index::init_schema(&conn).unwrap();
+        let index = SearchIndexState::new(conn);
+        index.index(tmp.path()).unwrap();
+
+        let walked = find_video_files(&season, None, &Exclusions::default(), None).unwrap();
+        let indexed =
+            find_video_files(&season, None, &Exclusions::default(), Some(&index)).unwrap();
+        let decoded: Vec<&str> = indexed
+            .iter()
+            .map(|v| v.decoded_relative_path.as_str())
+            .collect();
+        assert_eq!(decoded, ["Episode 2.mkv", "Episode 10.mkv"]);
+        let relative = |videos: &[VideoFile]| -> Vec<(String, String)> {
+            videos
+                .iter()
+                .map(|v| (v.path.clone(), v.relative_path.clone()))
+                .collect()
+        };
+        assert_eq!(relative(&indexed), relative(&walked));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_on_video_feeds_plaintext() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let file = tmp.path().join("movie.mkv");
+        std::fs::write(&file, "frames").unwrap();
+        let path = file.to_string_lossy().to_string();
+
+        let mut cat = Command::new("cat");
+        cat.arg(&path);
+        assert_eq!(run_on_video(cat, &path, None).unwrap().stdout, b"frames");
+
+        let key = SessionKey::derive("secret", b"0123456789abcdef").unwrap();
+        content_crypto::encrypt_file(&key, &file).unwrap();
+        let key = content_key_from(&file, Some(key)).unwrap();
+        assert!(key.is_some());
+        let output = run_on_video(Command::new("cat"), &path, key).unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"frames");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_on_video_reports_wrong_key() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let file = tmp.path().join("movie.mkv");
+        std::fs::write(&file, "frames").unwrap();
+        let path = file.to_string_lossy().to_string();
+        let key = SessionKey::derive("secret", b"0123456789abcdef").unwrap();
+        content_crypto::encrypt_file(&key, &file).unwrap();
+
+        let other = SessionKey::derive("guess", b"0123456789abcdef").unwrap();
+        let mut failing = Command::new("sh");
+        failing.args(["-c", "cat > /dev/null; exit 1"]);
+        let error = run_on_video(failing, &path, Some(other)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_content_key_only_for_encrypted_files() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let file = tmp.path().join("movie.mkv");
+        std::fs::write(&file, "frames").unwrap();
+        assert!(content_key_from(&file, None).unwrap().is_none());
+
+        let key = SessionKey::derive("secret", b"0123456789abcdef").unwrap();
+        content_crypto::encrypt_file(&key, &file).unwrap();
+        assert_eq!(
+            content_key_from(&file, None).unwrap_err(),
+            "Session is locked"
+        );
+        assert_eq!(input_arg("movie.mkv", &Some(key)), "pipe:0");
+    }
 
     #[test]
     fn test_fixed_mode_timestamps() {
@@ -0,0 +1,43 @@
+use std::path::Path;
+
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::exclusions::ExclusionState;
+use crate::session::SessionState;
+use crate::watcher::WatchState;
+
+pub const DIRECTORY_CHANGED_EVENT: &str = "directory-changed";
+
+/// Emits `directory-changed` events with the entries created, modified,
+/// removed or renamed directly inside `path`, a moment after they settle.
+/// Names are decoded with the session key current at that time, and the
+/// exclusion rules of `read_directory` apply.
+#[tauri::command]
+pub fn watch_directory(
+    path: String,
+    app: AppHandle,
+    watches: State<'_, WatchState>,
+) -> Result<(), String> {
+    let dir = Path::new(&path);
+    if !dir.is_dir() {
+        return Err(format!("Not a directory: {}", path));
+    }
+
+    let session_app = app.clone();
+    let exclusions_app = app.clone();
+    watches.watch(
+        dir,
+        move || session_app.state::<SessionState>().key(),
+        move || exclusions_app.state::<ExclusionState>().get(),
+        move |changes| {
+            let _ = app.emit(DIRECTORY_CHANGED_EVENT, changes);
+        },
+    )
+}
+
+/// Undoes one `watch_directory` of `path`. Events stop once every view that
+/// watched it has unwatched it.
+#[tauri::command]
+pub fn unwatch_directory(path: String, watches: State<'_, WatchState>) -> Result<(), String> {
+    watches.unwatch(Path::new(&path))
+}
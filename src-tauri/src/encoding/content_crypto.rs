@@ -0,0 +1,320 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32, Nonce, StreamBE32};
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::XChaCha20Poly1305;
+
+use super::name_crypto::SessionKey;
+
+/// Marks a file whose contents are encrypted, followed by the STREAM nonce
+/// prefix and the sealed chunks.
+const MAGIC: &[u8; 8] = b"VBCRYPT1";
+
+const NONCE_PREFIX_LEN: usize = 19;
+
+const HEADER_LEN: usize = MAGIC.len() + NONCE_PREFIX_LEN;
+
+/// Plaintext bytes per chunk. Only one chunk is held in memory at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+const TAG_LEN: usize = 16;
+
+const SEALED_CHUNK_SIZE: usize = CHUNK_SIZE + TAG_LEN;
+
+/// Suffix of the file contents are written to before they replace the
+/// original.
+const TEMP_SUFFIX: &str = ".crypt-tmp";
+
+type StreamNonce = Nonce<XChaCha20Poly1305, StreamBE32<XChaCha20Poly1305>>;
+
+/// Whether the file starts with the encrypted contents header.
+pub fn is_encrypted_file(path: &Path) -> bool {
+    let mut magic = [0u8; MAGIC.len()];
+    match File::open(path) {
+        Ok(mut file) => file.read_exact(&mut magic).is_ok() && &magic == MAGIC,
+        Err(_) => false,
+    }
+}
+
+/// Whether the name is that of a file being encrypted or decrypted right
+/// now, or left behind by a run that was interrupted.
+pub fn is_temp_name(physical_name: &str) -> bool {
+    physical_name.starts_with('.') && physical_name.ends_with(TEMP_SUFFIX)
+}
+
+/// Size of the plaintext stored in an encrypted file of the given size.
+pub fn plaintext_len(encrypted_len: u64) -> u64 {
+    let body = encrypted_len.saturating_sub(HEADER_LEN as u64);
+    let full_chunks = body / SEALED_CHUNK_SIZE as u64;
+    let remainder = body % SEALED_CHUNK_SIZE as u64;
+    full_chunks * CHUNK_SIZE as u64 + remainder.saturating_sub(TAG_LEN as u64)
+}
+
+/// Encrypts everything read from `reader` into `writer`, chunk by chunk.
+/// The last chunk is sealed differently, so truncation is detected.
+pub fn encrypt_stream<R: Read, W: Write>(
+    key: &SessionKey,
+    mut reader: R,
+    mut writer: W,
+) -> io::Result<()> {
+    let mut nonce = [0u8; NONCE_PREFIX_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&nonce)?;
+
+    let mut encryptor =
+        EncryptorBE32::from_aead(key.content_cipher(), StreamNonce::from_slice(&nonce));
+    let mut current = read_chunk(&mut reader, CHUNK_SIZE)?;
+    loop {
+        let next = if current.len() == CHUNK_SIZE {
+            read_chunk(&mut reader, CHUNK_SIZE)?
+        } else {
+            Vec::new()
+        };
+
+        if next.is_empty() {
+            let sealed = encryptor
+                .encrypt_last(current.as_slice())
+                .map_err(|_| crypto_error())?;
+            writer.write_all(&sealed)?;
+            return writer.flush();
+        }
+
+        let sealed = encryptor
+            .encrypt_next(current.as_slice())
+            .map_err(|_| crypto_error())?;
+        writer.write_all(&sealed)?;
+        current = next;
+    }
+}
+
+/// Reads the plaintext of an encrypted stream. Fails with `InvalidData` if
+/// the stream isn't encrypted, the key is wrong or the data was tampered with.
+pub struct DecryptingReader<R: Read> {
+    inner: R,
+    decryptor: Option<DecryptorBE32<XChaCha20Poly1305>>,
+    next_sealed: Vec<u8>,
+    plaintext: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    pub fn new(key: &SessionKey, mut inner: R) -> io::Result<Self> {
+        let mut header = [0u8; HEADER_LEN];
+        inner
+            .read_exact(&mut header)
+            .map_err(|_| not_encrypted_error())?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(not_encrypted_error());
+        }
+
+        let nonce = StreamNonce::from_slice(&header[MAGIC.len()..]);
+        let decryptor = DecryptorBE32::from_aead(key.content_cipher(), nonce);
+        let next_sealed = read_chunk(&mut inner, SEALED_CHUNK_SIZE)?;
+        Ok(DecryptingReader {
+            inner,
+            decryptor: Some(decryptor),
+            next_sealed,
+            plaintext: Vec::new(),
+            position: 0,
+        })
+    }
+
+    fn open_next_chunk(&mut self) -> io::Result<()> {
+        let current = std::mem::take(&mut self.next_sealed);
+        if current.len() == SEALED_CHUNK_SIZE {
+            self.next_sealed = read_chunk(&mut self.inner, SEALED_CHUNK_SIZE)?;
+        }
+
+        self.plaintext = if self.next_sealed.is_empty() {
+            let decryptor = self.decryptor.take().ok_or_else(crypto_error)?;
+            decryptor
+                .decrypt_last(current.as_slice())
+                .map_err(|_| crypto_error())?
+        } else {
+            let decryptor = self.decryptor.as_mut().ok_or_else(crypto_error)?;
+            decryptor
+                .decrypt_next(current.as_slice())
+                .map_err(|_| crypto_error())?
+        };
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.decryptor.is_none() {
+                return Ok(0);
+            }
+            self.open_next_chunk()?;
+        }
+
+        let count = buf.len().min(self.plaintext.len() - self.position);
+        buf[..count].copy_from_slice(&self.plaintext[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+/// Encrypts the file in place. Returns false if it was already encrypted.
+pub fn encrypt_file(key: &SessionKey, path: &Path) -> io::Result<bool> {
+    if is_encrypted_file(path) {
+        return Ok(false);
+    }
+    replace_contents(path, |reader, writer| encrypt_stream(key, reader, writer))?;
+    Ok(true)
+}
+
+/// Decrypts the file in place. Returns false if it wasn't encrypted.
+pub fn decrypt_file(key: &SessionKey, path: &Path) -> io::Result<bool> {
+    if !is_encrypted_file(path) {
+        return Ok(false);
+    }
+    replace_contents(path, |reader, writer| {
+        let mut plaintext = DecryptingReader::new(key, reader)?;
+        io::copy(&mut plaintext, writer)?;
+        Ok(())
+    })?;
+    Ok(true)
+}
+
+/// Writes the transformed contents next to the file and renames it over the
+/// original only once everything succeeded, so a failure leaves it untouched.
+fn replace_contents<F>(path: &Path, transform: F) -> io::Result<()>
+where
+    F: FnOnce(BufReader<File>, &mut BufWriter<File>) -> io::Result<()>,
+{
+    let temp_path = temp_path_for(path);
+    let result = (|| {
+        let reader = BufReader::new(File::open(path)?);
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        transform(reader, &mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::set_permissions(&temp_path, fs::metadata(path)?.permissions())?;
+        fs::rename(&temp_path, path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn temp_path_for(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}{}", name, TEMP_SUFFIX))
+}
+
+fn read_chunk<R: Read>(reader: &mut R, size: usize) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(size);
+    reader.take(size as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+fn crypto_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "wrong key or corrupted data")
+}
+
+fn not_encrypted_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "file is not encrypted")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn roundtrip(data: &[u8]) -> Vec<u8> {
+        let key = SessionKey::derive("secret", b"0123456789abcdef").unwrap();
+        let mut encrypted = Vec::new();
+        encrypt_stream(&key, data, &mut encrypted).unwrap();
+        assert_eq!(plaintext_len(encrypted.len() as u64), data.len() as u64);
+
+        let mut decrypted = Vec::new();
+        DecryptingReader::new(&key, encrypted.as_slice())
+            .unwrap()
+            .read_to_end(&mut decrypted)
+            .unwrap();
+        decrypted
+    }
+
+    #[test]
+    fn test_roundtrip_chunk_boundaries() {
+        for len in [
+            0,
+            1,
+            CHUNK_SIZE - 1,
+            CHUNK_SIZE,
+            CHUNK_SIZE + 1,
+            3 * CHUNK_SIZE,
+        ] {
+            let data = sample(len);
+            assert_eq!(roundtrip(&data), data, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn test_wrong_key_and_tampering_are_detected() {
+        let key = SessionKey::derive("secret", b"0123456789abcdef").unwrap();
+        let data = sample(2 * CHUNK_SIZE + 10);
+        let mut encrypted = Vec::new();
+        encrypt_stream(&key, data.as_slice(), &mut encrypted).unwrap();
+
+        let other = SessionKey::derive("guess", b"0123456789abcdef").unwrap();
+        let mut sink = Vec::new();
+        assert!(DecryptingReader::new(&other, encrypted.as_slice())
+            .unwrap()
+            .read_to_end(&mut sink)
+            .is_err());
+
+        let mut tampered = encrypted.clone();
+        tampered[HEADER_LEN + CHUNK_SIZE + 100] ^= 1;
+        assert!(DecryptingReader::new(&key, tampered.as_slice())
+            .unwrap()
+            .read_to_end(&mut sink)
+            .is_err());
+
+        // Dropping whole trailing chunks must not go unnoticed
+        let truncated = &encrypted[..HEADER_LEN + SEALED_CHUNK_SIZE];
+        assert!(DecryptingReader::new(&key, truncated)
+            .unwrap()
+            .read_to_end(&mut sink)
+            .is_err());
+    }
+
+    #[test]
+    fn test_encrypt_and_decrypt_file_in_place() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("movie.mp4");
+        let data = sample(CHUNK_SIZE + 5);
+        fs::write(&path, &data).unwrap();
+        let key = SessionKey::derive("secret", b"0123456789abcdef").unwrap();
+
+        assert!(encrypt_file(&key, &path).unwrap());
+        assert!(is_encrypted_file(&path));
+        assert!(!encrypt_file(&key, &path).unwrap());
+        assert_ne!(fs::read(&path).unwrap(), data);
+
+        // A wrong key leaves the file as it was
+        let other = SessionKey::derive("guess", b"0123456789abcdef").unwrap();
+        assert!(decrypt_file(&other, &path).is_err());
+        assert!(is_encrypted_file(&path));
+
+        assert!(decrypt_file(&key, &path).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), data);
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
+    }
+}
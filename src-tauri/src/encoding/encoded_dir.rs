@@ -1,100 +1,281 @@
 use std::path::Path;
 
-use super::encoding::{decode_string, encode_string};
+use super::fs_limits::{limits_for, name_len};
+use super::name_codec::{codec_for_name, NameCodec};
+use super::name_crypto::{is_encrypted_name, EncryptedNameCodec, SessionKey, ENCRYPTED_PREFIX};
+use super::name_index::{is_hashed_name, lookup_name, NameIndex};
+use crate::models::length_check::{LengthIssue, NameLimits};
 
-const ENCODING_PREFIX: &str = ".dat_";
+/// How a physical name is presented to the user.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NameState {
+    Plain,
+    Decoded(String),
+    /// Encrypted, and no key (or not the right one) is loaded
+    Locked,
+}
+
+/// Returns the decoded name if the filename starts with the prefix of one of
+/// the registered encoding schemes, or None if it's not encoded. Encrypted
+/// names are decrypted if a key is given.
+pub fn try_decode_name(physical_name: &str, key: Option<&SessionKey>) -> Option<String> {
+    codec_for_name(physical_name)
+        .and_then(|codec| codec.decode(&physical_name[codec.prefix().len()..]))
+        .or_else(|| {
+            let encrypted = physical_name.strip_prefix(ENCRYPTED_PREFIX)?;
+            EncryptedNameCodec::new(key?.clone()).decode(encrypted)
+        })
+}
+
+pub fn name_state(physical_name: &str, key: Option<&SessionKey>) -> NameState {
+    match try_decode_name(physical_name, key) {
+        Some(decoded) => NameState::Decoded(decoded),
+        None if is_encrypted_name(physical_name) => NameState::Locked,
+        None => NameState::Plain,
+    }
+}
+
+/// Like `name_state`, but also resolves hashed names through the sidecar
+/// index of `dir`. A hashed name missing from the index is shown as is.
+pub fn name_state_in(dir: &Path, physical_name: &str, key: Option<&SessionKey>) -> NameState {
+    if is_hashed_name(physical_name) {
+        return match lookup_name(dir, physical_name) {
+            Some(name) => NameState::Decoded(name),
+            None => NameState::Plain,
+        };
+    }
+    name_state(physical_name, key)
+}
+
+/// Like `name_state_in`, with the sidecar index of the directory already
+/// loaded, for going through many entries of one directory.
+pub fn name_state_with_index(
+    index: &NameIndex,
+    physical_name: &str,
+    key: Option<&SessionKey>,
+) -> NameState {
+    if is_hashed_name(physical_name) {
+        return match index.get(physical_name) {
+            Some(name) => NameState::Decoded(name.to_string()),
+            None => NameState::Plain,
+        };
+    }
+    name_state(physical_name, key)
+}
+
+/// Whether the name uses any encoding scheme, including encrypted names that
+/// cannot be read right now and hashed names kept in a sidecar index.
+pub fn is_encoded_name(physical_name: &str) -> bool {
+    try_decode_name(physical_name, None).is_some()
+        || is_encrypted_name(physical_name)
+        || is_hashed_name(physical_name)
+}
+
+/// Whether the name carries the prefix of an encoding scheme, whether or not
+/// the rest of it decodes. Encrypted names are left to `name_state`.
+pub fn has_encoding_prefix(physical_name: &str) -> bool {
+    codec_for_name(physical_name).is_some()
+}
+
+/// Stand-in display name for an encrypted entry while the session is locked.
+/// Includes the start of the ciphertext so locked siblings stay distinguishable.
+pub fn locked_placeholder(physical_name: &str) -> String {
+    let encrypted = physical_name.trim_start_matches(ENCRYPTED_PREFIX);
+    let short: String = encrypted.chars().take(8).collect();
+    format!("🔒 {}", short)
+}
 
-/// Returns the decoded name if the filename starts with the encoding prefix,
-/// or None if it's not encoded.
-pub fn try_decode_name(physical_name: &str) -> Option<String> {
-    physical_name
-        .strip_prefix(ENCODING_PREFIX)
-        .and_then(decode_string)
+/// Encodes a plain name into the prefixed format of the given scheme.
+pub fn encode_name_with(codec: &dyn NameCodec, name: &str) -> String {
+    format!("{}{}", codec.prefix(), codec.encode(name))
 }
 
-/// Encodes a plain name into the `.dat_` prefixed format.
-pub fn encode_name(name: &str) -> String {
-    format!("{}{}", ENCODING_PREFIX, encode_string(name))
+/// Checks whether `path` still fits the limits of its file system once its
+/// name is encoded with `codec`. Returns what is too long, if anything.
+pub fn check_encoded_length(codec: &dyn NameCodec, path: &Path) -> Option<LengthIssue> {
+    check_encoded_length_within(codec, path, limits_for(path))
 }
 
-/// Checks whether encoding this path would produce a path shorter than 256 chars.
-pub fn can_encode(path: &Path) -> bool {
+pub fn check_encoded_length_within(
+    codec: &dyn NameCodec,
+    path: &Path,
+    limits: NameLimits,
+) -> Option<LengthIssue> {
     let physical_name = path
         .file_name()
         .and_then(|n| n.to_str())
         .unwrap_or_default();
 
-    let encoded_name = encode_name(physical_name);
+    let encoded_name = encode_name_with(codec, physical_name);
+    let component_len = name_len(&encoded_name);
+    if component_len > limits.max_component_len {
+        return Some(LengthIssue::ComponentTooLong {
+            component: physical_name.to_string(),
+            length: component_len,
+            limit: limits.max_component_len,
+        });
+    }
+
     let parent = path.parent().unwrap_or(path);
-    let new_path = parent.join(&encoded_name);
+    let new_path = parent.join(&encoded_name).to_string_lossy().to_string();
+    let path_len = name_len(&new_path);
+    if path_len > limits.max_path_len {
+        return Some(LengthIssue::PathTooLong {
+            path: new_path,
+            length: path_len,
+            limit: limits.max_path_len,
+        });
+    }
 
-    new_path.to_string_lossy().len() < 256
+    None
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::encoding::name_codec::default_codec;
     use std::path::PathBuf;
     use tempfile::TempDir;
 
     #[test]
     fn test_try_decode_encoded_name() {
         assert_eq!(
-            try_decode_name(".dat_VGVzdA=="),
+            try_decode_name(".dat_VGVzdA==", None),
             Some("Test".to_string())
         );
     }
 
     #[test]
     fn test_try_decode_plain_name() {
-        assert_eq!(try_decode_name("regular_folder"), None);
+        assert_eq!(try_decode_name("regular_folder", None), None);
     }
 
     #[test]
     fn test_try_decode_not_base64_after_prefix() {
-        assert_eq!(try_decode_name(".dat_!!!invalid!!!"), None);
+        assert_eq!(try_decode_name(".dat_!!!invalid!!!", None), None);
     }
 
     #[test]
     fn test_encode_name_known_value() {
-        assert_eq!(encode_name("Test"), ".dat_VGVzdA==");
+        assert_eq!(encode_name_with(default_codec(), "Test"), ".dat2_VGVzdA");
     }
 
     #[test]
     fn test_encode_name_special_chars() {
-        let encoded = encode_name("éàè!+ç%&/^¨w ");
-        assert_eq!(encoded, ".dat_w6nDoMOoISvDpyUmL17CqHcg");
+        let encoded = encode_name_with(default_codec(), "éàè!+ç%&/^¨w ");
+        assert_eq!(encoded, ".dat2_w6nDoMOoISvDpyUmL17CqHcg");
+    }
+
+    #[test]
+    fn test_encode_name_never_contains_slash() {
+        assert_eq!(
+            encode_name_with(default_codec(), "???.mp4"),
+            ".dat2_Pz8_Lm1wNA"
+        );
+    }
+
+    #[test]
+    fn test_try_decode_legacy_name() {
+        assert_eq!(
+            try_decode_name(".dat_Pz8/Lm1wNA==", None),
+            Some("???.mp4".to_string())
+        );
     }
 
     #[test]
     fn test_encode_decode_name_roundtrip() {
         let original = "my_folder";
-        let encoded = encode_name(original);
-        let decoded = try_decode_name(&encoded);
+        let encoded = encode_name_with(default_codec(), original);
+        let decoded = try_decode_name(&encoded, None);
         assert_eq!(decoded, Some(original.to_string()));
     }
 
     #[test]
     fn test_encode_decode_name_roundtrip_unicode() {
         let original = "日本語フォルダ";
-        let encoded = encode_name(original);
-        let decoded = try_decode_name(&encoded);
+        let encoded = encode_name_with(default_codec(), original);
+        let decoded = try_decode_name(&encoded, None);
         assert_eq!(decoded, Some(original.to_string()));
     }
 
+    #[test]
+    fn test_try_decode_mixed_schemes() {
+        assert_eq!(
+            try_decode_name(".dat2_VGVzdA", None),
+            Some("Test".to_string())
+        );
+        assert_eq!(
+            try_decode_name(".dat32_krsxg5a", None),
+            Some("Test".to_string())
+        );
+    }
+
+    #[test]
+    fn test_encode_name_with_scheme() {
+        use crate::encoding::name_codec::Base32Codec;
+        assert_eq!(encode_name_with(&Base32Codec, "Test"), ".dat32_krsxg5a");
+    }
+
+    #[test]
+    fn test_name_state_encrypted() {
+        let key = SessionKey::derive("secret", b"0123456789abcdef").unwrap();
+        let physical = encode_name_with(&EncryptedNameCodec::new(key.clone()), "Test");
+
+        assert_eq!(try_decode_name(&physical, None), None);
+        assert_eq!(name_state(&physical, None), NameState::Locked);
+        assert_eq!(
+            name_state(&physical, Some(&key)),
+            NameState::Decoded("Test".to_string())
+        );
+        assert!(is_encoded_name(&physical));
+        assert_eq!(name_state("plain.txt", Some(&key)), NameState::Plain);
+    }
+
     #[test]
     fn test_can_encode_short_path() {
         let tmp = TempDir::new().unwrap();
         let file = tmp.path().join("short_name.txt");
         std::fs::write(&file, "").unwrap();
-        assert!(can_encode(&file));
+        assert!(check_encoded_length(default_codec(), &file).is_none());
     }
 
     #[test]
     fn test_can_encode_long_path() {
-        // Create a path that would exceed 256 chars when encoded
+        // The encoded name exceeds the 255 byte component limit
         let long_name = "a".repeat(200);
         let path = PathBuf::from("/tmp").join(&long_name);
-        assert!(!can_encode(&path));
+        assert!(check_encoded_length(default_codec(), &path).is_some());
+    }
+
+    #[test]
+    fn test_check_encoded_length_reports_reason() {
+        let limits = NameLimits {
+            max_component_len: 20,
+            max_path_len: 30,
+        };
+        let codec = default_codec();
+
+        let issue =
+            check_encoded_length_within(codec, Path::new("/videos/a long name.mp4"), limits);
+        assert_eq!(
+            issue,
+            Some(LengthIssue::ComponentTooLong {
+                component: "a long name.mp4".to_string(),
+                length: encode_name_with(default_codec(), "a long name.mp4").len(),
+                limit: 20,
+            })
+        );
+
+        let issue =
+            check_encoded_length_within(codec, Path::new("/some/deeply/nested/dir/a.mp4"), limits);
+        assert!(matches!(
+            issue,
+            Some(LengthIssue::PathTooLong { limit: 30, .. })
+        ));
+
+        assert_eq!(
+            check_encoded_length_within(codec, Path::new("/v/a.mp4"), limits),
+            None
+        );
     }
 }
@@ -4,17 +4,18 @@ pub fn encode_string(input: &str) -> String {
     STANDARD.encode(input.as_bytes())
 }
 
-pub fn decode_string(input: &str) -> Option<String> {
-    STANDARD
-        .decode(input)
-        .ok()
-        .and_then(|bytes| String::from_utf8(bytes).ok())
+pub fn decode_bytes(input: &str) -> Option<Vec<u8>> {
+    STANDARD.decode(input).ok()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn decode_string(input: &str) -> Option<String> {
+        decode_bytes(input).and_then(|bytes| String::from_utf8(bytes).ok())
+    }
+
     #[test]
     fn test_encode_known_value() {
         assert_eq!(encode_string("Test"), "VGVzdA==");
@@ -0,0 +1,93 @@
+use std::path::Path;
+
+use crate::models::length_check::NameLimits;
+
+#[cfg(not(windows))]
+const DEFAULT_LIMITS: NameLimits = NameLimits {
+    max_component_len: 255,
+    max_path_len: 4095,
+};
+
+/// MAX_PATH is 260 including the terminating NUL. Verbatim (`\\?\`) paths
+/// may be up to 32767 code units long.
+#[cfg(windows)]
+const DEFAULT_LIMITS: NameLimits = NameLimits {
+    max_component_len: 255,
+    max_path_len: 259,
+};
+
+#[cfg(windows)]
+const VERBATIM_MAX_PATH_LEN: usize = 32767;
+
+/// Length of a name or path in the unit the limits are expressed in.
+pub fn name_len(name: &str) -> usize {
+    #[cfg(windows)]
+    {
+        name.encode_utf16().count()
+    }
+    #[cfg(not(windows))]
+    {
+        name.len()
+    }
+}
+
+/// The limits of the file system `path` would be created on, queried from
+/// the closest existing ancestor. Falls back to the platform defaults when
+/// the file system doesn't report them.
+#[cfg(unix)]
+pub fn limits_for(path: &Path) -> NameLimits {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let Some(existing) = path.ancestors().find(|p| p.exists()) else {
+        return DEFAULT_LIMITS;
+    };
+    let Ok(c_path) = CString::new(existing.as_os_str().as_bytes()) else {
+        return DEFAULT_LIMITS;
+    };
+
+    let query = |name| {
+        // SAFETY: `c_path` is a valid NUL-terminated string
+        let value = unsafe { libc::pathconf(c_path.as_ptr(), name) };
+        usize::try_from(value).ok().filter(|v| *v > 0)
+    };
+    NameLimits {
+        max_component_len: query(libc::_PC_NAME_MAX).unwrap_or(DEFAULT_LIMITS.max_component_len),
+        // PATH_MAX counts the terminating NUL
+        max_path_len: query(libc::_PC_PATH_MAX)
+            .map(|v| v - 1)
+            .unwrap_or(DEFAULT_LIMITS.max_path_len),
+    }
+}
+
+#[cfg(windows)]
+pub fn limits_for(path: &Path) -> NameLimits {
+    let verbatim = path.to_string_lossy().starts_with(r"\\?\");
+    NameLimits {
+        max_path_len: if verbatim {
+            VERBATIM_MAX_PATH_LEN
+        } else {
+            DEFAULT_LIMITS.max_path_len
+        },
+        ..DEFAULT_LIMITS
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn limits_for(_path: &Path) -> NameLimits {
+    DEFAULT_LIMITS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_limits_for_missing_path_uses_existing_ancestor() {
+        let tmp = TempDir::new().unwrap();
+        let missing = tmp.path().join("missing").join("file.mp4");
+        assert_eq!(limits_for(&missing), limits_for(tmp.path()));
+        assert!(limits_for(&missing).max_component_len >= 255);
+    }
+}
@@ -0,0 +1,139 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use serde::{Deserialize, Serialize};
+
+use super::name_crypto::SessionKey;
+
+/// Hidden file in the root of a library holding the KDF salt and the key
+/// verifier. It travels with the library, so it unlocks on any machine.
+pub const KEYRING_FILE_NAME: &str = ".dat_keyring";
+
+const SALT_LEN: usize = 16;
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Keyring {
+    salt: String,
+    verifier: String,
+}
+
+/// The nearest directory from `path` up that has a keyring.
+pub fn find_library_root(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .find(|dir| dir.join(KEYRING_FILE_NAME).is_file())
+        .map(Path::to_path_buf)
+}
+
+/// Derives the key of the library `path` belongs to and checks it against
+/// the verifier of its keyring. Fails outside of any library; a new one is
+/// only ever made by `create`.
+pub fn unlock(path: &Path, passphrase: &str) -> Result<SessionKey, String> {
+    let root = find_library_root(path)
+        .ok_or_else(|| format!("No library keyring found for {}", path.display()))?;
+    let keyring = load(&root)?;
+    let salt = decode_field(&root, &keyring.salt)?;
+    let key = SessionKey::derive(passphrase, &salt)?;
+    if key.verifier()[..] != decode_field(&root, &keyring.verifier)?[..] {
+        return Err("Wrong passphrase".to_string());
+    }
+    Ok(key)
+}
+
+/// Makes the directory the root of a new library with a random salt and
+/// returns its key. Fails inside an existing library.
+pub fn create(dir: &Path, passphrase: &str) -> Result<SessionKey, String> {
+    if !dir.is_dir() {
+        return Err(format!("Not a directory: {}", dir.display()));
+    }
+    if let Some(root) = find_library_root(dir) {
+        return Err(format!("Already part of the library at {}", root.display()));
+    }
+    let mut salt = vec![0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let key = SessionKey::derive(passphrase, &salt)?;
+    let keyring = Keyring {
+        salt: STANDARD.encode(&salt),
+        verifier: STANDARD.encode(key.verifier()),
+    };
+    save(dir, &keyring)?;
+    Ok(key)
+}
+
+fn load(root: &Path) -> Result<Keyring, String> {
+    let keyring_path = root.join(KEYRING_FILE_NAME);
+    let contents = fs::read_to_string(&keyring_path)
+        .map_err(|e| format!("Failed to read {}: {}", keyring_path.display(), e))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| format!("Failed to parse {}: {}", keyring_path.display(), e))
+}
+
+fn save(root: &Path, keyring: &Keyring) -> Result<(), String> {
+    let keyring_path = root.join(KEYRING_FILE_NAME);
+    let contents = serde_json::to_string_pretty(keyring)
+        .map_err(|e| format!("Failed to serialize keyring: {}", e))?;
+    fs::write(&keyring_path, contents)
+        .map_err(|e| format!("Failed to write {}: {}", keyring_path.display(), e))
+}
+
+fn decode_field(root: &Path, value: &str) -> Result<Vec<u8>, String> {
+    STANDARD.decode(value).map_err(|e| {
+        format!(
+            "Failed to parse {}: {}",
+            root.join(KEYRING_FILE_NAME).display(),
+            e
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_create_uses_random_salt() {
+        let a = TempDir::new().unwrap();
+        let b = TempDir::new().unwrap();
+
+        let key_a = create(a.path(), "secret").unwrap();
+        let key_b = create(b.path(), "secret").unwrap();
+        assert!(a.path().join(KEYRING_FILE_NAME).exists());
+        assert_ne!(key_a.verifier(), key_b.verifier());
+        assert_ne!(load(a.path()).unwrap().salt, load(b.path()).unwrap().salt);
+    }
+
+    #[test]
+    fn test_unlock_checks_passphrase() {
+        let tmp = TempDir::new().unwrap();
+        let sub = tmp.path().join("season 1");
+        fs::create_dir(&sub).unwrap();
+
+        let key = create(tmp.path(), "secret").unwrap();
+        assert_eq!(unlock(&sub, "secret").unwrap().verifier(), key.verifier());
+        assert_eq!(unlock(&sub, "guess").unwrap_err(), "Wrong passphrase");
+        assert!(!sub.join(KEYRING_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn test_unlock_outside_library_fails() {
+        let tmp = TempDir::new().unwrap();
+
+        assert!(unlock(tmp.path(), "secret").is_err());
+        assert!(!tmp.path().join(KEYRING_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn test_create_inside_library_fails() {
+        let tmp = TempDir::new().unwrap();
+        let sub = tmp.path().join("season 1");
+        fs::create_dir(&sub).unwrap();
+
+        create(tmp.path(), "secret").unwrap();
+        assert!(create(&sub, "other").is_err());
+        assert!(!sub.join(KEYRING_FILE_NAME).exists());
+    }
+}
@@ -1,2 +1,9 @@
+pub mod content_crypto;
 pub mod encoded_dir;
 pub mod encoding;
+pub mod fs_limits;
+pub mod keyring;
+pub mod name_codec;
+pub mod name_crypto;
+pub mod name_index;
+pub mod rename_plan;
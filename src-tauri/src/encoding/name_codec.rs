@@ -0,0 +1,203 @@
+use std::fmt;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use data_encoding::BASE32_NOPAD;
+
+use super::encoding::{decode_bytes, encode_string};
+use super::name_index::HashedNameCodec;
+
+/// A way of turning a display name into a physical file name and back.
+/// Every scheme is recognised by its own prefix, so trees that mix several
+/// schemes still decode correctly.
+pub trait NameCodec: Sync {
+    /// Identifier used to select the scheme from the frontend
+    fn id(&self) -> &'static str;
+    /// Marks a physical name as encoded with this scheme
+    fn prefix(&self) -> &'static str;
+    /// Encodes the name, without the prefix
+    fn encode(&self, name: &str) -> String;
+    /// Decodes the part after the prefix into raw bytes, or None if it isn't
+    /// valid for this scheme
+    fn decode_bytes(&self, encoded: &str) -> Option<Vec<u8>>;
+    /// Decodes the part after the prefix, or None if it isn't valid for this
+    /// scheme or doesn't decode to UTF-8
+    fn decode(&self, encoded: &str) -> Option<String> {
+        self.decode_bytes(encoded)
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+    }
+}
+
+impl fmt::Debug for dyn NameCodec + '_ {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+/// The original `.dat_` scheme: standard base64. Its alphabet contains `/`,
+/// so some names cannot be represented as a single path component. Still
+/// decoded, but no longer used for new names; see `migrate_encoded_names`.
+pub struct Base64Codec;
+
+impl NameCodec for Base64Codec {
+    fn id(&self) -> &'static str {
+        "base64"
+    }
+
+    fn prefix(&self) -> &'static str {
+        ".dat_"
+    }
+
+    fn encode(&self, name: &str) -> String {
+        encode_string(name)
+    }
+
+    fn decode_bytes(&self, encoded: &str) -> Option<Vec<u8>> {
+        decode_bytes(encoded)
+    }
+}
+
+/// Base64 with the URL and filename safe alphabet (`-` and `_` instead of
+/// `+` and `/`) and without padding. The default scheme.
+pub struct UrlSafeBase64Codec;
+
+impl NameCodec for UrlSafeBase64Codec {
+    fn id(&self) -> &'static str {
+        "base64url"
+    }
+
+    fn prefix(&self) -> &'static str {
+        ".dat2_"
+    }
+
+    fn encode(&self, name: &str) -> String {
+        URL_SAFE_NO_PAD.encode(name.as_bytes())
+    }
+
+    fn decode_bytes(&self, encoded: &str) -> Option<Vec<u8>> {
+        URL_SAFE_NO_PAD.decode(encoded).ok()
+    }
+}
+
+/// Lower-case base32 without padding. Longer than base64, but names never
+/// differ only in case, so it is safe on case-insensitive file systems.
+pub struct Base32Codec;
+
+impl NameCodec for Base32Codec {
+    fn id(&self) -> &'static str {
+        "base32"
+    }
+
+    fn prefix(&self) -> &'static str {
+        ".dat32_"
+    }
+
+    fn encode(&self, name: &str) -> String {
+        BASE32_NOPAD.encode(name.as_bytes()).to_ascii_lowercase()
+    }
+
+    fn decode_bytes(&self, encoded: &str) -> Option<Vec<u8>> {
+        BASE32_NOPAD
+            .decode(encoded.to_ascii_uppercase().as_bytes())
+            .ok()
+    }
+}
+
+/// Every registered scheme. None of the prefixes is a prefix of another.
+pub static CODECS: &[&dyn NameCodec] = &[&Base64Codec, &UrlSafeBase64Codec, &Base32Codec];
+
+/// The scheme a physical name was written with, recognised by its prefix.
+/// Hashed names are included; encrypted names need a key and are left to
+/// the caller.
+pub fn codec_for_name(physical_name: &str) -> Option<&'static dyn NameCodec> {
+    CODECS
+        .iter()
+        .copied()
+        .chain([&HashedNameCodec as &dyn NameCodec])
+        .find(|codec| physical_name.starts_with(codec.prefix()))
+}
+
+/// The scheme used when no other one is requested.
+pub fn default_codec() -> &'static dyn NameCodec {
+    &UrlSafeBase64Codec
+}
+
+/// The scheme that `.dat_` names written by older versions use.
+pub fn legacy_codec() -> &'static dyn NameCodec {
+    &Base64Codec
+}
+
+pub fn codec_by_id(id: &str) -> Option<&'static dyn NameCodec> {
+    CODECS.iter().copied().find(|codec| codec.id() == id)
+}
+
+/// Resolves an optional scheme id coming from the frontend.
+pub fn resolve_codec(id: Option<&str>) -> Result<&'static dyn NameCodec, String> {
+    match id {
+        None => Ok(default_codec()),
+        Some(id) => codec_by_id(id).ok_or_else(|| format!("Unknown encoding scheme: {}", id)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefixes_are_unambiguous() {
+        for a in CODECS {
+            for b in CODECS {
+                if a.id() != b.id() {
+                    assert!(!a.prefix().starts_with(b.prefix()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_all_codecs() {
+        let original = "éàè!+ç%&/^¨w 日本語.mkv";
+        for codec in CODECS {
+            let encoded = codec.encode(original);
+            assert_eq!(
+                codec.decode(&encoded),
+                Some(original.to_string()),
+                "{}",
+                codec.id()
+            );
+        }
+    }
+
+    #[test]
+    fn test_url_safe_has_no_slash() {
+        // Standard base64 of this name contains a '/'
+        assert!(Base64Codec.encode("???.mp4").contains('/'));
+        assert!(!UrlSafeBase64Codec.encode("???.mp4").contains('/'));
+    }
+
+    #[test]
+    fn test_base32_is_lower_case_and_case_insensitive() {
+        let encoded = Base32Codec.encode("Test");
+        assert_eq!(encoded, "krsxg5a");
+        assert_eq!(
+            Base32Codec.decode(&encoded.to_uppercase()),
+            Some("Test".to_string())
+        );
+    }
+
+    #[test]
+    fn test_codec_for_name() {
+        assert_eq!(codec_for_name(".dat_VGVzdA").unwrap().id(), "base64");
+        assert_eq!(codec_for_name(".dat2_VGVzdA").unwrap().id(), "base64url");
+        assert_eq!(codec_for_name(".dat32_krsxg5a").unwrap().id(), "base32");
+        assert_eq!(codec_for_name(".dath_abc").unwrap().id(), "hashed");
+        assert!(codec_for_name(".datx_abc").is_none());
+        assert!(codec_for_name("movie.mkv").is_none());
+    }
+
+    #[test]
+    fn test_resolve_codec() {
+        assert_eq!(resolve_codec(None).unwrap().id(), "base64url");
+        assert_eq!(resolve_codec(Some("base32")).unwrap().id(), "base32");
+        assert!(resolve_codec(Some("rot13")).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use std::fmt;
+
+use argon2::Argon2;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use sha2::{Digest, Sha256};
+
+use super::name_codec::NameCodec;
+
+/// Scheme id used to request encrypted names from the frontend.
+pub const ENCRYPTED_SCHEME: &str = "encrypted";
+
+pub const ENCRYPTED_PREFIX: &str = ".datx_";
+
+/// Mixed into the verifier so it is never equal to a key.
+const VERIFIER_CONTEXT: &[u8] = b"videobrowser.key-verifier.v1";
+
+const NONCE_LEN: usize = 24;
+
+/// Symmetric keys derived from the user's passphrase: one for names and one
+/// for file contents, so the two never share a key.
+#[derive(Clone)]
+pub struct SessionKey {
+    name: [u8; 32],
+    content: [u8; 32],
+}
+
+impl fmt::Debug for SessionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SessionKey(..)")
+    }
+}
+
+impl SessionKey {
+    /// Both keys come from a single 64-byte Argon2 output: the first half is
+    /// the name key, the second the content key.
+    pub fn derive(passphrase: &str, salt: &[u8]) -> Result<SessionKey, String> {
+        let mut okm = [0u8; 64];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut okm)
+            .map_err(|e| format!("Failed to derive key: {}", e))?;
+        let mut key = SessionKey {
+            name: [0u8; 32],
+            content: [0u8; 32],
+        };
+        key.name.copy_from_slice(&okm[..32]);
+        key.content.copy_from_slice(&okm[32..]);
+        Ok(key)
+    }
+
+    /// Stored next to the salt, so a wrong passphrase is told apart from a
+    /// right one without decrypting anything.
+    pub fn verifier(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(VERIFIER_CONTEXT);
+        hasher.update(self.name);
+        hasher.update(self.content);
+        hasher.finalize().into()
+    }
+
+    pub fn name_cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(Key::from_slice(&self.name))
+    }
+
+    pub fn content_cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(Key::from_slice(&self.content))
+    }
+}
+
+/// Whether the name uses the encrypted scheme, regardless of whether it can
+/// currently be decrypted.
+pub fn is_encrypted_name(physical_name: &str) -> bool {
+    physical_name.starts_with(ENCRYPTED_PREFIX)
+}
+
+/// Authenticated encryption of names with XChaCha20-Poly1305 and a random
+/// nonce, stored as URL-safe base64 of `nonce || ciphertext`.
+pub struct EncryptedNameCodec {
+    key: SessionKey,
+}
+
+impl EncryptedNameCodec {
+    pub fn new(key: SessionKey) -> Self {
+        EncryptedNameCodec { key }
+    }
+}
+
+impl NameCodec for EncryptedNameCodec {
+    fn id(&self) -> &'static str {
+        ENCRYPTED_SCHEME
+    }
+
+    fn prefix(&self) -> &'static str {
+        ENCRYPTED_PREFIX
+    }
+
+    fn encode(&self, name: &str) -> String {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .key
+            .name_cipher()
+            .encrypt(&nonce, name.as_bytes())
+            .expect("encrypting into a Vec cannot fail");
+
+        let mut bytes = nonce.to_vec();
+        bytes.extend_from_slice(&ciphertext);
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    fn decode_bytes(&self, encoded: &str) -> Option<Vec<u8>> {
+        let bytes = URL_SAFE_NO_PAD.decode(encoded).ok()?;
+        if bytes.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        self.key
+            .name_cipher()
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_SALT: &[u8] = b"0123456789abcdef";
+
+    #[test]
+    fn test_roundtrip() {
+        let codec = EncryptedNameCodec::new(SessionKey::derive("secret", TEST_SALT).unwrap());
+        let encoded = codec.encode("Holiday 2024.mkv");
+        assert!(!encoded.contains('/'));
+        assert_eq!(codec.decode(&encoded), Some("Holiday 2024.mkv".to_string()));
+    }
+
+    #[test]
+    fn test_wrong_key_does_not_decode() {
+        let codec = EncryptedNameCodec::new(SessionKey::derive("secret", TEST_SALT).unwrap());
+        let other = EncryptedNameCodec::new(SessionKey::derive("guess", TEST_SALT).unwrap());
+        let encoded = codec.encode("Holiday 2024.mkv");
+        assert_eq!(other.decode(&encoded), None);
+    }
+
+    #[test]
+    fn test_decode_garbage() {
+        let codec = EncryptedNameCodec::new(SessionKey::derive("secret", TEST_SALT).unwrap());
+        assert_eq!(codec.decode("short"), None);
+        assert_eq!(codec.decode("!!!"), None);
+    }
+
+    #[test]
+    fn test_verifier_depends_on_passphrase_and_salt() {
+        let key = SessionKey::derive("secret", TEST_SALT).unwrap();
+        assert_eq!(
+            key.verifier(),
+            SessionKey::derive("secret", TEST_SALT).unwrap().verifier()
+        );
+        assert_ne!(
+            key.verifier(),
+            SessionKey::derive("guess", TEST_SALT).unwrap().verifier()
+        );
+        assert_ne!(
+            key.verifier(),
+            SessionKey::derive("secret", b"fedcba9876543210")
+                .unwrap()
+                .verifier()
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use data_encoding::BASE32_NOPAD;
+use sha2::{Digest, Sha256};
+
+use super::keyring::KEYRING_FILE_NAME;
+use super::name_codec::NameCodec;
+
+/// Hidden per-directory sidecar that maps hashed physical names to the names
+/// they stand for.
+pub const INDEX_FILE_NAME: &str = ".dat_index";
+
+/// Where the sidecar is written before it replaces the old one.
+const INDEX_TEMP_FILE_NAME: &str = ".dat_index.tmp";
+
+pub const HASHED_SCHEME: &str = "hashed";
+
+pub const HASHED_PREFIX: &str = ".dath_";
+
+/// Bytes of the SHA-256 digest kept in the physical name.
+const HASH_LEN: usize = 15;
+
+pub fn is_hashed_name(physical_name: &str) -> bool {
+    physical_name.starts_with(HASHED_PREFIX)
+}
+
+/// Whether the name is the sidecar of its directory, the file it is written
+/// to first, or the keyring of a library. Those belong with the directory,
+/// never renamed on their own.
+pub fn is_sidecar_name(physical_name: &str) -> bool {
+    physical_name == INDEX_FILE_NAME
+        || physical_name == INDEX_TEMP_FILE_NAME
+        || physical_name == KEYRING_FILE_NAME
+}
+
+/// Names that are too long to encode are replaced by a short hash of the
+/// name. The hash can't be decoded; the name itself is kept in the sidecar
+/// index of the directory, so decoding always returns None and lookups go
+/// through `lookup_name`.
+pub struct HashedNameCodec;
+
+impl NameCodec for HashedNameCodec {
+    fn id(&self) -> &'static str {
+        HASHED_SCHEME
+    }
+
+    fn prefix(&self) -> &'static str {
+        HASHED_PREFIX
+    }
+
+    fn encode(&self, name: &str) -> String {
+        let digest = Sha256::digest(name.as_bytes());
+        BASE32_NOPAD
+            .encode(&digest[..HASH_LEN])
+            .to_ascii_lowercase()
+    }
+
+    fn decode_bytes(&self, _encoded: &str) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// The sidecar index of one directory.
+#[derive(Debug, Default)]
+pub struct NameIndex {
+    names: BTreeMap<String, String>,
+}
+
+impl NameIndex {
+    /// Loads the index of `dir`; a missing sidecar is an empty index.
+    pub fn load(dir: &Path) -> Result<NameIndex, String> {
+        let index_path = dir.join(INDEX_FILE_NAME);
+        let contents = match fs::read_to_string(&index_path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(NameIndex::default()),
+            Err(e) => return Err(format!("Failed to read {}: {}", index_path.display(), e)),
+        };
+        let names = serde_json::from_str(&contents)
+            .map_err(|e| format!("Failed to parse {}: {}", index_path.display(), e))?;
+        Ok(NameIndex { names })
+    }
+
+    /// Writes the index back to `dir`, removing the sidecar once it's empty.
+    pub fn save(&self, dir: &Path) -> Result<(), String> {
+        let index_path = dir.join(INDEX_FILE_NAME);
+        if self.names.is_empty() {
+            return match fs::remove_file(&index_path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    Err(format!("Failed to remove {}: {}", index_path.display(), e))
+                }
+                _ => Ok(()),
+            };
+        }
+
+        let contents = serde_json::to_string_pretty(&self.names)
+            .map_err(|e| format!("Failed to serialize name index: {}", e))?;
+        let temp_path = dir.join(INDEX_TEMP_FILE_NAME);
+        fs::write(&temp_path, contents)
+            .and_then(|_| fs::rename(&temp_path, &index_path))
+            .map_err(|e| format!("Failed to write {}: {}", index_path.display(), e))
+    }
+
+    pub fn get(&self, physical_name: &str) -> Option<&str> {
+        self.names.get(physical_name).map(String::as_str)
+    }
+
+    pub fn insert(&mut self, physical_name: &str, name: &str) {
+        self.names
+            .insert(physical_name.to_string(), name.to_string());
+    }
+
+    pub fn remove(&mut self, physical_name: &str) -> Option<String> {
+        self.names.remove(physical_name)
+    }
+}
+
+/// The name a hashed entry of `dir` stands for, if the sidecar knows it.
+pub fn lookup_name(dir: &Path, physical_name: &str) -> Option<String> {
+    let index = NameIndex::load(dir).ok()?;
+    index.get(physical_name).map(str::to_string)
+}
+
+/// Records `name` for the hashed entry `physical_name` of `dir`.
+pub fn record_name(dir: &Path, physical_name: &str, name: &str) -> Result<(), String> {
+    let mut index = NameIndex::load(dir)?;
+    index.insert(physical_name, name);
+    index.save(dir)
+}
+
+/// Drops the entry from the sidecar of `dir` and returns the name it had.
+pub fn forget_name(dir: &Path, physical_name: &str) -> Result<Option<String>, String> {
+    let mut index = NameIndex::load(dir)?;
+    let name = index.remove(physical_name);
+    if name.is_some() {
+        index.save(dir)?;
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_hashed_name_is_short_and_stable() {
+        let long_name = "x".repeat(400);
+        let encoded = HashedNameCodec.encode(&long_name);
+        assert_eq!(encoded.len(), 24);
+        assert_eq!(encoded, HashedNameCodec.encode(&long_name));
+        assert_ne!(encoded, HashedNameCodec.encode("other"));
+    }
+
+    #[test]
+    fn test_record_lookup_and_forget() {
+        let tmp = TempDir::new().unwrap();
+        record_name(tmp.path(), ".dath_abc", "A very long title.mkv").unwrap();
+        assert!(tmp.path().join(INDEX_FILE_NAME).exists());
+        assert_eq!(
+            lookup_name(tmp.path(), ".dath_abc"),
+            Some("A very long title.mkv".to_string())
+        );
+
+        assert_eq!(
+            forget_name(tmp.path(), ".dath_abc").unwrap(),
+            Some("A very long title.mkv".to_string())
+        );
+        assert_eq!(lookup_name(tmp.path(), ".dath_abc"), None);
+        // The sidecar goes away with its last entry
+        assert!(!tmp.path().join(INDEX_FILE_NAME).exists());
+    }
+}
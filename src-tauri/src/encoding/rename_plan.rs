@@ -0,0 +1,808 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::encoded_dir::{
+    check_encoded_length, encode_name_with, has_encoding_prefix, is_encoded_name,
+    name_state_with_index, NameState,
+};
+use super::name_codec::NameCodec;
+use super::name_crypto::{SessionKey, ENCRYPTED_SCHEME};
+use super::name_index::{
+    self, is_hashed_name, is_sidecar_name, HashedNameCodec, NameIndex, HASHED_SCHEME,
+};
+use crate::models::length_check::LengthIssue;
+use crate::models::rename_report::{CollisionPolicy, CollisionResolution, PlanIssueKind};
+
+/// A single physical rename. Both paths are valid at the moment the step is
+/// applied: children are planned before their parent, so a step never refers
+/// to a directory that an earlier step has already renamed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenameStep {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// Set when `to` is a hashed name: the name to record for it in the
+    /// sidecar index of its directory
+    pub indexed_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Direction<'a> {
+    Encode(&'a dyn NameCodec),
+    Decode,
+    /// Rewrites names encoded with one scheme into another one
+    Reencode {
+        from: &'a dyn NameCodec,
+        to: &'a dyn NameCodec,
+    },
+}
+
+/// Result of applying a plan. `applied` lists the steps that are in effect
+/// on disk. On failure the applied steps are undone again: the ones that
+/// could be reverted move to `rolled_back`, and any left in `applied` could
+/// not.
+#[derive(Debug, Default)]
+pub struct PlanOutcome {
+    pub applied: Vec<RenameStep>,
+    pub rolled_back: Vec<RenameStep>,
+    /// Steps that revert `applied`, in the order they have to be applied
+    pub undo: Vec<RenameStep>,
+    pub error: Option<String>,
+}
+
+/// Something that would go wrong if the plan were applied as-is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanIssue {
+    pub path: PathBuf,
+    pub kind: PlanIssueKind,
+}
+
+/// A target name that was already taken, and what the policy made of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Collision {
+    pub path: PathBuf,
+    pub target: PathBuf,
+    pub resolution: CollisionResolution,
+    pub resolved: Option<PathBuf>,
+}
+
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub steps: Vec<RenameStep>,
+    pub issues: Vec<PlanIssue>,
+    pub collisions: Vec<Collision>,
+    /// Entries of each directory looked at so far, by the name they are
+    /// shown with
+    display_names: HashMap<PathBuf, HashMap<String, Vec<PathBuf>>>,
+    /// Sources of the steps resolved so far
+    sources: HashSet<PathBuf>,
+    /// Targets of the steps resolved so far
+    targets: HashSet<PathBuf>,
+    /// Directory and display name of the targets of the steps resolved so far
+    planned_names: HashSet<(PathBuf, String)>,
+}
+
+impl Plan {
+    /// Whether a collision stops the plan from being applied.
+    pub fn has_failed_collisions(&self) -> bool {
+        self.collisions
+            .iter()
+            .any(|c| c.resolution == CollisionResolution::Failed)
+    }
+
+    /// Counts the step as planned when looking for collisions, whether or not
+    /// it has been pushed to `steps` yet.
+    fn reserve(&mut self, step: &RenameStep, display_name: &str) {
+        self.sources.insert(step.from.clone());
+        self.targets.insert(step.to.clone());
+        if let Some(dir) = step.to.parent() {
+            self.planned_names
+                .insert((dir.to_path_buf(), display_name.to_string()));
+        }
+    }
+}
+
+/// Plans every rename needed to encode or decode `path` and, if it is a
+/// directory, everything below it. Nothing on disk is touched. `key` is
+/// needed to decode encrypted names.
+pub fn plan(
+    path: &Path,
+    direction: Direction,
+    policy: CollisionPolicy,
+    key: Option<&SessionKey>,
+) -> Result<Plan, String> {
+    let parent = path.parent().ok_or("No parent directory")?;
+    let mut plan = Plan::default();
+    let context = PlanContext {
+        direction,
+        policy,
+        key,
+    };
+    let index = NameIndex::load(parent).unwrap_or_default();
+    plan_recursive(path, parent, &index, &context, &mut plan)?;
+    Ok(plan)
+}
+
+struct PlanContext<'a> {
+    direction: Direction<'a>,
+    policy: CollisionPolicy,
+    key: Option<&'a SessionKey>,
+}
+
+/// `final_parent` is the path the parent directory will have once the whole
+/// plan has been applied, used to check the length of the resulting path.
+/// `index` is the sidecar index of the parent directory.
+fn plan_recursive(
+    path: &Path,
+    final_parent: &Path,
+    index: &NameIndex,
+    context: &PlanContext,
+    plan: &mut Plan,
+) -> Result<(), String> {
+    let policy = context.policy;
+    let name = path.file_name().ok_or("Invalid file name")?;
+
+    let step = match name.to_str() {
+        None => {
+            plan.issues.push(PlanIssue {
+                path: path.to_path_buf(),
+                kind: PlanIssueKind::InvalidUtf8,
+            });
+            None
+        }
+        Some(name) => match context.direction {
+            Direction::Encode(codec) if !is_encoded_name(name) => {
+                let plain_path = final_parent.join(name);
+                let codec = fitting_codec(codec, &plain_path);
+                check_length(path, &plain_path, codec, plan);
+                resolve_step(path, name, Some(codec), policy, context.key, plan)?
+            }
+            Direction::Encode(_) => None,
+            Direction::Decode => match name_state_with_index(index, name, context.key) {
+                NameState::Decoded(decoded) => {
+                    resolve_step(path, &decoded, None, policy, context.key, plan)?
+                }
+                NameState::Locked => {
+                    plan.issues.push(PlanIssue {
+                        path: path.to_path_buf(),
+                        kind: PlanIssueKind::Locked,
+                    });
+                    None
+                }
+                NameState::Plain if has_encoding_prefix(name) => {
+                    plan.issues.push(PlanIssue {
+                        path: path.to_path_buf(),
+                        kind: PlanIssueKind::Undecodable,
+                    });
+                    None
+                }
+                NameState::Plain => None,
+            },
+            Direction::Reencode { from, to } => match name.strip_prefix(from.prefix()) {
+                Some(encoded) => match from.decode(encoded) {
+                    Some(decoded) => {
+                        check_length(path, &final_parent.join(&decoded), to, plan);
+                        resolve_step(path, &decoded, Some(to), policy, context.key, plan)?
+                    }
+                    None => {
+                        plan.issues.push(PlanIssue {
+                            path: path.to_path_buf(),
+                            kind: PlanIssueKind::Undecodable,
+                        });
+                        None
+                    }
+                },
+                None => None,
+            },
+        },
+    };
+
+    if path.is_dir() {
+        let final_name = step.as_ref().and_then(|s| s.to.file_name()).unwrap_or(name);
+        let final_dir = final_parent.join(final_name);
+        let entries = fs::read_dir(path).map_err(|e| format!("Failed to read directory: {}", e))?;
+        let child_index = NameIndex::load(path).unwrap_or_default();
+        for entry in entries {
+            let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
+            if entry.file_name().to_str().is_some_and(is_sidecar_name) {
+                continue;
+            }
+            plan_recursive(&entry.path(), &final_dir, &child_index, context, plan)?;
+        }
+    }
+
+    if let Some(step) = step {
+        plan.steps.push(step);
+    }
+
+    Ok(())
+}
+
+/// `codec`, unless the encoded name of `plain_path` would be too long for a
+/// single path component. Such names get a short hashed name instead, with
+/// the name itself kept in the directory's sidecar index. Encrypted names
+/// never fall back, since the sidecar would store them in the clear.
+pub fn fitting_codec<'a>(codec: &'a dyn NameCodec, plain_path: &Path) -> &'a dyn NameCodec {
+    match check_encoded_length(codec, plain_path) {
+        Some(LengthIssue::ComponentTooLong { .. }) if codec.id() != ENCRYPTED_SCHEME => {
+            &HashedNameCodec
+        }
+        _ => codec,
+    }
+}
+
+/// Flags `path` if `plain_path` (its plain name at its final location) would
+/// become too long once encoded with `codec`.
+fn check_length(path: &Path, plain_path: &Path, codec: &dyn NameCodec, plan: &mut Plan) {
+    if check_encoded_length(codec, plain_path).is_some() {
+        plan.issues.push(PlanIssue {
+            path: path.to_path_buf(),
+            kind: PlanIssueKind::TooLong,
+        });
+    }
+}
+
+/// Works out the step that renames `from` so that it shows up as
+/// `display_name`, encoding the name if a codec is given. If the target is already
+/// taken on disk or by a step in `plan`, or a sibling is shown with the same
+/// name, the collision is recorded and `policy` decides the outcome; `None`
+/// means the entry is left alone. `key` decrypts the names of siblings. The
+/// returned step counts as planned for the steps resolved after it.
+pub fn resolve_step(
+    from: &Path,
+    display_name: &str,
+    codec: Option<&dyn NameCodec>,
+    policy: CollisionPolicy,
+    key: Option<&SessionKey>,
+    plan: &mut Plan,
+) -> Result<Option<RenameStep>, String> {
+    let parent = from.parent().ok_or("No parent directory")?;
+    let physical = |name: &str| match codec {
+        Some(codec) => encode_name_with(codec, name),
+        None => name.to_string(),
+    };
+    let step = |name: String| RenameStep {
+        from: from.to_path_buf(),
+        to: parent.join(physical(&name)),
+        indexed_name: codec.filter(|c| c.id() == HASHED_SCHEME).map(|_| name),
+    };
+    let target = parent.join(physical(display_name));
+
+    if !is_taken(&target, display_name, from, key, plan) {
+        let step = step(display_name.to_string());
+        plan.reserve(&step, display_name);
+        return Ok(Some(step));
+    }
+
+    let (resolution, resolved) = match policy {
+        CollisionPolicy::Fail => (CollisionResolution::Failed, Some(display_name.to_string())),
+        CollisionPolicy::Skip => (CollisionResolution::Skipped, None),
+        CollisionPolicy::Suffix => {
+            let is_dir = from.is_dir();
+            let free = (2..)
+                .map(|n| with_suffix(display_name, n, is_dir))
+                .find(|name| !is_taken(&parent.join(physical(name)), name, from, key, plan))
+                .expect("unbounded range");
+            (CollisionResolution::Suffixed, Some(free))
+        }
+    };
+
+    if resolution == CollisionResolution::Failed {
+        plan.issues.push(PlanIssue {
+            path: from.to_path_buf(),
+            kind: PlanIssueKind::Collision,
+        });
+    }
+    let resolved = resolved.map(|name| {
+        let resolved = step(name.clone());
+        plan.reserve(&resolved, &name);
+        resolved
+    });
+    plan.collisions.push(Collision {
+        path: from.to_path_buf(),
+        target,
+        resolution,
+        resolved: resolved
+            .as_ref()
+            .filter(|_| resolution == CollisionResolution::Suffixed)
+            .map(|s| s.to.clone()),
+    });
+
+    Ok(resolved)
+}
+
+/// Whether `from` can't become `target`, shown as `display_name`: the path
+/// is taken, or another entry of the directory is (or will be) shown with the
+/// same name, e.g. because it uses another scheme.
+fn is_taken(
+    target: &Path,
+    display_name: &str,
+    from: &Path,
+    key: Option<&SessionKey>,
+    plan: &mut Plan,
+) -> bool {
+    if target == from {
+        return false;
+    }
+    if fs::symlink_metadata(target).is_ok() || plan.targets.contains(target) {
+        return true;
+    }
+
+    let Some(dir) = target.parent() else {
+        return false;
+    };
+    if plan
+        .planned_names
+        .contains(&(dir.to_path_buf(), display_name.to_string()))
+    {
+        return true;
+    }
+    // Entries with a planned step are shown with the name of the step
+    display_names(&mut plan.display_names, dir, key)
+        .get(display_name)
+        .is_some_and(|shown| {
+            shown
+                .iter()
+                .any(|path| path != from && !plan.sources.contains(path))
+        })
+}
+
+/// The entries of `dir` by the names they are shown with, read once per
+/// directory into `cache`. Encrypted names that `key` can't decrypt are left
+/// out.
+fn display_names<'a>(
+    cache: &'a mut HashMap<PathBuf, HashMap<String, Vec<PathBuf>>>,
+    dir: &Path,
+    key: Option<&SessionKey>,
+) -> &'a HashMap<String, Vec<PathBuf>> {
+    cache.entry(dir.to_path_buf()).or_insert_with(|| {
+        let mut names: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        let index = NameIndex::load(dir).unwrap_or_default();
+        let Ok(entries) = fs::read_dir(dir) else {
+            return names;
+        };
+        for entry in entries.flatten() {
+            let Ok(physical) = entry.file_name().into_string() else {
+                continue;
+            };
+            let name = match name_state_with_index(&index, &physical, key) {
+                NameState::Plain => physical,
+                NameState::Decoded(decoded) => decoded,
+                NameState::Locked => continue,
+            };
+            names.entry(name).or_default().push(entry.path());
+        }
+        names
+    })
+}
+
+/// Turns "name.mp4" into "name (2).mp4". Directories and dotfiles keep their
+/// whole name in front of the suffix.
+pub fn with_suffix(name: &str, n: u32, is_dir: bool) -> String {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 && !is_dir => format!("{} ({}){}", &name[..dot], n, &name[dot..]),
+        _ => format!("{} ({})", name, n),
+    }
+}
+
+/// Returns the path `path` will have once `steps` (planned for it) are applied.
+pub fn final_path(path: &Path, steps: &[RenameStep]) -> PathBuf {
+    steps
+        .iter()
+        .find(|step| step.from == path)
+        .map(|step| step.to.clone())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Applies the steps in order. If one fails, the steps already applied are
+/// reverted in reverse order so the tree ends up as it was before.
+pub fn apply(steps: &[RenameStep]) -> PlanOutcome {
+    apply_checked(steps, |_, _| Ok(()))
+}
+
+/// Like `apply`, but runs `check` right before each step (with its index)
+/// and treats an error from it like a failed rename.
+pub fn apply_checked(
+    steps: &[RenameStep],
+    mut check: impl FnMut(usize, &RenameStep) -> Result<(), String>,
+) -> PlanOutcome {
+    let mut outcome = PlanOutcome::default();
+    let mut inverses = Vec::new();
+
+    for (index, step) in steps.iter().enumerate() {
+        match check(index, step).and_then(|_| apply_step(step)) {
+            Ok(inverse) => inverses.push(inverse),
+            Err(e) => {
+                outcome.error = Some(e);
+                break;
+            }
+        }
+        outcome.applied.push(step.clone());
+    }
+
+    if let Some(error) = outcome.error.as_mut() {
+        let mut still_applied = Vec::new();
+        for (step, inverse) in outcome.applied.drain(..).zip(&inverses).rev() {
+            match apply_step(inverse) {
+                Ok(_) => outcome.rolled_back.push(step),
+                Err(e) => {
+                    error.push_str(&format!(
+                        "; failed to roll back {}: {}",
+                        step.to.display(),
+                        e
+                    ));
+                    still_applied.push(step);
+                }
+            }
+        }
+        still_applied.reverse();
+        outcome.applied = still_applied;
+    } else {
+        inverses.reverse();
+        outcome.undo = inverses;
+    }
+
+    outcome
+}
+
+/// Renames and keeps the sidecar indexes of both directories in sync.
+/// Returns the step that undoes it.
+fn apply_step(step: &RenameStep) -> Result<RenameStep, String> {
+    fs::rename(&step.from, &step.to)
+        .map_err(|e| format!("Failed to rename {}: {}", step.from.display(), e))?;
+    finish_step(step)
+}
+
+/// Applies a single step like `apply`, but a rename that fails because
+/// `step.to` is on another file system is reported as `Ok(None)`, with
+/// nothing changed.
+pub fn apply_same_device(step: &RenameStep) -> Result<Option<RenameStep>, String> {
+    match fs::rename(&step.from, &step.to) {
+        Ok(()) => finish_step(step).map(Some),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => Ok(None),
+        Err(e) => Err(format!("Failed to rename {}: {}", step.from.display(), e)),
+    }
+}
+
+fn finish_step(step: &RenameStep) -> Result<RenameStep, String> {
+    match sync_index(step) {
+        Ok(previous_name) => Ok(RenameStep {
+            from: step.to.clone(),
+            to: step.from.clone(),
+            indexed_name: previous_name,
+        }),
+        Err(e) => {
+            let _ = fs::rename(&step.to, &step.from);
+            Err(e)
+        }
+    }
+}
+
+/// Moves the entry for `step` from one sidecar index to the other, once the
+/// entry itself has been moved. Returns the name `step.from` had in its
+/// sidecar index, if it had one.
+pub fn sync_index(step: &RenameStep) -> Result<Option<String>, String> {
+    let from_name = step
+        .from
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    let previous_name = match step.from.parent() {
+        Some(dir) if is_hashed_name(from_name) => name_index::forget_name(dir, from_name)?,
+        _ => None,
+    };
+
+    if let (Some(name), Some(dir), Some(to_name)) = (
+        &step.indexed_name,
+        step.to.parent(),
+        step.to.file_name().and_then(|n| n.to_str()),
+    ) {
+        name_index::record_name(dir, to_name, name)?;
+    }
+
+    Ok(previous_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::name_codec::{default_codec, legacy_codec};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_plan_encode_children_before_parent() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("parent");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("child.txt"), "").unwrap();
+
+        let steps = plan(
+            &dir,
+            Direction::Encode(default_codec()),
+            CollisionPolicy::Fail,
+            None,
+        )
+        .unwrap()
+        .steps;
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].from, dir.join("child.txt"));
+        assert_eq!(
+            steps[0].to,
+            dir.join(encode_name_with(default_codec(), "child.txt"))
+        );
+        assert_eq!(steps[1].from, dir);
+        assert_eq!(
+            final_path(&dir, &steps),
+            tmp.path().join(encode_name_with(default_codec(), "parent"))
+        );
+    }
+
+    #[test]
+    fn test_plan_skips_names_already_in_target_form() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("plain");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join(".dat_VGVzdA=="), "").unwrap();
+
+        assert_eq!(
+            plan(
+                &dir,
+                Direction::Encode(default_codec()),
+                CollisionPolicy::Fail,
+                None
+            )
+            .unwrap()
+            .steps
+            .len(),
+            1
+        );
+        assert_eq!(
+            plan(&dir, Direction::Decode, CollisionPolicy::Fail, None)
+                .unwrap()
+                .steps
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_plan_flags_decode_collision() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("dir");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join(".dat_VGVzdA=="), "").unwrap();
+        fs::write(dir.join("Test"), "").unwrap();
+
+        let plan = plan(&dir, Direction::Decode, CollisionPolicy::Fail, None).unwrap();
+        assert_eq!(plan.issues.len(), 1);
+        assert_eq!(plan.issues[0].kind, PlanIssueKind::Collision);
+        assert_eq!(plan.issues[0].path, dir.join(".dat_VGVzdA=="));
+        assert_eq!(plan.collisions[0].resolution, CollisionResolution::Failed);
+    }
+
+    #[test]
+    fn test_collisions_compare_display_names() {
+        use crate::encoding::name_crypto::EncryptedNameCodec;
+
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join(".dat_VGVzdA=="), "").unwrap();
+        fs::write(tmp.path().join("Test"), "").unwrap();
+
+        // Encoding "Test" would leave two entries shown as "Test"
+        let plan = plan(
+            &tmp.path().join("Test"),
+            Direction::Encode(default_codec()),
+            CollisionPolicy::Fail,
+            None,
+        )
+        .unwrap();
+        assert_eq!(plan.issues[0].kind, PlanIssueKind::Collision);
+
+        // Encrypting again gives another physical name for the same name
+        let key = SessionKey::derive("secret", b"0123456789abcdef").unwrap();
+        let codec = EncryptedNameCodec::new(key.clone());
+        let dir = tmp.path().join("encrypted");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join(encode_name_with(&codec, "clip.mp4")), "").unwrap();
+        fs::write(dir.join("other.mp4"), "").unwrap();
+        let mut plan = Plan::default();
+        resolve_step(
+            &dir.join("other.mp4"),
+            "clip.mp4",
+            Some(&codec),
+            CollisionPolicy::Fail,
+            Some(&key),
+            &mut plan,
+        )
+        .unwrap();
+        assert!(plan.has_failed_collisions());
+    }
+
+    #[test]
+    fn test_plan_skip_policy_drops_colliding_step() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("dir");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join(".dat_VGVzdA=="), "").unwrap();
+        fs::write(dir.join("Test"), "").unwrap();
+
+        let plan = plan(&dir, Direction::Decode, CollisionPolicy::Skip, None).unwrap();
+        assert!(plan.steps.is_empty());
+        assert!(plan.issues.is_empty());
+        assert_eq!(plan.collisions[0].resolution, CollisionResolution::Skipped);
+    }
+
+    #[test]
+    fn test_plan_suffix_policy_picks_free_name() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("dir");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join(encode_name_with(default_codec(), "clip.mp4")), "").unwrap();
+        fs::write(dir.join("clip.mp4"), "").unwrap();
+        fs::write(dir.join("clip (2).mp4"), "").unwrap();
+
+        let plan = plan(&dir, Direction::Decode, CollisionPolicy::Suffix, None).unwrap();
+        assert_eq!(plan.steps[0].to, dir.join("clip (3).mp4"));
+        assert_eq!(plan.collisions[0].resolved, Some(dir.join("clip (3).mp4")));
+    }
+
+    #[test]
+    fn test_plan_reencode_only_touches_source_scheme() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join(".dat_cGFyZW50");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join(".dat_VGVzdA=="), "").unwrap();
+        fs::write(dir.join(encode_name_with(default_codec(), "new.mp4")), "").unwrap();
+        fs::write(dir.join("plain.mp4"), "").unwrap();
+
+        let direction = Direction::Reencode {
+            from: legacy_codec(),
+            to: default_codec(),
+        };
+        let plan = plan(&dir, direction, CollisionPolicy::Fail, None).unwrap();
+        let targets: Vec<_> = plan.steps.iter().map(|s| s.to.clone()).collect();
+        assert_eq!(
+            targets,
+            vec![dir.join(".dat2_VGVzdA"), tmp.path().join(".dat2_cGFyZW50")]
+        );
+    }
+
+    #[test]
+    fn test_plan_decode_locked_names() {
+        use crate::encoding::name_crypto::EncryptedNameCodec;
+
+        let tmp = TempDir::new().unwrap();
+        let key = SessionKey::derive("secret", b"0123456789abcdef").unwrap();
+        let file = tmp.path().join(encode_name_with(
+            &EncryptedNameCodec::new(key.clone()),
+            "Test",
+        ));
+        fs::write(&file, "").unwrap();
+
+        let locked = plan(&file, Direction::Decode, CollisionPolicy::Fail, None).unwrap();
+        assert!(locked.steps.is_empty());
+        assert_eq!(locked.issues[0].kind, PlanIssueKind::Locked);
+
+        let unlocked = plan(&file, Direction::Decode, CollisionPolicy::Fail, Some(&key)).unwrap();
+        assert_eq!(unlocked.steps[0].to, tmp.path().join("Test"));
+
+        let encode = plan(
+            &file,
+            Direction::Encode(default_codec()),
+            CollisionPolicy::Fail,
+            None,
+        )
+        .unwrap();
+        assert!(encode.steps.is_empty());
+    }
+
+    #[test]
+    fn test_with_suffix() {
+        assert_eq!(with_suffix("name.mp4", 2, false), "name (2).mp4");
+        assert_eq!(with_suffix("name.mp4", 2, true), "name.mp4 (2)");
+        assert_eq!(with_suffix(".hidden", 3, false), ".hidden (3)");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_plan_flags_too_long_using_final_parent() {
+        let tmp = TempDir::new().unwrap();
+        // Every component fits once encoded, and so does the plain path, but
+        // the deepest paths don't once all their parents are encoded too
+        let top = tmp.path().join("d".repeat(180));
+        let mut deepest = top.clone();
+        for _ in 0..17 {
+            deepest.push("d".repeat(180));
+        }
+        fs::create_dir_all(&deepest).unwrap();
+
+        let plan = plan(
+            &top,
+            Direction::Encode(default_codec()),
+            CollisionPolicy::Fail,
+            None,
+        )
+        .unwrap();
+        let too_long: Vec<_> = plan
+            .issues
+            .iter()
+            .filter(|i| i.kind == PlanIssueKind::TooLong)
+            .map(|i| i.path.clone())
+            .collect();
+        assert!(too_long.contains(&deepest));
+        assert!(!too_long.contains(&top));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_plan_flags_invalid_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let tmp = TempDir::new().unwrap();
+        let file = tmp.path().join(OsStr::from_bytes(b"bad\xFFname"));
+        fs::write(&file, "").unwrap();
+
+        let plan = plan(
+            &file,
+            Direction::Encode(default_codec()),
+            CollisionPolicy::Fail,
+            None,
+        )
+        .unwrap();
+        assert!(plan.steps.is_empty());
+        assert_eq!(plan.issues[0].kind, PlanIssueKind::InvalidUtf8);
+    }
+
+    #[test]
+    fn test_plan_flags_names_decoding_to_invalid_utf8() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("dir");
+        fs::create_dir(&dir).unwrap();
+        // "__4" is 0xFF 0xFE in URL-safe base64
+        let corrupt = dir.join(".dat2___4");
+        fs::write(&corrupt, "").unwrap();
+        fs::write(dir.join(encode_name_with(default_codec(), "fine")), "").unwrap();
+
+        let plan = plan(&dir, Direction::Decode, CollisionPolicy::Fail, None).unwrap();
+        assert_eq!(plan.steps.len(), 1);
+        assert_eq!(
+            plan.issues,
+            [PlanIssue {
+                path: corrupt,
+                kind: PlanIssueKind::Undecodable
+            }]
+        );
+    }
+
+    #[test]
+    fn test_apply_rolls_back_on_failure() {
+        let tmp = TempDir::new().unwrap();
+        let a = tmp.path().join("a.txt");
+        fs::write(&a, "a").unwrap();
+
+        let steps = vec![
+            RenameStep {
+                from: a.clone(),
+                to: tmp.path().join("b.txt"),
+                indexed_name: None,
+            },
+            RenameStep {
+                from: tmp.path().join("missing"),
+                to: tmp.path().join("c.txt"),
+                indexed_name: None,
+            },
+        ];
+        let outcome = apply(&steps);
+
+        assert!(outcome.error.is_some());
+        assert!(outcome.applied.is_empty());
+        assert_eq!(outcome.rolled_back.len(), 1);
+        assert!(a.exists());
+        assert!(!tmp.path().join("b.txt").exists());
+    }
+}
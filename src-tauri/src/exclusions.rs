@@ -0,0 +1,460 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+
+use crate::encoding::content_crypto::is_temp_name;
+use crate::encoding::encoded_dir::{name_state_in, NameState};
+use crate::encoding::name_crypto::SessionKey;
+use crate::encoding::name_index::is_sidecar_name;
+use crate::models::exclusion_rules::ExclusionRules;
+
+/// The exclusion rules in effect, and the file they are saved in.
+pub struct ExclusionState {
+    path: PathBuf,
+    current: RwLock<Arc<Exclusions>>,
+}
+
+pub fn init(app_data_dir: &Path) -> Result<ExclusionState, String> {
+    std::fs::create_dir_all(app_data_dir)
+        .map_err(|e| format!("Failed to create app data dir: {}", e))?;
+
+    let path = rules_path(app_data_dir);
+    let rules = match fs::read(&path) {
+        Ok(data) => serde_json::from_slice(&data)
+            .map_err(|e| format!("Failed to parse exclusion rules: {}", e))?,
+        Err(_) => ExclusionRules::default(),
+    };
+    Ok(ExclusionState {
+        path,
+        current: RwLock::new(Arc::new(Exclusions::new(rules)?)),
+    })
+}
+
+fn rules_path(app_data_dir: &Path) -> PathBuf {
+    app_data_dir.join("exclusions.json")
+}
+
+impl ExclusionState {
+    /// The default rules, for when the saved ones can't be loaded. Saving
+    /// new rules still replaces the file.
+    pub fn with_defaults(app_data_dir: &Path) -> ExclusionState {
+        ExclusionState {
+            path: rules_path(app_data_dir),
+            current: RwLock::new(Arc::new(Exclusions::default())),
+        }
+    }
+
+    pub fn get(&self) -> Arc<Exclusions> {
+        match self.current.read() {
+            Ok(current) => current.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    pub fn set(&self, rules: ExclusionRules) -> Result<(), String> {
+        let exclusions = Exclusions::new(rules)?;
+        let data = serde_json::to_vec_pretty(exclusions.rules())
+            .map_err(|e| format!("Failed to serialize exclusion rules: {}", e))?;
+        fs::write(&self.path, data)
+            .map_err(|e| format!("Failed to save exclusion rules: {}", e))?;
+        let mut current = self
+            .current
+            .write()
+            .map_err(|e| format!("Exclusion lock error: {}", e))?;
+        *current = Arc::new(exclusions);
+        Ok(())
+    }
+}
+
+/// Compiled `ExclusionRules`.
+pub struct Exclusions {
+    rules: ExclusionRules,
+    patterns: Gitignore,
+    /// Parsed ignore files by path, with the modified time they were read at
+    ignore_files: Mutex<HashMap<PathBuf, (SystemTime, Arc<Gitignore>)>>,
+}
+
+impl Default for Exclusions {
+    fn default() -> Self {
+        Exclusions::new(ExclusionRules::default()).expect("default rules are valid")
+    }
+}
+
+impl Exclusions {
+    pub fn new(rules: ExclusionRules) -> Result<Exclusions, String> {
+        let mut builder = GitignoreBuilder::new("");
+        for pattern in &rules.patterns {
+            builder
+                .add_line(None, pattern)
+                .map_err(|e| format!("Invalid pattern {}: {}", pattern, e))?;
+        }
+        let patterns = builder
+            .build()
+            .map_err(|e| format!("Invalid exclusion patterns: {}", e))?;
+        Ok(Exclusions {
+            rules,
+            patterns,
+            ignore_files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn rules(&self) -> &ExclusionRules {
+        &self.rules
+    }
+
+    /// The rules for the entries of `dir`, including the ignore files of
+    /// `dir` and all its ancestors. Patterns containing a `/` match paths
+    /// relative to `dir`.
+    pub fn in_dir<'a>(&'a self, dir: &Path, key: Option<&'a SessionKey>) -> DirExclusions<'a> {
+        let mut ancestors: Vec<&Path> = dir.ancestors().collect();
+        ancestors.reverse();
+        DirExclusions {
+            exclusions: self,
+            key,
+            dir: dir.to_path_buf(),
+            relative: PathBuf::new(),
+            ignores: ancestors
+                .into_iter()
+                .filter_map(|d| self.load_ignore_file(d))
+                .collect(),
+        }
+    }
+
+    /// The ignore file of `dir`, parsed again only when it was modified.
+    fn load_ignore_file(&self, dir: &Path) -> Option<Arc<Gitignore>> {
+        let path = dir.join(self.rules.ignore_file.as_deref()?);
+        let metadata = fs::metadata(&path).ok().filter(|m| m.is_file())?;
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+
+        let mut cache = match self.ignore_files.lock() {
+            Ok(cache) => cache,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some((cached_at, ignore)) = cache.get(&path) {
+            if *cached_at == modified {
+                return Some(ignore.clone());
+            }
+        }
+        // Lines with bad globs are skipped, like git does
+        let (ignore, _) = Gitignore::new(&path);
+        let ignore = Arc::new(ignore);
+        cache.insert(path, (modified, ignore.clone()));
+        Some(ignore)
+    }
+}
+
+/// The exclusion rules as they apply inside one directory.
+pub struct DirExclusions<'a> {
+    exclusions: &'a Exclusions,
+    key: Option<&'a SessionKey>,
+    dir: PathBuf,
+    /// `dir` relative to where the rules were made for, by display name
+    relative: PathBuf,
+    /// Ignore files of the directory and its ancestors, outermost first
+    ignores: Vec<Arc<Gitignore>>,
+}
+
+impl DirExclusions<'_> {
+    /// The rules for a subdirectory, for walking a tree without reading the
+    /// ignore files of the ancestors again.
+    pub fn subdir(&self, dir: &Path) -> Self {
+        let mut ignores = self.ignores.clone();
+        ignores.extend(self.exclusions.load_ignore_file(dir));
+        let physical_name = dir.file_name().unwrap_or_default().to_string_lossy();
+        let relative = match name_state_in(&self.dir, &physical_name, self.key) {
+            NameState::Decoded(decoded) => self.relative.join(decoded),
+            _ => self.relative.join(&*physical_name),
+        };
+        DirExclusions {
+            exclusions: self.exclusions,
+            key: self.key,
+            dir: dir.to_path_buf(),
+            relative,
+            ignores,
+        }
+    }
+
+    /// Whether the entry `physical_name` of the directory is left out.
+    /// Patterns match the display name, or the physical name while it is
+    /// locked. Ignore files match physical paths, like git does. Sidecar
+    /// files, temp files of content encryption and the ignore files are
+    /// always left out.
+    pub fn is_excluded(&self, physical_name: &str, state: &NameState, is_dir: bool) -> bool {
+        let rules = &self.exclusions.rules;
+        if is_sidecar_name(physical_name)
+            || is_temp_name(physical_name)
+            || rules.ignore_file.as_deref() == Some(physical_name)
+        {
+            return true;
+        }
+
+        let display_name = match state {
+            NameState::Plain => Some(physical_name),
+            NameState::Decoded(decoded) => Some(decoded.as_str()),
+            NameState::Locked => None,
+        };
+        if rules.hide_dotfiles && display_name.is_some_and(|name| name.starts_with('.')) {
+            return true;
+        }
+
+        let name = display_name.unwrap_or(physical_name);
+        if self
+            .exclusions
+            .patterns
+            .matched(self.relative.join(name), is_dir)
+            .is_ignore()
+        {
+            return true;
+        }
+        let path = self.dir.join(physical_name);
+        for ignore in self.ignores.iter().rev() {
+            match ignore.matched(&path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+}
+
+/// Checks entries anywhere below `root` against the rules, for when the tree
+/// isn't walked (e.g. results from the search index). An entry is only
+/// visible if none of the directories between it and `root` is excluded.
+pub struct TreeExclusions<'a> {
+    root: PathBuf,
+    key: Option<&'a SessionKey>,
+    exclusions: &'a Exclusions,
+    /// Rules of the directories looked at so far, None if it is excluded
+    dirs: HashMap<PathBuf, Option<DirExclusions<'a>>>,
+}
+
+impl<'a> TreeExclusions<'a> {
+    pub fn new(root: &Path, key: Option<&'a SessionKey>, exclusions: &'a Exclusions) -> Self {
+        TreeExclusions {
+            root: root.to_path_buf(),
+            key,
+            exclusions,
+            dirs: HashMap::new(),
+        }
+    }
+
+    /// The name state of `path` if it is visible.
+    pub fn visible_state(&mut self, path: &Path, is_dir: bool) -> Option<NameState> {
+        let parent = path.parent()?;
+        let physical_name = path.file_name()?.to_str()?;
+        self.load(parent);
+        let rules = self.dirs.get(parent)?.as_ref()?;
+        let state = name_state_in(parent, physical_name, self.key);
+        (!rules.is_excluded(physical_name, &state, is_dir)).then_some(state)
+    }
+
+    fn load(&mut self, dir: &Path) {
+        if self.dirs.contains_key(dir) {
+            return;
+        }
+        let rules = if dir == self.root {
+            Some(self.exclusions.in_dir(dir, self.key))
+        } else if !dir.starts_with(&self.root) {
+            None
+        } else {
+            let visible = self.visible_state(dir, true).is_some();
+            let parent_rules = dir
+                .parent()
+                .and_then(|parent| self.dirs.get(parent)?.as_ref());
+            match parent_rules {
+                Some(parent_rules) if visible => Some(parent_rules.subdir(dir)),
+                _ => None,
+            }
+        };
+        self.dirs.insert(dir.to_path_buf(), rules);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::encoded_dir::encode_name_with;
+    use crate::encoding::keyring::KEYRING_FILE_NAME;
+    use crate::encoding::name_codec::default_codec;
+    use crate::encoding::name_index::INDEX_FILE_NAME;
+    use tempfile::TempDir;
+
+    fn excluded(rules: &DirExclusions, dir: &Path, name: &str) -> bool {
+        let state = name_state_in(dir, name, None);
+        rules.is_excluded(name, &state, dir.join(name).is_dir())
+    }
+
+    #[test]
+    fn test_patterns_and_dotfiles() {
+        let tmp = TempDir::new().unwrap();
+        let exclusions = Exclusions::new(ExclusionRules {
+            patterns: vec!["*.part".to_string(), "Thumbs.db".to_string()],
+            hide_dotfiles: true,
+            ignore_file: None,
+        })
+        .unwrap();
+        let rules = exclusions.in_dir(tmp.path(), None);
+
+        assert!(excluded(&rules, tmp.path(), "movie.mkv.part"));
+        assert!(excluded(&rules, tmp.path(), "Thumbs.db"));
+        assert!(excluded(&rules, tmp.path(), ".hidden"));
+        assert!(excluded(&rules, tmp.path(), INDEX_FILE_NAME));
+        assert!(excluded(&rules, tmp.path(), KEYRING_FILE_NAME));
+        assert!(excluded(&rules, tmp.path(), ".movie.mkv.crypt-tmp"));
+        assert!(!excluded(&rules, tmp.path(), "movie.mkv"));
+        // Encoded names count by their decoded name
+        assert!(!excluded(
+            &rules,
+            tmp.path(),
+            &encode_name_with(default_codec(), "movie.mkv")
+        ));
+        assert!(excluded(
+            &rules,
+            tmp.path(),
+            &encode_name_with(default_codec(), "movie.part")
+        ));
+        assert!(excluded(
+            &rules,
+            tmp.path(),
+            &encode_name_with(default_codec(), ".hidden")
+        ));
+    }
+
+    #[test]
+    fn test_ignore_files_apply_below_their_directory() {
+        let tmp = TempDir::new().unwrap();
+        let sub = tmp.path().join("sub");
+        fs::create_dir(&sub).unwrap();
+        fs::write(tmp.path().join(".vbignore"), "*.nfo\nsamples/\n").unwrap();
+        fs::write(sub.join(".vbignore"), "!keep.nfo\n").unwrap();
+        fs::create_dir(sub.join("samples")).unwrap();
+
+        let exclusions = Exclusions::default();
+        let top = exclusions.in_dir(tmp.path(), None);
+        assert!(excluded(&top, tmp.path(), "a.nfo"));
+        assert!(excluded(&top, tmp.path(), ".vbignore"));
+        assert!(excluded(&top, tmp.path(), ".gitignore"));
+
+        for rules in [top.subdir(&sub), exclusions.in_dir(&sub, None)] {
+            assert!(excluded(&rules, &sub, "b.nfo"));
+            assert!(!excluded(&rules, &sub, "keep.nfo"));
+            assert!(excluded(&rules, &sub, "samples"));
+            assert!(!excluded(&rules, &sub, "b.mkv"));
+        }
+    }
+
+    #[test]
+    fn test_patterns_with_slash_match_relative_paths() {
+        let tmp = TempDir::new().unwrap();
+        let extras = tmp.path().join(encode_name_with(default_codec(), "extras"));
+        fs::create_dir_all(extras.join("samples")).unwrap();
+        fs::create_dir(tmp.path().join("samples")).unwrap();
+        let exclusions = Exclusions::new(ExclusionRules {
+            patterns: vec!["extras/samples/".to_string()],
+            ..ExclusionRules::default()
+        })
+        .unwrap();
+
+        let top = exclusions.in_dir(tmp.path(), None);
+        assert!(!excluded(&top, tmp.path(), "samples"));
+        assert!(excluded(&top.subdir(&extras), &extras, "samples"));
+    }
+
+    #[test]
+    fn test_ignore_files_match_physical_names_and_are_reloaded() {
+        let tmp = TempDir::new().unwrap();
+        let ignore_file = tmp.path().join(".vbignore");
+        let secret = encode_name_with(default_codec(), "secret.mkv");
+        fs::write(&ignore_file, format!("{}\n", secret)).unwrap();
+
+        let exclusions = Exclusions::default();
+        assert!(excluded(
+            &exclusions.in_dir(tmp.path(), None),
+            tmp.path(),
+            &secret
+        ));
+        assert!(!excluded(
+            &exclusions.in_dir(tmp.path(), None),
+            tmp.path(),
+            "a.nfo"
+        ));
+
+        fs::write(&ignore_file, "*.nfo\n").unwrap();
+        let later = SystemTime::now() + std::time::Duration::from_secs(10);
+        fs::File::options()
+            .write(true)
+            .open(&ignore_file)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        let rules = exclusions.in_dir(tmp.path(), None);
+        assert!(excluded(&rules, tmp.path(), "a.nfo"));
+        assert!(!excluded(&rules, tmp.path(), &secret));
+    }
+
+    #[test]
+    fn test_tree_exclusions_hide_excluded_subtrees() {
+        let tmp = TempDir::new().unwrap();
+        let samples = tmp.path().join("show").join("samples");
+        fs::create_dir_all(&samples).unwrap();
+        fs::write(tmp.path().join(".vbignore"), "samples/\n*.nfo\n").unwrap();
+
+        let exclusions = Exclusions::default();
+        let mut tree = TreeExclusions::new(tmp.path(), None, &exclusions);
+        let show = tmp.path().join("show");
+        assert_eq!(
+            tree.visible_state(&show.join("e01.mkv"), false),
+            Some(NameState::Plain)
+        );
+        assert_eq!(tree.visible_state(&show.join("e01.nfo"), false), None);
+        assert_eq!(tree.visible_state(&samples, true), None);
+        assert_eq!(tree.visible_state(&samples.join("sample.mkv"), false), None);
+        assert_eq!(
+            tree.visible_state(Path::new("/elsewhere/e01.mkv"), false),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rules_are_saved() {
+        let tmp = TempDir::new().unwrap();
+        let state = init(tmp.path()).unwrap();
+        assert_eq!(state.get().rules(), &ExclusionRules::default());
+
+        let rules = ExclusionRules {
+            patterns: vec!["*.tmp".to_string()],
+            hide_dotfiles: true,
+            ignore_file: None,
+        };
+        state.set(rules.clone()).unwrap();
+        assert_eq!(init(tmp.path()).unwrap().get().rules(), &rules);
+        assert!(state
+            .set(ExclusionRules {
+                patterns: vec!["a**b/**c{".to_string()],
+                ..rules
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_unreadable_rules_fall_back_to_defaults() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(rules_path(tmp.path()), "{not json").unwrap();
+        assert!(init(tmp.path()).is_err());
+
+        let state = ExclusionState::with_defaults(tmp.path());
+        assert_eq!(state.get().rules(), &ExclusionRules::default());
+        let rules = ExclusionRules {
+            hide_dotfiles: true,
+            ..ExclusionRules::default()
+        };
+        state.set(rules.clone()).unwrap();
+        assert_eq!(init(tmp.path()).unwrap().get().rules(), &rules);
+    }
+}
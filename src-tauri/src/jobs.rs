@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// A long-running command the frontend can cancel, or answer questions of
+/// type `R` it asks through an event.
+pub struct Job<R> {
+    cancelled: AtomicBool,
+    reply: Mutex<Option<R>>,
+    replied: Condvar,
+}
+
+impl<R> Job<R> {
+    fn new() -> Self {
+        Job {
+            cancelled: AtomicBool::new(false),
+            reply: Mutex::new(None),
+            replied: Condvar::new(),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        let _guard = self.reply.lock();
+        self.replied.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn reply(&self, reply: R) {
+        if let Ok(mut slot) = self.reply.lock() {
+            *slot = Some(reply);
+            self.replied.notify_all();
+        }
+    }
+
+    /// Blocks until `reply` is called. Returns `None` if the job is
+    /// cancelled instead.
+    pub fn wait_for_reply(&self) -> Option<R> {
+        let mut slot = self.reply.lock().ok()?;
+        loop {
+            if self.is_cancelled() {
+                return None;
+            }
+            if let Some(reply) = slot.take() {
+                return Some(reply);
+            }
+            slot = self.replied.wait(slot).ok()?;
+        }
+    }
+}
+
+/// Running jobs by the id the frontend started them with.
+pub struct JobRegistry<R>(Mutex<HashMap<String, Arc<Job<R>>>>);
+
+impl<R> Default for JobRegistry<R> {
+    fn default() -> Self {
+        JobRegistry(Mutex::new(HashMap::new()))
+    }
+}
+
+impl<R> JobRegistry<R> {
+    pub fn start(&self, id: &str) -> Result<Arc<Job<R>>, String> {
+        let mut jobs = self
+            .0
+            .lock()
+            .map_err(|e| format!("Job lock error: {}", e))?;
+        if jobs.contains_key(id) {
+            return Err(format!("Job is already running: {}", id));
+        }
+        let job = Arc::new(Job::new());
+        jobs.insert(id.to_string(), job.clone());
+        Ok(job)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Arc<Job<R>>, String> {
+        let jobs = self
+            .0
+            .lock()
+            .map_err(|e| format!("Job lock error: {}", e))?;
+        jobs.get(id)
+            .cloned()
+            .ok_or_else(|| format!("No such job: {}", id))
+    }
+
+    pub fn finish(&self, id: &str) {
+        if let Ok(mut jobs) = self.0.lock() {
+            jobs.remove(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_wait_for_reply_returns_reply() {
+        let registry = JobRegistry::<u32>::default();
+        let job = registry.start("copy-1").unwrap();
+
+        let waiter = thread::spawn(move || job.wait_for_reply());
+        registry.get("copy-1").unwrap().reply(7);
+        assert_eq!(waiter.join().unwrap(), Some(7));
+    }
+
+    #[test]
+    fn test_cancel_unblocks_waiter() {
+        let registry = JobRegistry::<u32>::default();
+        let job = registry.start("copy-1").unwrap();
+
+        let waiting = job.clone();
+        let waiter = thread::spawn(move || waiting.wait_for_reply());
+        job.cancel();
+        assert_eq!(waiter.join().unwrap(), None);
+        assert!(job.is_cancelled());
+    }
+
+    #[test]
+    fn test_ids_are_unique_while_running() {
+        let registry = JobRegistry::<()>::default();
+        registry.start("a").unwrap();
+        assert!(registry.start("a").is_err());
+        registry.finish("a");
+        assert!(registry.start("a").is_ok());
+        assert!(registry.get("b").is_err());
+    }
+}
@@ -0,0 +1,574 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::encoding::rename_plan::{self, RenameStep};
+use crate::models::operation::{OperationKind, OperationRecord};
+use crate::transfer;
+use crate::trash;
+
+/// Oldest operations are dropped once the journal holds more than this.
+const MAX_HISTORY: i64 = 500;
+
+pub struct JournalState(pub Mutex<Connection>);
+
+/// Where operations are recorded once they have been performed.
+pub trait Recorder {
+    fn record(&self, kind: OperationKind, path: &Path, data: &JournalData);
+}
+
+impl Recorder for JournalState {
+    /// A failure to record the operation only costs the ability to undo it,
+    /// so it is not reported.
+    fn record(&self, kind: OperationKind, path: &Path, data: &JournalData) {
+        if let Ok(conn) = self.0.lock() {
+            let _ = record(&conn, kind, path, data);
+        }
+    }
+}
+
+/// Collects the operations of a batch, so they are undone and redone as one.
+#[derive(Default)]
+pub struct BatchRecorder(Mutex<Vec<JournalData>>);
+
+impl Recorder for BatchRecorder {
+    fn record(&self, _kind: OperationKind, _path: &Path, data: &JournalData) {
+        if let Ok(mut parts) = self.0.lock() {
+            parts.push(data.clone());
+        }
+    }
+}
+
+impl BatchRecorder {
+    /// Records everything collected as a single operation on `path`.
+    pub fn finish(self, journal: &dyn Recorder, kind: OperationKind, path: &Path) {
+        let mut parts = self.0.into_inner().unwrap_or_default();
+        match parts.len() {
+            0 => {}
+            1 => journal.record(kind, path, &parts.remove(0)),
+            _ => journal.record(kind, path, &JournalData::Batch { parts }),
+        }
+    }
+}
+
+pub fn init_db(app_data_dir: &Path) -> Result<JournalState, String> {
+    fs::create_dir_all(app_data_dir)
+        .map_err(|e| format!("Failed to create app data dir: {}", e))?;
+
+    let db_path = app_data_dir.join("journal.db");
+    let conn = Connection::open(&db_path)
+        .map_err(|e| format!("Failed to open journal database: {}", e))?;
+    init_schema(&conn)?;
+
+    Ok(JournalState(Mutex::new(conn)))
+}
+
+pub fn init_schema(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS operations (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            path TEXT NOT NULL,
+            performed_at INTEGER NOT NULL,
+            undone INTEGER NOT NULL DEFAULT 0,
+            data TEXT NOT NULL
+        );",
+    )
+    .map_err(|e| format!("Failed to create journal table: {}", e))
+}
+
+/// What an operation did to the file system, in enough detail to revert it
+/// and to perform it again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum JournalData {
+    #[serde(rename_all = "camelCase")]
+    Renames { steps: Vec<JournaledRename> },
+    #[serde(rename_all = "camelCase")]
+    Trash {
+        path: PathBuf,
+        trash_id: String,
+        fingerprint: Option<Fingerprint>,
+    },
+    /// A move to another file system, which copied the node. `fingerprint`
+    /// is taken from the copy, wherever the node currently is.
+    #[serde(rename_all = "camelCase")]
+    DeviceMove {
+        step: RenameStep,
+        fingerprint: Option<Fingerprint>,
+    },
+    /// Operations performed together, undone in reverse order
+    #[serde(rename_all = "camelCase")]
+    Batch { parts: Vec<JournalData> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournaledRename {
+    pub forward: RenameStep,
+    pub undo: RenameStep,
+    /// The renamed entry, taken before the rename
+    pub fingerprint: Option<Fingerprint>,
+}
+
+impl JournalData {
+    /// `undo` is in the order `rename_plan::apply` reports it (the reverse of
+    /// `applied`), `fingerprints` in the order of `applied`.
+    pub fn renames(
+        applied: &[RenameStep],
+        undo: &[RenameStep],
+        fingerprints: Vec<Option<Fingerprint>>,
+    ) -> Self {
+        let steps = applied
+            .iter()
+            .zip(undo.iter().rev())
+            .zip(fingerprints)
+            .map(|((forward, undo), fingerprint)| JournaledRename {
+                forward: forward.clone(),
+                undo: undo.clone(),
+                fingerprint,
+            })
+            .collect();
+        JournalData::Renames { steps }
+    }
+}
+
+/// Identifies an entry well enough to notice that it was replaced or
+/// modified. Renaming an entry keeps its fingerprint. Size and modification
+/// time are only kept for files: a directory's change whenever one of its
+/// children is renamed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Fingerprint {
+    pub is_dir: bool,
+    pub len: Option<u64>,
+    pub modified: Option<(u64, u32)>,
+    pub inode: Option<u64>,
+}
+
+pub fn fingerprint(path: &Path) -> Option<Fingerprint> {
+    let metadata = fs::symlink_metadata(path).ok()?;
+    let is_dir = metadata.is_dir();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| (d.as_secs(), d.subsec_nanos()));
+
+    #[cfg(unix)]
+    let inode = {
+        use std::os::unix::fs::MetadataExt;
+        Some(metadata.ino())
+    };
+    #[cfg(not(unix))]
+    let inode = None;
+
+    Some(Fingerprint {
+        is_dir,
+        len: (!is_dir).then_some(metadata.len()),
+        modified: if is_dir { None } else { modified },
+        inode,
+    })
+}
+
+pub fn record(
+    conn: &Connection,
+    kind: OperationKind,
+    path: &Path,
+    data: &JournalData,
+) -> Result<i64, String> {
+    let data =
+        serde_json::to_string(data).map_err(|e| format!("Failed to serialize operation: {}", e))?;
+    let performed_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+
+    // A new operation ends the chain of operations that could be redone
+    conn.execute("DELETE FROM operations WHERE undone = 1", [])
+        .map_err(|e| format!("Failed to update journal: {}", e))?;
+    conn.execute(
+        "INSERT INTO operations (kind, path, performed_at, data) VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![kind_name(kind), path.to_string_lossy(), performed_at, data],
+    )
+    .map_err(|e| format!("Failed to record operation: {}", e))?;
+    let id = conn.last_insert_rowid();
+    conn.execute("DELETE FROM operations WHERE id <= ?1", [id - MAX_HISTORY])
+        .map_err(|e| format!("Failed to update journal: {}", e))?;
+
+    Ok(id)
+}
+
+/// Most recent operations first.
+pub fn history(conn: &Connection, limit: usize) -> Result<Vec<OperationRecord>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, kind, path, performed_at, undone FROM operations ORDER BY id DESC LIMIT ?1",
+        )
+        .map_err(|e| format!("Failed to read journal: {}", e))?;
+    let rows = stmt
+        .query_map([limit as i64], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            ))
+        })
+        .map_err(|e| format!("Failed to read journal: {}", e))?;
+
+    let mut records = Vec::new();
+    for row in rows {
+        let (id, kind, path, performed_at, undone) =
+            row.map_err(|e| format!("Failed to read journal: {}", e))?;
+        records.push(OperationRecord {
+            id,
+            kind: parse_kind(&kind)?,
+            path,
+            performed_at,
+            undone,
+        });
+    }
+    Ok(records)
+}
+
+/// Reverts the most recent operation that has not been undone yet. Nothing
+/// is touched if any entry involved has changed since the operation.
+pub fn undo_last(conn: &Connection) -> Result<OperationRecord, String> {
+    let (mut record, mut data) =
+        find_operation(conn, "undone = 0 ORDER BY id DESC")?.ok_or("Nothing to undo")?;
+
+    // Trash ids can change even if the batch is rolled back, so the data is
+    // stored either way
+    let result = undo_data(&mut data);
+    set_undone(conn, record.id, result.is_ok(), &data)?;
+    result.map_err(|e| format!("Cannot undo: {}", e))?;
+
+    record.undone = true;
+    Ok(record)
+}
+
+/// Performs the most recently undone operation again.
+pub fn redo(conn: &Connection) -> Result<OperationRecord, String> {
+    let (mut record, mut data) =
+        find_operation(conn, "undone = 1 ORDER BY id ASC")?.ok_or("Nothing to redo")?;
+
+    let result = redo_data(&mut data);
+    set_undone(conn, record.id, result.is_err(), &data)?;
+    result.map_err(|e| format!("Cannot redo: {}", e))?;
+
+    record.undone = false;
+    Ok(record)
+}
+
+fn undo_data(data: &mut JournalData) -> Result<(), String> {
+    match data {
+        JournalData::Renames { steps } => {
+            let undo: Vec<RenameStep> = steps.iter().rev().map(|s| s.undo.clone()).collect();
+            let fingerprints: Vec<&Option<Fingerprint>> =
+                steps.iter().rev().map(|s| &s.fingerprint).collect();
+            let outcome =
+                rename_plan::apply_checked(&undo, |i, step| check_step(step, fingerprints[i]));
+            outcome.error.map_or(Ok(()), Err)
+        }
+        JournalData::Trash { trash_id, .. } => trash::restore(trash_id).map(|_| ()),
+        JournalData::DeviceMove { step, fingerprint } => {
+            let back = RenameStep {
+                from: step.to.clone(),
+                to: step.from.clone(),
+                indexed_name: step.indexed_name.clone(),
+            };
+            move_back_and_forth(&back, fingerprint)
+        }
+        JournalData::Batch { parts } => {
+            for i in (0..parts.len()).rev() {
+                if let Err(e) = undo_data(&mut parts[i]) {
+                    for part in &mut parts[i + 1..] {
+                        if let Err(rollback) = redo_data(part) {
+                            return Err(format!("{}; failed to roll back: {}", e, rollback));
+                        }
+                    }
+                    return Err(e);
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+fn redo_data(data: &mut JournalData) -> Result<(), String> {
+    match data {
+        JournalData::Renames { steps } => {
+            let forward: Vec<RenameStep> = steps.iter().map(|s| s.forward.clone()).collect();
+            let outcome = rename_plan::apply_checked(&forward, |i, step| {
+                check_step(step, &steps[i].fingerprint)
+            });
+            outcome.error.map_or(Ok(()), Err)
+        }
+        JournalData::Trash {
+            path,
+            trash_id,
+            fingerprint,
+        } => {
+            check_unchanged(path, fingerprint)?;
+            *trash_id = trash::move_to_trash(path)?;
+            Ok(())
+        }
+        JournalData::DeviceMove { step, fingerprint } => move_back_and_forth(step, fingerprint),
+        JournalData::Batch { parts } => {
+            for i in 0..parts.len() {
+                if let Err(e) = redo_data(&mut parts[i]) {
+                    for part in parts[..i].iter_mut().rev() {
+                        if let Err(rollback) = undo_data(part) {
+                            return Err(format!("{}; failed to roll back: {}", e, rollback));
+                        }
+                    }
+                    return Err(e);
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Copies the node of a `DeviceMove` over and takes the fingerprint of the
+/// new copy.
+fn move_back_and_forth(
+    step: &RenameStep,
+    fingerprint: &mut Option<Fingerprint>,
+) -> Result<(), String> {
+    check_step(step, fingerprint)?;
+    transfer::move_across_devices(step, false, &mut |_| {})?;
+    *fingerprint = self::fingerprint(&step.to);
+    Ok(())
+}
+
+fn find_operation(
+    conn: &Connection,
+    order: &str,
+) -> Result<Option<(OperationRecord, JournalData)>, String> {
+    let row = conn
+        .query_row(
+            &format!("SELECT id, kind, path, performed_at, undone, data FROM operations WHERE {} LIMIT 1", order),
+            [],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, bool>(4)?,
+                    row.get::<_, String>(5)?,
+                ))
+            },
+        )
+        .optional()
+        .map_err(|e| format!("Failed to read journal: {}", e))?;
+
+    let Some((id, kind, path, performed_at, undone, data)) = row else {
+        return Ok(None);
+    };
+    let data: JournalData =
+        serde_json::from_str(&data).map_err(|e| format!("Corrupt journal entry {}: {}", id, e))?;
+    let record = OperationRecord {
+        id,
+        kind: parse_kind(&kind)?,
+        path,
+        performed_at,
+        undone,
+    };
+    Ok(Some((record, data)))
+}
+
+fn set_undone(conn: &Connection, id: i64, undone: bool, data: &JournalData) -> Result<(), String> {
+    let data =
+        serde_json::to_string(data).map_err(|e| format!("Failed to serialize operation: {}", e))?;
+    conn.execute(
+        "UPDATE operations SET undone = ?1, data = ?2 WHERE id = ?3",
+        rusqlite::params![undone, data, id],
+    )
+    .map_err(|e| format!("Failed to update journal: {}", e))?;
+    Ok(())
+}
+
+/// The entry must still be the one that was renamed, and the name it goes
+/// back to must be free: `fs::rename` would silently replace a file there.
+fn check_step(step: &RenameStep, fingerprint: &Option<Fingerprint>) -> Result<(), String> {
+    check_unchanged(&step.from, fingerprint)?;
+    if fs::symlink_metadata(&step.to).is_ok() {
+        return Err(format!("{} already exists", step.to.display()));
+    }
+    Ok(())
+}
+
+fn check_unchanged(path: &Path, expected: &Option<Fingerprint>) -> Result<(), String> {
+    match (fingerprint(path), expected) {
+        (None, _) => Err(format!("{} no longer exists", path.display())),
+        (Some(actual), Some(expected)) if actual != *expected => Err(format!(
+            "{} has changed since the operation",
+            path.display()
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn kind_name(kind: OperationKind) -> &'static str {
+    match kind {
+        OperationKind::Rename => "rename",
+        OperationKind::Move => "move",
+        OperationKind::Encode => "encode",
+        OperationKind::Decode => "decode",
+        OperationKind::Migrate => "migrate",
+        OperationKind::Delete => "delete",
+    }
+}
+
+fn parse_kind(name: &str) -> Result<OperationKind, String> {
+    Ok(match name {
+        "rename" => OperationKind::Rename,
+        "move" => OperationKind::Move,
+        "encode" => OperationKind::Encode,
+        "decode" => OperationKind::Decode,
+        "migrate" => OperationKind::Migrate,
+        "delete" => OperationKind::Delete,
+        _ => return Err(format!("Unknown operation kind in journal: {}", name)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn open_journal() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        conn
+    }
+
+    fn step(from: &Path, to: &Path) -> RenameStep {
+        RenameStep {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+            indexed_name: None,
+        }
+    }
+
+    /// Renames `dir/a.txt` to `dir/b.txt` and then `dir` to `renamed`, the
+    /// way a plan for a directory is applied, and records it.
+    fn rename_tree(tmp: &TempDir, conn: &Connection) {
+        let dir = tmp.path().join("dir");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "content").unwrap();
+
+        let steps = vec![
+            step(&dir.join("a.txt"), &dir.join("b.txt")),
+            step(&dir, &tmp.path().join("renamed")),
+        ];
+        let fingerprints = steps.iter().map(|s| fingerprint(&s.from)).collect();
+        let outcome = rename_plan::apply(&steps);
+        assert!(outcome.error.is_none());
+        let data = JournalData::renames(&outcome.applied, &outcome.undo, fingerprints);
+        record(conn, OperationKind::Rename, &dir, &data).unwrap();
+    }
+
+    #[test]
+    fn test_undo_and_redo_renames() {
+        let tmp = TempDir::new().unwrap();
+        let conn = open_journal();
+        rename_tree(&tmp, &conn);
+
+        let undone = undo_last(&conn).unwrap();
+        assert!(undone.undone);
+        assert!(tmp.path().join("dir").join("a.txt").exists());
+        assert!(!tmp.path().join("renamed").exists());
+
+        redo(&conn).unwrap();
+        assert!(tmp.path().join("renamed").join("b.txt").exists());
+        assert!(!tmp.path().join("dir").exists());
+
+        assert!(undo_last(&conn).is_ok());
+        assert_eq!(undo_last(&conn).unwrap_err(), "Nothing to undo");
+    }
+
+    #[test]
+    fn test_undo_refuses_modified_file() {
+        let tmp = TempDir::new().unwrap();
+        let conn = open_journal();
+        rename_tree(&tmp, &conn);
+
+        let renamed_file = tmp.path().join("renamed").join("b.txt");
+        fs::write(&renamed_file, "changed content").unwrap();
+
+        let err = undo_last(&conn).unwrap_err();
+        assert!(err.contains("has changed"), "{}", err);
+        // The directory is renamed back first, and rolled back once the file fails
+        assert!(renamed_file.exists());
+        assert!(!tmp.path().join("dir").exists());
+        assert!(!history(&conn, 10).unwrap()[0].undone);
+    }
+
+    #[test]
+    fn test_undo_refuses_taken_name() {
+        let tmp = TempDir::new().unwrap();
+        let conn = open_journal();
+        rename_tree(&tmp, &conn);
+        fs::create_dir(tmp.path().join("dir")).unwrap();
+
+        let err = undo_last(&conn).unwrap_err();
+        assert!(err.contains("already exists"), "{}", err);
+        assert!(tmp.path().join("renamed").join("b.txt").exists());
+    }
+
+    #[test]
+    fn test_undo_and_redo_device_move() {
+        let tmp = TempDir::new().unwrap();
+        let conn = open_journal();
+        let source = tmp.path().join("a.txt");
+        let target = tmp.path().join("b.txt");
+        fs::write(&source, "content").unwrap();
+        let moved = step(&source, &target);
+        transfer::move_across_devices(&moved, false, &mut |_| {}).unwrap();
+        let data = JournalData::DeviceMove {
+            fingerprint: fingerprint(&target),
+            step: moved,
+        };
+        record(&conn, OperationKind::Move, &source, &data).unwrap();
+
+        undo_last(&conn).unwrap();
+        assert_eq!(fs::read_to_string(&source).unwrap(), "content");
+        assert!(!target.exists());
+
+        redo(&conn).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "content");
+        assert!(!source.exists());
+
+        fs::write(&target, "changed content").unwrap();
+        let err = undo_last(&conn).unwrap_err();
+        assert!(err.contains("has changed"), "{}", err);
+        assert!(!source.exists());
+    }
+
+    #[test]
+    fn test_new_operation_discards_redo() {
+        let tmp = TempDir::new().unwrap();
+        let conn = open_journal();
+        rename_tree(&tmp, &conn);
+        undo_last(&conn).unwrap();
+
+        let other = tmp.path().join("other.txt");
+        fs::write(&other, "x").unwrap();
+        let data = JournalData::renames(&[], &[], Vec::new());
+        record(&conn, OperationKind::Move, &other, &data).unwrap();
+
+        let records = history(&conn, 10).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].kind, OperationKind::Move);
+        assert_eq!(redo(&conn).unwrap_err(), "Nothing to redo");
+    }
+}
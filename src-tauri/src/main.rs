@@ -3,12 +3,36 @@
 mod cache;
 mod commands;
 mod encoding;
+mod exclusions;
+mod jobs;
+mod journal;
 mod models;
+mod natural_sort;
+mod rename_template;
+mod search;
+mod search_index;
+mod search_query;
+mod session;
+mod transfer;
+mod trash;
+mod watcher;
 
 use tauri::Manager;
 
+use commands::batch_commands;
+use commands::bulk_rename_commands;
+use commands::copy_commands;
+use commands::directory_page_commands;
+use commands::exclusion_commands;
 use commands::fs_commands;
+use commands::index_commands;
+use commands::journal_commands;
+use commands::search_commands;
+use commands::session_commands;
+use commands::trash_commands;
+use commands::verify_commands;
 use commands::video_commands;
+use commands::watch_commands;
 
 fn main() {
     tauri::Builder::default()
@@ -18,15 +42,49 @@ fn main() {
                 .path()
                 .app_data_dir()
                 .expect("failed to resolve app data dir");
-            let cache_state = cache::init_db(&data_dir)
-                .expect("failed to initialize frame cache");
+            let cache_state = cache::init_db(&data_dir).expect("failed to initialize frame cache");
             app.manage(cache_state);
+            let journal_state =
+                journal::init_db(&data_dir).expect("failed to initialize operation journal");
+            app.manage(journal_state);
+            let exclusion_state = exclusions::init(&data_dir).unwrap_or_else(|e| {
+                eprintln!("{}; using the default exclusion rules", e);
+                exclusions::ExclusionState::with_defaults(&data_dir)
+            });
+            app.manage(exclusion_state);
+            let search_index_state =
+                search_index::init_db(&data_dir).expect("failed to initialize search index");
+            app.manage(search_index_state);
+            app.manage(session::SessionState::default());
+            app.manage(copy_commands::CopyJobs::default());
+            app.manage(directory_page_commands::PageCache::default());
+            app.manage(search_commands::SearchJobs::default());
+            app.manage(watcher::WatchState::default());
+            index_commands::resume_indexing(app.handle())?;
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
+            batch_commands::batch_encode_nodes,
+            batch_commands::batch_decode_nodes,
+            batch_commands::batch_move_nodes,
+            batch_commands::batch_delete_nodes,
+            batch_commands::batch_rename_nodes,
+            bulk_rename_commands::preview_bulk_rename,
+            bulk_rename_commands::bulk_rename,
+            copy_commands::copy_node,
+            copy_commands::resolve_copy_conflict,
+            copy_commands::cancel_copy,
+            directory_page_commands::read_directory_page,
+            exclusion_commands::get_exclusion_rules,
+            exclusion_commands::set_exclusion_rules,
             fs_commands::read_directory,
             fs_commands::encode_node,
             fs_commands::decode_node,
+            fs_commands::preview_encode_node,
+            fs_commands::preview_decode_node,
+            fs_commands::migrate_encoded_names,
+            fs_commands::encrypt_node,
+            fs_commands::decrypt_node,
             fs_commands::can_encode_node,
             fs_commands::copy_to_clipboard,
             fs_commands::rename_node,
@@ -37,9 +95,27 @@ fn main() {
             fs_commands::open_in_vlc,
             fs_commands::get_cli_path,
             fs_commands::search_files,
+            journal_commands::undo_last_operation,
+            journal_commands::redo_operation,
+            journal_commands::list_operation_history,
+            search_commands::start_search,
+            search_commands::cancel_search,
+            session_commands::unlock_session,
+            session_commands::create_keyring,
+            session_commands::lock_session,
+            session_commands::is_session_unlocked,
+            trash_commands::list_trash,
+            trash_commands::restore_from_trash,
+            trash_commands::empty_trash,
+            verify_commands::verify_tree,
             video_commands::list_video_files,
             video_commands::get_video_info,
             video_commands::extract_video_frame,
+            watch_commands::watch_directory,
+            watch_commands::unwatch_directory,
+            index_commands::index_directory,
+            index_commands::remove_index,
+            index_commands::list_indexed_directories,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+
+use super::file_entry::FileEntry;
+use super::operation::OperationKind;
+use super::transfer_progress::TransferProgress;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchItemResult {
+    pub path: String,
+    /// The entry after the operation, if there still is one
+    pub entry: Option<FileEntry>,
+    /// Set if the operation failed for this item
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchReport {
+    /// One result per attempted item, in the order they were given
+    pub results: Vec<BatchItemResult>,
+    /// Items left alone because an earlier one failed
+    pub not_attempted: Vec<String>,
+}
+
+/// Sent as a `batch-progress` event before each item and once at the end.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchProgress {
+    pub kind: OperationKind,
+    pub done: usize,
+    pub total: usize,
+    /// The item being worked on, None once the batch is finished
+    pub current_path: Option<String>,
+    /// How far the item has been copied, while a move copies it to another
+    /// file system
+    pub transfer: Option<TransferProgress>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchRename {
+    pub path: String,
+    pub new_name: String,
+}
@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+
+/// Case conversion applied to the new name. The extension is left alone.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CaseConversion {
+    #[default]
+    Keep,
+    Lower,
+    Upper,
+    /// First letter of every word upper case, the rest lower case
+    Title,
+}
+
+/// How `bulk_rename` builds the new names. `replace` may contain `{name}`,
+/// `{ext}`, `{n}` (the counter, `{n:3}` pads it to three digits),
+/// `{duration}`, `{resolution}`, `{width}`, `{height}` and `{codec}`, and with
+/// `find` set also `$1` or `${group}` for capture groups.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenameTemplate {
+    /// Regex whose matches are replaced; without it `replace` is the whole new name
+    pub find: Option<String>,
+    pub replace: String,
+    #[serde(default)]
+    pub case: CaseConversion,
+    /// Whether the extension of files is renamed too, instead of kept as it is
+    #[serde(default)]
+    pub include_extension: bool,
+    /// Value of `{n}` for the first entry (default 1)
+    pub counter_start: Option<u64>,
+    /// How much `{n}` grows from one entry to the next (default 1)
+    pub counter_step: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BulkCollisionKind {
+    /// Another entry of the set would get the same name
+    Duplicate,
+    /// An entry outside the set already has the name
+    Existing,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkRenameCollision {
+    pub kind: BulkCollisionKind,
+    /// Absolute path of the entry the name collides with
+    pub with: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedBulkRename {
+    /// Absolute path of the entry as it is now
+    pub path: String,
+    /// Decoded display name
+    pub old_name: String,
+    /// The display name it would get; None if it couldn't be worked out
+    pub new_name: Option<String>,
+    /// Whether the new name is encoded again, with the entry's own scheme
+    pub encode: bool,
+    pub collision: Option<BulkRenameCollision>,
+    /// Why the name couldn't be worked out, e.g. a video without metadata
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkRenamePreview {
+    /// One item per entry, in the order they were given
+    pub items: Vec<PlannedBulkRename>,
+}
+
+impl BulkRenamePreview {
+    pub fn problems(&self) -> usize {
+        self.items
+            .iter()
+            .filter(|item| item.collision.is_some() || item.error.is_some())
+            .count()
+    }
+}
@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+
+use super::file_entry::FileEntry;
+use super::rename_report::RenameRecord;
+
+/// What happens to names while copying.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum NameMode {
+    /// Copies keep the physical names of the originals
+    #[default]
+    Keep,
+    /// Plain names are encoded; encoded names keep their scheme
+    Encode,
+    /// Encoded names are decoded where possible
+    Decode,
+}
+
+/// What to do when the name a copy would get is already taken.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConflictPolicy {
+    Skip,
+    /// Replace files; directories are merged
+    Overwrite,
+    /// Append " (2)", " (3)", ... to the name of the copy until it is free
+    KeepBoth,
+    /// Send a `copy-conflict` event and wait for `resolve_copy_conflict`
+    #[default]
+    Ask,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConflictResolution {
+    Skip,
+    Overwrite,
+    KeepBoth,
+}
+
+/// The answer to a `copy-conflict` event.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConflictAnswer {
+    pub resolution: ConflictResolution,
+    /// Use the same resolution for the remaining conflicts of the copy
+    pub apply_to_all: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CopyOptions {
+    #[serde(default)]
+    pub name_mode: NameMode,
+    /// Scheme for names encoded during the copy (default scheme if None)
+    pub scheme: Option<String>,
+    #[serde(default)]
+    pub conflict_policy: ConflictPolicy,
+}
+
+/// Sent as a `copy-conflict` event when the policy is to ask.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CopyConflict {
+    pub copy_id: String,
+    pub source: String,
+    /// The existing entry that is in the way
+    pub target: String,
+    /// Display name of the entry being copied
+    pub name: String,
+    pub is_directory: bool,
+    pub target_is_directory: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CopyReport {
+    /// The copy of the node, unless it was skipped
+    pub entry: Option<FileEntry>,
+    pub files_copied: usize,
+    /// Source paths that were not copied because their name was taken
+    pub skipped: Vec<String>,
+    /// Target paths that were replaced
+    pub overwritten: Vec<String>,
+    /// Copies that got a suffixed name: `from` is the taken path, `to` the copy
+    pub renamed: Vec<RenameRecord>,
+}
@@ -0,0 +1,36 @@
+use serde::Serialize;
+
+use super::file_entry::FileEntry;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenamedEntry {
+    /// Absolute path before the rename
+    pub from: String,
+    /// The entry under its new name
+    pub entry: FileEntry,
+}
+
+/// Sent as a `directory-changed` event for a watched directory, once the
+/// changes have settled.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryChanges {
+    /// The watched directory
+    pub path: String,
+    pub created: Vec<FileEntry>,
+    /// Entries whose contents or metadata changed
+    pub modified: Vec<FileEntry>,
+    /// Absolute paths of entries that are gone
+    pub removed: Vec<String>,
+    pub renamed: Vec<RenamedEntry>,
+}
+
+impl DirectoryChanges {
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty()
+            && self.modified.is_empty()
+            && self.removed.is_empty()
+            && self.renamed.is_empty()
+    }
+}
@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+use super::file_entry::FileEntry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SortKey {
+    /// Display name, with numbers compared by value
+    #[default]
+    Name,
+    Size,
+    Modified,
+    /// Video duration; everything else sorts last
+    Duration,
+    Kind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SortOrder {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PageRequest {
+    pub sort_key: SortKey,
+    pub order: SortOrder,
+    pub directories_first: bool,
+    /// Where the page starts; ignored when `cursor` is set
+    pub offset: usize,
+    pub limit: usize,
+    /// `next_cursor` of the previous page. The page continues after the last
+    /// entry of that page even if entries were added or removed since.
+    pub cursor: Option<String>,
+}
+
+impl Default for PageRequest {
+    fn default() -> Self {
+        PageRequest {
+            sort_key: SortKey::Name,
+            order: SortOrder::Ascending,
+            directories_first: true,
+            offset: 0,
+            limit: 500,
+            cursor: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryPage {
+    pub entries: Vec<FileEntry>,
+    /// Position of the first entry in the sorted directory
+    pub offset: usize,
+    /// Number of entries in the directory
+    pub total: usize,
+    /// Pass as `cursor` to get the next page; None on the last page
+    pub next_cursor: Option<String>,
+}
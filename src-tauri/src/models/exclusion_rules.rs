@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+/// Which entries the browser leaves out of listings, searches and counts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ExclusionRules {
+    /// Patterns in gitignore syntax, matched against display names, e.g.
+    /// "*.part" or "Thumbs.db"
+    pub patterns: Vec<String>,
+    /// Hide entries whose display name starts with a dot. Encoded entries
+    /// count by their decoded name, so `.dat_` files stay visible.
+    pub hide_dotfiles: bool,
+    /// Name of the per-directory ignore files in gitignore syntax, which
+    /// apply to their directory and everything below it; None turns them off
+    pub ignore_file: Option<String>,
+}
+
+impl Default for ExclusionRules {
+    fn default() -> Self {
+        ExclusionRules {
+            patterns: vec![".gitignore".to_string()],
+            hide_dotfiles: false,
+            ignore_file: Some(".vbignore".to_string()),
+        }
+    }
+}
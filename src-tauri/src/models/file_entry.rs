@@ -1,4 +1,4 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -14,8 +14,133 @@ pub struct FileEntry {
     pub is_encoded: bool,
     /// The decoded name if encoded, None otherwise
     pub decoded_name: Option<String>,
+    /// Whether the name is encrypted and can't be shown until the session is unlocked
+    pub is_locked: bool,
     /// Whether this directory has children (for lazy loading)
     pub has_children: bool,
     /// File size in bytes (0 for directories)
     pub file_size: u64,
+    /// Seconds since the Unix epoch, if the file system records it
+    pub created: Option<i64>,
+    /// Seconds since the Unix epoch
+    pub modified: Option<i64>,
+    /// Seconds since the Unix epoch
+    pub accessed: Option<i64>,
+    /// Whether the entry is a symlink; the other fields describe its target
+    pub is_symlink: bool,
+    /// Where the symlink points, as stored in the link
+    pub symlink_target: Option<String>,
+    /// Unix permission bits (e.g. 0o644), None on other platforms
+    pub permissions: Option<u32>,
+    pub is_read_only: bool,
+    /// What the file holds, going by the extension of the display name
+    pub kind: FileKind,
+    /// MIME type going by the extension of the display name, None for
+    /// directories, locked names and unknown extensions
+    pub mime_type: Option<&'static str>,
+}
+
+/// Ordered as listed when sorting by kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FileKind {
+    Video,
+    Image,
+    Audio,
+    Subtitle,
+    /// Anything else, including directories and locked names
+    Other,
+}
+
+/// Known extensions, with their MIME type and what files with them hold.
+const FILE_TYPES: &[(&str, &str, FileKind)] = &[
+    ("mp4", "video/mp4", FileKind::Video),
+    ("m4v", "video/x-m4v", FileKind::Video),
+    ("mkv", "video/x-matroska", FileKind::Video),
+    ("avi", "video/x-msvideo", FileKind::Video),
+    ("webm", "video/webm", FileKind::Video),
+    ("mov", "video/quicktime", FileKind::Video),
+    ("mpg", "video/mpeg", FileKind::Video),
+    ("mpeg", "video/mpeg", FileKind::Video),
+    ("jpg", "image/jpeg", FileKind::Image),
+    ("jpeg", "image/jpeg", FileKind::Image),
+    ("png", "image/png", FileKind::Image),
+    ("gif", "image/gif", FileKind::Image),
+    ("webp", "image/webp", FileKind::Image),
+    ("bmp", "image/bmp", FileKind::Image),
+    ("tif", "image/tiff", FileKind::Image),
+    ("tiff", "image/tiff", FileKind::Image),
+    ("heic", "image/heic", FileKind::Image),
+    ("mp3", "audio/mpeg", FileKind::Audio),
+    ("flac", "audio/flac", FileKind::Audio),
+    ("wav", "audio/wav", FileKind::Audio),
+    ("aac", "audio/aac", FileKind::Audio),
+    ("m4a", "audio/mp4", FileKind::Audio),
+    ("ogg", "audio/ogg", FileKind::Audio),
+    ("opus", "audio/opus", FileKind::Audio),
+    ("wma", "audio/x-ms-wma", FileKind::Audio),
+    ("srt", "application/x-subrip", FileKind::Subtitle),
+    ("ass", "text/x-ssa", FileKind::Subtitle),
+    ("ssa", "text/x-ssa", FileKind::Subtitle),
+    ("vtt", "text/vtt", FileKind::Subtitle),
+    ("sub", "text/x-microdvd", FileKind::Subtitle),
+    ("idx", "application/x-vobsub", FileKind::Subtitle),
+    ("sup", "application/x-pgs", FileKind::Subtitle),
+    ("nfo", "text/x-nfo", FileKind::Other),
+    ("txt", "text/plain", FileKind::Other),
+];
+
+/// The row of `FILE_TYPES` for the extension of a (display) name.
+fn file_type(name: &str) -> Option<&'static (&'static str, &'static str, FileKind)> {
+    let (_, ext) = name.rsplit_once('.')?;
+    let ext = ext.to_lowercase();
+    FILE_TYPES.iter().find(|(known, _, _)| *known == ext)
+}
+
+impl FileKind {
+    /// Classifies a file by the extension of its (display) name.
+    pub fn from_name(name: &str) -> FileKind {
+        file_type(name).map_or(FileKind::Other, |&(_, _, kind)| kind)
+    }
+}
+
+/// The MIME type of a file by the extension of its (display) name.
+pub fn mime_type_from_name(name: &str) -> Option<&'static str> {
+    file_type(name).map(|&(_, mime_type, _)| mime_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mime_type_from_name() {
+        assert_eq!(
+            mime_type_from_name("Episode 1.mkv"),
+            Some("video/x-matroska")
+        );
+        assert_eq!(mime_type_from_name("clip.MP4"), Some("video/mp4"));
+        assert_eq!(mime_type_from_name("movie.webm"), Some("video/webm"));
+        assert_eq!(
+            mime_type_from_name("movie.en.srt"),
+            Some("application/x-subrip")
+        );
+        assert_eq!(mime_type_from_name("movie.vtt"), Some("text/vtt"));
+        assert_eq!(mime_type_from_name("movie.ass"), Some("text/x-ssa"));
+        assert_eq!(mime_type_from_name("poster.jpg"), Some("image/jpeg"));
+        assert_eq!(mime_type_from_name("poster.PNG"), Some("image/png"));
+        assert_eq!(mime_type_from_name("archive.xyz"), None);
+        assert_eq!(mime_type_from_name("README"), None);
+    }
+
+    #[test]
+    fn test_kind_from_name() {
+        assert_eq!(FileKind::from_name("Episode 1.mkv"), FileKind::Video);
+        assert_eq!(FileKind::from_name("clip.M4V"), FileKind::Video);
+        assert_eq!(FileKind::from_name("poster.jpg"), FileKind::Image);
+        assert_eq!(FileKind::from_name("theme.m4a"), FileKind::Audio);
+        assert_eq!(FileKind::from_name("movie.en.srt"), FileKind::Subtitle);
+        assert_eq!(FileKind::from_name("movie.nfo"), FileKind::Other);
+        assert_eq!(FileKind::from_name("README"), FileKind::Other);
+    }
 }
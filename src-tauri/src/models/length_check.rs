@@ -0,0 +1,41 @@
+use serde::Serialize;
+
+/// Length limits of the file system a path lives on. Lengths are in bytes,
+/// except on Windows where they are in UTF-16 code units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NameLimits {
+    /// Longest allowed single path component
+    pub max_component_len: usize,
+    /// Longest allowed full path
+    pub max_path_len: usize,
+}
+
+/// Why an encoded name would not fit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum LengthIssue {
+    /// The encoded name of `component` is longer than a path component may be
+    #[serde(rename_all = "camelCase")]
+    ComponentTooLong {
+        component: String,
+        length: usize,
+        limit: usize,
+    },
+    /// The full path after encoding is longer than a path may be
+    #[serde(rename_all = "camelCase")]
+    PathTooLong {
+        path: String,
+        length: usize,
+        limit: usize,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EncodeCheck {
+    pub can_encode: bool,
+    /// Set when `can_encode` is false
+    pub reason: Option<LengthIssue>,
+    pub limits: NameLimits,
+}
@@ -1,2 +1,15 @@
+pub mod batch_report;
+pub mod bulk_rename;
+pub mod copy_report;
+pub mod directory_change;
+pub mod directory_page;
+pub mod exclusion_rules;
 pub mod file_entry;
+pub mod length_check;
+pub mod operation;
+pub mod rename_report;
+pub mod search;
+pub mod transfer_progress;
+pub mod trash_entry;
+pub mod verify_report;
 pub mod video_frame;
@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OperationKind {
+    Rename,
+    Move,
+    Encode,
+    Decode,
+    /// Re-encoding legacy names with the default scheme
+    Migrate,
+    /// Moving a node to the trash
+    Delete,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationRecord {
+    pub id: i64,
+    pub kind: OperationKind,
+    /// Path the operation was started on
+    pub path: String,
+    /// Seconds since the Unix epoch
+    pub performed_at: i64,
+    /// Undone operations can be redone until the next operation is recorded
+    pub undone: bool,
+}
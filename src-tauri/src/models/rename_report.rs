@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+
+use super::file_entry::FileEntry;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenameRecord {
+    /// Absolute path before the rename
+    pub from: String,
+    /// Absolute path after the rename
+    pub to: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenameReport {
+    /// The node after the operation (or unchanged, if it was rolled back)
+    pub entry: Option<FileEntry>,
+    /// Renames that are in effect; none of them if the operation was rolled back
+    pub renamed: Vec<RenameRecord>,
+    /// Renames that were undone again after a failure
+    pub rolled_back: Vec<RenameRecord>,
+    /// Every name collision that was found, and how it was handled
+    pub collisions: Vec<CollisionRecord>,
+    /// Set if the operation failed; the tree has been restored in that case
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PlanIssueKind {
+    /// The encoded path would exceed the length limit
+    TooLong,
+    /// The new name is already taken by a sibling and the policy is to fail
+    Collision,
+    /// The physical name is not valid UTF-8 and cannot be converted
+    InvalidUtf8,
+    /// The name is encrypted and the session is locked
+    Locked,
+    /// The name carries an encoding prefix but doesn't decode, e.g. to
+    /// invalid UTF-8
+    Undecodable,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedRename {
+    /// Absolute path of the entry as it is now
+    pub path: String,
+    pub old_name: String,
+    pub new_name: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenameIssue {
+    /// Absolute path of the affected entry as it is now
+    pub path: String,
+    pub kind: PlanIssueKind,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenamePreview {
+    /// Renames in the order they would be applied
+    pub renames: Vec<PlannedRename>,
+    pub issues: Vec<RenameIssue>,
+    pub collisions: Vec<CollisionRecord>,
+}
+
+/// What to do when the new name of an entry is already taken by a sibling.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CollisionPolicy {
+    /// Abort the whole operation without touching anything
+    #[default]
+    Fail,
+    /// Leave the colliding entry as it is
+    Skip,
+    /// Append " (2)", " (3)", ... to the name until it is free
+    Suffix,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CollisionResolution {
+    Failed,
+    Skipped,
+    Suffixed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollisionRecord {
+    /// Absolute path of the entry that was to be renamed
+    pub path: String,
+    /// The path that was already taken
+    pub target: String,
+    pub resolution: CollisionResolution,
+    /// Where the entry went instead, if it was suffixed
+    pub resolved_path: Option<String>,
+}
@@ -0,0 +1,76 @@
+use serde::Serialize;
+
+use super::file_entry::FileEntry;
+
+/// Sent as a `search-results` event with the matches found since the last
+/// batch.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResults {
+    pub search_id: String,
+    pub entries: Vec<FileEntry>,
+}
+
+/// Sent as a `search-progress` event while a search runs, and once more
+/// with `finished` set when it ends.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchProgress {
+    pub search_id: String,
+    pub directories_scanned: usize,
+    pub results_found: usize,
+    pub finished: bool,
+    /// There were more matches than the result cap; the rest weren't reported
+    pub truncated: bool,
+    pub cancelled: bool,
+    pub error: Option<String>,
+}
+
+/// A search query that can't be parsed. `start` and `end` are the character
+/// offsets of `token` in the query.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryError {
+    pub message: String,
+    pub token: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Why a search couldn't run: a bad query, or anything else.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum SearchError {
+    Query(QueryError),
+    Other { message: String },
+}
+
+impl From<String> for SearchError {
+    fn from(message: String) -> Self {
+        SearchError::Other { message }
+    }
+}
+
+impl From<QueryError> for SearchError {
+    fn from(error: QueryError) -> Self {
+        SearchError::Query(error)
+    }
+}
+
+/// A directory kept in the search index. Until its first scan is done
+/// (`ready`), searches below it walk the tree.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexedDirectory {
+    pub path: String,
+    pub ready: bool,
+}
+
+/// Sent when the scan of an indexed directory failed. The directory is
+/// dropped from the index.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexFailure {
+    pub path: String,
+    pub message: String,
+}
@@ -0,0 +1,26 @@
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TransferPhase {
+    Copying,
+    /// Comparing the copy with the source before the source is removed
+    Verifying,
+}
+
+/// Sent as a `move-progress` event while a node is copied to another file
+/// system, or as part of `batch-progress` when a batch moves it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferProgress {
+    /// The node being moved
+    pub source: String,
+    pub phase: TransferPhase,
+    /// Bytes copied or verified so far in this phase
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub files_done: usize,
+    pub files_total: usize,
+    /// File currently being copied or verified
+    pub current_path: Option<String>,
+}
@@ -0,0 +1,19 @@
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashEntry {
+    /// Opaque id to pass to `restore_from_trash`
+    pub id: String,
+    /// Display name (decoded if the name was encoded)
+    pub name: String,
+    /// Name the entry had on disk before it was trashed
+    pub physical_name: String,
+    pub is_encoded: bool,
+    pub is_locked: bool,
+    pub is_directory: bool,
+    /// Where the entry is restored to
+    pub original_path: String,
+    /// Seconds since the Unix epoch, if known
+    pub deleted_at: Option<i64>,
+}
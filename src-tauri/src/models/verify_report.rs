@@ -0,0 +1,41 @@
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TreeIssueKind {
+    /// The name carries an encoding prefix but doesn't decode
+    Undecodable,
+    /// The directory mixes encoded and plain names
+    PartiallyEncoded,
+    /// The decoded name contains path separators or control characters
+    UnsafeName,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeIssue {
+    pub path: String,
+    pub physical_name: String,
+    pub kind: TreeIssueKind,
+    /// Human readable explanation, e.g. "invalid UTF-8"
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyCounts {
+    /// Entries looked at, not counting excluded files
+    pub entries: usize,
+    pub undecodable: usize,
+    pub partially_encoded: usize,
+    pub unsafe_names: usize,
+    /// Encrypted names that couldn't be checked because the session is locked
+    pub locked: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyReport {
+    pub issues: Vec<TreeIssue>,
+    pub counts: VerifyCounts,
+}
@@ -20,3 +20,15 @@ pub struct VideoInfo {
     pub bitrate: Option<u64>,
     pub framerate: Option<String>,
 }
+
+/// A video found by `list_video_files`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoFile {
+    /// Absolute path on disk
+    pub path: String,
+    /// Path relative to the listed directory, with `/` separators
+    pub relative_path: String,
+    /// `relative_path` with every component decoded
+    pub decoded_relative_path: String,
+}
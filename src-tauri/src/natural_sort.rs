@@ -0,0 +1,199 @@
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+
+use icu_normalizer::properties::CanonicalCombiningClassMapBorrowed;
+use icu_normalizer::DecomposingNormalizerBorrowed;
+
+/// A name folded once, ordered the way people read names: runs of digits
+/// by their numeric value, so "Episode 2" comes before "Episode 10", and
+/// letters ignoring case, accents and width, so "Élan" sorts with the E's
+/// rather than after "Z". Names that only differ in those are ordered
+/// consistently so the result is a total order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NaturalKey {
+    tokens: Vec<Token>,
+    /// Leading zeros of each number, which only break ties
+    zeros: Vec<usize>,
+    name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Char(char),
+    /// A run of digits without its leading zeros
+    Number(String),
+}
+
+pub fn natural_key(name: &str) -> NaturalKey {
+    let folded = fold(name);
+    let mut chars = folded.chars().peekable();
+    let mut tokens = Vec::new();
+    let mut zeros = Vec::new();
+    while let Some(&c) = chars.peek() {
+        if c.is_ascii_digit() {
+            let (digits, leading_zeros) = take_number(&mut chars);
+            tokens.push(Token::Number(digits));
+            zeros.push(leading_zeros);
+        } else {
+            tokens.push(Token::Char(c));
+            chars.next();
+        }
+    }
+    NaturalKey {
+        tokens,
+        zeros,
+        name: name.to_string(),
+    }
+}
+
+impl Ord for NaturalKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.tokens
+            .cmp(&other.tokens)
+            .then_with(|| self.zeros.cmp(&other.zeros))
+            .then_with(|| self.name.cmp(&other.name))
+    }
+}
+
+impl PartialOrd for NaturalKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Token {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Token::Char(l), Token::Char(r)) => l.cmp(r),
+            (Token::Number(l), Token::Number(r)) => l.len().cmp(&r.len()).then_with(|| l.cmp(r)),
+            // A number against a letter compares like any digit would
+            (Token::Number(_), Token::Char(r)) => '0'.cmp(r),
+            (Token::Char(l), Token::Number(_)) => l.cmp(&'0'),
+        }
+    }
+}
+
+impl PartialOrd for Token {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The keys of the components of a path. Paths compare component by
+/// component, so a folder sorts before its siblings' contents regardless of
+/// the separator.
+pub fn natural_path_key(path: &Path) -> Vec<NaturalKey> {
+    path.iter()
+        .map(|component| natural_key(&component.to_string_lossy()))
+        .collect()
+}
+
+/// The name in compatibility decomposition with combining marks dropped and
+/// lowercased: "Ｅｐ．１" becomes "ep.1" and "Café" becomes "cafe".
+fn fold(name: &str) -> String {
+    let combining_class = CanonicalCombiningClassMapBorrowed::new();
+    DecomposingNormalizerBorrowed::new_nfkd()
+        .normalize_iter(name.chars())
+        .filter(|&c| combining_class.get_u8(c) == 0)
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Consumes a run of digits, returning it without leading zeros along with
+/// the number of zeros dropped.
+fn take_number(chars: &mut Peekable<Chars>) -> (String, usize) {
+    let mut digits = String::new();
+    let mut zeros = 0;
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        if c == '0' && digits.is_empty() {
+            zeros += 1;
+        } else {
+            digits.push(c);
+        }
+    }
+    (digits, zeros)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        names.sort_by_cached_key(|name| natural_key(name));
+        names
+    }
+
+    #[test]
+    fn test_numbers_sort_by_value() {
+        assert_eq!(
+            sorted(&[
+                "Episode 10.mkv",
+                "Episode 2.mkv",
+                "Episode 1.mkv",
+                "episode 3.mkv"
+            ]),
+            [
+                "Episode 1.mkv",
+                "Episode 2.mkv",
+                "episode 3.mkv",
+                "Episode 10.mkv"
+            ]
+        );
+        assert_eq!(
+            sorted(&["a10b", "a9c", "a9b", "a"]),
+            ["a", "a9b", "a9c", "a10b"]
+        );
+    }
+
+    #[test]
+    fn test_ties_are_ordered() {
+        assert_eq!(sorted(&["a02", "a2", "a002"]), ["a2", "a02", "a002"]);
+        assert_eq!(sorted(&["b", "B", "a"]), ["a", "B", "b"]);
+        assert_eq!(natural_key("x1").cmp(&natural_key("x1")), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_keys_are_a_total_order() {
+        let names = [
+            "a", "a1", "a01", "a1b", "a-1", "a 1", "A1", "1", "01a", "é", "e", "x10y2", "x10y02",
+            "x9",
+        ];
+        for a in names {
+            for b in names {
+                let order = natural_key(a).cmp(&natural_key(b));
+                assert_eq!(order == Ordering::Equal, a == b, "{} vs {}", a, b);
+                assert_eq!(order, natural_key(b).cmp(&natural_key(a)).reverse());
+            }
+        }
+        assert_eq!(
+            natural_path_key(Path::new("Season 2/e1"))
+                .cmp(&natural_path_key(Path::new("Season 10"))),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn test_accents_and_width_are_folded() {
+        assert_eq!(
+            sorted(&[
+                "Zebra",
+                "Éclair",
+                "eclair",
+                "Apple",
+                "Ｅｐｉｓｏｄｅ １０",
+                "Episode 9"
+            ]),
+            [
+                "Apple",
+                "eclair",
+                "Éclair",
+                "Episode 9",
+                "Ｅｐｉｓｏｄｅ １０",
+                "Zebra"
+            ]
+        );
+    }
+}
@@ -0,0 +1,475 @@
+use regex::{Captures, Regex};
+
+use crate::models::bulk_rename::{CaseConversion, RenameTemplate};
+use crate::models::video_frame::VideoInfo;
+
+/// Widest counter `{n:width}` pads to, the number of digits of `u64::MAX`.
+const MAX_COUNTER_WIDTH: usize = 20;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Group(Group),
+    Field(Field),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Group {
+    Index(usize),
+    Name(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Name,
+    Ext,
+    Counter { width: usize },
+    Duration,
+    Resolution,
+    Width,
+    Height,
+    Codec,
+}
+
+impl Field {
+    fn needs_video_info(self) -> bool {
+        matches!(
+            self,
+            Field::Duration | Field::Resolution | Field::Width | Field::Height | Field::Codec
+        )
+    }
+}
+
+/// The `replace` string of a template, split into literal text, capture
+/// group references and fields.
+fn parse(template: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.char_indices().peekable();
+
+    while let Some((pos, c)) = chars.next() {
+        let part = match c {
+            '{' if chars.next_if(|&(_, c)| c == '{').is_some() => {
+                literal.push('{');
+                continue;
+            }
+            '}' if chars.next_if(|&(_, c)| c == '}').is_some() => {
+                literal.push('}');
+                continue;
+            }
+            '}' => return Err(format!("Unmatched '}}' at position {}", pos)),
+            '$' if chars.next_if(|&(_, c)| c == '$').is_some() => {
+                literal.push('$');
+                continue;
+            }
+            '{' => {
+                let spec: String = chars
+                    .by_ref()
+                    .map(|(_, c)| c)
+                    .take_while(|&c| c != '}')
+                    .collect();
+                if !template[pos..].contains('}') {
+                    return Err(format!("Unclosed '{{' at position {}", pos));
+                }
+                Part::Field(parse_field(&spec)?)
+            }
+            '$' if chars.next_if(|&(_, c)| c == '{').is_some() => {
+                let name: String = chars
+                    .by_ref()
+                    .map(|(_, c)| c)
+                    .take_while(|&c| c != '}')
+                    .collect();
+                if !template[pos..].contains('}') {
+                    return Err(format!("Unclosed '${{' at position {}", pos));
+                }
+                Part::Group(match name.parse() {
+                    Ok(index) => Group::Index(index),
+                    Err(_) if !name.is_empty() => Group::Name(name),
+                    Err(_) => return Err(format!("Empty group name at position {}", pos)),
+                })
+            }
+            '$' => {
+                let mut digits = String::new();
+                while let Some((_, d)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+                    digits.push(d);
+                }
+                match digits.parse() {
+                    Ok(index) => Part::Group(Group::Index(index)),
+                    Err(_) => {
+                        return Err(format!("Expected a group after '$' at position {}", pos))
+                    }
+                }
+            }
+            c => {
+                literal.push(c);
+                continue;
+            }
+        };
+        if !literal.is_empty() {
+            parts.push(Part::Literal(std::mem::take(&mut literal)));
+        }
+        parts.push(part);
+    }
+
+    if !literal.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
+    Ok(parts)
+}
+
+fn parse_field(spec: &str) -> Result<Field, String> {
+    let (name, arg) = match spec.split_once(':') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (spec, None),
+    };
+    let field = match name {
+        "name" => Field::Name,
+        "ext" => Field::Ext,
+        "n" => Field::Counter {
+            width: match arg {
+                Some(width) => match width.parse() {
+                    Ok(width) if width <= MAX_COUNTER_WIDTH => width,
+                    Ok(_) => {
+                        return Err(format!(
+                            "Counter width can be at most {}",
+                            MAX_COUNTER_WIDTH
+                        ))
+                    }
+                    Err(_) => return Err(format!("Invalid counter width: {}", width)),
+                },
+                None => 0,
+            },
+        },
+        "duration" => Field::Duration,
+        "resolution" => Field::Resolution,
+        "width" => Field::Width,
+        "height" => Field::Height,
+        "codec" => Field::Codec,
+        _ => return Err(format!("Unknown field: {{{}}}", spec)),
+    };
+    if arg.is_some() && !matches!(field, Field::Counter { .. }) {
+        return Err(format!("Field takes no argument: {{{}}}", spec));
+    }
+    Ok(field)
+}
+
+/// What the fields of a template expand to for one entry.
+pub struct Context<'a> {
+    /// The display name without the extension (the whole name for directories)
+    pub stem: &'a str,
+    /// The extension without the dot, empty if there is none
+    pub ext: &'a str,
+    pub counter: u64,
+    pub video: Option<&'a VideoInfo>,
+}
+
+/// A parsed `RenameTemplate`, ready to be applied to many names.
+pub struct Renamer {
+    find: Option<Regex>,
+    parts: Vec<Part>,
+    case: CaseConversion,
+    include_extension: bool,
+    counter_start: u64,
+    counter_step: u64,
+}
+
+impl Renamer {
+    pub fn new(template: &RenameTemplate) -> Result<Renamer, String> {
+        let find = match &template.find {
+            Some(find) => Some(Regex::new(find).map_err(|e| format!("Invalid regex: {}", e))?),
+            None => None,
+        };
+        let parts = parse(&template.replace)?;
+
+        for part in &parts {
+            let Part::Group(group) = part else { continue };
+            let known = match (&find, group) {
+                (None, _) => return Err("Capture groups need a regex to match".to_string()),
+                (Some(find), Group::Index(index)) => *index < find.captures_len(),
+                (Some(find), Group::Name(name)) => {
+                    find.capture_names().any(|n| n == Some(name.as_str()))
+                }
+            };
+            if !known {
+                return Err(format!("The regex has no group {}", group_label(group)));
+            }
+        }
+
+        Ok(Renamer {
+            find,
+            parts,
+            case: template.case,
+            include_extension: template.include_extension,
+            counter_start: template.counter_start.unwrap_or(1),
+            counter_step: template.counter_step.unwrap_or(1),
+        })
+    }
+
+    pub fn needs_video_info(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Field(field) if field.needs_video_info()))
+    }
+
+    /// The new name of the `index`th entry, whose display name is `name`.
+    pub fn rename(
+        &self,
+        name: &str,
+        is_dir: bool,
+        index: usize,
+        video: Option<&VideoInfo>,
+    ) -> Result<String, String> {
+        let (stem, ext) = split_extension(name, is_dir);
+        let counter = u64::try_from(index)
+            .ok()
+            .and_then(|index| self.counter_step.checked_mul(index))
+            .and_then(|offset| self.counter_start.checked_add(offset))
+            .ok_or("The counter is too large")?;
+        let context = Context {
+            stem,
+            ext,
+            counter,
+            video,
+        };
+
+        let target = if self.include_extension { name } else { stem };
+        let renamed = match &self.find {
+            Some(find) => {
+                let mut renamed = String::new();
+                let mut last = 0;
+                for captures in find.captures_iter(target) {
+                    let matched = captures.get(0).expect("group 0 always matches");
+                    renamed.push_str(&target[last..matched.start()]);
+                    renamed.push_str(&self.expand(&context, Some(&captures))?);
+                    last = matched.end();
+                }
+                renamed.push_str(&target[last..]);
+                renamed
+            }
+            None => self.expand(&context, None)?,
+        };
+
+        let mut new_name = convert_case(&renamed, self.case);
+        if !self.include_extension && !ext.is_empty() {
+            new_name.push('.');
+            new_name.push_str(ext);
+        }
+        check_name(&new_name)?;
+        Ok(new_name)
+    }
+
+    fn expand(&self, context: &Context, captures: Option<&Captures>) -> Result<String, String> {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => out.push_str(text),
+                Part::Group(group) => {
+                    let captures = captures.expect("groups are checked against the regex");
+                    let matched = match group {
+                        Group::Index(index) => captures.get(*index),
+                        Group::Name(name) => captures.name(name),
+                    };
+                    out.push_str(matched.map_or("", |m| m.as_str()));
+                }
+                Part::Field(field) => out.push_str(&expand_field(*field, context)?),
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn group_label(group: &Group) -> String {
+    match group {
+        Group::Index(index) => format!("${}", index),
+        Group::Name(name) => format!("${{{}}}", name),
+    }
+}
+
+fn expand_field(field: Field, context: &Context) -> Result<String, String> {
+    let video = || context.video.ok_or("No video metadata");
+    let missing = |what: &str| format!("The video has no {}", what);
+    Ok(match field {
+        Field::Name => context.stem.to_string(),
+        Field::Ext => context.ext.to_string(),
+        Field::Counter { width } => format!("{:0width$}", context.counter, width = width),
+        Field::Duration => format_duration(video()?.duration_secs),
+        Field::Resolution => {
+            let video = video()?;
+            match (video.width, video.height) {
+                (Some(width), Some(height)) => format!("{}x{}", width, height),
+                _ => return Err(missing("resolution")),
+            }
+        }
+        Field::Width => video()?.width.ok_or_else(|| missing("width"))?.to_string(),
+        Field::Height => video()?
+            .height
+            .ok_or_else(|| missing("height"))?
+            .to_string(),
+        Field::Codec => video()?.codec.clone().ok_or_else(|| missing("codec"))?,
+    })
+}
+
+/// "1h02m03s", or "42m07s" for videos shorter than an hour. Colons are not
+/// allowed in Windows file names.
+fn format_duration(secs: f64) -> String {
+    let total = secs.round() as u64;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+    if hours > 0 {
+        format!("{}h{:02}m{:02}s", hours, minutes, seconds)
+    } else {
+        format!("{}m{:02}s", minutes, seconds)
+    }
+}
+
+/// Splits "show.s01e01.mkv" into ("show.s01e01", "mkv"). Directories and
+/// dotfiles like ".hidden" have no extension.
+fn split_extension(name: &str, is_dir: bool) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(dot) if !is_dir && dot > 0 => (&name[..dot], &name[dot + 1..]),
+        _ => (name, ""),
+    }
+}
+
+fn convert_case(name: &str, case: CaseConversion) -> String {
+    match case {
+        CaseConversion::Keep => name.to_string(),
+        CaseConversion::Lower => name.to_lowercase(),
+        CaseConversion::Upper => name.to_uppercase(),
+        CaseConversion::Title => {
+            let mut out = String::with_capacity(name.len());
+            let mut word_start = true;
+            for c in name.chars() {
+                if word_start {
+                    out.extend(c.to_uppercase());
+                } else {
+                    out.extend(c.to_lowercase());
+                }
+                word_start = !c.is_alphanumeric() && c != '\'';
+            }
+            out
+        }
+    }
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(format!("Invalid file name: {:?}", name));
+    }
+    if name.contains(['/', '\0']) || (cfg!(windows) && name.contains('\\')) {
+        return Err(format!("File name contains a path separator: {}", name));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(find: Option<&str>, replace: &str) -> RenameTemplate {
+        RenameTemplate {
+            find: find.map(str::to_string),
+            replace: replace.to_string(),
+            case: CaseConversion::Keep,
+            include_extension: false,
+            counter_start: None,
+            counter_step: None,
+        }
+    }
+
+    fn video() -> VideoInfo {
+        VideoInfo {
+            duration_secs: 3723.4,
+            file_size_bytes: 0,
+            width: Some(1920),
+            height: Some(1080),
+            display_aspect_ratio: None,
+            codec: Some("hevc".to_string()),
+            bitrate: None,
+            framerate: None,
+        }
+    }
+
+    #[test]
+    fn test_regex_with_capture_groups() {
+        let renamer = Renamer::new(&template(
+            Some(r"(?i)s(\d+)e(?<ep>\d+)"),
+            "Season $1 Episode ${ep}",
+        ))
+        .unwrap();
+        assert_eq!(
+            renamer.rename("Show.S01E05.mkv", false, 0, None).unwrap(),
+            "Show.Season 01 Episode 05.mkv"
+        );
+        assert_eq!(
+            renamer.rename("Trailer.mkv", false, 0, None).unwrap(),
+            "Trailer.mkv"
+        );
+    }
+
+    #[test]
+    fn test_counter_and_fields() {
+        let mut tpl = template(
+            None,
+            "Episode {n:2} - {name} [{resolution} {codec} {duration}]",
+        );
+        tpl.counter_start = Some(9);
+        let renamer = Renamer::new(&tpl).unwrap();
+        assert!(renamer.needs_video_info());
+        assert_eq!(
+            renamer
+                .rename("pilot.mp4", false, 2, Some(&video()))
+                .unwrap(),
+            "Episode 11 - pilot [1920x1080 hevc 1h02m03s].mp4"
+        );
+        assert!(renamer.rename("pilot.mp4", false, 0, None).is_err());
+    }
+
+    #[test]
+    fn test_counter_limits() {
+        assert!(Renamer::new(&template(None, "{n:20}")).is_ok());
+        assert!(Renamer::new(&template(None, "{n:21}")).is_err());
+        assert!(Renamer::new(&template(None, "{n:99999999999}")).is_err());
+
+        let mut tpl = template(None, "{n}");
+        tpl.counter_start = Some(u64::MAX - 1);
+        tpl.counter_step = Some(u64::MAX / 2);
+        let renamer = Renamer::new(&tpl).unwrap();
+        assert_eq!(
+            renamer.rename("a", false, 0, None).unwrap(),
+            (u64::MAX - 1).to_string()
+        );
+        assert!(renamer.rename("a", false, 1, None).is_err());
+        assert!(renamer.rename("a", false, 3, None).is_err());
+    }
+
+    #[test]
+    fn test_case_conversion_keeps_extension() {
+        let mut tpl = template(None, "{name}");
+        tpl.case = CaseConversion::Title;
+        let renamer = Renamer::new(&tpl).unwrap();
+        assert_eq!(
+            renamer.rename("the BIG one.MKV", false, 0, None).unwrap(),
+            "The Big One.MKV"
+        );
+        assert_eq!(renamer.rename("my.dir", true, 0, None).unwrap(), "My.Dir");
+    }
+
+    #[test]
+    fn test_escapes() {
+        let renamer = Renamer::new(&template(None, "{{$$}}{name}")).unwrap();
+        assert_eq!(renamer.rename("a.txt", false, 0, None).unwrap(), "{$}a.txt");
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        assert!(Renamer::new(&template(None, "{nope}")).is_err());
+        assert!(Renamer::new(&template(None, "{name")).is_err());
+        assert!(Renamer::new(&template(None, "$1")).is_err());
+        assert!(Renamer::new(&template(Some("(a)"), "$2")).is_err());
+        assert!(Renamer::new(&template(Some("("), "x")).is_err());
+        assert!(Renamer::new(&template(None, "a/b"))
+            .unwrap()
+            .rename("x", false, 0, None)
+            .is_err());
+    }
+}
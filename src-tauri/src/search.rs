@@ -0,0 +1,353 @@
+use std::fs;
+use std::path::Path;
+
+use crate::commands::video_commands::read_video_info;
+use crate::encoding::encoded_dir::{name_state_in, NameState};
+use crate::encoding::name_crypto::SessionKey;
+use crate::exclusions::{DirExclusions, Exclusions, TreeExclusions};
+use crate::models::file_entry::FileEntry;
+use crate::models::search::SearchProgress;
+use crate::models::video_frame::VideoInfo;
+use crate::search_index::SearchIndexState;
+use crate::search_query::{Candidate, Query};
+
+pub enum SearchEvent {
+    Match(Box<FileEntry>),
+    /// A directory has been read; matches in it have been reported
+    DirectoryScanned,
+}
+
+/// What a search runs with, besides the query and where it starts.
+pub struct SearchOptions<'a> {
+    pub key: Option<&'a SessionKey>,
+    pub exclusions: &'a Exclusions,
+    /// Matches to report at most; None for all of them
+    pub max_results: Option<usize>,
+    pub is_cancelled: &'a dyn Fn() -> bool,
+}
+
+impl SearchOptions<'_> {
+    /// Whether `found` matches are all that may be reported.
+    fn is_full(&self, found: usize) -> bool {
+        self.max_results.is_some_and(|max| found >= max)
+    }
+}
+
+/// Searches below `root` in the search index if it is in an indexed
+/// directory, and walks the tree otherwise. Video info is probed as needed,
+/// and kept in the index when it is used.
+pub fn search(
+    index: Option<&SearchIndexState>,
+    root: &Path,
+    query: &Query,
+    options: &SearchOptions,
+    on_event: &mut dyn FnMut(SearchEvent, &SearchProgress),
+) -> Result<SearchProgress, String> {
+    let key = options.key;
+    match index.filter(|index| index.ready_root_for(root).is_some()) {
+        Some(index) => {
+            let probe = |entry: &FileEntry| index.video_info(entry, key);
+            search_indexed(index, root, query, options, &probe, on_event)
+        }
+        None => {
+            let probe = |entry: &FileEntry| read_video_info(&entry.path, key).ok();
+            search_tree(root, query, options, &probe, on_event)
+        }
+    }
+}
+
+/// Walks the tree below `root` for entries matching `query` and reports them
+/// through `on_event` as they are found. `probe` reads the video info for
+/// video filters. Stops once a match beyond `max_results` is found or
+/// `is_cancelled` returns true; the returned progress says which.
+pub fn search_tree(
+    root: &Path,
+    query: &Query,
+    options: &SearchOptions,
+    probe: &dyn Fn(&FileEntry) -> Option<VideoInfo>,
+    on_event: &mut dyn FnMut(SearchEvent, &SearchProgress),
+) -> Result<SearchProgress, String> {
+    if !root.is_dir() {
+        return Err(format!("Not a directory: {}", root.display()));
+    }
+
+    let mut search = TreeSearch {
+        query,
+        options,
+        probe,
+        on_event,
+        progress: SearchProgress::default(),
+    };
+    search.walk(root, &options.exclusions.in_dir(root, options.key))?;
+    search.progress.finished = true;
+    Ok(search.progress)
+}
+
+/// Like `search_tree`, but takes the entries from the search index instead
+/// of walking the tree. `root` has to be in an indexed directory. Matches are
+/// reported in path order, and the tree counts as a single directory.
+pub fn search_indexed(
+    index: &SearchIndexState,
+    root: &Path,
+    query: &Query,
+    options: &SearchOptions,
+    probe: &dyn Fn(&FileEntry) -> Option<VideoInfo>,
+    on_event: &mut dyn FnMut(SearchEvent, &SearchProgress),
+) -> Result<SearchProgress, String> {
+    if !root.is_dir() {
+        return Err(format!("Not a directory: {}", root.display()));
+    }
+
+    let key = options.key;
+    let candidates = index.candidates(root, &query.index_filter(), key.is_some())?;
+    let mut rules = TreeExclusions::new(root, key, options.exclusions);
+    let mut progress = SearchProgress::default();
+    for indexed in candidates {
+        if (options.is_cancelled)() {
+            progress.cancelled = true;
+            return Ok(progress);
+        }
+        let name = match rules.visible_state(&indexed.path, indexed.is_directory) {
+            Some(NameState::Plain) => indexed.name,
+            Some(NameState::Decoded(decoded)) => Some(decoded),
+            Some(NameState::Locked) | None => None,
+        };
+        let Some(name) = name else { continue };
+
+        let candidate = Candidate::new(&name, &indexed.path, key, probe);
+        if query.matches(&candidate) {
+            if let Some(entry) = candidate.into_entry() {
+                if options.is_full(progress.results_found) {
+                    progress.truncated = true;
+                    return Ok(progress);
+                }
+                progress.results_found += 1;
+                on_event(SearchEvent::Match(Box::new(entry)), &progress);
+            }
+        }
+    }
+
+    progress.directories_scanned = 1;
+    on_event(SearchEvent::DirectoryScanned, &progress);
+    progress.finished = true;
+    Ok(progress)
+}
+
+struct TreeSearch<'a> {
+    query: &'a Query,
+    options: &'a SearchOptions<'a>,
+    probe: &'a dyn Fn(&FileEntry) -> Option<VideoInfo>,
+    on_event: &'a mut dyn FnMut(SearchEvent, &SearchProgress),
+    progress: SearchProgress,
+}
+
+impl TreeSearch<'_> {
+    /// Whether the walk should stop, noting why.
+    fn should_stop(&mut self) -> bool {
+        if (self.options.is_cancelled)() {
+            self.progress.cancelled = true;
+        }
+        self.progress.cancelled || self.progress.truncated
+    }
+
+    fn walk(&mut self, dir: &Path, rules: &DirExclusions) -> Result<(), String> {
+        let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read directory: {}", e))?;
+        let mut subdirs = Vec::new();
+        let key = self.options.key;
+
+        for entry in entries {
+            if self.should_stop() {
+                return Ok(());
+            }
+            let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let entry_path = entry.path();
+            let is_dir = entry_path.is_dir();
+
+            let state = name_state_in(dir, &file_name, key);
+            if rules.is_excluded(&file_name, &state, is_dir) {
+                continue;
+            }
+
+            // Locked names are only placeholders, so they never match
+            let display_name = match state {
+                NameState::Plain => Some(file_name),
+                NameState::Decoded(decoded) => Some(decoded),
+                NameState::Locked => None,
+            };
+
+            if let Some(name) = display_name {
+                let candidate = Candidate::new(&name, &entry_path, key, self.probe);
+                if self.query.matches(&candidate) {
+                    if let Some(entry) = candidate.into_entry() {
+                        if self.options.is_full(self.progress.results_found) {
+                            self.progress.truncated = true;
+                            return Ok(());
+                        }
+                        self.progress.results_found += 1;
+                        (self.on_event)(SearchEvent::Match(Box::new(entry)), &self.progress);
+                    }
+                }
+            }
+
+            if is_dir {
+                subdirs.push(entry_path);
+            }
+        }
+
+        self.progress.directories_scanned += 1;
+        (self.on_event)(SearchEvent::DirectoryScanned, &self.progress);
+
+        for subdir in subdirs {
+            if self.should_stop() {
+                break;
+            }
+            self.walk(&subdir, &rules.subdir(&subdir))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use tempfile::TempDir;
+
+    fn search_for(
+        root: &Path,
+        query: &str,
+        max_results: Option<usize>,
+        is_cancelled: &dyn Fn() -> bool,
+        on_event: &mut dyn FnMut(SearchEvent, &SearchProgress),
+    ) -> SearchProgress {
+        let query = Query::parse(query).unwrap();
+        let options = SearchOptions {
+            key: None,
+            exclusions: &Exclusions::default(),
+            max_results,
+            is_cancelled,
+        };
+        search_tree(root, &query, &options, &|_| None, on_event).unwrap()
+    }
+
+    fn library() -> TempDir {
+        let tmp = TempDir::new().unwrap();
+        for season in ["Season 1", "Season 2"] {
+            let dir = tmp.path().join(season);
+            fs::create_dir(&dir).unwrap();
+            for episode in 1..=3 {
+                fs::write(dir.join(format!("Show E{}.mkv", episode)), "").unwrap();
+            }
+        }
+        tmp
+    }
+
+    #[test]
+    fn test_reports_matches_and_progress() {
+        let tmp = library();
+        let mut found = Vec::new();
+        let mut scanned = 0;
+        let progress = search_for(
+            tmp.path(),
+            "show",
+            None,
+            &|| false,
+            &mut |event, _| match event {
+                SearchEvent::Match(entry) => found.push(entry.name),
+                SearchEvent::DirectoryScanned => scanned += 1,
+            },
+        );
+
+        assert_eq!(found.len(), 6);
+        assert_eq!(scanned, 3);
+        assert_eq!(progress.directories_scanned, 3);
+        assert_eq!(progress.results_found, 6);
+        assert!(progress.finished && !progress.truncated && !progress.cancelled);
+    }
+
+    #[test]
+    fn test_stops_at_the_cap() {
+        let tmp = library();
+        let mut found = 0;
+        let progress = search_for(tmp.path(), "e", Some(4), &|| false, &mut |event, _| {
+            if let SearchEvent::Match(_) = event {
+                found += 1;
+            }
+        });
+
+        assert_eq!(found, 4);
+        assert!(progress.truncated);
+
+        // Exactly as many matches as the cap aren't truncated
+        let progress = search_for(tmp.path(), "show", Some(6), &|| false, &mut |_, _| {});
+        assert_eq!(progress.results_found, 6);
+        assert!(progress.finished && !progress.truncated);
+    }
+
+    #[test]
+    fn test_indexed_search_matches_walk() {
+        let tmp = library();
+        fs::write(tmp.path().join(".vbignore"), "Season 2/\n").unwrap();
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::search_index::init_schema(&conn).unwrap();
+        let index = SearchIndexState::new(conn);
+        index.index(tmp.path()).unwrap();
+
+        for query in ["show e2", "e1 OR e3", "mkv -e2", "size<1k"] {
+            let query = Query::parse(query).unwrap();
+            let mut walked = Vec::new();
+            let mut indexed = Vec::new();
+            let options = SearchOptions {
+                key: None,
+                exclusions: &Exclusions::default(),
+                max_results: None,
+                is_cancelled: &|| false,
+            };
+            search_tree(tmp.path(), &query, &options, &|_| None, &mut |event, _| {
+                if let SearchEvent::Match(entry) = event {
+                    walked.push(entry.path);
+                }
+            })
+            .unwrap();
+            search_indexed(
+                &index,
+                tmp.path(),
+                &query,
+                &options,
+                &|_| None,
+                &mut |event, _| {
+                    if let SearchEvent::Match(entry) = event {
+                        indexed.push(entry.path);
+                    }
+                },
+            )
+            .unwrap();
+            walked.sort();
+            assert!(!walked.is_empty());
+            assert_eq!(indexed, walked);
+        }
+    }
+
+    #[test]
+    fn test_stops_when_cancelled() {
+        let tmp = library();
+        let cancelled = Cell::new(false);
+        let mut found = 0;
+        let progress = search_for(
+            tmp.path(),
+            "show",
+            None,
+            &|| cancelled.get(),
+            &mut |event, _| {
+                if let SearchEvent::Match(_) = event {
+                    found += 1;
+                    cancelled.set(true);
+                }
+            },
+        );
+
+        assert_eq!(found, 1);
+        assert!(progress.cancelled);
+    }
+}
@@ -0,0 +1,861 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Instant, UNIX_EPOCH};
+
+use notify::event::{AccessKind, AccessMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+
+use crate::commands::video_commands::read_video_info;
+use crate::encoding::content_crypto;
+use crate::encoding::encoded_dir::{name_state_in, NameState};
+use crate::encoding::name_crypto::SessionKey;
+use crate::encoding::name_index::{is_hashed_name, INDEX_FILE_NAME};
+use crate::models::file_entry::FileEntry;
+use crate::models::search::IndexedDirectory;
+use crate::models::video_frame::VideoInfo;
+use crate::search_query::IndexFilter;
+use crate::transfer::CANCELLED;
+use crate::watcher::{MAX_DELAY, QUIET_PERIOD};
+
+/// The search index: every entry below the indexed directories with its
+/// display name, size, times and probed video metadata.
+///
+/// Names that need the session key to decode are stored without a name, so
+/// encrypted names never end up on disk in the clear.
+pub struct SearchIndexState {
+    conn: Arc<Mutex<Connection>>,
+    /// Indexed directories being watched for changes, with the flag that
+    /// stops their scans once they are removed
+    watchers: Mutex<HashMap<PathBuf, (RecommendedWatcher, Arc<AtomicBool>)>>,
+}
+
+pub fn init_db(app_data_dir: &Path) -> Result<SearchIndexState, String> {
+    std::fs::create_dir_all(app_data_dir)
+        .map_err(|e| format!("Failed to create app data dir: {}", e))?;
+
+    let db_path = app_data_dir.join("search_index.db");
+    let conn =
+        Connection::open(&db_path).map_err(|e| format!("Failed to open search index: {}", e))?;
+    init_schema(&conn)?;
+    Ok(SearchIndexState::new(conn))
+}
+
+pub fn init_schema(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS roots (
+            path TEXT PRIMARY KEY,
+            ready INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE TABLE IF NOT EXISTS entries (
+            id INTEGER PRIMARY KEY,
+            path TEXT NOT NULL UNIQUE,
+            parent TEXT NOT NULL,
+            name TEXT,
+            is_directory INTEGER NOT NULL,
+            is_encoded INTEGER NOT NULL,
+            size INTEGER NOT NULL,
+            modified INTEGER,
+            mtime_ns INTEGER,
+            listed_mtime_ns INTEGER,
+            duration_secs REAL,
+            width INTEGER,
+            height INTEGER,
+            codec TEXT
+        );
+        CREATE INDEX IF NOT EXISTS entries_parent ON entries (parent);
+        CREATE VIRTUAL TABLE IF NOT EXISTS entry_names USING fts5(
+            name, content = 'entries', content_rowid = 'id', tokenize = 'trigram remove_diacritics 1'
+        );
+        CREATE TRIGGER IF NOT EXISTS entries_insert AFTER INSERT ON entries BEGIN
+            INSERT INTO entry_names (rowid, name) VALUES (new.id, new.name);
+        END;
+        CREATE TRIGGER IF NOT EXISTS entries_delete AFTER DELETE ON entries BEGIN
+            INSERT INTO entry_names (entry_names, rowid, name) VALUES ('delete', old.id, old.name);
+        END;
+        CREATE TRIGGER IF NOT EXISTS entries_update AFTER UPDATE OF name ON entries BEGIN
+            INSERT INTO entry_names (entry_names, rowid, name) VALUES ('delete', old.id, old.name);
+            INSERT INTO entry_names (rowid, name) VALUES (new.id, new.name);
+        END;",
+    )
+    .map_err(|e| format!("Failed to create search index tables: {}", e))
+}
+
+/// How much of a directory `refresh` looks at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Refresh {
+    /// The whole tree, re-reading only directories whose mtime changed.
+    /// Files changed in place don't touch the mtime of their directory;
+    /// those are picked up by the watcher.
+    Tree,
+    /// The directory itself, whatever its mtime, and any new subdirectories
+    Dir,
+}
+
+/// An indexed entry below the directory being searched or listed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedEntry {
+    pub path: PathBuf,
+    /// None if the name can only be decoded with the session key
+    pub name: Option<String>,
+    pub is_directory: bool,
+}
+
+impl SearchIndexState {
+    pub fn new(conn: Connection) -> Self {
+        SearchIndexState {
+            conn: Arc::new(Mutex::new(conn)),
+            watchers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn conn(&self) -> Result<MutexGuard<'_, Connection>, String> {
+        self.conn
+            .lock()
+            .map_err(|e| format!("Search index lock error: {}", e))
+    }
+
+    /// The indexed directories and whether their first scan is done.
+    pub fn roots(&self) -> Result<Vec<IndexedDirectory>, String> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare("SELECT path, ready FROM roots ORDER BY path")
+            .map_err(|e| format!("Failed to read search index: {}", e))?;
+        let roots = stmt
+            .query_map([], |row| {
+                Ok(IndexedDirectory {
+                    path: row.get(0)?,
+                    ready: row.get(1)?,
+                })
+            })
+            .and_then(|rows| rows.collect())
+            .map_err(|e| format!("Failed to read search index: {}", e))?;
+        Ok(roots)
+    }
+
+    /// The indexed directory `path` is in, once its first scan is done.
+    /// Searches below it can use the index instead of walking the tree.
+    pub fn ready_root_for(&self, path: &Path) -> Option<PathBuf> {
+        self.roots()
+            .ok()?
+            .into_iter()
+            .filter(|root| root.ready)
+            .map(|root| PathBuf::from(root.path))
+            .find(|root| path.starts_with(root))
+    }
+
+    /// Indexes the tree below `root`, or brings the index of it up to date,
+    /// and keeps it up to date while the app runs. Blocks until the tree has
+    /// been scanned.
+    pub fn index(&self, root: &Path) -> Result<(), String> {
+        if !root.is_dir() {
+            return Err(format!("Not a directory: {}", root.display()));
+        }
+        let root_str = root.to_string_lossy();
+        {
+            let conn = self.conn()?;
+            conn.execute(
+                "INSERT OR IGNORE INTO roots (path) VALUES (?1)",
+                [&root_str],
+            )
+            .map_err(|e| format!("Failed to add indexed directory: {}", e))?;
+            upsert_entry(
+                &conn,
+                root,
+                &fs::metadata(root)
+                    .map_err(|e| format!("Failed to read {}: {}", root.display(), e))?,
+            )?;
+        }
+
+        let removed = self.watch(root)?;
+        refresh(&self.conn, root, Refresh::Tree, &removed)?;
+        self.conn()?
+            .execute("UPDATE roots SET ready = 1 WHERE path = ?1", [&root_str])
+            .map_err(|e| format!("Failed to update search index: {}", e))?;
+        Ok(())
+    }
+
+    /// Stops indexing `root`, including a scan of it that is still running,
+    /// and drops what was indexed below it that no other indexed directory
+    /// covers.
+    pub fn remove(&self, root: &Path) -> Result<(), String> {
+        if let Ok(mut watchers) = self.watchers.lock() {
+            if let Some((_, removed)) = watchers.remove(root) {
+                removed.store(true, Ordering::SeqCst);
+            }
+        }
+        let conn = self.conn()?;
+        conn.execute(
+            "DELETE FROM roots WHERE path = ?1",
+            [root.to_string_lossy()],
+        )
+        .map_err(|e| format!("Failed to remove indexed directory: {}", e))?;
+        drop(conn);
+
+        let others: Vec<PathBuf> = self
+            .roots()?
+            .into_iter()
+            .map(|other| PathBuf::from(other.path))
+            .collect();
+        if others.iter().any(|other| root.starts_with(other)) {
+            return Ok(());
+        }
+        let nested: Vec<&PathBuf> = others
+            .iter()
+            .filter(|other| other.starts_with(root))
+            .collect();
+        delete_subtree_except(&*self.conn()?, root, &nested)
+    }
+
+    /// Watches `root` unless it already is, and returns the flag that is set
+    /// once it is removed.
+    fn watch(&self, root: &Path) -> Result<Arc<AtomicBool>, String> {
+        let mut watchers = self
+            .watchers
+            .lock()
+            .map_err(|e| format!("Watch lock error: {}", e))?;
+        if let Some((_, removed)) = watchers.get(root) {
+            return Ok(removed.clone());
+        }
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)
+            .map_err(|e| format!("Failed to create watcher: {}", e))?;
+        watcher
+            .watch(root, RecursiveMode::Recursive)
+            .map_err(|e| format!("Failed to watch {}: {}", root.display(), e))?;
+        let conn = self.conn.clone();
+        let watched = root.to_path_buf();
+        let removed = Arc::new(AtomicBool::new(false));
+        let stop = removed.clone();
+        thread::spawn(move || apply_changes(events, &conn, &watched, &stop));
+        watchers.insert(root.to_path_buf(), (watcher, removed.clone()));
+        Ok(removed)
+    }
+
+    /// The entries below `dir` that may match a query with `filter`, by path.
+    /// Entries without a name are included if `include_unnamed` is set, as
+    /// the session key may decode them.
+    pub fn candidates(
+        &self,
+        dir: &Path,
+        filter: &IndexFilter,
+        include_unnamed: bool,
+    ) -> Result<Vec<IndexedEntry>, String> {
+        let (low, high) = subtree_range(dir);
+        let mut sql = String::from(
+            "SELECT path, name, is_directory FROM entries WHERE path > ?1 AND path < ?2",
+        );
+        let mut values: Vec<rusqlite::types::Value> = vec![low.into(), high.into()];
+
+        // Trigrams can't find anything shorter than three characters
+        let phrases: Vec<String> = filter
+            .name_contains
+            .iter()
+            .filter(|text| text.chars().count() >= 3)
+            .map(|text| format!("\"{}\"", text.replace('"', "\"\"")))
+            .collect();
+        let named = if phrases.is_empty() {
+            "name IS NOT NULL".to_string()
+        } else {
+            values.push(phrases.join(" AND ").into());
+            format!(
+                "id IN (SELECT rowid FROM entry_names WHERE entry_names MATCH ?{})",
+                values.len()
+            )
+        };
+        if include_unnamed {
+            sql.push_str(&format!(" AND ({} OR name IS NULL)", named));
+        } else {
+            sql.push_str(&format!(" AND {}", named));
+        }
+        for (column, op, value) in &filter.comparisons {
+            values.push((*value).into());
+            sql.push_str(&format!(" AND {} {} ?{}", column, op, values.len()));
+        }
+        sql.push_str(" ORDER BY path");
+
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(&sql)
+            .map_err(|e| format!("Failed to query search index: {}", e))?;
+        let entries = stmt
+            .query_map(params_from_iter(values), |row| {
+                Ok(IndexedEntry {
+                    path: PathBuf::from(row.get::<_, String>(0)?),
+                    name: row.get(1)?,
+                    is_directory: row.get(2)?,
+                })
+            })
+            .and_then(|rows| rows.collect())
+            .map_err(|e| format!("Failed to query search index: {}", e))?;
+        Ok(entries)
+    }
+
+    /// All entries below `dir`, by path.
+    pub fn entries_below(&self, dir: &Path) -> Result<Vec<IndexedEntry>, String> {
+        self.candidates(dir, &IndexFilter::default(), true)
+    }
+
+    /// The video info of the file, probed once and kept in the index until
+    /// the file changes. Videos with encrypted contents are probed every
+    /// time, like their frames aren't cached.
+    pub fn video_info(&self, entry: &FileEntry, key: Option<&SessionKey>) -> Option<VideoInfo> {
+        let path = Path::new(&entry.path);
+        let cacheable = !content_crypto::is_encrypted_file(path);
+        let mtime = fs::metadata(path).ok().and_then(|m| mtime_ns(&m));
+        if cacheable {
+            if let Some(info) = self.cached_video_info(&entry.path, mtime) {
+                return Some(info);
+            }
+        }
+
+        let info = read_video_info(&entry.path, key).ok()?;
+        if cacheable {
+            if let Ok(conn) = self.conn() {
+                let _ = conn.execute(
+                    "UPDATE entries SET duration_secs = ?2, width = ?3, height = ?4, codec = ?5
+                     WHERE path = ?1 AND mtime_ns IS ?6",
+                    params![
+                        entry.path,
+                        info.duration_secs,
+                        info.width,
+                        info.height,
+                        info.codec,
+                        mtime
+                    ],
+                );
+            }
+        }
+        Some(info)
+    }
+
+    fn cached_video_info(&self, path: &str, mtime: Option<i64>) -> Option<VideoInfo> {
+        let conn = self.conn().ok()?;
+        conn.query_row(
+            "SELECT duration_secs, size, width, height, codec FROM entries
+             WHERE path = ?1 AND mtime_ns IS ?2 AND duration_secs IS NOT NULL",
+            params![path, mtime],
+            |row| {
+                Ok(VideoInfo {
+                    duration_secs: row.get(0)?,
+                    file_size_bytes: row.get(1)?,
+                    width: row.get(2)?,
+                    height: row.get(3)?,
+                    display_aspect_ratio: None,
+                    codec: row.get(4)?,
+                    bitrate: None,
+                    framerate: None,
+                })
+            },
+        )
+        .ok()
+    }
+}
+
+/// Brings the index of `dir` up to date. The index is locked for one
+/// directory at a time, so searches don't wait for a whole scan. Stops once
+/// `stop` is set, which is checked while the index is locked so nothing is
+/// written after whoever set it took the lock.
+pub fn refresh(
+    conn: &Mutex<Connection>,
+    dir: &Path,
+    mode: Refresh,
+    stop: &AtomicBool,
+) -> Result<(), String> {
+    let subdirs = {
+        let mut conn = conn
+            .lock()
+            .map_err(|e| format!("Search index lock error: {}", e))?;
+        if stop.load(Ordering::SeqCst) {
+            return Err(CANCELLED.to_string());
+        }
+        let tx = conn
+            .transaction()
+            .map_err(|e| format!("Failed to update search index: {}", e))?;
+        let subdirs = update_listing(&tx, dir, mode)?;
+        tx.commit()
+            .map_err(|e| format!("Failed to update search index: {}", e))?;
+        subdirs
+    };
+
+    for (subdir, is_new) in subdirs {
+        if mode == Refresh::Tree || is_new {
+            refresh(conn, &subdir, Refresh::Tree, stop)?;
+        }
+    }
+    Ok(())
+}
+
+/// Re-reads `dir` if needed and returns its subdirectories to look at next,
+/// with whether they were just added.
+fn update_listing(
+    conn: &Connection,
+    dir: &Path,
+    mode: Refresh,
+) -> Result<Vec<(PathBuf, bool)>, String> {
+    let dir_str = dir.to_string_lossy();
+    let metadata = match fs::metadata(dir) {
+        Ok(metadata) if metadata.is_dir() => metadata,
+        _ => {
+            delete_subtree(conn, dir)?;
+            return Ok(Vec::new());
+        }
+    };
+    let mtime = mtime_ns(&metadata);
+    let listed: Option<i64> = conn
+        .query_row(
+            "SELECT listed_mtime_ns FROM entries WHERE path = ?1",
+            [&dir_str],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| format!("Failed to read search index: {}", e))?
+        .flatten();
+
+    if mode == Refresh::Tree && mtime.is_some() && listed == mtime {
+        let mut stmt = conn
+            .prepare_cached("SELECT path FROM entries WHERE parent = ?1 AND is_directory = 1 AND listed_mtime_ns IS NOT NULL")
+            .map_err(|e| format!("Failed to read search index: {}", e))?;
+        let subdirs = stmt
+            .query_map([&dir_str], |row| row.get::<_, String>(0))
+            .and_then(|rows| {
+                rows.map(|path| path.map(|p| (PathBuf::from(p), false)))
+                    .collect()
+            })
+            .map_err(|e| format!("Failed to read search index: {}", e))?;
+        return Ok(subdirs);
+    }
+
+    // Unreadable directories keep what was indexed before
+    let Ok(listing) = fs::read_dir(dir) else {
+        return Ok(Vec::new());
+    };
+    let mut known: HashMap<String, (i64, Option<i64>, bool)> = HashMap::new();
+    {
+        let mut stmt = conn
+            .prepare_cached("SELECT path, size, mtime_ns, listed_mtime_ns IS NOT NULL FROM entries WHERE parent = ?1")
+            .map_err(|e| format!("Failed to read search index: {}", e))?;
+        let rows = stmt
+            .query_map([&dir_str], |row| {
+                Ok((row.get(0)?, (row.get(1)?, row.get(2)?, row.get(3)?)))
+            })
+            .map_err(|e| format!("Failed to read search index: {}", e))?;
+        for row in rows {
+            let (path, state) = row.map_err(|e| format!("Failed to read search index: {}", e))?;
+            known.insert(path, state);
+        }
+    }
+
+    let mut subdirs = Vec::new();
+    // Hashed names come from the sidecar index, so when it changes they
+    // are stored again even though the entries themselves didn't change
+    let mut index_changed = false;
+    let mut unchanged_hashed = Vec::new();
+    for entry in listing.flatten() {
+        let path = entry.path();
+        let Ok(link_metadata) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        let is_symlink = link_metadata.file_type().is_symlink();
+        let metadata = fs::metadata(&path).unwrap_or(link_metadata);
+        let path_str = path.to_string_lossy().to_string();
+
+        let previous = known.remove(&path_str);
+        let size = if metadata.is_dir() {
+            0
+        } else {
+            metadata.len() as i64
+        };
+        let unchanged = previous.is_some_and(|(old_size, old_mtime, _)| {
+            old_size == size && old_mtime == mtime_ns(&metadata)
+        });
+        if !unchanged {
+            upsert_entry(conn, &path, &metadata)?;
+            index_changed |= entry.file_name() == INDEX_FILE_NAME;
+        } else if entry.file_name().to_str().is_some_and(is_hashed_name) {
+            unchanged_hashed.push((path.clone(), metadata.clone()));
+        }
+        // Symlinked directories aren't followed, so the walk can't loop
+        if metadata.is_dir() && !is_symlink {
+            let listed = previous.is_some_and(|(_, _, listed)| listed);
+            subdirs.push((path, !listed));
+        }
+    }
+    let index_removed = known.contains_key(dir.join(INDEX_FILE_NAME).to_string_lossy().as_ref());
+    if index_changed || index_removed {
+        for (path, metadata) in &unchanged_hashed {
+            upsert_entry(conn, path, metadata)?;
+        }
+    }
+    for gone in known.into_keys() {
+        delete_subtree(conn, Path::new(&gone))?;
+    }
+
+    conn.execute(
+        "UPDATE entries SET listed_mtime_ns = ?2 WHERE path = ?1",
+        params![dir_str, mtime],
+    )
+    .map_err(|e| format!("Failed to update search index: {}", e))?;
+    Ok(subdirs)
+}
+
+/// Adds or updates the entry. Probed video metadata is dropped, as the file
+/// may have changed. The size is the size on disk, as listings show it.
+fn upsert_entry(conn: &Connection, path: &Path, metadata: &Metadata) -> Result<(), String> {
+    let parent = path.parent().unwrap_or(path);
+    let physical_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let (name, is_encoded) = match name_state_in(parent, &physical_name, None) {
+        NameState::Plain => (Some(physical_name), false),
+        NameState::Decoded(decoded) => (Some(decoded), true),
+        NameState::Locked => (None, true),
+    };
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64);
+    let size = if metadata.is_dir() {
+        0
+    } else {
+        metadata.len() as i64
+    };
+
+    conn.prepare_cached(
+        "INSERT INTO entries (path, parent, name, is_directory, is_encoded, size, modified, mtime_ns)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT (path) DO UPDATE SET
+            name = excluded.name, is_directory = excluded.is_directory, is_encoded = excluded.is_encoded,
+            size = excluded.size, modified = excluded.modified, mtime_ns = excluded.mtime_ns,
+            duration_secs = NULL, width = NULL, height = NULL, codec = NULL",
+    )
+    .and_then(|mut stmt| {
+        stmt.execute(params![
+            path.to_string_lossy(),
+            parent.to_string_lossy(),
+            name,
+            metadata.is_dir(),
+            is_encoded,
+            size,
+            modified,
+            mtime_ns(metadata),
+        ])
+    })
+    .map_err(|e| format!("Failed to update search index: {}", e))?;
+    Ok(())
+}
+
+/// Like `delete_subtree`, but keeps the entries of the directories in `keep`
+/// and below them.
+fn delete_subtree_except(conn: &Connection, path: &Path, keep: &[&PathBuf]) -> Result<(), String> {
+    let (low, high) = subtree_range(path);
+    let mut sql =
+        String::from("DELETE FROM entries WHERE (path = ?1 OR (path > ?2 AND path < ?3))");
+    let mut values: Vec<String> = vec![path.to_string_lossy().to_string(), low, high];
+    for kept in keep {
+        let (low, high) = subtree_range(kept);
+        values.extend([kept.to_string_lossy().to_string(), low, high]);
+        let n = values.len();
+        sql.push_str(&format!(
+            " AND NOT (path = ?{} OR (path > ?{} AND path < ?{}))",
+            n - 2,
+            n - 1,
+            n
+        ));
+    }
+    conn.execute(&sql, params_from_iter(values))
+        .map_err(|e| format!("Failed to update search index: {}", e))?;
+    Ok(())
+}
+
+fn delete_subtree(conn: &Connection, path: &Path) -> Result<(), String> {
+    let (low, high) = subtree_range(path);
+    conn.execute(
+        "DELETE FROM entries WHERE path = ?1 OR (path > ?2 AND path < ?3)",
+        params![path.to_string_lossy(), low, high],
+    )
+    .map_err(|e| format!("Failed to update search index: {}", e))?;
+    Ok(())
+}
+
+/// Bounds of the paths below `dir`: everything after "dir/" and before
+/// "dir0" ('0' comes right after '/', and ']' after '\').
+fn subtree_range(dir: &Path) -> (String, String) {
+    let dir = dir.to_string_lossy();
+    let dir = dir.trim_end_matches(MAIN_SEPARATOR);
+    let after = char::from(MAIN_SEPARATOR as u8 + 1);
+    (
+        format!("{}{}", dir, MAIN_SEPARATOR),
+        format!("{}{}", dir, after),
+    )
+}
+
+fn mtime_ns(metadata: &Metadata) -> Option<i64> {
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    i64::try_from(modified.as_nanos()).ok()
+}
+
+/// Updates the index as the watcher reports changes: the directories they
+/// happened in are re-read once things have settled.
+fn apply_changes(
+    events: Receiver<notify::Result<Event>>,
+    conn: &Mutex<Connection>,
+    root: &Path,
+    stop: &AtomicBool,
+) {
+    while let Ok(event) = events.recv() {
+        let mut dirs = HashSet::new();
+        let mut rescan = false;
+        let mut add = |event: notify::Result<Event>| match event {
+            Ok(event) if event.need_rescan() => rescan = true,
+            Ok(event) => {
+                if let EventKind::Access(kind) = event.kind {
+                    if kind != AccessKind::Close(AccessMode::Write) {
+                        return;
+                    }
+                }
+                for path in event.paths {
+                    if path.is_dir() {
+                        dirs.insert(path.clone());
+                    }
+                    if let Some(parent) = path.parent() {
+                        dirs.insert(parent.to_path_buf());
+                    }
+                }
+            }
+            Err(_) => rescan = true,
+        };
+
+        add(event);
+        let started = Instant::now();
+        while let Some(left) = MAX_DELAY.checked_sub(started.elapsed()) {
+            match events.recv_timeout(QUIET_PERIOD.min(left)) {
+                Ok(event) => add(event),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+
+        if rescan {
+            let _ = refresh(conn, root, Refresh::Tree, stop);
+            continue;
+        }
+        for dir in dirs.iter().filter(|dir| dir.starts_with(root)) {
+            let _ = refresh(conn, dir, Refresh::Dir, stop);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::encoded_dir::encode_name_with;
+    use crate::encoding::name_codec::default_codec;
+    use crate::search_query::Query;
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+
+    fn index_state() -> SearchIndexState {
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        SearchIndexState::new(conn)
+    }
+
+    fn paths(entries: &[IndexedEntry], root: &Path) -> Vec<String> {
+        entries
+            .iter()
+            .map(|e| {
+                e.path
+                    .strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    fn found(index: &SearchIndexState, root: &Path, query: &str) -> Vec<String> {
+        let filter = Query::parse(query).unwrap().index_filter();
+        paths(&index.candidates(root, &filter, false).unwrap(), root)
+    }
+
+    #[test]
+    fn test_index_finds_decoded_names() {
+        let tmp = TempDir::new().unwrap();
+        let show = tmp
+            .path()
+            .join(encode_name_with(default_codec(), "The Show"));
+        fs::create_dir(&show).unwrap();
+        fs::write(
+            show.join(encode_name_with(default_codec(), "Café E01.mkv")),
+            vec![0u8; 4096],
+        )
+        .unwrap();
+        fs::write(show.join("notes.txt"), "").unwrap();
+
+        let index = index_state();
+        index.index(tmp.path()).unwrap();
+        assert_eq!(index.ready_root_for(&show), Some(tmp.path().to_path_buf()));
+
+        let episode = format!(
+            "{}/{}",
+            encode_name_with(default_codec(), "The Show"),
+            encode_name_with(default_codec(), "Café E01.mkv")
+        );
+        assert_eq!(found(&index, tmp.path(), "cafe e01"), [episode.as_str()]);
+        assert_eq!(
+            found(&index, tmp.path(), "show"),
+            [encode_name_with(default_codec(), "The Show")]
+        );
+        assert_eq!(
+            found(&index, tmp.path(), "e01 size>2k encoded:yes"),
+            [episode.as_str()]
+        );
+        assert!(found(&index, tmp.path(), "e01 size>1MB").is_empty());
+        assert_eq!(found(&index, tmp.path(), "modified<1h").len(), 3);
+        assert_eq!(
+            paths(&index.entries_below(&show).unwrap(), tmp.path()).len(),
+            2
+        );
+
+        index.remove(tmp.path()).unwrap();
+        assert_eq!(index.ready_root_for(&show), None);
+        assert!(index.entries_below(tmp.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_remove_keeps_nested_indexed_directories() {
+        let tmp = TempDir::new().unwrap();
+        let tv = tmp.path().join("tv");
+        fs::create_dir(&tv).unwrap();
+        fs::write(tv.join("show e01.mkv"), "").unwrap();
+        fs::write(tmp.path().join("movie.mkv"), "").unwrap();
+
+        let index = index_state();
+        index.index(tmp.path()).unwrap();
+        index.index(&tv).unwrap();
+
+        index.remove(&tv).unwrap();
+        assert_eq!(
+            found(&index, tmp.path(), "mkv"),
+            ["movie.mkv", "tv/show e01.mkv"]
+        );
+
+        index.index(&tv).unwrap();
+        index.remove(tmp.path()).unwrap();
+        assert_eq!(index.ready_root_for(&tv.join("x")), Some(tv.clone()));
+        assert_eq!(found(&index, tmp.path(), "mkv"), ["tv/show e01.mkv"]);
+        assert_eq!(found(&index, &tv, "e01"), ["show e01.mkv"]);
+    }
+
+    #[test]
+    fn test_sidecar_changes_rename_hashed_entries() {
+        use crate::encoding::name_index::record_name;
+
+        let tmp = TempDir::new().unwrap();
+        let hashed = ".dath_0123456789abcdef";
+        fs::write(tmp.path().join(hashed), "").unwrap();
+        record_name(tmp.path(), hashed, "Pilot.mkv").unwrap();
+
+        let index = index_state();
+        index.index(tmp.path()).unwrap();
+        assert_eq!(found(&index, tmp.path(), "pilot"), [hashed]);
+
+        record_name(tmp.path(), hashed, "Finale.mkv").unwrap();
+        refresh(
+            &index.conn,
+            tmp.path(),
+            Refresh::Dir,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert!(found(&index, tmp.path(), "pilot").is_empty());
+        assert_eq!(found(&index, tmp.path(), "finale"), [hashed]);
+    }
+
+    #[test]
+    fn test_stopped_scan_writes_nothing() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join("movie.mkv"), "").unwrap();
+        let index = index_state();
+
+        let result = refresh(
+            &index.conn,
+            tmp.path(),
+            Refresh::Tree,
+            &AtomicBool::new(true),
+        );
+        assert_eq!(result.unwrap_err(), CANCELLED);
+        assert!(found(&index, tmp.path(), "mkv").is_empty());
+    }
+
+    #[test]
+    fn test_refresh_rereads_changed_directories() {
+        let tmp = TempDir::new().unwrap();
+        let season = tmp.path().join("Season 1");
+        fs::create_dir_all(season.join("extras")).unwrap();
+        fs::write(season.join("e01.mkv"), "").unwrap();
+        fs::write(season.join("extras").join("blooper.mkv"), "").unwrap();
+
+        let index = index_state();
+        index.index(tmp.path()).unwrap();
+        assert_eq!(found(&index, tmp.path(), "mkv").len(), 2);
+
+        // A new file changes the mtime of its directory
+        fs::write(season.join("e02.mkv"), "").unwrap();
+        fs::remove_dir_all(season.join("extras")).unwrap();
+        refresh(
+            &index.conn,
+            tmp.path(),
+            Refresh::Tree,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(
+            found(&index, tmp.path(), "mkv"),
+            ["Season 1/e01.mkv", "Season 1/e02.mkv"]
+        );
+
+        // Directories whose mtime is unchanged aren't read again...
+        let mtime = fs::metadata(&season).unwrap().modified().unwrap();
+        fs::write(season.join("e03.mkv"), "").unwrap();
+        File::open(&season).unwrap().set_modified(mtime).unwrap();
+        refresh(
+            &index.conn,
+            tmp.path(),
+            Refresh::Tree,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(found(&index, tmp.path(), "mkv").len(), 2);
+
+        // ...unless the watcher says something happened in them
+        refresh(&index.conn, &season, Refresh::Dir, &AtomicBool::new(false)).unwrap();
+        assert_eq!(found(&index, tmp.path(), "mkv").len(), 3);
+    }
+
+    #[test]
+    fn test_watcher_updates_index() {
+        let tmp = TempDir::new().unwrap();
+        let index = index_state();
+        index.index(tmp.path()).unwrap();
+
+        let sub = tmp.path().join("new folder");
+        fs::create_dir(&sub).unwrap();
+        fs::write(sub.join("movie.mkv"), "").unwrap();
+
+        let deadline = SystemTime::now() + Duration::from_secs(10);
+        while found(&index, tmp.path(), "movie").is_empty() && SystemTime::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(found(&index, tmp.path(), "movie"), ["new folder/movie.mkv"]);
+    }
+}
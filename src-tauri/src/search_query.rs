@@ -0,0 +1,880 @@
+use std::cell::OnceCell;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use regex::{Regex, RegexBuilder};
+
+use crate::commands::fs_commands::build_file_entry;
+use crate::encoding::name_crypto::SessionKey;
+use crate::models::file_entry::{FileEntry, FileKind};
+use crate::models::search::QueryError;
+use crate::models::video_frame::VideoInfo;
+
+/// A parsed search query.
+///
+/// Terms are matched against display names: plain words as case-insensitive
+/// substrings, `"quoted phrases"` likewise, words with `*`, `?` or `[...]`
+/// as globs over the whole name, and `/regex/` (`/regex/i` to ignore case).
+/// Terms next to each other must all match; `OR`, `NOT` (or a leading `-`)
+/// and parentheses combine them. Field filters:
+///
+/// - `size>1GB`, `size<=700MB`
+/// - `ext:mkv`, `ext:mkv,mp4`
+/// - `encoded:yes`, `encoded:no`
+/// - `kind:video` (video, image, audio, subtitle, other)
+/// - `modified<30d`: modified less than 30 days ago (s, m, h, d, w, y)
+/// - `duration>20m`, `width>=1920`, `height>=1080`, `codec:hevc` for videos
+#[derive(Debug)]
+pub struct Query {
+    expr: Expr,
+    /// Seconds since the epoch when the query was parsed, for `modified`
+    now: i64,
+}
+
+#[derive(Debug)]
+enum Expr {
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Not(Box<Expr>),
+    Term(Term),
+}
+
+#[derive(Debug)]
+enum Term {
+    /// Lowercased substring
+    Contains(String),
+    Pattern(Regex),
+    Size(Cmp, u64),
+    /// Age in seconds
+    Modified(Cmp, i64),
+    Ext(Vec<String>),
+    Encoded(bool),
+    Kind(FileKind),
+    Duration(Cmp, f64),
+    Width(Cmp, u32),
+    Height(Cmp, u32),
+    Codec(Vec<String>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Cmp {
+    Lt,
+    Le,
+    Eq,
+    Ge,
+    Gt,
+}
+
+impl Cmp {
+    fn symbol(self) -> &'static str {
+        match self {
+            Cmp::Lt => "<",
+            Cmp::Le => "<=",
+            Cmp::Eq => "=",
+            Cmp::Ge => ">=",
+            Cmp::Gt => ">",
+        }
+    }
+
+    /// The comparison with the sides swapped: `a < b` is `b > a`.
+    fn flipped(self) -> Cmp {
+        match self {
+            Cmp::Lt => Cmp::Gt,
+            Cmp::Le => Cmp::Ge,
+            Cmp::Eq => Cmp::Eq,
+            Cmp::Ge => Cmp::Le,
+            Cmp::Gt => Cmp::Lt,
+        }
+    }
+
+    fn test<T: PartialOrd>(self, value: T, limit: T) -> bool {
+        match self {
+            Cmp::Lt => value < limit,
+            Cmp::Le => value <= limit,
+            Cmp::Eq => value == limit,
+            Cmp::Ge => value >= limit,
+            Cmp::Gt => value > limit,
+        }
+    }
+}
+
+/// What a term needs to be checked, cheapest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Cost {
+    Name,
+    Metadata,
+    Probe,
+}
+
+impl Term {
+    fn cost(&self) -> Cost {
+        match self {
+            Term::Contains(_) | Term::Pattern(_) | Term::Ext(_) => Cost::Name,
+            Term::Size(..) | Term::Modified(..) | Term::Encoded(_) | Term::Kind(_) => {
+                Cost::Metadata
+            }
+            Term::Duration(..) | Term::Width(..) | Term::Height(..) | Term::Codec(_) => Cost::Probe,
+        }
+    }
+
+    fn matches(&self, candidate: &Candidate, now: i64) -> bool {
+        let name = candidate.name;
+        match self {
+            Term::Contains(text) => name.to_lowercase().contains(text),
+            Term::Pattern(regex) => regex.is_match(name),
+            Term::Ext(extensions) => name
+                .rsplit_once('.')
+                .is_some_and(|(_, ext)| extensions.contains(&ext.to_lowercase())),
+            Term::Size(cmp, limit) => candidate
+                .entry()
+                .is_some_and(|e| !e.is_directory && cmp.test(e.file_size, *limit)),
+            Term::Modified(cmp, age) => candidate
+                .entry()
+                .and_then(|e| e.modified)
+                .is_some_and(|modified| cmp.test(now.saturating_sub(modified), *age)),
+            Term::Encoded(encoded) => candidate.entry().is_some_and(|e| e.is_encoded == *encoded),
+            Term::Kind(kind) => candidate.entry().is_some_and(|e| e.kind == *kind),
+            Term::Duration(cmp, limit) => candidate
+                .video()
+                .is_some_and(|v| cmp.test(v.duration_secs, *limit)),
+            Term::Width(cmp, limit) => candidate
+                .video()
+                .and_then(|v| v.width)
+                .is_some_and(|w| cmp.test(w, *limit)),
+            Term::Height(cmp, limit) => candidate
+                .video()
+                .and_then(|v| v.height)
+                .is_some_and(|h| cmp.test(h, *limit)),
+            Term::Codec(codecs) => candidate
+                .video()
+                .and_then(|v| v.codec.as_ref())
+                .is_some_and(|codec| codecs.contains(&codec.to_lowercase())),
+        }
+    }
+}
+
+impl Expr {
+    fn cost(&self) -> Cost {
+        match self {
+            Expr::And(exprs) | Expr::Or(exprs) => {
+                exprs.iter().map(Expr::cost).max().unwrap_or(Cost::Name)
+            }
+            Expr::Not(expr) => expr.cost(),
+            Expr::Term(term) => term.cost(),
+        }
+    }
+
+    fn matches(&self, candidate: &Candidate, now: i64) -> bool {
+        match self {
+            Expr::And(exprs) => exprs.iter().all(|e| e.matches(candidate, now)),
+            Expr::Or(exprs) => exprs.iter().any(|e| e.matches(candidate, now)),
+            Expr::Not(expr) => !expr.matches(candidate, now),
+            Expr::Term(term) => term.matches(candidate, now),
+        }
+    }
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Query, QueryError> {
+        let tokens = tokenize(query)?;
+        if tokens.is_empty() {
+            return Err(QueryError {
+                message: "Empty query".to_string(),
+                token: String::new(),
+                start: 0,
+                end: 0,
+            });
+        }
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(token.error("Unexpected ')'"));
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        Ok(Query { expr, now })
+    }
+
+    pub fn matches(&self, candidate: &Candidate) -> bool {
+        self.expr.matches(candidate, self.now)
+    }
+
+    /// What every match has to meet, going by the terms that must all hold.
+    /// Entries that fail it can't match, but those that pass still have to
+    /// be checked with `matches`.
+    pub fn index_filter(&self) -> IndexFilter {
+        let mut filter = IndexFilter::default();
+        let terms = match &self.expr {
+            Expr::And(exprs) => exprs.iter().collect(),
+            expr => vec![expr],
+        };
+        for term in terms {
+            match term {
+                Expr::Term(Term::Contains(text)) => filter.name_contains.push(text.clone()),
+                Expr::Term(Term::Size(cmp, size)) => {
+                    filter.comparisons.push(("is_directory", "=", 0));
+                    filter
+                        .comparisons
+                        .push(("size", cmp.symbol(), *size as i64));
+                }
+                // An age below the limit is a modified time above now - limit
+                Expr::Term(Term::Modified(cmp, age)) => {
+                    filter.comparisons.push((
+                        "modified",
+                        cmp.flipped().symbol(),
+                        self.now.saturating_sub(*age),
+                    ));
+                }
+                Expr::Term(Term::Encoded(encoded)) => {
+                    filter
+                        .comparisons
+                        .push(("is_encoded", "=", *encoded as i64))
+                }
+                _ => {}
+            }
+        }
+        filter
+    }
+}
+
+/// Conditions on the columns of the search index that every match of a
+/// query meets.
+#[derive(Debug, Default, PartialEq)]
+pub struct IndexFilter {
+    /// Lowercased substrings of the display name
+    pub name_contains: Vec<String>,
+    /// Column, operator and value, e.g. `("size", ">", 1024)`
+    pub comparisons: Vec<(&'static str, &'static str, i64)>,
+}
+
+/// An entry being matched. Its metadata is only read, and the video only
+/// probed, if a term needs them.
+pub struct Candidate<'a> {
+    name: &'a str,
+    path: &'a Path,
+    key: Option<&'a SessionKey>,
+    probe: &'a dyn Fn(&FileEntry) -> Option<VideoInfo>,
+    entry: OnceCell<Option<FileEntry>>,
+    video: OnceCell<Option<VideoInfo>>,
+}
+
+impl<'a> Candidate<'a> {
+    pub fn new(
+        name: &'a str,
+        path: &'a Path,
+        key: Option<&'a SessionKey>,
+        probe: &'a dyn Fn(&FileEntry) -> Option<VideoInfo>,
+    ) -> Self {
+        Candidate {
+            name,
+            path,
+            key,
+            probe,
+            entry: OnceCell::new(),
+            video: OnceCell::new(),
+        }
+    }
+
+    fn entry(&self) -> Option<&FileEntry> {
+        self.entry
+            .get_or_init(|| build_file_entry(self.path, self.key).ok())
+            .as_ref()
+    }
+
+    fn video(&self) -> Option<&VideoInfo> {
+        self.video
+            .get_or_init(|| {
+                let entry = self.entry()?;
+                match (entry.is_directory, entry.kind) {
+                    (false, FileKind::Video) => (self.probe)(entry),
+                    _ => None,
+                }
+            })
+            .as_ref()
+    }
+
+    pub fn into_entry(self) -> Option<FileEntry> {
+        match self.entry.into_inner() {
+            Some(entry) => entry,
+            None => build_file_entry(self.path, self.key).ok(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Word,
+    Phrase(String),
+    Regex(String, String),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    text: String,
+    start: usize,
+    end: usize,
+}
+
+impl Token {
+    fn error(&self, message: impl Into<String>) -> QueryError {
+        QueryError {
+            message: message.into(),
+            token: self.text.clone(),
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, QueryError> {
+    let chars: Vec<char> = query.chars().collect();
+    let is_boundary = |c: char| c.is_whitespace() || c == '(' || c == ')';
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        if chars[i].is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let kind = match chars[i] {
+            '(' => {
+                i += 1;
+                TokenKind::Open
+            }
+            ')' => {
+                i += 1;
+                TokenKind::Close
+            }
+            '-' if chars.get(i + 1).is_some_and(|&c| !c.is_whitespace()) => {
+                i += 1;
+                TokenKind::Not
+            }
+            quote @ ('"' | '/') => {
+                let mut text = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => {
+                            let what = if quote == '"' { "quote" } else { "regex" };
+                            return Err(QueryError {
+                                message: format!("Unterminated {}", what),
+                                token: chars[start..].iter().collect(),
+                                start,
+                                end: chars.len(),
+                            });
+                        }
+                        Some(&c) if c == quote => break,
+                        // Quotes keep the escaped character; regexes keep
+                        // their escapes except for the delimiter
+                        Some('\\') if chars.get(i + 1) == Some(&quote) => {
+                            text.push(quote);
+                            i += 1;
+                        }
+                        Some('\\') if quote == '"' && i + 1 < chars.len() => {
+                            text.push(chars[i + 1]);
+                            i += 1;
+                        }
+                        Some(&c) => text.push(c),
+                    }
+                    i += 1;
+                }
+                i += 1;
+                if quote == '"' {
+                    TokenKind::Phrase(text)
+                } else {
+                    let flags_start = i;
+                    while chars.get(i).is_some_and(|c| c.is_ascii_alphabetic()) {
+                        i += 1;
+                    }
+                    TokenKind::Regex(text, chars[flags_start..i].iter().collect())
+                }
+            }
+            _ => {
+                while i < chars.len() && !is_boundary(chars[i]) {
+                    i += 1;
+                }
+                match chars[start..i].iter().collect::<String>().as_str() {
+                    "AND" => TokenKind::And,
+                    "OR" => TokenKind::Or,
+                    "NOT" => TokenKind::Not,
+                    _ => TokenKind::Word,
+                }
+            }
+        };
+        tokens.push(Token {
+            kind,
+            text: chars[start..i].iter().collect(),
+            start,
+            end: i,
+        });
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.pos).map(|t| &t.kind)
+    }
+
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if self.peek() == Some(kind) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, QueryError> {
+        let mut exprs = vec![self.parse_and()?];
+        while self.eat(&TokenKind::Or) {
+            exprs.push(self.parse_and()?);
+        }
+        Ok(if exprs.len() == 1 {
+            exprs.remove(0)
+        } else {
+            Expr::Or(exprs)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, QueryError> {
+        let mut exprs = vec![self.parse_not()?];
+        loop {
+            match self.peek() {
+                None | Some(TokenKind::Close) | Some(TokenKind::Or) => break,
+                Some(TokenKind::And) => self.pos += 1,
+                Some(_) => {}
+            }
+            exprs.push(self.parse_not()?);
+        }
+        if exprs.len() == 1 {
+            return Ok(exprs.remove(0));
+        }
+        // Cheap terms first, so entries they rule out are never probed
+        exprs.sort_by_key(Expr::cost);
+        Ok(Expr::And(exprs))
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, QueryError> {
+        if self.eat(&TokenKind::Not) {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, QueryError> {
+        let Some(token) = self.tokens.get(self.pos).cloned() else {
+            let last = &self.tokens[self.pos - 1];
+            return Err(last.error(format!("Expected a search term after '{}'", last.text)));
+        };
+        self.pos += 1;
+        match &token.kind {
+            TokenKind::Open => {
+                let expr = self.parse_or()?;
+                if !self.eat(&TokenKind::Close) {
+                    return Err(token.error("Unmatched '('"));
+                }
+                Ok(expr)
+            }
+            TokenKind::Close => Err(token.error("Unexpected ')'")),
+            TokenKind::And | TokenKind::Or => {
+                Err(token.error(format!("Expected a search term before '{}'", token.text)))
+            }
+            TokenKind::Not => unreachable!("handled by parse_not"),
+            TokenKind::Phrase(text) => Ok(Expr::Term(Term::Contains(text.to_lowercase()))),
+            TokenKind::Regex(pattern, flags) => {
+                let mut builder = RegexBuilder::new(pattern);
+                for flag in flags.chars() {
+                    match flag {
+                        'i' => builder.case_insensitive(true),
+                        _ => return Err(token.error(format!("Unknown regex flag '{}'", flag))),
+                    };
+                }
+                let regex = builder
+                    .build()
+                    .map_err(|e| token.error(format!("Invalid regex: {}", e)))?;
+                Ok(Expr::Term(Term::Pattern(regex)))
+            }
+            TokenKind::Word => parse_word(&token).map(Expr::Term),
+        }
+    }
+}
+
+const OPERATORS: &[(&str, Cmp)] = &[
+    (">=", Cmp::Ge),
+    ("<=", Cmp::Le),
+    (">", Cmp::Gt),
+    ("<", Cmp::Lt),
+    ("=", Cmp::Eq),
+    (":", Cmp::Eq),
+];
+
+/// Fields that can be filtered on. Any other `word:value` is searched for
+/// as text, like "Movie:" or "12:30".
+const FIELDS: &[&str] = &[
+    "size", "modified", "duration", "width", "height", "ext", "codec", "encoded", "kind",
+];
+
+fn parse_word(token: &Token) -> Result<Term, QueryError> {
+    let word = token.text.as_str();
+    let field_len = word
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(word.len());
+    let field = word[..field_len].to_lowercase();
+    let operator = OPERATORS
+        .iter()
+        .find(|(op, _)| word[field_len..].starts_with(op));
+    match operator {
+        Some(&(op, cmp)) if FIELDS.contains(&field.as_str()) => {
+            let value = &word[field_len + op.len()..];
+            if value.is_empty() {
+                return Err(token.error(format!(
+                    "Expected a value after '{}{}'",
+                    &word[..field_len],
+                    op
+                )));
+            }
+            parse_filter(token, &field, op, cmp, value)
+        }
+        _ if word.contains(['*', '?', '[']) => glob_regex(word)
+            .map(Term::Pattern)
+            .ok_or_else(|| token.error("Invalid glob")),
+        _ => Ok(Term::Contains(word.to_lowercase())),
+    }
+}
+
+fn parse_filter(
+    token: &Token,
+    field: &str,
+    op: &str,
+    cmp: Cmp,
+    value: &str,
+) -> Result<Term, QueryError> {
+    let invalid = |what: &str| token.error(format!("Invalid {} '{}'", what, value));
+    let equality_only = || {
+        if cmp == Cmp::Eq {
+            Ok(())
+        } else {
+            Err(token.error(format!(
+                "'{}' can't be used with {}; use '{}:'",
+                op, field, field
+            )))
+        }
+    };
+    let list = || {
+        value
+            .split(',')
+            .map(|v| v.trim_start_matches('.').to_lowercase())
+            .collect()
+    };
+
+    Ok(match field {
+        "size" => Term::Size(cmp, parse_size(value).ok_or_else(|| invalid("size"))?),
+        "modified" => {
+            if cmp == Cmp::Eq {
+                return Err(token.error("Use '<' or '>' with modified, e.g. modified<30d"));
+            }
+            let age = parse_seconds(value).ok_or_else(|| invalid("age"))?;
+            Term::Modified(cmp, age as i64)
+        }
+        "duration" => Term::Duration(
+            cmp,
+            parse_seconds(value).ok_or_else(|| invalid("duration"))?,
+        ),
+        "width" => Term::Width(cmp, value.parse().map_err(|_| invalid("width"))?),
+        "height" => Term::Height(
+            cmp,
+            value
+                .trim_end_matches(['p', 'P'])
+                .parse()
+                .map_err(|_| invalid("height"))?,
+        ),
+        "ext" => {
+            equality_only()?;
+            Term::Ext(list())
+        }
+        "codec" => {
+            equality_only()?;
+            Term::Codec(list())
+        }
+        "encoded" => {
+            equality_only()?;
+            match value.to_lowercase().as_str() {
+                "yes" | "true" => Term::Encoded(true),
+                "no" | "false" => Term::Encoded(false),
+                _ => return Err(invalid("value for encoded (yes or no)")),
+            }
+        }
+        "kind" => {
+            equality_only()?;
+            Term::Kind(match value.to_lowercase().as_str() {
+                "video" => FileKind::Video,
+                "image" => FileKind::Image,
+                "audio" => FileKind::Audio,
+                "subtitle" => FileKind::Subtitle,
+                "other" => FileKind::Other,
+                _ => return Err(invalid("kind")),
+            })
+        }
+        _ => unreachable!("{} is not in FIELDS", field),
+    })
+}
+
+/// Parses sizes like "700MB" or "1.5g", in powers of 1024.
+fn parse_size(value: &str) -> Option<u64> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let number: f64 = value[..split].parse().ok()?;
+    let exponent = match value[split..].to_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" | "kib" => 1,
+        "m" | "mb" | "mib" => 2,
+        "g" | "gb" | "gib" => 3,
+        "t" | "tb" | "tib" => 4,
+        _ => return None,
+    };
+    Some((number * 1024f64.powi(exponent)) as u64)
+}
+
+/// Parses durations like "20m", "1h30m" or "90" (seconds). Negative and
+/// infinite durations are rejected.
+fn parse_seconds(value: &str) -> Option<f64> {
+    let valid = |seconds: f64| (seconds.is_finite() && seconds >= 0.0).then_some(seconds);
+    if let Ok(seconds) = value.parse::<f64>() {
+        return valid(seconds);
+    }
+    let mut total = 0.0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let split = rest.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+        let number: f64 = rest[..split].parse().ok()?;
+        let unit_len = rest[split..]
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len() - split);
+        let unit = match &rest[split..split + unit_len] {
+            "s" => 1.0,
+            "m" | "min" => 60.0,
+            "h" => 3600.0,
+            "d" => 86400.0,
+            "w" => 7.0 * 86400.0,
+            "y" => 365.0 * 86400.0,
+            _ => return None,
+        };
+        total += number * unit;
+        rest = &rest[split + unit_len..];
+    }
+    valid(total)
+}
+
+/// A case-insensitive regex matching the whole name against a glob.
+fn glob_regex(glob: &str) -> Option<Regex> {
+    let mut pattern = String::from("(?i)^");
+    let mut chars = glob.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            '[' => {
+                pattern.push('[');
+                let mut class: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                if let Some(negated) = class.strip_prefix('!') {
+                    class = format!("^{}", negated);
+                }
+                pattern.push_str(&class.replace('\\', "\\\\"));
+                pattern.push(']');
+            }
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    if glob.matches('[').count() > glob.matches(']').count() {
+        return None;
+    }
+    pattern.push('$');
+    Regex::new(&pattern).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::encoded_dir::encode_name_with;
+    use crate::encoding::name_codec::default_codec;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn no_probe(_: &FileEntry) -> Option<VideoInfo> {
+        None
+    }
+
+    fn matches_name(query: &str, name: &str) -> bool {
+        let query = Query::parse(query).unwrap();
+        query.matches(&Candidate::new(
+            name,
+            Path::new("/nonexistent"),
+            None,
+            &no_probe,
+        ))
+    }
+
+    fn error(query: &str) -> QueryError {
+        Query::parse(query).unwrap_err()
+    }
+
+    #[test]
+    fn test_name_terms() {
+        assert!(matches_name("show e01", "The Show E01.mkv"));
+        assert!(!matches_name("show e02", "The Show E01.mkv"));
+        assert!(matches_name("e01 OR e02", "Show E02.mkv"));
+        assert!(matches_name("show -e01", "Show E02.mkv"));
+        assert!(!matches_name("show NOT (e01 OR e02)", "Show E02.mkv"));
+        assert!(matches_name("\"the show\"", "The Show E01.mkv"));
+        assert!(!matches_name("\"the show\"", "Show The E01.mkv"));
+        assert!(matches_name("*.MKV", "Show.mkv"));
+        assert!(!matches_name("*.mkv", "Show.mkv.part"));
+        assert!(matches_name("show.e0[1-3]*", "Show.E02.mkv"));
+        assert!(matches_name("/E\\d{2}/", "Show E02.mkv"));
+        assert!(!matches_name("/e\\d{2}/", "Show E02.mkv"));
+        assert!(matches_name("/e\\d{2}/i", "Show E02.mkv"));
+        assert!(matches_name("ext:mkv,mp4", "Show.MP4"));
+        assert!(!matches_name("ext:mkv", "Show.mkv.part"));
+        // Only known fields are filters; anything else is text
+        assert!(matches_name("movie: part", "Movie: Part 2.mkv"));
+        assert!(matches_name("12:30", "Call 12:30.mp4"));
+        assert!(!matches_name("foo:bar", "foo.mkv"));
+    }
+
+    #[test]
+    fn test_metadata_filters() {
+        let tmp = TempDir::new().unwrap();
+        let name = "Movie.mkv";
+        let path = tmp.path().join(encode_name_with(default_codec(), name));
+        fs::write(&path, vec![0u8; 2048]).unwrap();
+        let probe = |_: &FileEntry| {
+            Some(VideoInfo {
+                duration_secs: 1500.0,
+                file_size_bytes: 2048,
+                width: Some(1920),
+                height: Some(1080),
+                display_aspect_ratio: None,
+                codec: Some("hevc".to_string()),
+                bitrate: None,
+                framerate: None,
+            })
+        };
+        let matches = |query: &str| {
+            Query::parse(query)
+                .unwrap()
+                .matches(&Candidate::new(name, &path, None, &probe))
+        };
+
+        assert!(matches("size>1KB size<=2k"));
+        assert!(!matches("size>2KB"));
+        assert!(matches("encoded:yes kind:video"));
+        assert!(!matches("encoded:no"));
+        assert!(matches("modified<1d"));
+        assert!(!matches("modified>1h"));
+        assert!(matches(
+            "duration>20m duration<1h height>=1080p width:1920 codec:HEVC"
+        ));
+        assert!(!matches("duration>25m1s"));
+        assert!(matches("codec:h264 OR codec:hevc"));
+    }
+
+    #[test]
+    fn test_index_filter_uses_required_terms() {
+        let query = Query::parse("show size>1k encoded:yes (e01 OR e02) -trailer").unwrap();
+        let filter = query.index_filter();
+        assert_eq!(filter.name_contains, ["show"]);
+        assert_eq!(
+            filter.comparisons,
+            [
+                ("is_directory", "=", 0),
+                ("size", ">", 1024),
+                ("is_encoded", "=", 1)
+            ]
+        );
+
+        let query = Query::parse("modified<1d").unwrap();
+        assert_eq!(
+            query.index_filter().comparisons,
+            [("modified", ">", query.now - 86400)]
+        );
+        assert_eq!(
+            Query::parse("a OR b").unwrap().index_filter(),
+            IndexFilter::default()
+        );
+
+        let query = Query::parse("modified<9999999999999y").unwrap();
+        assert_eq!(
+            query.index_filter().comparisons,
+            [("modified", ">", query.now - i64::MAX)]
+        );
+    }
+
+    #[test]
+    fn test_durations_must_be_finite_and_positive() {
+        for value in [
+            "-5",
+            "inf",
+            "-inf",
+            "NaN",
+            "1e400",
+            "9".repeat(400).as_str(),
+        ] {
+            assert!(
+                Query::parse(&format!("modified<{}", value)).is_err(),
+                "{}",
+                value
+            );
+            assert!(
+                Query::parse(&format!("duration>{}", value)).is_err(),
+                "{}",
+                value
+            );
+        }
+        assert!(Query::parse("duration>1.5e3").is_ok());
+    }
+
+    #[test]
+    fn test_errors_point_at_the_token() {
+        let e = error("show size>1XB");
+        assert_eq!((e.token.as_str(), e.start, e.end), ("size>1XB", 5, 13));
+        assert!(e.message.contains("Invalid size"));
+
+        let e = error("a (b OR c");
+        assert_eq!((e.token.as_str(), e.start, e.end), ("(", 2, 3));
+        assert_eq!(e.message, "Unmatched '('");
+
+        let e = error("a OR");
+        assert_eq!((e.token.as_str(), e.start), ("OR", 2));
+
+        let e = error("café size:big");
+        assert_eq!((e.token.as_str(), e.start, e.end), ("size:big", 5, 13));
+        assert!(e.message.starts_with("Invalid size"));
+
+        assert_eq!(error("ext>mkv").token, "ext>mkv");
+        assert_eq!(error("a )").token, ")");
+        assert_eq!(
+            error("/[/").message.split(':').next(),
+            Some("Invalid regex")
+        );
+        assert_eq!(error("\"open").message, "Unterminated quote");
+        assert_eq!(error("size>").message, "Expected a value after 'size>'");
+        assert_eq!(error("  ").message, "Empty query");
+    }
+}
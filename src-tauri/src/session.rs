@@ -0,0 +1,14 @@
+use std::sync::RwLock;
+
+use crate::encoding::name_crypto::SessionKey;
+
+/// Holds the keys for encrypted names and contents while the session is
+/// unlocked.
+#[derive(Default)]
+pub struct SessionState(pub RwLock<Option<SessionKey>>);
+
+impl SessionState {
+    pub fn key(&self) -> Option<SessionKey> {
+        self.0.read().ok().and_then(|key| key.clone())
+    }
+}
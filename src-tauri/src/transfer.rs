@@ -0,0 +1,497 @@
+use std::collections::HashMap;
+use std::fs::{self, File, FileTimes, Metadata};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha256};
+
+use crate::encoding::rename_plan::{self, RenameStep};
+use crate::models::transfer_progress::{TransferPhase, TransferProgress};
+
+const BUFFER_SIZE: usize = 1024 * 1024;
+/// Progress is reported at most this often, plus once per finished file.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An entry below the copied node, relative to it.
+struct Item {
+    relative: PathBuf,
+    metadata: Metadata,
+}
+
+/// Returned when `is_cancelled` of the reporter turned true during a copy.
+pub const CANCELLED: &str = "Cancelled";
+
+/// Tracks the progress of a transfer and hands it to `on_progress`.
+pub struct Reporter<'a> {
+    pub progress: TransferProgress,
+    last_report: Instant,
+    on_progress: &'a mut dyn FnMut(&TransferProgress),
+    is_cancelled: &'a dyn Fn() -> bool,
+}
+
+impl<'a> Reporter<'a> {
+    pub fn new(
+        source: &Path,
+        bytes_total: u64,
+        files_total: usize,
+        on_progress: &'a mut dyn FnMut(&TransferProgress),
+        is_cancelled: &'a dyn Fn() -> bool,
+    ) -> Self {
+        Reporter {
+            progress: TransferProgress {
+                source: source.to_string_lossy().to_string(),
+                phase: TransferPhase::Copying,
+                bytes_done: 0,
+                bytes_total,
+                files_done: 0,
+                files_total,
+                current_path: None,
+            },
+            last_report: Instant::now(),
+            on_progress,
+            is_cancelled,
+        }
+    }
+
+    pub fn report(&mut self, force: bool) {
+        if force || self.last_report.elapsed() >= PROGRESS_INTERVAL {
+            self.last_report = Instant::now();
+            (self.on_progress)(&self.progress);
+        }
+    }
+
+    fn start_phase(&mut self, phase: TransferPhase) {
+        self.progress.phase = phase;
+        self.progress.bytes_done = 0;
+        self.progress.files_done = 0;
+        self.progress.current_path = None;
+        self.report(true);
+    }
+
+    fn check_cancelled(&self) -> Result<(), String> {
+        if (self.is_cancelled)() {
+            return Err(CANCELLED.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Copies `from` (a file, symlink or directory tree) to `to`, keeping
+/// modification times and permissions, and then checks that every file
+/// arrived with its full size and, with `verify_checksum`, its SHA-256. On
+/// failure the partial copy is removed again; the source is never touched.
+pub fn copy_tree(
+    from: &Path,
+    to: &Path,
+    verify_checksum: bool,
+    on_progress: &mut dyn FnMut(&TransferProgress),
+) -> Result<(), String> {
+    if fs::symlink_metadata(to).is_ok() {
+        return Err(format!("Target already exists: {}", to.display()));
+    }
+
+    let mut items = Vec::new();
+    collect_items(from, Path::new(""), &mut items)?;
+    let files: Vec<&Item> = items
+        .iter()
+        .filter(|item| item.metadata.is_file())
+        .collect();
+
+    let bytes_total = files.iter().map(|item| item.metadata.len()).sum();
+    let mut reporter = Reporter::new(from, bytes_total, files.len(), on_progress, &|| false);
+
+    let result = copy_items(from, to, &items, verify_checksum, &mut reporter)
+        .and_then(|checksums| verify_items(to, &files, &checksums, &mut reporter));
+    if result.is_err() {
+        let _ = remove_tree(to);
+    }
+    result
+}
+
+/// `fs::rename` cannot move to another file system, so the node of `step` is
+/// copied, verified, and only then removed from the source. Its sidecar
+/// index entry moves along, as with `rename_plan::apply`.
+pub fn move_across_devices(
+    step: &RenameStep,
+    verify_checksum: bool,
+    on_progress: &mut dyn FnMut(&TransferProgress),
+) -> Result<(), String> {
+    copy_tree(&step.from, &step.to, verify_checksum, on_progress)?;
+    if let Err(e) = rename_plan::sync_index(step) {
+        let _ = remove_tree(&step.to);
+        return Err(e);
+    }
+    remove_tree(&step.from).map_err(|e| {
+        format!(
+            "Copied to {}, but failed to remove the source: {}",
+            step.to.display(),
+            e
+        )
+    })
+}
+
+/// Removes a file, symlink or directory tree.
+pub fn remove_tree(path: &Path) -> Result<(), String> {
+    let metadata = fs::symlink_metadata(path)
+        .map_err(|e| format!("Path does not exist: {}: {}", path.display(), e))?;
+    let result = if metadata.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    result.map_err(|e| format!("Failed to remove {}: {}", path.display(), e))
+}
+
+/// Parents are listed before their children.
+fn collect_items(root: &Path, relative: &Path, items: &mut Vec<Item>) -> Result<(), String> {
+    let path = item_path(root, relative);
+    let metadata = fs::symlink_metadata(&path)
+        .map_err(|e| format!("Failed to read metadata of {}: {}", path.display(), e))?;
+    let is_dir = metadata.is_dir();
+    items.push(Item {
+        relative: relative.to_path_buf(),
+        metadata,
+    });
+
+    if is_dir {
+        let entries = fs::read_dir(&path)
+            .map_err(|e| format!("Failed to read directory {}: {}", path.display(), e))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
+            collect_items(root, &relative.join(entry.file_name()), items)?;
+        }
+    }
+    Ok(())
+}
+
+/// `root` itself for the empty path: joining that would add a trailing
+/// slash, which fails for files.
+fn item_path(root: &Path, relative: &Path) -> PathBuf {
+    if relative.as_os_str().is_empty() {
+        root.to_path_buf()
+    } else {
+        root.join(relative)
+    }
+}
+
+/// Returns the checksum of every copied file if `verify_checksum` is set.
+fn copy_items(
+    from: &Path,
+    to: &Path,
+    items: &[Item],
+    verify_checksum: bool,
+    reporter: &mut Reporter,
+) -> Result<HashMap<PathBuf, [u8; 32]>, String> {
+    let mut checksums = HashMap::new();
+
+    for item in items {
+        let source = item_path(from, &item.relative);
+        let target = item_path(to, &item.relative);
+        let file_type = item.metadata.file_type();
+
+        if file_type.is_dir() {
+            fs::create_dir(&target)
+                .map_err(|e| format!("Failed to create directory {}: {}", target.display(), e))?;
+        } else if file_type.is_symlink() {
+            copy_symlink(&source, &target)?;
+        } else {
+            reporter.progress.current_path = Some(source.to_string_lossy().to_string());
+            let checksum = copy_file(&source, &target, &item.metadata, verify_checksum, reporter)?;
+            if let Some(checksum) = checksum {
+                checksums.insert(item.relative.clone(), checksum);
+            }
+            reporter.progress.files_done += 1;
+            reporter.report(true);
+        }
+    }
+
+    for item in items.iter().rev().filter(|item| item.metadata.is_dir()) {
+        finish_dir(&item_path(to, &item.relative), &item.metadata)?;
+    }
+
+    Ok(checksums)
+}
+
+/// Gives a copied directory the times and permissions from `metadata`. This
+/// has to wait until its children are copied: adding them changes the
+/// times, and a read-only directory could not take them.
+pub fn finish_dir(target: &Path, metadata: &Metadata) -> Result<(), String> {
+    if let Ok(dir) = File::open(target) {
+        let _ = dir.set_times(file_times(metadata));
+    }
+    fs::set_permissions(target, metadata.permissions())
+        .map_err(|e| format!("Failed to set permissions of {}: {}", target.display(), e))
+}
+
+/// Copies a single file to `target`, which must not exist yet, keeping the
+/// times and permissions from `metadata`. Returns its SHA-256 if
+/// `verify_checksum` is set. A partial copy is removed again on failure.
+pub fn copy_file(
+    source: &Path,
+    target: &Path,
+    metadata: &Metadata,
+    verify_checksum: bool,
+    reporter: &mut Reporter,
+) -> Result<Option<[u8; 32]>, String> {
+    let mut output = File::create_new(target)
+        .map_err(|e| format!("Failed to create {}: {}", target.display(), e))?;
+    let result = write_copy(
+        source,
+        target,
+        &mut output,
+        metadata,
+        verify_checksum,
+        reporter,
+    );
+    if result.is_err() {
+        drop(output);
+        let _ = fs::remove_file(target);
+    }
+    result
+}
+
+fn write_copy(
+    source: &Path,
+    target: &Path,
+    output: &mut File,
+    metadata: &Metadata,
+    verify_checksum: bool,
+    reporter: &mut Reporter,
+) -> Result<Option<[u8; 32]>, String> {
+    let mut input =
+        File::open(source).map_err(|e| format!("Failed to open {}: {}", source.display(), e))?;
+    let mut hasher = verify_checksum.then(Sha256::new);
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+
+    loop {
+        reporter.check_cancelled()?;
+        let read = input
+            .read(&mut buffer)
+            .map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+        if read == 0 {
+            break;
+        }
+        output
+            .write_all(&buffer[..read])
+            .map_err(|e| format!("Failed to write {}: {}", target.display(), e))?;
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&buffer[..read]);
+        }
+        reporter.progress.bytes_done += read as u64;
+        reporter.report(false);
+    }
+
+    output
+        .sync_all()
+        .map_err(|e| format!("Failed to write {}: {}", target.display(), e))?;
+    output
+        .set_times(file_times(metadata))
+        .map_err(|e| format!("Failed to set times of {}: {}", target.display(), e))?;
+    fs::set_permissions(target, metadata.permissions())
+        .map_err(|e| format!("Failed to set permissions of {}: {}", target.display(), e))?;
+
+    Ok(hasher.map(|hasher| hasher.finalize().into()))
+}
+
+pub fn copy_symlink(source: &Path, target: &Path) -> Result<(), String> {
+    let link = fs::read_link(source)
+        .map_err(|e| format!("Failed to read link {}: {}", source.display(), e))?;
+
+    #[cfg(unix)]
+    let result = std::os::unix::fs::symlink(&link, target);
+    #[cfg(windows)]
+    let result = if source.is_dir() {
+        std::os::windows::fs::symlink_dir(&link, target)
+    } else {
+        std::os::windows::fs::symlink_file(&link, target)
+    };
+
+    result.map_err(|e| format!("Failed to create link {}: {}", target.display(), e))
+}
+
+fn verify_items(
+    to: &Path,
+    files: &[&Item],
+    checksums: &HashMap<PathBuf, [u8; 32]>,
+    reporter: &mut Reporter,
+) -> Result<(), String> {
+    reporter.start_phase(TransferPhase::Verifying);
+
+    for item in files {
+        let target = item_path(to, &item.relative);
+        reporter.progress.current_path = Some(target.to_string_lossy().to_string());
+        let copied_len = fs::metadata(&target)
+            .map_err(|e| format!("Failed to read metadata of {}: {}", target.display(), e))?
+            .len();
+        if copied_len != item.metadata.len() {
+            return Err(format!(
+                "Size mismatch after copying {}: expected {} bytes, found {}",
+                target.display(),
+                item.metadata.len(),
+                copied_len
+            ));
+        }
+
+        if let Some(expected) = checksums.get(&item.relative) {
+            if checksum(&target, reporter)? != *expected {
+                return Err(format!(
+                    "Checksum mismatch after copying {}",
+                    target.display()
+                ));
+            }
+        } else {
+            reporter.progress.bytes_done += copied_len;
+        }
+        reporter.progress.files_done += 1;
+        reporter.report(true);
+    }
+    Ok(())
+}
+
+fn checksum(path: &Path, reporter: &mut Reporter) -> Result<[u8; 32], String> {
+    let mut file =
+        File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if read == 0 {
+            return Ok(hasher.finalize().into());
+        }
+        hasher.update(&buffer[..read]);
+        reporter.progress.bytes_done += read as u64;
+        reporter.report(false);
+    }
+}
+
+fn file_times(metadata: &Metadata) -> FileTimes {
+    let mut times = FileTimes::new();
+    if let Ok(accessed) = metadata.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    if let Ok(modified) = metadata.modified() {
+        times = times.set_modified(modified);
+    }
+    times
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+    use tempfile::TempDir;
+
+    fn create_tree(tmp: &TempDir) -> PathBuf {
+        let source = tmp.path().join("source");
+        fs::create_dir_all(source.join("season 1")).unwrap();
+        fs::write(
+            source.join("season 1").join("episode.mkv"),
+            vec![7u8; 3 * BUFFER_SIZE + 11],
+        )
+        .unwrap();
+        fs::write(source.join("notes.txt"), "hello").unwrap();
+        source
+    }
+
+    #[test]
+    fn test_copy_tree_copies_and_reports_progress() {
+        let tmp = TempDir::new().unwrap();
+        let source = create_tree(&tmp);
+        let target = tmp.path().join("target");
+
+        let mut reports = Vec::new();
+        copy_tree(&source, &target, true, &mut |p| reports.push(p.clone())).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(target.join("notes.txt")).unwrap(),
+            "hello"
+        );
+        assert_eq!(
+            fs::read(target.join("season 1").join("episode.mkv")).unwrap(),
+            fs::read(source.join("season 1").join("episode.mkv")).unwrap()
+        );
+
+        let last = reports.last().unwrap();
+        assert_eq!(last.phase, TransferPhase::Verifying);
+        assert_eq!(last.files_done, 2);
+        assert_eq!(last.bytes_done, last.bytes_total);
+        assert_eq!(last.bytes_total, 3 * BUFFER_SIZE as u64 + 16);
+        assert!(reports
+            .iter()
+            .any(|p| p.phase == TransferPhase::Copying && p.files_done == 2));
+    }
+
+    #[test]
+    fn test_copy_tree_keeps_times_and_permissions() {
+        let tmp = TempDir::new().unwrap();
+        let source = create_tree(&tmp);
+        let file = source.join("notes.txt");
+        let modified = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let mut permissions = fs::metadata(&file).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&file, permissions).unwrap();
+
+        let target = tmp.path().join("target");
+        copy_tree(&source, &target, false, &mut |_| {}).unwrap();
+
+        let copied = fs::metadata(target.join("notes.txt")).unwrap();
+        assert_eq!(copied.modified().unwrap(), modified);
+        assert!(copied.permissions().readonly());
+    }
+
+    #[test]
+    fn test_copy_tree_refuses_existing_target() {
+        let tmp = TempDir::new().unwrap();
+        let source = create_tree(&tmp);
+        let target = tmp.path().join("target");
+        fs::create_dir(&target).unwrap();
+
+        let result = copy_tree(&source, &target, false, &mut |_| {});
+        assert!(result.unwrap_err().contains("already exists"));
+        assert!(target.exists());
+    }
+
+    #[test]
+    fn test_remove_tree() {
+        let tmp = TempDir::new().unwrap();
+        let source = create_tree(&tmp);
+
+        remove_tree(&source.join("notes.txt")).unwrap();
+        remove_tree(&source).unwrap();
+        assert!(!source.exists());
+    }
+
+    #[test]
+    fn test_move_across_devices_copies_and_removes_source() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("source_dir");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("child.txt"), "hello").unwrap();
+        let target = tmp.path().join("target_dir");
+        fs::create_dir(&target).unwrap();
+        let step = RenameStep {
+            from: dir.clone(),
+            to: target.join("source_dir"),
+            indexed_name: None,
+        };
+
+        let mut reports = 0;
+        move_across_devices(&step, true, &mut |_| reports += 1).unwrap();
+
+        assert!(!dir.exists());
+        assert_eq!(
+            fs::read_to_string(step.to.join("child.txt")).unwrap(),
+            "hello"
+        );
+        assert!(reports > 0);
+    }
+}
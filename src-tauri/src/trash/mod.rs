@@ -0,0 +1,77 @@
+use std::path::{Path, PathBuf};
+
+use trash::{os_limited, TrashItem, TrashItemSize};
+
+/// Something sitting in the trash, as the platform reports it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrashedItem {
+    /// Opaque id used to restore the item
+    pub id: String,
+    /// Name the item had before it was trashed
+    pub physical_name: String,
+    pub original_path: PathBuf,
+    /// Seconds since the Unix epoch, if known
+    pub deleted_at: Option<i64>,
+    pub is_directory: bool,
+}
+
+/// Moves the file or directory to the trash of the current user and returns
+/// the id of the trashed item. Deleting does not report where the item ended
+/// up, so its id is the most recent item trashed from `path`.
+pub fn move_to_trash(path: &Path) -> Result<String, String> {
+    trash::delete(path)
+        .map_err(|e| format!("Failed to move {} to the trash: {}", path.display(), e))?;
+    read_trash()?
+        .into_iter()
+        .filter(|item| item.original_path() == path)
+        .max_by_key(|item| item.time_deleted)
+        .map(|item| item.id.to_string_lossy().to_string())
+        .ok_or_else(|| format!("Trashed item not found: {}", path.display()))
+}
+
+/// Everything in the current user's trash, across all mounted file systems.
+pub fn list() -> Result<Vec<TrashedItem>, String> {
+    Ok(read_trash()?
+        .into_iter()
+        .map(|item| {
+            let is_directory = matches!(
+                os_limited::metadata(&item).map(|m| m.size),
+                Ok(TrashItemSize::Entries(_))
+            );
+            TrashedItem {
+                id: item.id.to_string_lossy().to_string(),
+                physical_name: item.name.to_string_lossy().to_string(),
+                original_path: item.original_path(),
+                deleted_at: (item.time_deleted >= 0).then_some(item.time_deleted),
+                is_directory,
+            }
+        })
+        .collect())
+}
+
+/// Moves the item back to where it was trashed from and returns that path.
+pub fn restore(id: &str) -> Result<PathBuf, String> {
+    let item = read_trash()?
+        .into_iter()
+        .find(|item| item.id.to_string_lossy() == id)
+        .ok_or("Trash entry not found")?;
+    let original_path = item.original_path();
+    if original_path.exists() {
+        return Err(format!(
+            "Target already exists: {}",
+            original_path.display()
+        ));
+    }
+    os_limited::restore_all([item])
+        .map_err(|e| format!("Failed to restore {}: {}", original_path.display(), e))?;
+    Ok(original_path)
+}
+
+/// Permanently deletes everything in the trash.
+pub fn empty() -> Result<(), String> {
+    os_limited::purge_all(read_trash()?).map_err(|e| format!("Failed to empty the trash: {}", e))
+}
+
+fn read_trash() -> Result<Vec<TrashItem>, String> {
+    os_limited::list().map_err(|e| format!("Failed to read the trash: {}", e))
+}
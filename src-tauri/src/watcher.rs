@@ -0,0 +1,391 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use notify::event::{AccessKind, AccessMode, ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::commands::fs_commands::build_file_entry;
+use crate::encoding::encoded_dir::name_state_in;
+use crate::encoding::name_crypto::SessionKey;
+use crate::exclusions::{DirExclusions, Exclusions};
+use crate::models::directory_change::{DirectoryChanges, RenamedEntry};
+
+/// Changes are reported once nothing has happened for this long...
+pub const QUIET_PERIOD: Duration = Duration::from_millis(250);
+/// ...or at the latest this long after the first one, so a running download
+/// still shows up.
+pub const MAX_DELAY: Duration = Duration::from_secs(2);
+
+/// The directories being watched, with how many views watch each of them.
+/// Dropping a watcher stops its events, which ends its debounce thread.
+#[derive(Default)]
+pub struct WatchState(Mutex<HashMap<PathBuf, (RecommendedWatcher, usize)>>);
+
+impl WatchState {
+    /// Starts watching `dir` (not its subdirectories). Watching a directory
+    /// that is already watched only counts the watch, and the events keep
+    /// going to whoever watched it first.
+    pub fn watch(
+        &self,
+        dir: &Path,
+        key: impl Fn() -> Option<SessionKey> + Send + 'static,
+        exclusions: impl Fn() -> Arc<Exclusions> + Send + 'static,
+        emit: impl FnMut(DirectoryChanges) + Send + 'static,
+    ) -> Result<(), String> {
+        let mut watches = self
+            .0
+            .lock()
+            .map_err(|e| format!("Watch lock error: {}", e))?;
+        if let Some((_, count)) = watches.get_mut(dir) {
+            *count += 1;
+            return Ok(());
+        }
+
+        let tracker = ChangeTracker::new(dir)?;
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)
+            .map_err(|e| format!("Failed to create watcher: {}", e))?;
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(|e| format!("Failed to watch {}: {}", dir.display(), e))?;
+        thread::spawn(move || debounce(events, tracker, key, exclusions, emit));
+
+        watches.insert(dir.to_path_buf(), (watcher, 1));
+        Ok(())
+    }
+
+    /// Drops one watch of `dir`; the directory stops being watched once the
+    /// last one is dropped.
+    pub fn unwatch(&self, dir: &Path) -> Result<(), String> {
+        let mut watches = self
+            .0
+            .lock()
+            .map_err(|e| format!("Watch lock error: {}", e))?;
+        if let Some((_, count)) = watches.get_mut(dir) {
+            *count -= 1;
+            if *count == 0 {
+                watches.remove(dir);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn debounce(
+    events: Receiver<notify::Result<Event>>,
+    mut tracker: ChangeTracker,
+    key: impl Fn() -> Option<SessionKey>,
+    exclusions: impl Fn() -> Arc<Exclusions>,
+    mut emit: impl FnMut(DirectoryChanges),
+) {
+    while let Ok(event) = events.recv() {
+        tracker.add(event);
+        let started = Instant::now();
+        while let Some(left) = MAX_DELAY.checked_sub(started.elapsed()) {
+            match events.recv_timeout(QUIET_PERIOD.min(left)) {
+                Ok(event) => tracker.add(event),
+                Err(RecvTimeoutError::Timeout) => break,
+                // Unwatched while changes were pending; nobody wants them
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+
+        let changes = tracker.flush(key().as_ref(), &exclusions());
+        if !changes.is_empty() {
+            emit(changes);
+        }
+    }
+}
+
+/// Collects the names touched by events and works out what happened to them
+/// by comparing with the names that were there before. This copes with
+/// events that cancel out, like a temporary file created and removed again.
+struct ChangeTracker {
+    dir: PathBuf,
+    known: HashSet<OsString>,
+    touched: Vec<OsString>,
+    renames: Vec<(OsString, OsString)>,
+    /// Events were lost, so every name has to be checked
+    rescan: bool,
+}
+
+impl ChangeTracker {
+    fn new(dir: &Path) -> Result<ChangeTracker, String> {
+        Ok(ChangeTracker {
+            dir: dir.to_path_buf(),
+            known: list_names(dir)?,
+            touched: Vec::new(),
+            renames: Vec::new(),
+            rescan: false,
+        })
+    }
+
+    fn add(&mut self, event: notify::Result<Event>) {
+        let event = match event {
+            Ok(event) => event,
+            Err(_) => {
+                self.rescan = true;
+                return;
+            }
+        };
+        if event.need_rescan() {
+            self.rescan = true;
+        }
+
+        match event.kind {
+            EventKind::Access(AccessKind::Close(AccessMode::Write)) => {}
+            EventKind::Access(_) => return,
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                if let (Some(from), Some(to)) = (
+                    self.own_name(&event.paths[0]),
+                    self.own_name(&event.paths[1]),
+                ) {
+                    self.renames.push((from, to));
+                    return;
+                }
+            }
+            _ => {}
+        }
+        for path in &event.paths {
+            if let Some(name) = self.own_name(path) {
+                self.touch(name);
+            }
+        }
+    }
+
+    /// The name of `path` if it is directly inside the watched directory.
+    fn own_name(&self, path: &Path) -> Option<OsString> {
+        let name = path.file_name()?;
+        (path.parent() == Some(&self.dir)).then(|| name.to_os_string())
+    }
+
+    fn touch(&mut self, name: OsString) {
+        if !self.touched.contains(&name) {
+            self.touched.push(name);
+        }
+    }
+
+    /// Works out what happened to the touched names. Entries the exclusion
+    /// rules leave out are not reported, except when they are removed.
+    fn flush(&mut self, key: Option<&SessionKey>, exclusions: &Exclusions) -> DirectoryChanges {
+        let rules = exclusions.in_dir(&self.dir, key);
+        let mut changes = DirectoryChanges {
+            path: self.dir.to_string_lossy().to_string(),
+            ..Default::default()
+        };
+
+        if std::mem::take(&mut self.rescan) {
+            let mut names: Vec<OsString> = self.known.iter().cloned().collect();
+            names.extend(list_names(&self.dir).unwrap_or_default());
+            names.sort();
+            for name in names {
+                self.touch(name);
+            }
+        }
+
+        for (from, to) in std::mem::take(&mut self.renames) {
+            let to_path = self.dir.join(&to);
+            let renamed = self.known.contains(&from) && !self.exists(&from) && self.exists(&to);
+            match build_file_entry(&to_path, key) {
+                Ok(entry) if renamed => {
+                    self.known.remove(&from);
+                    self.known.insert(to.clone());
+                    self.touched.retain(|name| *name != from && *name != to);
+                    let from_path = self.dir.join(&from).to_string_lossy().to_string();
+                    match (
+                        self.is_visible(&rules, &from, entry.is_directory, key),
+                        self.is_visible(&rules, &to, entry.is_directory, key),
+                    ) {
+                        (true, true) => changes.renamed.push(RenamedEntry {
+                            from: from_path,
+                            entry,
+                        }),
+                        (false, true) => changes.created.push(entry),
+                        (true, false) => changes.removed.push(from_path),
+                        (false, false) => {}
+                    }
+                }
+                _ => {
+                    self.touch(from);
+                    self.touch(to);
+                }
+            }
+        }
+
+        for name in std::mem::take(&mut self.touched) {
+            let path = self.dir.join(&name);
+            match (self.exists(&name), self.known.contains(&name)) {
+                (true, known) => {
+                    self.known.insert(name.clone());
+                    let Ok(entry) = build_file_entry(&path, key) else {
+                        continue;
+                    };
+                    if !self.is_visible(&rules, &name, entry.is_directory, key) {
+                        continue;
+                    }
+                    if known {
+                        changes.modified.push(entry);
+                    } else {
+                        changes.created.push(entry);
+                    }
+                }
+                (false, true) => {
+                    self.known.remove(&name);
+                    changes.removed.push(path.to_string_lossy().to_string());
+                }
+                (false, false) => {}
+            }
+        }
+        changes
+    }
+
+    fn is_visible(
+        &self,
+        rules: &DirExclusions,
+        name: &OsString,
+        is_dir: bool,
+        key: Option<&SessionKey>,
+    ) -> bool {
+        let Some(name) = name.to_str() else {
+            return false;
+        };
+        let state = name_state_in(&self.dir, name, key);
+        !rules.is_excluded(name, &state, is_dir)
+    }
+
+    fn exists(&self, name: &OsString) -> bool {
+        fs::symlink_metadata(self.dir.join(name)).is_ok()
+    }
+}
+
+fn list_names(dir: &Path) -> Result<HashSet<OsString>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read directory: {}", e))?;
+    Ok(entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::encoded_dir::encode_name_with;
+    use crate::encoding::name_codec::default_codec;
+    use notify::event::{CreateKind, RemoveKind};
+    use tempfile::TempDir;
+
+    fn event(kind: EventKind, paths: &[&Path]) -> notify::Result<Event> {
+        let mut event = Event::new(kind);
+        for path in paths {
+            event = event.add_path(path.to_path_buf());
+        }
+        Ok(event)
+    }
+
+    #[test]
+    fn test_flush_sorts_out_created_modified_and_removed() {
+        let tmp = TempDir::new().unwrap();
+        let (kept, gone, new, temp) = (
+            tmp.path().join("kept.mkv"),
+            tmp.path().join("gone.mkv"),
+            tmp.path()
+                .join(encode_name_with(default_codec(), "new.mkv")),
+            tmp.path().join("part.tmp"),
+        );
+        fs::write(&kept, "a").unwrap();
+        fs::write(&gone, "a").unwrap();
+        let mut tracker = ChangeTracker::new(tmp.path()).unwrap();
+
+        fs::write(&kept, "ab").unwrap();
+        tracker.add(event(EventKind::Modify(ModifyKind::Any), &[&kept]));
+        fs::remove_file(&gone).unwrap();
+        tracker.add(event(EventKind::Remove(RemoveKind::File), &[&gone]));
+        fs::write(&new, "a").unwrap();
+        tracker.add(event(EventKind::Create(CreateKind::File), &[&new]));
+        tracker.add(event(EventKind::Create(CreateKind::File), &[&temp]));
+        tracker.add(event(EventKind::Remove(RemoveKind::File), &[&temp]));
+
+        let changes = tracker.flush(None, &Exclusions::default());
+        assert_eq!(changes.modified.len(), 1);
+        assert_eq!(changes.modified[0].file_size, 2);
+        assert_eq!(changes.removed, vec![gone.to_string_lossy().to_string()]);
+        assert_eq!(changes.created.len(), 1);
+        assert_eq!(changes.created[0].name, "new.mkv");
+        assert!(tracker.flush(None, &Exclusions::default()).is_empty());
+    }
+
+    #[test]
+    fn test_flush_reports_renames_with_decoded_names() {
+        let tmp = TempDir::new().unwrap();
+        let from = tmp.path().join("a.mkv");
+        let to = tmp.path().join(encode_name_with(default_codec(), "b.mkv"));
+        fs::write(&from, "a").unwrap();
+        let mut tracker = ChangeTracker::new(tmp.path()).unwrap();
+
+        fs::rename(&from, &to).unwrap();
+        tracker.add(event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            &[&from, &to],
+        ));
+
+        let changes = tracker.flush(None, &Exclusions::default());
+        assert_eq!(changes.renamed.len(), 1);
+        assert_eq!(changes.renamed[0].from, from.to_string_lossy());
+        assert_eq!(changes.renamed[0].entry.name, "b.mkv");
+        assert!(changes.created.is_empty() && changes.removed.is_empty());
+    }
+
+    #[test]
+    fn test_rescan_finds_changes_without_events() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join("old.mkv"), "a").unwrap();
+        let mut tracker = ChangeTracker::new(tmp.path()).unwrap();
+
+        fs::remove_file(tmp.path().join("old.mkv")).unwrap();
+        fs::write(tmp.path().join("new.mkv"), "a").unwrap();
+        fs::write(tmp.path().join(".gitignore"), "a").unwrap();
+        tracker.add(Err(notify::Error::generic("queue overflow")));
+
+        let changes = tracker.flush(None, &Exclusions::default());
+        assert_eq!(changes.created.len(), 1);
+        assert_eq!(changes.removed.len(), 1);
+    }
+
+    #[test]
+    fn test_watch_emits_changes() {
+        let tmp = TempDir::new().unwrap();
+        let state = WatchState::default();
+        let (sender, received) = mpsc::channel();
+        for _ in 0..2 {
+            let sender = sender.clone();
+            state
+                .watch(
+                    tmp.path(),
+                    || None,
+                    || Arc::new(Exclusions::default()),
+                    move |changes| sender.send(changes).unwrap(),
+                )
+                .unwrap();
+        }
+
+        fs::write(tmp.path().join("download.mkv"), "a").unwrap();
+        let changes = received.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(changes.created[0].name, "download.mkv");
+
+        // Still watched for the other view
+        state.unwatch(tmp.path()).unwrap();
+        fs::write(tmp.path().join("next.mkv"), "a").unwrap();
+        let changes = received.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(changes.created[0].name, "next.mkv");
+
+        state.unwatch(tmp.path()).unwrap();
+        fs::write(tmp.path().join("later.mkv"), "a").unwrap();
+        assert!(received.recv_timeout(Duration::from_secs(1)).is_err());
+    }
+}
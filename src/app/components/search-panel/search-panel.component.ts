@@ -1,4 +1,4 @@
-import { Component, ElementRef, inject, viewChild } from '@angular/core';
+import { Component, computed, ElementRef, inject, signal, viewChild } from '@angular/core';
 import { SearchService } from '../../services/search.service';
 import { FileTreeService } from '../../services/file-tree.service';
 import { FileEntry } from '../../models/file-node.model';
@@ -13,6 +13,19 @@ export class SearchPanelComponent {
   protected readonly searchService = inject(SearchService);
   private readonly fileTreeService = inject(FileTreeService);
   private readonly searchInput = viewChild<ElementRef<HTMLInputElement>>('searchInput');
+  protected readonly showIndexedDirectories = signal(false);
+
+  /** The indexed folder the open folder is in, if any. */
+  protected readonly rootIndex = computed(() => {
+    const root = this.fileTreeService.root();
+    if (!root) return null;
+    const rootPath = root.entry.path;
+    return (
+      this.searchService
+        .indexedDirectories()
+        .find((dir) => rootPath === dir.path || rootPath.startsWith(dir.path + '/')) ?? null
+    );
+  });
 
   focusInput(): void {
     this.searchInput()?.nativeElement.focus();
@@ -33,6 +46,16 @@ export class SearchPanelComponent {
     this.searchService.search(root.entry.path, query);
   }
 
+  indexRoot(): void {
+    const root = this.fileTreeService.root();
+    if (!root) return;
+    this.searchService.indexDirectory(root.entry.path);
+  }
+
+  removeIndex(path: string): void {
+    this.searchService.removeIndex(path);
+  }
+
   selectResult(entry: FileEntry): void {
     this.searchService.selectResult(entry);
   }
@@ -5,8 +5,309 @@ export interface FileEntry {
   isDirectory: boolean;
   isEncoded: boolean;
   decodedName: string | null;
+  isLocked: boolean;
   hasChildren: boolean;
   fileSize: number;
+  /** Seconds since the Unix epoch */
+  created: number | null;
+  modified: number | null;
+  accessed: number | null;
+  isSymlink: boolean;
+  symlinkTarget: string | null;
+  /** Unix permission bits, null on other platforms */
+  permissions: number | null;
+  isReadOnly: boolean;
+  kind: FileKind;
+  /** MIME type by extension, null for directories, locked and unknown names */
+  mimeType: string | null;
+}
+
+export type FileKind = 'video' | 'image' | 'audio' | 'subtitle' | 'other';
+
+export type NameScheme = 'base64' | 'base64url' | 'base32' | 'encrypted';
+
+export interface RenameRecord {
+  from: string;
+  to: string;
+}
+
+export type CollisionPolicy = 'fail' | 'skip' | 'suffix';
+
+export type CollisionResolution = 'failed' | 'skipped' | 'suffixed';
+
+export interface CollisionRecord {
+  path: string;
+  target: string;
+  resolution: CollisionResolution;
+  resolvedPath: string | null;
+}
+
+export interface RenameReport {
+  entry: FileEntry | null;
+  renamed: RenameRecord[];
+  rolledBack: RenameRecord[];
+  collisions: CollisionRecord[];
+  error: string | null;
+}
+
+export type PlanIssueKind = 'tooLong' | 'collision' | 'invalidUtf8' | 'locked' | 'undecodable';
+
+export interface PlannedRename {
+  path: string;
+  oldName: string;
+  newName: string;
+}
+
+export interface RenameIssue {
+  path: string;
+  kind: PlanIssueKind;
+}
+
+export interface RenamePreview {
+  renames: PlannedRename[];
+  issues: RenameIssue[];
+  collisions: CollisionRecord[];
+}
+
+export interface NameLimits {
+  maxComponentLen: number;
+  maxPathLen: number;
+}
+
+export type LengthIssue =
+  | { kind: 'componentTooLong'; component: string; length: number; limit: number }
+  | { kind: 'pathTooLong'; path: string; length: number; limit: number };
+
+export interface EncodeCheck {
+  canEncode: boolean;
+  reason: LengthIssue | null;
+  limits: NameLimits;
+}
+
+export type TreeIssueKind = 'undecodable' | 'partiallyEncoded' | 'unsafeName';
+
+export interface TreeIssue {
+  path: string;
+  physicalName: string;
+  kind: TreeIssueKind;
+  detail: string;
+}
+
+export interface VerifyCounts {
+  entries: number;
+  undecodable: number;
+  partiallyEncoded: number;
+  unsafeNames: number;
+  locked: number;
+}
+
+export interface VerifyReport {
+  issues: TreeIssue[];
+  counts: VerifyCounts;
+}
+
+export interface TrashEntry {
+  id: string;
+  name: string;
+  physicalName: string;
+  isEncoded: boolean;
+  isLocked: boolean;
+  isDirectory: boolean;
+  originalPath: string;
+  deletedAt: number | null;
+}
+
+export type OperationKind = 'rename' | 'move' | 'encode' | 'decode' | 'migrate' | 'delete';
+
+export interface OperationRecord {
+  id: number;
+  kind: OperationKind;
+  path: string;
+  performedAt: number;
+  undone: boolean;
+}
+
+export type TransferPhase = 'copying' | 'verifying';
+
+export interface TransferProgress {
+  source: string;
+  phase: TransferPhase;
+  bytesDone: number;
+  bytesTotal: number;
+  filesDone: number;
+  filesTotal: number;
+  currentPath: string | null;
+}
+
+export type NameMode = 'keep' | 'encode' | 'decode';
+export type ConflictPolicy = 'skip' | 'overwrite' | 'keepBoth' | 'ask';
+export type ConflictResolution = 'skip' | 'overwrite' | 'keepBoth';
+
+export interface CopyOptions {
+  nameMode?: NameMode;
+  scheme?: NameScheme;
+  conflictPolicy?: ConflictPolicy;
+}
+
+export interface CopyConflict {
+  copyId: string;
+  source: string;
+  target: string;
+  name: string;
+  isDirectory: boolean;
+  targetIsDirectory: boolean;
+}
+
+export interface CopyReport {
+  entry: FileEntry | null;
+  filesCopied: number;
+  skipped: string[];
+  overwritten: string[];
+  renamed: RenameRecord[];
+}
+
+export interface BatchItemResult {
+  path: string;
+  entry: FileEntry | null;
+  error: string | null;
+}
+
+export interface BatchReport {
+  results: BatchItemResult[];
+  notAttempted: string[];
+}
+
+export interface BatchProgress {
+  kind: OperationKind;
+  done: number;
+  total: number;
+  currentPath: string | null;
+  /** How far the item has been copied, while a move copies it to another file system */
+  transfer: TransferProgress | null;
+}
+
+export interface BatchRename {
+  path: string;
+  newName: string;
+}
+
+export type CaseConversion = 'keep' | 'lower' | 'upper' | 'title';
+
+/**
+ * `replace` may contain `{name}`, `{ext}`, `{n}` / `{n:3}`, `{duration}`, `{resolution}`,
+ * `{width}`, `{height}` and `{codec}`, and with `find` set `$1` or `${group}`.
+ */
+export interface RenameTemplate {
+  find?: string;
+  replace: string;
+  case?: CaseConversion;
+  includeExtension?: boolean;
+  counterStart?: number;
+  counterStep?: number;
+}
+
+export type BulkCollisionKind = 'duplicate' | 'existing';
+
+export interface BulkRenameCollision {
+  kind: BulkCollisionKind;
+  with: string;
+}
+
+export interface PlannedBulkRename {
+  path: string;
+  oldName: string;
+  newName: string | null;
+  encode: boolean;
+  collision: BulkRenameCollision | null;
+  error: string | null;
+}
+
+export interface BulkRenamePreview {
+  items: PlannedBulkRename[];
+}
+
+export interface RenamedEntry {
+  from: string;
+  entry: FileEntry;
+}
+
+export interface DirectoryChanges {
+  path: string;
+  created: FileEntry[];
+  modified: FileEntry[];
+  removed: string[];
+  renamed: RenamedEntry[];
+}
+
+/** Which entries listings, searches and counts leave out. */
+export interface ExclusionRules {
+  /** Gitignore-style patterns matched against display names. */
+  patterns: string[];
+  /** Hides dotfiles; encoded entries count by their decoded name. */
+  hideDotfiles: boolean;
+  /** Per-directory ignore file in gitignore syntax, or null to turn them off. */
+  ignoreFile: string | null;
+}
+
+export interface SearchResults {
+  searchId: string;
+  entries: FileEntry[];
+}
+
+export interface SearchProgress {
+  searchId: string;
+  directoriesScanned: number;
+  resultsFound: number;
+  finished: boolean;
+  /** The search stopped at the result cap. */
+  truncated: boolean;
+  cancelled: boolean;
+  error: string | null;
+}
+
+/** A query that can't be parsed; start and end are character offsets of token. */
+export interface QueryError {
+  message: string;
+  token: string;
+  start: number;
+  end: number;
+}
+
+export type SearchError = ({ kind: 'query' } & QueryError) | { kind: 'other'; message: string };
+
+/** A directory in the search index; searches below it use the index once ready. */
+export interface IndexedDirectory {
+  path: string;
+  ready: boolean;
+}
+
+/** Sent when scanning an indexed directory failed; it is dropped from the index. */
+export interface IndexFailure {
+  path: string;
+  message: string;
+}
+
+export type SortKey = 'name' | 'size' | 'modified' | 'duration' | 'kind';
+
+export type SortOrder = 'ascending' | 'descending';
+
+/** All fields are optional; by default pages of 500 sorted by name, directories first. */
+export interface PageRequest {
+  sortKey?: SortKey;
+  order?: SortOrder;
+  directoriesFirst?: boolean;
+  /** Ignored when cursor is set. */
+  offset?: number;
+  limit?: number;
+  /** nextCursor of the previous page. */
+  cursor?: string | null;
+}
+
+export interface DirectoryPage {
+  entries: FileEntry[];
+  offset: number;
+  total: number;
+  nextCursor: string | null;
 }
 
 export interface FileTreeNode {
@@ -15,6 +15,14 @@ export interface VideoInfo {
   framerate: string | null;
 }
 
+export interface VideoFile {
+  path: string;
+  /** Relative to the listed folder, with '/' separators. */
+  relativePath: string;
+  /** relativePath with every component decoded. */
+  decodedRelativePath: string;
+}
+
 export type FrameMode =
   | { type: 'fixed'; count: number }
   | { type: 'interval'; minutes: number };
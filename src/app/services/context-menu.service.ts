@@ -1,6 +1,6 @@
 import { inject, Injectable } from '@angular/core';
 import { ask, message } from '@tauri-apps/plugin-dialog';
-import { FileTreeNode } from '../models/file-node.model';
+import { FileTreeNode, LengthIssue } from '../models/file-node.model';
 import { ContextMenuComponent, ContextMenuItem } from '../components/context-menu/context-menu.component';
 import { RenameDialogComponent } from '../components/rename-dialog/rename-dialog.component';
 import { FileTreeService } from './file-tree.service';
@@ -32,7 +32,7 @@ export class ContextMenuService {
 
     if (!this.menuComponent) return;
 
-    const canEncode = await this.fs.canEncode(node.entry.path);
+    const encodeCheck = await this.fs.canEncode(node.entry.path);
     const encodable = node.entry.isDirectory || !node.entry.isEncoded;
     const decodable = node.entry.isDirectory || node.entry.isEncoded;
     const moveSource = this.fileTreeService.moveSource();
@@ -66,7 +66,7 @@ export class ContextMenuService {
       },
       { label: '', enabled: false, separator: true, action: () => {} },
       {
-        label: 'Encode' + (!canEncode ? ' (maybe too long)' : ''),
+        label: 'Encode' + this.lengthHint(encodeCheck.reason),
         enabled: encodable,
         action: () => this.fileTreeService.encodeNode(node),
       },
@@ -110,6 +110,13 @@ export class ContextMenuService {
     this.menuComponent.show(event.clientX, event.clientY, items);
   }
 
+  private lengthHint(reason: LengthIssue | null): string {
+    if (!reason) return '';
+    return reason.kind === 'componentTooLong'
+      ? ` (name too long: ${reason.length}/${reason.limit})`
+      : ` (path too long: ${reason.length}/${reason.limit})`;
+  }
+
   openRenameDialog(node: FileTreeNode): void {
     this.renameDialog?.show(node);
   }
@@ -128,8 +135,8 @@ export class ContextMenuService {
     }
 
     const childInfo = childCount > 0 ? `\nIt has ${childCount} children` : '';
-    const confirmed = await ask(`Really delete '${node.entry.name}'?${childInfo}`, {
-      title: 'Really delete?',
+    const confirmed = await ask(`Move '${node.entry.name}' to the trash?${childInfo}`, {
+      title: 'Move to trash?',
       kind: 'warning',
     });
 
@@ -1,8 +1,37 @@
 import { Injectable } from '@angular/core';
 import { invoke } from '@tauri-apps/api/core';
+import { listen, UnlistenFn } from '@tauri-apps/api/event';
 import { open } from '@tauri-apps/plugin-dialog';
-import { FileEntry } from '../models/file-node.model';
-import { VideoFrame, VideoInfo } from '../models/video-frame.model';
+import {
+  BatchProgress,
+  BatchRename,
+  BatchReport,
+  BulkRenamePreview,
+  CollisionPolicy,
+  ConflictResolution,
+  CopyConflict,
+  CopyOptions,
+  CopyReport,
+  DirectoryChanges,
+  DirectoryPage,
+  EncodeCheck,
+  ExclusionRules,
+  FileEntry,
+  IndexedDirectory,
+  IndexFailure,
+  NameScheme,
+  OperationRecord,
+  PageRequest,
+  RenamePreview,
+  RenameReport,
+  RenameTemplate,
+  SearchProgress,
+  SearchResults,
+  TransferProgress,
+  TrashEntry,
+  VerifyReport,
+} from '../models/file-node.model';
+import { VideoFile, VideoFrame, VideoInfo } from '../models/video-frame.model';
 
 @Injectable({ providedIn: 'root' })
 export class FileSystemService {
@@ -10,32 +39,224 @@ export class FileSystemService {
     return invoke<FileEntry[]>('read_directory', { path });
   }
 
-  async encodeNode(path: string): Promise<FileEntry> {
-    return invoke<FileEntry>('encode_node', { path });
+  async readDirectoryPage(path: string, request: PageRequest = {}): Promise<DirectoryPage> {
+    return invoke<DirectoryPage>('read_directory_page', { path, request });
   }
 
-  async decodeNode(path: string): Promise<FileEntry> {
-    return invoke<FileEntry>('decode_node', { path });
+  async encodeNode(
+    path: string,
+    scheme?: NameScheme,
+    collisionPolicy?: CollisionPolicy,
+  ): Promise<RenameReport> {
+    return invoke<RenameReport>('encode_node', {
+      path,
+      scheme: scheme ?? null,
+      collisionPolicy: collisionPolicy ?? null,
+    });
   }
 
-  async canEncode(path: string): Promise<boolean> {
-    return invoke<boolean>('can_encode_node', { path });
+  async decodeNode(path: string, collisionPolicy?: CollisionPolicy): Promise<RenameReport> {
+    return invoke<RenameReport>('decode_node', { path, collisionPolicy: collisionPolicy ?? null });
   }
 
-  async renameNode(path: string, newName: string, encode: boolean): Promise<FileEntry> {
-    return invoke<FileEntry>('rename_node', { path, newName, encode });
+  async previewEncodeNode(
+    path: string,
+    scheme?: NameScheme,
+    collisionPolicy?: CollisionPolicy,
+  ): Promise<RenamePreview> {
+    return invoke<RenamePreview>('preview_encode_node', {
+      path,
+      scheme: scheme ?? null,
+      collisionPolicy: collisionPolicy ?? null,
+    });
+  }
+
+  async previewDecodeNode(path: string, collisionPolicy?: CollisionPolicy): Promise<RenamePreview> {
+    return invoke<RenamePreview>('preview_decode_node', { path, collisionPolicy: collisionPolicy ?? null });
+  }
+
+  async migrateEncodedNames(path: string, collisionPolicy?: CollisionPolicy): Promise<RenameReport> {
+    return invoke<RenameReport>('migrate_encoded_names', { path, collisionPolicy: collisionPolicy ?? null });
+  }
+
+  async encryptNode(path: string): Promise<FileEntry> {
+    return invoke<FileEntry>('encrypt_node', { path });
+  }
+
+  async decryptNode(path: string): Promise<FileEntry> {
+    return invoke<FileEntry>('decrypt_node', { path });
+  }
+
+  async verifyTree(path: string): Promise<VerifyReport> {
+    return invoke<VerifyReport>('verify_tree', { path });
+  }
+
+  async canEncode(path: string): Promise<EncodeCheck> {
+    return invoke<EncodeCheck>('can_encode_node', { path });
+  }
+
+  async renameNode(
+    path: string,
+    newName: string,
+    encode: boolean,
+    scheme?: NameScheme,
+    collisionPolicy?: CollisionPolicy,
+  ): Promise<RenameReport> {
+    return invoke<RenameReport>('rename_node', {
+      path,
+      newName,
+      encode,
+      scheme: scheme ?? null,
+      collisionPolicy: collisionPolicy ?? null,
+    });
   }
 
   async deleteNode(path: string): Promise<void> {
     return invoke<void>('delete_node', { path });
   }
 
+  async listTrash(): Promise<TrashEntry[]> {
+    return invoke<TrashEntry[]>('list_trash');
+  }
+
+  async restoreFromTrash(id: string): Promise<FileEntry> {
+    return invoke<FileEntry>('restore_from_trash', { id });
+  }
+
+  async emptyTrash(): Promise<void> {
+    return invoke<void>('empty_trash');
+  }
+
+  async undoLastOperation(): Promise<OperationRecord> {
+    return invoke<OperationRecord>('undo_last_operation');
+  }
+
+  async redoOperation(): Promise<OperationRecord> {
+    return invoke<OperationRecord>('redo_operation');
+  }
+
+  async listOperationHistory(limit?: number): Promise<OperationRecord[]> {
+    return invoke<OperationRecord[]>('list_operation_history', { limit: limit ?? null });
+  }
+
   async countChildren(path: string): Promise<number> {
     return invoke<number>('count_children', { path });
   }
 
-  async moveNode(source: string, targetDir: string): Promise<FileEntry> {
-    return invoke<FileEntry>('move_node', { source, targetDir });
+  async moveNode(source: string, targetDir: string, verifyChecksum?: boolean): Promise<FileEntry> {
+    return invoke<FileEntry>('move_node', { source, targetDir, verifyChecksum: verifyChecksum ?? null });
+  }
+
+  async copyNode(copyId: string, source: string, targetDir: string, options?: CopyOptions): Promise<CopyReport> {
+    return invoke<CopyReport>('copy_node', { copyId, source, targetDir, options: options ?? null });
+  }
+
+  async resolveCopyConflict(copyId: string, resolution: ConflictResolution, applyToAll = false): Promise<void> {
+    return invoke<void>('resolve_copy_conflict', { copyId, resolution, applyToAll });
+  }
+
+  async cancelCopy(copyId: string): Promise<void> {
+    return invoke<void>('cancel_copy', { copyId });
+  }
+
+  async onCopyProgress(handler: (progress: TransferProgress) => void): Promise<UnlistenFn> {
+    return listen<TransferProgress>('copy-progress', (event) => handler(event.payload));
+  }
+
+  /** Conflicts of copies started with the `ask` policy; answer with `resolveCopyConflict`. */
+  async onCopyConflict(handler: (conflict: CopyConflict) => void): Promise<UnlistenFn> {
+    return listen<CopyConflict>('copy-conflict', (event) => handler(event.payload));
+  }
+
+  /** Progress of moves to another file system, which copy the node. */
+  async onMoveProgress(handler: (progress: TransferProgress) => void): Promise<UnlistenFn> {
+    return listen<TransferProgress>('move-progress', (event) => handler(event.payload));
+  }
+
+  async batchEncodeNodes(
+    paths: string[],
+    scheme?: NameScheme,
+    collisionPolicy?: CollisionPolicy,
+    continueOnError = false,
+  ): Promise<BatchReport> {
+    return invoke<BatchReport>('batch_encode_nodes', {
+      paths,
+      scheme: scheme ?? null,
+      collisionPolicy: collisionPolicy ?? null,
+      continueOnError,
+    });
+  }
+
+  async batchDecodeNodes(paths: string[], collisionPolicy?: CollisionPolicy, continueOnError = false): Promise<BatchReport> {
+    return invoke<BatchReport>('batch_decode_nodes', { paths, collisionPolicy: collisionPolicy ?? null, continueOnError });
+  }
+
+  async batchMoveNodes(
+    paths: string[],
+    targetDir: string,
+    verifyChecksum?: boolean,
+    continueOnError = false,
+  ): Promise<BatchReport> {
+    return invoke<BatchReport>('batch_move_nodes', {
+      paths,
+      targetDir,
+      verifyChecksum: verifyChecksum ?? null,
+      continueOnError,
+    });
+  }
+
+  async batchDeleteNodes(paths: string[], continueOnError = false): Promise<BatchReport> {
+    return invoke<BatchReport>('batch_delete_nodes', { paths, continueOnError });
+  }
+
+  async batchRenameNodes(
+    renames: BatchRename[],
+    encode: boolean,
+    scheme?: NameScheme,
+    collisionPolicy?: CollisionPolicy,
+    continueOnError = false,
+  ): Promise<BatchReport> {
+    return invoke<BatchReport>('batch_rename_nodes', {
+      renames,
+      encode,
+      scheme: scheme ?? null,
+      collisionPolicy: collisionPolicy ?? null,
+      continueOnError,
+    });
+  }
+
+  async previewBulkRename(paths: string[], template: RenameTemplate): Promise<BulkRenamePreview> {
+    return invoke<BulkRenamePreview>('preview_bulk_rename', { paths, template });
+  }
+
+  /** Fails without renaming anything if the preview has collisions or errors. */
+  async bulkRename(paths: string[], template: RenameTemplate): Promise<BatchReport> {
+    return invoke<BatchReport>('bulk_rename', { paths, template });
+  }
+
+  async onBatchProgress(handler: (progress: BatchProgress) => void): Promise<UnlistenFn> {
+    return listen<BatchProgress>('batch-progress', (event) => handler(event.payload));
+  }
+
+  async getExclusionRules(): Promise<ExclusionRules> {
+    return invoke<ExclusionRules>('get_exclusion_rules');
+  }
+
+  async setExclusionRules(rules: ExclusionRules): Promise<void> {
+    return invoke<void>('set_exclusion_rules', { rules });
+  }
+
+  async watchDirectory(path: string): Promise<void> {
+    return invoke<void>('watch_directory', { path });
+  }
+
+  async unwatchDirectory(path: string): Promise<void> {
+    return invoke<void>('unwatch_directory', { path });
+  }
+
+  /** Changes inside directories passed to `watchDirectory`, debounced. */
+  async onDirectoryChanged(handler: (changes: DirectoryChanges) => void): Promise<UnlistenFn> {
+    return listen<DirectoryChanges>('directory-changed', (event) => handler(event.payload));
   }
 
   async showInFileManager(path: string): Promise<void> {
@@ -54,8 +275,75 @@ export class FileSystemService {
     return invoke<FileEntry[]>('search_files', { path, pattern });
   }
 
-  async listVideoFiles(path: string): Promise<string[]> {
-    return invoke<string[]>('list_video_files', { path });
+  /**
+   * Starts a search in the background. `searchId` tags its events; pick a new
+   * one for each search. Rejects with a `SearchError` if the query doesn't
+   * parse.
+   *
+   * Queries combine words, "phrases", globs and /regexes/ with AND, OR, NOT
+   * and parentheses, plus filters such as size>1GB, ext:mkv, encoded:yes,
+   * kind:video, modified<30d, duration>20m, height>=1080 and codec:hevc.
+   */
+  async startSearch(searchId: string, path: string, pattern: string, maxResults?: number): Promise<void> {
+    return invoke<void>('start_search', { searchId, path, pattern, maxResults: maxResults ?? null });
+  }
+
+  async cancelSearch(searchId: string): Promise<void> {
+    return invoke<void>('cancel_search', { searchId });
+  }
+
+  async onSearchResults(handler: (results: SearchResults) => void): Promise<UnlistenFn> {
+    return listen<SearchResults>('search-results', (event) => handler(event.payload));
+  }
+
+  /** Sent while a search runs, and once more with `finished` set. */
+  async onSearchProgress(handler: (progress: SearchProgress) => void): Promise<UnlistenFn> {
+    return listen<SearchProgress>('search-progress', (event) => handler(event.payload));
+  }
+
+  /** Scans the directory into the search index in the background. */
+  async indexDirectory(path: string): Promise<void> {
+    return invoke<void>('index_directory', { path });
+  }
+
+  async removeIndex(path: string): Promise<void> {
+    return invoke<void>('remove_index', { path });
+  }
+
+  async listIndexedDirectories(): Promise<IndexedDirectory[]> {
+    return invoke<IndexedDirectory[]>('list_indexed_directories');
+  }
+
+  /** Sent when an indexed directory has been scanned and searches use the index. */
+  async onSearchIndexReady(handler: (directory: IndexedDirectory) => void): Promise<UnlistenFn> {
+    return listen<IndexedDirectory>('search-index-ready', (event) => handler(event.payload));
+  }
+
+  /** Sent when scanning an indexed directory failed and it was dropped from the index. */
+  async onSearchIndexFailed(handler: (failure: IndexFailure) => void): Promise<UnlistenFn> {
+    return listen<IndexFailure>('search-index-failed', (event) => handler(event.payload));
+  }
+
+  /** Unlocks the library `path` belongs to; rejects a wrong passphrase or a path outside any library. */
+  async unlockSession(path: string, passphrase: string): Promise<void> {
+    return invoke<void>('unlock_session', { path, passphrase });
+  }
+
+  /** Makes `path` the root of a new library and unlocks it; rejects inside an existing one. */
+  async createKeyring(path: string, passphrase: string): Promise<void> {
+    return invoke<void>('create_keyring', { path, passphrase });
+  }
+
+  async lockSession(): Promise<void> {
+    return invoke<void>('lock_session');
+  }
+
+  async isSessionUnlocked(): Promise<boolean> {
+    return invoke<boolean>('is_session_unlocked');
+  }
+
+  async listVideoFiles(path: string): Promise<VideoFile[]> {
+    return invoke<VideoFile[]>('list_video_files', { path });
   }
 
   async getVideoInfo(path: string): Promise<VideoInfo> {
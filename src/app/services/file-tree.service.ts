@@ -27,8 +27,18 @@ export class FileTreeService {
       isDirectory: true,
       isEncoded: false,
       decodedName: null,
+      isLocked: false,
       hasChildren: entries.length > 0,
       fileSize: 0,
+      created: null,
+      modified: null,
+      accessed: null,
+      isSymlink: false,
+      symlinkTarget: null,
+      permissions: null,
+      isReadOnly: false,
+      kind: 'other',
+      mimeType: null,
     };
 
     const root: FileTreeNode = {
@@ -67,8 +77,11 @@ export class FileTreeService {
   }
 
   async encodeNode(node: FileTreeNode): Promise<void> {
-    const updatedEntry = await this.fs.encodeNode(node.entry.path);
-    node.entry = updatedEntry;
+    const report = await this.fs.encodeNode(node.entry.path);
+    if (report.error) {
+      throw new Error(report.error);
+    }
+    node.entry = report.entry ?? node.entry;
     if (node.entry.isDirectory && node.isExpanded) {
       node.children = null;
       const entries = await this.fs.readDirectory(node.entry.path);
@@ -78,8 +91,11 @@ export class FileTreeService {
   }
 
   async decodeNode(node: FileTreeNode): Promise<void> {
-    const updatedEntry = await this.fs.decodeNode(node.entry.path);
-    node.entry = updatedEntry;
+    const report = await this.fs.decodeNode(node.entry.path);
+    if (report.error) {
+      throw new Error(report.error);
+    }
+    node.entry = report.entry ?? node.entry;
     if (node.entry.isDirectory && node.isExpanded) {
       node.children = null;
       const entries = await this.fs.readDirectory(node.entry.path);
@@ -89,8 +105,11 @@ export class FileTreeService {
   }
 
   async renameNode(node: FileTreeNode, newName: string, encode: boolean): Promise<void> {
-    const updatedEntry = await this.fs.renameNode(node.entry.path, newName, encode);
-    node.entry = updatedEntry;
+    const report = await this.fs.renameNode(node.entry.path, newName, encode);
+    if (report.error) {
+      throw new Error(report.error);
+    }
+    node.entry = report.entry ?? node.entry;
     this.notifyChange();
   }
 
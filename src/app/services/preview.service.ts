@@ -103,19 +103,14 @@ export class PreviewService {
         return;
       }
 
-      for (const filePath of files) {
+      for (const file of files) {
         if (id !== this.generationId) return;
 
-        const relativePath = filePath.startsWith(folderPath)
-          ? filePath.substring(folderPath.length).replace(/^[/\\]/, '')
-          : filePath;
-
-        const decodedRelativePath = this.decodeRelativePath(relativePath);
-
+        const filePath = file.path;
         const entry: FolderVideoEntry = {
           filePath,
-          relativePath,
-          decodedRelativePath,
+          relativePath: file.relativePath,
+          decodedRelativePath: file.decodedRelativePath,
           info: null,
           frames: [],
           error: null,
@@ -196,19 +191,6 @@ export class PreviewService {
     this.folderPath.set(null);
   }
 
-  private decodeRelativePath(relativePath: string): string {
-    return relativePath.split(/[/\\]/).map(segment => {
-      if (segment.startsWith('.dat_')) {
-        try {
-          return atob(segment.substring(5));
-        } catch {
-          return segment;
-        }
-      }
-      return segment;
-    }).join('/');
-  }
-
   private yield(): Promise<void> {
     return new Promise(resolve => setTimeout(resolve, 0));
   }
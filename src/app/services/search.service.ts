@@ -1,5 +1,5 @@
 import { inject, Injectable, signal } from '@angular/core';
-import { FileEntry } from '../models/file-node.model';
+import { FileEntry, IndexedDirectory, SearchError, SearchProgress } from '../models/file-node.model';
 import { FileSystemService } from './file-system.service';
 import { FileTreeService } from './file-tree.service';
 
@@ -8,23 +8,106 @@ export class SearchService {
   readonly results = signal<FileEntry[]>([]);
   readonly searchActive = signal(false);
   readonly query = signal('');
+  readonly progress = signal<SearchProgress | null>(null);
+  readonly error = signal<SearchError | null>(null);
+  /** Folders in the search index; searches below ready ones use the index. */
+  readonly indexedDirectories = signal<IndexedDirectory[]>([]);
 
   private readonly fs = inject(FileSystemService);
   private readonly fileTreeService = inject(FileTreeService);
+  private currentSearchId: string | null = null;
+
+  constructor() {
+    this.fs.onSearchResults((results) => {
+      if (results.searchId !== this.currentSearchId) return;
+      this.results.update((prev) => [...prev, ...results.entries]);
+    });
+    this.fs.onSearchProgress((progress) => {
+      if (progress.searchId !== this.currentSearchId) return;
+      this.progress.set(progress);
+      if (progress.finished) {
+        this.currentSearchId = null;
+      }
+    });
+    this.fs.onSearchIndexReady((ready) => {
+      this.indexedDirectories.update((dirs) => [
+        ...dirs.filter((dir) => dir.path !== ready.path),
+        ready,
+      ]);
+    });
+    this.fs.onSearchIndexFailed((failure) => {
+      this.indexedDirectories.update((dirs) => dirs.filter((dir) => dir.path !== failure.path));
+      this.error.set({
+        kind: 'other',
+        message: `Failed to index ${failure.path}: ${failure.message}`,
+      });
+    });
+    this.loadIndexedDirectories();
+  }
+
+  async loadIndexedDirectories(): Promise<void> {
+    try {
+      this.indexedDirectories.set(await this.fs.listIndexedDirectories());
+    } catch (e) {
+      console.error('Failed to list indexed folders:', e);
+    }
+  }
+
+  /** Adds the folder to the search index; it is scanned in the background. */
+  async indexDirectory(path: string): Promise<void> {
+    try {
+      await this.fs.indexDirectory(path);
+      this.indexedDirectories.update((dirs) =>
+        dirs.some((dir) => dir.path === path) ? dirs : [...dirs, { path, ready: false }],
+      );
+    } catch (e) {
+      this.error.set({ kind: 'other', message: String(e) });
+    }
+  }
+
+  async removeIndex(path: string): Promise<void> {
+    try {
+      await this.fs.removeIndex(path);
+      this.indexedDirectories.update((dirs) => dirs.filter((dir) => dir.path !== path));
+    } catch (e) {
+      this.error.set({ kind: 'other', message: String(e) });
+    }
+  }
 
   async search(rootPath: string, pattern: string): Promise<void> {
+    this.cancel();
     this.query.set(pattern);
+    this.results.set([]);
+    this.progress.set(null);
+    this.error.set(null);
     if (!pattern.trim()) {
-      this.results.set([]);
       return;
     }
-    const entries = await this.fs.searchFiles(rootPath, pattern);
-    this.results.set(entries);
+    // Set before starting, as the first events can arrive before the invoke resolves
+    const searchId = crypto.randomUUID();
+    this.currentSearchId = searchId;
+    try {
+      await this.fs.startSearch(searchId, rootPath, pattern);
+    } catch (e: any) {
+      if (this.currentSearchId !== searchId) return;
+      this.currentSearchId = null;
+      this.error.set(e?.kind ? e : { kind: 'other', message: String(e) });
+    }
+  }
+
+  cancel(): void {
+    if (this.currentSearchId) {
+      this.fs.cancelSearch(this.currentSearchId);
+      this.currentSearchId = null;
+    }
   }
 
   clear(): void {
+    this.cancel();
     this.searchActive.set(false);
     this.results.set([]);
+    this.progress.set(null);
+    this.error.set(null);
     this.query.set('');
   }
 